@@ -0,0 +1,262 @@
+//! Runs arbitrary sequences of native escrow instructions, with parties
+//! dropping their signatures and accounts swapped or substituted, against a
+//! solana-program-test bank, checking after every step that:
+//!
+//! - lamports never appear from nowhere: the accounts the instructions can
+//!   reach never hold more lamports in total than before;
+//! - an escrow never leaves Released, other than by being closed.
+//!
+//! Run with `cargo +nightly fuzz run instruction_sequences` from the
+//! repository root.
+
+#![no_main]
+
+use std::collections::BTreeSet;
+
+use arbitrary::Arbitrary;
+use escrow_program::{
+    instruction,
+    processor::process_instruction,
+    state::{
+        find_config_address, find_escrow_address, find_registry_address, find_vault_address, EscrowState, EscrowStatus, Feature,
+        DEFAULT_MAX_EXPIRY_HORIZON,
+    },
+};
+use libfuzzer_sys::fuzz_target;
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    clock::Clock,
+    instruction::Instruction,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+/// Escrow ids the sequences spread over.
+const ESCROWS: u8 = 3;
+/// Longest sequence run, to bound the time an input takes.
+const MAX_STEPS: usize = 32;
+
+#[derive(Arbitrary, Debug)]
+enum Action {
+    Initialize { amount: u32, expiry_secs: u16, arbiter: bool },
+    Deposit,
+    DepositAdditional { amount: u32 },
+    Withdraw,
+    WithdrawPartial { amount: u32 },
+    Cancel,
+    Refund,
+    Close,
+    Dispute { by_taker: bool },
+    Resolve { release_to_taker: bool },
+    MutualCancel,
+    ExtendDeadline { secs: u16 },
+    Warp { secs: u16 },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Step {
+    action:   Action,
+    escrow:   u8,
+    /// Parties, one bit per `Parties` index, whose signatures are dropped
+    unsigned: u8,
+    /// Two accounts of the instruction to exchange
+    swap:     Option<(u8, u8)>,
+    /// An account of the instruction to replace with a party's
+    replace:  Option<(u8, u8)>,
+}
+
+/// The keypairs that can sign: initializer, taker, arbiter and a stranger.
+struct Parties([Keypair; 4]);
+
+impl Parties {
+    fn key(&self, index: u8) -> Pubkey {
+        self.0[usize::from(index) % self.0.len()].pubkey()
+    }
+
+    fn find(&self, key: &Pubkey) -> Option<usize> {
+        self.0.iter().position(|party| party.pubkey() == *key)
+    }
+}
+
+struct Env {
+    ctx:        ProgramTestContext,
+    program_id: Pubkey,
+    parties:    Parties,
+    treasury:   Pubkey,
+}
+
+impl Env {
+    async fn start() -> Self {
+        let program_id = Pubkey::new_unique();
+        let parties    = Parties([Keypair::new(), Keypair::new(), Keypair::new(), Keypair::new()]);
+        let treasury   = Pubkey::new_unique();
+
+        let mut program_test = ProgramTest::new("escrow_program", program_id, processor!(process_instruction));
+        for key in parties.0.iter().map(|party| party.pubkey()).chain([treasury]) {
+            program_test.add_account(key, Account { lamports: 10 * LAMPORTS_PER_SOL, ..Account::default() });
+        }
+        let mut env = Env { ctx: program_test.start_with_context().await, program_id, parties, treasury };
+
+        let admin = env.ctx.payer.pubkey();
+        let ix    = instruction::initialize_config(&program_id, &admin, 0, &treasury);
+        assert!(env.send(ix).await, "config must initialize");
+        let ix = instruction::set_feature(&program_id, &admin, Feature::Fees, true);
+        assert!(env.send(ix).await, "fees must switch on");
+        let ix = instruction::update_config(
+            &program_id, &admin, &admin, 100, &treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+            0, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+        );
+        assert!(env.send(ix).await, "fee must be set");
+        env
+    }
+
+    /// Sends `ix` signed by the fee payer and the parties it marks as
+    /// signers, returning whether it succeeded.
+    async fn send(&mut self, ix: Instruction) -> bool {
+        let mut signing = BTreeSet::new();
+        for meta in ix.accounts.iter().filter(|meta| meta.is_signer) {
+            match self.parties.find(&meta.pubkey) {
+                Some(index) => {
+                    signing.insert(index);
+                }
+                None if meta.pubkey == self.ctx.payer.pubkey() => {}
+                // A signature nobody here can give: the runtime would refuse it anyway
+                None => return false,
+            }
+        }
+        let blockhash   = self.ctx.get_new_latest_blockhash().await.unwrap();
+        let mut signers = vec![&self.ctx.payer];
+        signers.extend(signing.into_iter().map(|index| &self.parties.0[index]));
+        let tx = Transaction::new_signed_with_payer(&[ix], Some(&self.ctx.payer.pubkey()), &signers, blockhash);
+        self.ctx.banks_client.process_transaction(tx).await.is_ok()
+    }
+
+    async fn now(&mut self) -> i64 {
+        self.ctx.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+    }
+
+    /// Every account an instruction built from the parties can credit.
+    fn tracked(&self) -> Vec<Pubkey> {
+        let initializer = self.parties.key(0);
+        let mut keys: Vec<Pubkey> = self.parties.0.iter().map(|party| party.pubkey()).collect();
+        keys.extend([self.treasury, self.ctx.payer.pubkey(), find_config_address(&self.program_id).0]);
+        keys.push(find_registry_address(&self.program_id, &initializer).0);
+        for id in 0..u64::from(ESCROWS) {
+            let (escrow, _) = find_escrow_address(&self.program_id, &initializer, id);
+            keys.extend([escrow, find_vault_address(&self.program_id, &escrow).0]);
+        }
+        keys
+    }
+
+    async fn total_lamports(&mut self) -> u128 {
+        let mut total = 0;
+        for key in self.tracked() {
+            total += u128::from(self.ctx.banks_client.get_balance(key).await.unwrap());
+        }
+        total
+    }
+
+    async fn status(&mut self, id: u64) -> Option<EscrowStatus> {
+        let (escrow, _) = find_escrow_address(&self.program_id, &self.parties.key(0), id);
+        let account     = self.ctx.banks_client.get_account(escrow).await.unwrap()?;
+        EscrowState::unpack(&account.data).ok().map(|state| state.status)
+    }
+
+    async fn build(&mut self, action: &Action, id: u64) -> Option<Instruction> {
+        let program_id = self.program_id;
+        let (initializer, taker, arbiter) = (self.parties.key(0), self.parties.key(1), self.parties.key(2));
+        let ix = match *action {
+            Action::Initialize { amount, expiry_secs, arbiter: with_arbiter } => {
+                let expiry = if expiry_secs == 0 { 0 } else { self.now().await + i64::from(expiry_secs) };
+                let arbiter = with_arbiter.then_some(&arbiter);
+                instruction::initialize(&program_id, &initializer, &taker, u64::from(amount), id, expiry, arbiter)
+            }
+            Action::Deposit => instruction::deposit(&program_id, &initializer, &taker, id),
+            Action::DepositAdditional { amount } => {
+                instruction::deposit_additional(&program_id, &initializer, &taker, id, u64::from(amount))
+            }
+            Action::Withdraw => instruction::withdraw(&program_id, &initializer, &taker, &self.treasury, id),
+            Action::WithdrawPartial { amount } => {
+                instruction::withdraw_partial(&program_id, &initializer, &taker, &self.treasury, id, u64::from(amount))
+            }
+            Action::Cancel => instruction::cancel(&program_id, &initializer, id),
+            Action::Refund => instruction::refund(&program_id, &initializer, id),
+            Action::Close => instruction::close(&program_id, &initializer, id),
+            Action::Dispute { by_taker } => {
+                let party = if by_taker { taker } else { initializer };
+                instruction::dispute(&program_id, &party, &initializer, id)
+            }
+            Action::Resolve { release_to_taker } => {
+                instruction::resolve(&program_id, &arbiter, &initializer, &taker, id, release_to_taker)
+            }
+            Action::MutualCancel => instruction::mutual_cancel(&program_id, &initializer, &taker, id),
+            Action::ExtendDeadline { secs } => {
+                let expiry = self.now().await + i64::from(secs);
+                instruction::extend_deadline(&program_id, &initializer, &taker, id, expiry)
+            }
+            Action::Warp { .. } => return None,
+        };
+        let ix = instruction::with_features(&program_id, ix);
+        Some(instruction::with_stats(&program_id, ix, &[initializer, taker]))
+    }
+
+    /// Drops the signatures and moves the accounts `step` asks for. The system
+    /// program, whose id is the default pubkey, stays in place: the
+    /// program-test bank panics, where a validator would fail the
+    /// transaction, on a CPI to a program the transaction doesn't list.
+    fn mangle(&self, step: &Step, mut ix: Instruction) -> Instruction {
+        for meta in &mut ix.accounts {
+            if let Some(index) = self.parties.find(&meta.pubkey) {
+                if step.unsigned & (1 << index) != 0 {
+                    meta.is_signer = false;
+                }
+            }
+        }
+        let movable: Vec<usize> = (0..ix.accounts.len()).filter(|&i| ix.accounts[i].pubkey != Pubkey::default()).collect();
+        let pick = |index: u8| movable[usize::from(index) % movable.len()];
+        if let Some((a, b)) = step.swap {
+            ix.accounts.swap(pick(a), pick(b));
+        }
+        if let Some((index, party)) = step.replace {
+            ix.accounts[pick(index)].pubkey = self.parties.key(party);
+        }
+        ix
+    }
+}
+
+async fn run(steps: &[Step]) {
+    let mut env = Env::start().await;
+    for step in steps.iter().take(MAX_STEPS) {
+        let id = u64::from(step.escrow % ESCROWS);
+        let before_status = env.status(id).await;
+        let Some(ix) = env.build(&step.action, id).await else {
+            if let Action::Warp { secs } = step.action {
+                let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+                clock.unix_timestamp += i64::from(secs);
+                env.ctx.set_sysvar(&clock);
+            }
+            continue;
+        };
+        let ix = env.mangle(step, ix);
+
+        let before = env.total_lamports().await;
+        env.send(ix).await;
+        let after  = env.total_lamports().await;
+        assert!(after <= before, "lamports appeared from nowhere: {before} -> {after} after {step:?}");
+
+        if before_status == Some(EscrowStatus::Released) {
+            let status = env.status(id).await;
+            assert!(
+                matches!(status, None | Some(EscrowStatus::Released)),
+                "escrow {id} regressed from Released to {status:?} after {step:?}",
+            );
+        }
+    }
+}
+
+fuzz_target!(|steps: Vec<Step>| {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(run(&steps));
+});
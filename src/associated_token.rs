@@ -0,0 +1,38 @@
+//! Minimal client of the Associated Token Account program, enough to derive
+//! a wallet's token account for a mint and create it when missing.
+
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+    system_program,
+};
+
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey = pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+const CREATE_IDEMPOTENT: u8                    = 1;
+
+/// Derives `wallet`'s associated token account for `mint` under `token_program`.
+pub fn find_associated_token_address(wallet: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+/// `CreateIdempotent`: creates the associated token account, paid for by
+/// `payer`, unless it already exists.
+pub fn create_idempotent(payer: &Pubkey, wallet: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts:   vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(find_associated_token_address(wallet, mint, token_program), false),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data:       vec![CREATE_IDEMPOTENT],
+    }
+}
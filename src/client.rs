@@ -0,0 +1,111 @@
+//! Off-chain helpers over `RpcClient`, behind the `client` feature, for the
+//! glue every integration otherwise rewrites: reading escrows, finding them
+//! and building the transaction that creates one.
+
+// The helpers return `RpcClient`'s own `ClientError`, large as it is
+#![allow(clippy::result_large_err)]
+
+pub mod nonblocking;
+
+use solana_client::{
+    client_error::Result as ClientResult,
+    rpc_client::RpcClient,
+    rpc_config::RpcProgramAccountsConfig,
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+use crate::{
+    instruction,
+    state::{find_registry_address, EscrowState, RegistryState, ESCROW_DISCRIMINATOR},
+};
+
+/// Offset of `initializer_pubkey` in an escrow account, after the
+/// discriminator and `is_initialized`.
+const INITIALIZER_OFFSET: usize = 8 + 1;
+
+/// Fetches and decodes the escrow state account at `escrow`.
+pub fn fetch_escrow(rpc: &RpcClient, escrow: &Pubkey) -> ClientResult<EscrowState> {
+    let data = rpc.get_account_data(escrow)?;
+    Ok(EscrowState::unpack(&data)?)
+}
+
+/// Id the next escrow created by `initializer` will get, read from their
+/// registry; 0 before their first escrow.
+pub fn next_escrow_id(rpc: &RpcClient, program_id: &Pubkey, initializer: &Pubkey) -> ClientResult<u64> {
+    let (registry, _) = find_registry_address(program_id, initializer);
+    let Some(account) = rpc.get_account_with_commitment(&registry, rpc.commitment())?.value else {
+        return Ok(0);
+    };
+    Ok(RegistryState::unpack(&account.data)?.next_id)
+}
+
+/// Builds and signs a transaction creating a native escrow of `amount`
+/// lamports between `initializer`, who pays, and `taker`, with the next id
+/// from the initializer's registry.
+pub fn create_escrow(
+    rpc: &RpcClient,
+    program_id: &Pubkey,
+    initializer: &Keypair,
+    taker: &Keypair,
+    amount: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+) -> ClientResult<Transaction> {
+    let id = next_escrow_id(rpc, program_id, &initializer.pubkey())?;
+    let ix = instruction::initialize(
+        program_id, &initializer.pubkey(), &taker.pubkey(), amount, id, expiry_ts, arbiter,
+    );
+    Ok(Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&initializer.pubkey()),
+        &[initializer, taker],
+        rpc.get_latest_blockhash()?,
+    ))
+}
+
+/// Lists every escrow account created by `initializer`, whatever its status,
+/// with memcmp filters on the discriminator and the initializer.
+pub fn find_escrows_for_initializer(
+    rpc: &RpcClient,
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+) -> ClientResult<Vec<(Pubkey, EscrowState)>> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(initializer_filters(initializer)),
+        ..RpcProgramAccountsConfig::default()
+    };
+    rpc.get_program_accounts_with_config(program_id, config)?
+        .into_iter()
+        .map(|(key, account)| Ok((key, EscrowState::unpack(&account.data)?)))
+        .collect()
+}
+
+fn initializer_filters(initializer: &Pubkey) -> Vec<RpcFilterType> {
+    vec![
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, &ESCROW_DISCRIMINATOR)),
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(INITIALIZER_OFFSET, initializer.as_ref())),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initializer_filter_matches_the_state_layout() {
+        let initializer = Pubkey::new_unique();
+        let mut state = EscrowState::default();
+        state.discriminator      = ESCROW_DISCRIMINATOR;
+        state.initializer_pubkey = initializer;
+        let data = bytemuck::bytes_of(&state);
+        for filter in initializer_filters(&initializer) {
+            let RpcFilterType::Memcmp(memcmp) = filter else { unreachable!() };
+            assert!(memcmp.bytes_match(data));
+        }
+    }
+}
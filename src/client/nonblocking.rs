@@ -0,0 +1,133 @@
+//! Async counterparts of the `client` helpers over the nonblocking
+//! `RpcClient`. Transactions are built as v0 messages, optionally against an
+//! address lookup table, with ComputeBudget instructions for the priority
+//! fee and a unit limit estimated by simulation.
+
+use borsh::BorshDeserialize;
+use solana_client::{
+    client_error::{ClientErrorKind, Result as ClientResult},
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcProgramAccountsConfig, RpcSimulateTransactionConfig},
+};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
+    message::{v0, AddressLookupTableAccount, VersionedMessage},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::VersionedTransaction,
+};
+
+use super::initializer_filters;
+use crate::{
+    instruction,
+    state::{find_registry_address, EscrowState, RegistryState},
+};
+
+/// Most compute units a transaction can request, used while simulating.
+const MAX_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// How a transaction built by these helpers is priced and compiled.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TransactionOptions<'a> {
+    /// Priority fee in micro-lamports per compute unit, 0 for none
+    pub priority_fee: u64,
+    /// Extra compute units on top of the simulated usage, in percent
+    pub unit_margin_pct: u32,
+    /// Table the v0 message may load accounts from
+    pub lookup_table: Option<&'a AddressLookupTableAccount>,
+}
+
+/// Fetches and decodes the escrow state account at `escrow`.
+pub async fn fetch_escrow(rpc: &RpcClient, escrow: &Pubkey) -> ClientResult<EscrowState> {
+    let data = rpc.get_account_data(escrow).await?;
+    Ok(EscrowState::unpack(&data)?)
+}
+
+/// Id the next escrow created by `initializer` will get, read from their
+/// registry; 0 before their first escrow.
+pub async fn next_escrow_id(rpc: &RpcClient, program_id: &Pubkey, initializer: &Pubkey) -> ClientResult<u64> {
+    let (registry, _) = find_registry_address(program_id, initializer);
+    let Some(account) = rpc.get_account_with_commitment(&registry, rpc.commitment()).await?.value else {
+        return Ok(0);
+    };
+    Ok(RegistryState::deserialize(&mut &account.data[..])?.next_id)
+}
+
+/// Lists every escrow account created by `initializer`, whatever its status.
+pub async fn find_escrows_for_initializer(
+    rpc: &RpcClient,
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+) -> ClientResult<Vec<(Pubkey, EscrowState)>> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(initializer_filters(initializer)),
+        ..RpcProgramAccountsConfig::default()
+    };
+    rpc.get_program_accounts_with_config(program_id, config)
+        .await?
+        .into_iter()
+        .map(|(key, account)| Ok((key, EscrowState::unpack(&account.data)?)))
+        .collect()
+}
+
+/// Builds and signs the v0 transaction creating a native escrow of `amount`
+/// lamports between `initializer`, who pays, and `taker`.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_escrow(
+    rpc: &RpcClient,
+    program_id: &Pubkey,
+    initializer: &Keypair,
+    taker: &Keypair,
+    amount: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+    options: &TransactionOptions<'_>,
+) -> ClientResult<VersionedTransaction> {
+    let id = next_escrow_id(rpc, program_id, &initializer.pubkey()).await?;
+    let ix = instruction::initialize(
+        program_id, &initializer.pubkey(), &taker.pubkey(), amount, id, expiry_ts, arbiter,
+    );
+    build_transaction(rpc, &[ix], &[initializer, taker], options).await
+}
+
+/// Builds and signs a v0 transaction running `instructions`, paid by the
+/// first signer. It is simulated first with the largest unit limit, then
+/// limited to the units the simulation used plus the margin, and charged
+/// the priority fee.
+pub async fn build_transaction(
+    rpc: &RpcClient,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+    options: &TransactionOptions<'_>,
+) -> ClientResult<VersionedTransaction> {
+    let payer     = signers.first().ok_or_else(|| ClientErrorKind::Custom("no signers".into()))?.pubkey();
+    let blockhash = rpc.get_latest_blockhash().await?;
+    let tables    = options.lookup_table.map(std::slice::from_ref).unwrap_or_default();
+    let compile   = |unit_limit: u32| {
+        let mut all = vec![
+            ComputeBudgetInstruction::set_compute_unit_limit(unit_limit),
+            ComputeBudgetInstruction::set_compute_unit_price(options.priority_fee),
+        ];
+        all.extend_from_slice(instructions);
+        v0::Message::try_compile(&payer, &all, tables, blockhash)
+            .map(VersionedMessage::V0)
+            .map_err(|e| ClientErrorKind::Custom(e.to_string()))
+    };
+
+    // Signatures are not checked in simulation, so placeholders will do
+    let message   = compile(MAX_COMPUTE_UNITS)?;
+    let unsigned  = VersionedTransaction {
+        signatures: vec![Signature::default(); message.header().num_required_signatures as usize],
+        message,
+    };
+    let config    = RpcSimulateTransactionConfig { replace_recent_blockhash: true, ..Default::default() };
+    let simulated = rpc.simulate_transaction_with_config(&unsigned, config).await?.value;
+    if let Some(err) = simulated.err {
+        return Err(err.into());
+    }
+    let used       = simulated.units_consumed.unwrap_or(u64::from(MAX_COMPUTE_UNITS));
+    let unit_limit = used * u64::from(100 + options.unit_margin_pct) / 100;
+    let unit_limit = u32::try_from(unit_limit).unwrap_or(MAX_COMPUTE_UNITS).min(MAX_COMPUTE_UNITS);
+    Ok(VersionedTransaction::try_new(compile(unit_limit)?, signers)?)
+}
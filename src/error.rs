@@ -0,0 +1,310 @@
+use solana_program::program_error::ProgramError;
+use thiserror::Error;
+
+/// Errors specific to this program, surfaced as `ProgramError::Custom(code)`.
+///
+/// Discriminants are part of the client-facing ABI: never reorder or reuse
+/// them, only append.
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+pub enum EscrowError {
+    /// The escrow has already been funded
+    #[error("escrow is already funded")]
+    AlreadyFunded       = 0,
+    /// The escrow has no deadline or it has not passed yet
+    #[error("escrow has not expired")]
+    NotExpired          = 1,
+    /// The escrow account holds no initialized state
+    #[error("escrow is not initialized")]
+    NotInitialized      = 2,
+    /// Nothing has been deposited into the escrow yet
+    #[error("escrow is not funded")]
+    NotFunded           = 3,
+    /// The initializer account does not match the stored initializer
+    #[error("initializer does not match escrow")]
+    WrongInitializer    = 4,
+    /// The taker account does not match the stored taker
+    #[error("taker does not match escrow")]
+    WrongTaker          = 5,
+    /// The mint does not match the escrow, or a native handler got a token escrow
+    #[error("mint does not match escrow")]
+    WrongMint           = 6,
+    /// The escrow deadline has passed
+    #[error("escrow has expired")]
+    Expired             = 7,
+    /// A lamport or token balance would overflow
+    #[error("amount overflow")]
+    AmountOverflow      = 8,
+    /// The escrow was already released or cancelled
+    #[error("escrow is already settled")]
+    AlreadySettled      = 9,
+    /// The escrow must be released or cancelled first
+    #[error("escrow is not settled")]
+    NotSettled          = 10,
+    /// The escrow is frozen until its arbiter resolves the dispute
+    #[error("escrow is under dispute")]
+    Disputed            = 11,
+    /// The signer is not the escrow's arbiter
+    #[error("signer is not the escrow arbiter")]
+    WrongArbiter        = 12,
+    /// Disputes need an arbiter, and this escrow was created without one
+    #[error("escrow has no arbiter")]
+    NoArbiter           = 13,
+    /// The signer is neither the initializer nor the taker
+    #[error("signer is not a party to the escrow")]
+    NotParty            = 14,
+    /// The amount is zero or larger than what the escrow still holds
+    #[error("invalid amount")]
+    InvalidAmount       = 15,
+    /// The escrow already has a taker
+    #[error("escrow taker is already set")]
+    TakerAlreadySet     = 16,
+    /// The metadata account is not the Metaplex metadata PDA for the mint
+    #[error("invalid NFT metadata")]
+    InvalidMetadata     = 17,
+    /// The NFT is not a verified member of the escrow's collection
+    #[error("NFT collection does not match escrow")]
+    CollectionMismatch  = 18,
+    /// The mint is not a single-supply, zero-decimal NFT mint
+    #[error("mint is not an NFT")]
+    NotAnNft            = 19,
+    /// Swap escrows settle only through ExecuteSwap
+    #[error("not available on a swap escrow")]
+    SwapEscrow          = 20,
+    /// The taker's leg of the swap is missing, or still held
+    #[error("swap taker deposit is missing or still held")]
+    TakerDeposit        = 21,
+    /// The treasury account does not match the config
+    #[error("treasury does not match config")]
+    WrongTreasury       = 22,
+    /// Fees are capped at 10 000 basis points
+    #[error("fee exceeds 100%")]
+    InvalidFee          = 23,
+    /// The program is paused by its admin
+    #[error("program is paused")]
+    Paused              = 24,
+    /// The signer is not the config admin
+    #[error("signer is not the config admin")]
+    WrongAdmin          = 25,
+    /// The preimage does not hash to the escrow's hash lock
+    #[error("preimage does not match hash lock")]
+    InvalidPreimage     = 26,
+    /// Vesting needs start <= cliff <= end with start < end, a
+    /// subscription a positive interval
+    #[error("invalid vesting schedule")]
+    InvalidSchedule     = 27,
+    /// No vested amount or subscription tranche is left to withdraw yet
+    #[error("nothing vested to withdraw")]
+    NothingVested       = 28,
+    /// No milestone at that index, or it was already approved
+    #[error("invalid milestone")]
+    InvalidMilestone    = 29,
+    /// No approved milestone is waiting to be withdrawn
+    #[error("no approved milestone to withdraw")]
+    NothingApproved     = 30,
+    /// The instruction data does not decode to any instruction
+    #[error("malformed instruction data")]
+    MalformedInstruction = 31,
+    /// The instruction data has bytes left over after decoding
+    #[error("trailing bytes in instruction data")]
+    TrailingBytes       = 32,
+    /// A payout would leave a live account below rent exemption
+    #[error("payout would drop account below rent exemption")]
+    BelowRentFloor      = 33,
+    /// Crowdfunds take deposits through Contribute and refund through ClaimRefund
+    #[error("not available on a crowdfund escrow")]
+    Crowdfund           = 34,
+    /// Contribute and ClaimRefund only apply to crowdfund escrows
+    #[error("escrow is not a crowdfund")]
+    NotCrowdfund        = 35,
+    /// The oracle account is not the escrow's, or holds no usable price
+    #[error("invalid oracle account")]
+    InvalidOracle       = 36,
+    /// The oracle price is older than `MAX_PRICE_AGE_SECS`
+    #[error("oracle price is stale")]
+    StalePrice          = 37,
+    /// The oracle price is on the wrong side of the escrow's threshold
+    #[error("price condition not met")]
+    PriceConditionNotMet = 38,
+    /// Price-conditioned escrows are released in full through Withdraw
+    #[error("escrow has a price condition")]
+    PriceConditioned    = 39,
+    /// The preceding instruction is not an ed25519 check of the arbiter's
+    /// signature over this escrow's voucher message
+    #[error("missing or invalid voucher")]
+    InvalidVoucher      = 40,
+    /// ClaimWithEthSignature needs an Ethereum release authority set first
+    #[error("escrow has no Ethereum release authority")]
+    NoEthAuthority      = 41,
+    /// The secp256k1 signature does not recover to the escrow's `eth_authority`
+    #[error("invalid Ethereum signature")]
+    InvalidEthSignature = 42,
+    /// ReleaseWithVaa needs a Wormhole emitter set first
+    #[error("escrow has no Wormhole emitter")]
+    NoWormholeEmitter   = 43,
+    /// The account is not a posted VAA from the escrow's emitter releasing it
+    #[error("invalid Wormhole VAA")]
+    InvalidVaa          = 44,
+    /// FinalizeWithdraw needs a RequestWithdraw first
+    #[error("withdraw was not requested")]
+    WithdrawNotRequested = 45,
+    /// RequestWithdraw was already called for this escrow
+    #[error("withdraw already requested")]
+    WithdrawRequested   = 46,
+    /// The initializer can still dispute the requested withdraw
+    #[error("dispute window still open")]
+    DisputeWindowOpen   = 47,
+    /// DepositBond on an escrow that asks for no taker bond
+    #[error("escrow takes no taker bond")]
+    NoBond              = 48,
+    /// DepositBond was already called for this escrow
+    #[error("taker bond already posted")]
+    BondPosted          = 49,
+    /// The escrow cannot be released to the taker before their bond is in
+    #[error("taker bond not posted")]
+    BondNotPosted       = 50,
+    /// The new expiry is not later than the current one, or beyond the
+    /// config's `max_expiry_horizon`
+    #[error("invalid expiry")]
+    InvalidExpiry       = 51,
+    /// The signer is not on the escrow's allowlist
+    #[error("taker not allowed")]
+    NotAllowed          = 52,
+    /// Allowlists hold 1 to `MAX_ALLOWED_TAKERS` members and need an open escrow
+    #[error("invalid allowlist")]
+    InvalidAllowlist    = 53,
+    /// Distribution escrows pay out only through ClaimDistribution
+    #[error("not available on a distribution escrow")]
+    Distribution        = 54,
+    /// ClaimDistribution only applies to distribution escrows
+    #[error("escrow is not a distribution")]
+    NotDistribution     = 55,
+    /// The proof does not lead from the claim to the escrow's Merkle root
+    #[error("invalid Merkle proof")]
+    InvalidProof        = 56,
+    /// The allocation at that index was already paid
+    #[error("allocation already claimed")]
+    AlreadyClaimed      = 57,
+    /// Splits hold 1 to `MAX_SPLITS` recipients whose shares add up to
+    /// 10 000 bps, passed to the release in the same order; a ResolvePartial
+    /// gives the taker at most 10 000 bps
+    #[error("invalid split")]
+    InvalidSplit        = 58,
+    /// The escrow's payout is split, so it can only be released whole by
+    /// Withdraw or Resolve
+    #[error("escrow payout is split")]
+    Split               = 59,
+    /// The referrer account is not the one recorded at Initialize, or is one
+    /// of the parties
+    #[error("wrong referrer")]
+    WrongReferrer       = 60,
+    /// A lamport transfer would have changed the total held by its two accounts
+    #[error("lamports not conserved")]
+    LamportsNotConserved = 61,
+    /// The escrow's layout version is not the one this program expects, or
+    /// MigrateState was given an escrow that is already current
+    #[error("unsupported escrow state version")]
+    StateVersion        = 62,
+    /// EmitEvent was not signed by this program's event authority
+    #[error("wrong event authority")]
+    EventAuthority      = 63,
+    /// The escrow's state does not fit in return data
+    #[error("state too large for return data")]
+    StateTooLarge       = 64,
+    /// A batch holds 1 to `MAX_BATCH_LEN` items, each followed by its
+    /// `[taker, escrow, vault]` accounts in the same order
+    #[error("invalid batch")]
+    InvalidBatch        = 65,
+    /// Strict mode requires a Deposit of the new escrow later in the same
+    /// transaction, and the Instructions sysvar to find it
+    #[error("escrow must be deposited in the same transaction")]
+    DepositMissing      = 66,
+    /// Invoked through CPI by a program other than the config's
+    /// `allowed_caller`, or without the accounts to check it
+    #[error("caller program not allowed")]
+    CallerNotAllowed    = 67,
+    /// DepositSol and WithdrawSol only handle wrapped SOL escrows
+    #[error("mint is not wrapped SOL")]
+    NotNativeMint       = 68,
+    /// Stake escrows only settle through WithdrawStake or ReclaimStake
+    #[error("stake escrow")]
+    StakeEscrow         = 69,
+    /// The stake account is not the one the escrow holds, or not a stake
+    /// account at all
+    #[error("wrong stake account")]
+    WrongStakeAccount   = 70,
+    /// The yield of a stake pool escrow goes to the config's
+    /// `yield_beneficiary`, or the initializer when none is set
+    #[error("wrong yield beneficiary")]
+    WrongBeneficiary    = 71,
+    /// An auction needs floor <= start price, start < end and a
+    /// non-negative step
+    #[error("invalid auction")]
+    InvalidAuction      = 72,
+    /// BuyAuction and CancelAuction only apply to auction escrows
+    #[error("not an auction")]
+    NotAuction          = 73,
+    /// AcceptTerms needs terms proposed by the other party
+    #[error("no proposal to accept")]
+    NoProposal          = 74,
+    /// The relayed Initialize was submitted after the deadline its
+    /// initializer signed
+    #[error("relay authorization expired")]
+    RelayExpired        = 75,
+    /// Capture on an escrow not created by InitializeAuthorization
+    #[error("escrow is not an authorization")]
+    NotAuthorization    = 76,
+    /// CancelInvoice on an escrow its taker didn't issue
+    #[error("escrow is not an invoice")]
+    NotInvoice          = 77,
+    /// A template with a negative window or expiry, or an Initialize whose
+    /// arbiter differs from its template's
+    #[error("invalid template")]
+    InvalidTemplate     = 78,
+    /// The signer is neither the config admin nor holds the role the
+    /// instruction needs
+    #[error("missing role")]
+    MissingRole         = 79,
+    /// A grant to more than `MAX_AUTHORITIES` keys
+    #[error("invalid role change")]
+    InvalidRoleChange   = 80,
+    /// No emergency withdrawal is pending, or its delay has not passed
+    #[error("emergency withdrawal not executable")]
+    NoEmergency         = 81,
+    /// The instruction uses a behavior the feature flags have switched off
+    #[error("feature disabled")]
+    FeatureDisabled     = 82,
+    /// A deposit's transfer credited the vault with other than the amount
+    /// it was for
+    #[error("deposit mismatch")]
+    DepositMismatch     = 83,
+    /// A new native escrow's amount is below the config's `min_amount` or
+    /// above its `max_amount`
+    #[error("amount outside the configured limits")]
+    AmountOutOfLimits   = 84,
+    /// The initializer already created the config's `max_escrows_per_epoch`
+    /// escrows this epoch
+    #[error("too many escrows this epoch")]
+    RateLimited         = 85,
+    /// SetMetadata was given an empty or overlong URI, or a zero hash
+    #[error("invalid terms URI or hash")]
+    InvalidTerms        = 86,
+    /// The escrow's terms were already fixed by SetMetadata
+    #[error("terms already set")]
+    TermsAlreadySet     = 87,
+    /// The ruling can't be carried out before its appeal window closes
+    #[error("appeal window still open")]
+    AppealWindowOpen    = 88,
+    /// The ruling's appeal window already closed
+    #[error("appeal window closed")]
+    AppealWindowClosed  = 89,
+    /// An instruction about to charge a fee or enforce strict mode wasn't
+    /// given the feature flags PDA
+    #[error("feature flags account missing")]
+    FeaturesAccountMissing = 91,
+}
+
+impl From<EscrowError> for ProgramError {
+    fn from(e: EscrowError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
@@ -0,0 +1,158 @@
+//! Events logged with `sol_log_data` on the main lifecycle transitions, so
+//! indexers can rebuild an escrow's history from transaction logs. Each one
+//! is logged as a single `Program data:` entry holding its 8-byte tag
+//! followed by its Borsh encoding. The last byte of the tag is the layout
+//! version: a change to an event's fields gets a new tag.
+//!
+//! Logs can be truncated by busy RPC nodes, so when a client appends the
+//! event authority and the program to an instruction (`with_event_cpi`),
+//! its events are also passed to an EmitEvent self-CPI, where they survive
+//! as inner instruction data.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    log::sol_log_data,
+    program::invoke_signed,
+    pubkey::Pubkey,
+};
+
+use crate::{instruction::EscrowInstruction, state::EVENT_AUTHORITY_PDA_SEED};
+
+/// The event authority PDA and the program account, found at the end of an
+/// instruction's accounts, that events are self-CPI'd with.
+#[derive(Clone, Copy)]
+pub struct EventCpi<'a, 'info> {
+    pub(crate) authority: &'a AccountInfo<'info>,
+    pub(crate) program:   &'a AccountInfo<'info>,
+    pub(crate) bump:      u8,
+}
+
+pub trait Event: BorshSerialize {
+    const TAG: [u8; 8];
+
+    /// The tag followed by the Borsh encoding, as logged by `emit`.
+    fn data(&self) -> Vec<u8> {
+        let mut data = Self::TAG.to_vec();
+        // Serializing into a Vec can't fail
+        let _ = self.serialize(&mut data);
+        data
+    }
+
+    /// Logs the event, then also invokes EmitEvent with it when given the
+    /// accounts for the self-CPI.
+    fn emit(&self, cpi: Option<EventCpi>) -> ProgramResult {
+        let data = self.data();
+        sol_log_data(&[&data]);
+        let Some(cpi) = cpi else { return Ok(()) };
+        let ix = Instruction::new_with_borsh(
+            *cpi.program.key,
+            &EscrowInstruction::EmitEvent { data },
+            vec![AccountMeta::new_readonly(*cpi.authority.key, true)],
+        );
+        invoke_signed(
+            &ix,
+            &[cpi.authority.clone(), cpi.program.clone()],
+            &[&[EVENT_AUTHORITY_PDA_SEED, &[cpi.bump]]],
+        )
+    }
+
+    /// Decodes a `Program data:` entry (after base64), `None` if it's
+    /// another event or another version of this one.
+    fn decode(data: &[u8]) -> Option<Self>
+    where
+        Self: BorshDeserialize,
+    {
+        let body = data.strip_prefix(&Self::TAG)?;
+        Self::try_from_slice(body).ok()
+    }
+}
+
+/// An escrow was created, by any of the Initialize variants.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EscrowInitialized {
+    pub escrow:      Pubkey,
+    pub initializer: Pubkey,
+    /// `Pubkey::default()` for an open escrow
+    pub taker:       Pubkey,
+    pub id:          u64,
+    pub amount:      u64,
+    /// `Pubkey::default()` for a native escrow
+    pub mint:        Pubkey,
+    /// Given to InitializeWithNonce, 0 otherwise
+    pub nonce:       u64,
+    /// Given to InitializeWithReference, zero otherwise
+    pub reference:   [u8; 32],
+}
+
+impl Event for EscrowInitialized {
+    const TAG: [u8; 8] = *b"flo:ini3";
+}
+
+/// An escrow received its full amount.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EscrowFunded {
+    pub escrow:    Pubkey,
+    pub deposited: u64,
+}
+
+impl Event for EscrowFunded {
+    const TAG: [u8; 8] = *b"flo:fnd1";
+}
+
+/// An escrow paid out its last funds to the taker's side. `amount` and
+/// `fee` are those of the releasing instruction, which for tranche-based
+/// escrows is only the last tranche.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EscrowReleased {
+    pub escrow:    Pubkey,
+    pub recipient: Pubkey,
+    pub amount:    u64,
+    pub fee:       u64,
+}
+
+impl Event for EscrowReleased {
+    const TAG: [u8; 8] = *b"flo:rel1";
+}
+
+/// An escrow was unwound. `refunded` is what the cancelling instruction
+/// returned to the funding side, which for a crowdfund is only the last
+/// contributor's refund.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EscrowCancelled {
+    pub escrow:   Pubkey,
+    pub refunded: u64,
+}
+
+impl Event for EscrowCancelled {
+    const TAG: [u8; 8] = *b"flo:cnl1";
+}
+
+/// The admin proposed recovering an escrow's funds to `recipient`; unless a
+/// party vetoes it, it can execute from `executable_at`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EmergencyWithdrawProposed {
+    pub escrow:        Pubkey,
+    pub recipient:     Pubkey,
+    pub executable_at: i64,
+}
+
+impl Event for EmergencyWithdrawProposed {
+    const TAG: [u8; 8] = *b"flo:emg1";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_checks_the_tag() {
+        let event = EscrowFunded { escrow: Pubkey::new_unique(), deposited: 7 };
+        let data  = event.data();
+        assert_eq!(data[..8], *b"flo:fnd1");
+        assert_eq!(EscrowFunded::decode(&data), Some(event));
+        assert_eq!(EscrowCancelled::decode(&data), None);
+    }
+}
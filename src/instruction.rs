@@ -0,0 +1,3624 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use shank::ShankInstruction;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    stake, system_program, sysvar,
+};
+
+use crate::{
+    associated_token::{find_associated_token_address, ASSOCIATED_TOKEN_PROGRAM_ID},
+    error::EscrowError,
+    memo::MEMO_PROGRAM_ID,
+    merkle::MAX_PROOF_LEN,
+    metadata::find_metadata_address,
+    stake_pool::{StakePool, STAKE_POOL_PROGRAM_ID},
+    state::{
+        find_authorities_address, find_claimed_address, find_config_address, find_escrow_address,
+        find_event_authority_address, find_features_address, find_receipt_address, find_registry_address, find_stats_address,
+        find_taker_vault_address, find_template_address, find_vault_address, Auction, Feature, Role, Split, Subscription,
+        MAX_ALLOWED_TAKERS, MAX_BATCH_LEN, MAX_METADATA_URI_LEN, MAX_FEE_BPS, MAX_MILESTONES, MAX_SPLITS, SPL_TOKEN_NATIVE_MINT,
+    },
+};
+
+/// Instructions understood by the escrow program. Account lists are declared
+/// with shank so clients can be generated from the IDL; the builders below
+/// produce the same layouts.
+#[derive(BorshSerialize, BorshDeserialize, Debug, ShankInstruction)]
+pub enum EscrowInstruction {
+    /// Creates a native escrow for `amount` lamports.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts unless a payer is given")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes, or the default pubkey for none")]
+    #[account(8, optional, name = "referrer", desc = "Receives the config's referral share of the fee on Withdraw, or the default pubkey for none")]
+    #[account(9, optional, name = "instructions", desc = "Instructions sysvar, in strict mode, or the default pubkey")]
+    #[account(10, writable, optional_signer, name = "payer", desc = "Pays the rent in the Sender's place, or the default pubkey")]
+    #[account(11, optional, name = "template", desc = "Template PDA whose fee, dispute window, default expiry and arbiter the escrow takes")]
+    Initialize { amount: u64, expiry_ts: i64 },
+
+    /// Funds a native escrow with the missing part of its amount.
+    #[account(0, writable, optional_signer, name = "initializer", desc = "Sender, signs and pays unless a funder does")]
+    #[account(1, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, signer, optional, name = "funder", desc = "Third party paying on the Sender's behalf")]
+    Deposit {},
+
+    /// Pays the remaining lamports to the taker, less the protocol fee, of
+    /// which the referrer gets its share. The payer of an invoice can
+    /// release it alone, to the taker itself.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, writable, optional_signer, name = "taker", desc = "Receiver, who need not sign an invoice")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    #[account(5, writable, name = "treasury", desc = "Fee recipient recorded in the config")]
+    #[account(6, writable, optional, name = "referrer", desc = "Escrow's referrer, for referred escrows; the accounts below follow it")]
+    #[account(7, optional, name = "oracle", desc = "Price account, for escrows with a price condition; the accounts below follow it")]
+    #[account(8, writable, optional, name = "recipient", desc = "Receives the payout instead of the taker; for split escrows, the first of the split recipients, in order")]
+    Withdraw {},
+
+    /// Closes a native escrow that was never fully funded, or whose taker
+    /// has not consented to it yet.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, receives everything back")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, writable, name = "registry", desc = "Initializer's registry PDA")]
+    #[account(4, writable, optional, name = "claimed", desc = "Claimed bitmap PDA, for distributions")]
+    Cancel {},
+
+    /// Creates a token escrow for `amount` tokens of `mint`.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(4, name = "mint", desc = "Escrowed mint")]
+    #[account(5, name = "token_program", desc = "SPL Token or Token-2022")]
+    #[account(6, name = "system_program", desc = "System program")]
+    #[account(7, name = "config", desc = "Config PDA")]
+    #[account(8, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(9, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes")]
+    InitializeToken { amount: u64, expiry_ts: i64 },
+
+    /// Moves the agreed tokens into the vault.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "initializer_token", desc = "Sender's token account")]
+    #[account(4, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(5, name = "mint", desc = "Escrowed mint")]
+    #[account(6, name = "token_program", desc = "SPL Token or Token-2022")]
+    #[account(7, name = "config", desc = "Config PDA")]
+    DepositToken {},
+
+    /// Pays the remaining tokens to the taker. If `taker_token` doesn't
+    /// exist yet, it must be the taker's associated token account, which is
+    /// created first at the taker's expense.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, signer, name = "taker", desc = "Receiver, writable when paying for taker_token")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(4, writable, name = "taker_token", desc = "Receiver's token account")]
+    #[account(5, name = "mint", desc = "Escrowed mint")]
+    #[account(6, name = "token_program", desc = "SPL Token or Token-2022")]
+    #[account(7, optional, name = "system_program", desc = "System program, to create taker_token")]
+    #[account(8, optional, name = "associated_token_program", desc = "Associated Token program, to create taker_token")]
+    WithdrawToken {},
+
+    /// Returns a funded native escrow to the initializer after `expiry_ts`.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    Refund {},
+
+    /// Closes a settled escrow and its vault, returning the rent.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, receives the rent")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, writable, name = "registry", desc = "Initializer's registry PDA")]
+    #[account(4, writable, optional, name = "token_program", desc = "Token program for token escrows, claimed bitmap PDA for distributions")]
+    Close {},
+
+    /// Creates and funds a native escrow in one step.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes")]
+    InitializeAndDeposit { amount: u64, expiry_ts: i64 },
+
+    /// Freezes a funded escrow until its arbiter resolves it.
+    #[account(0, signer, name = "party", desc = "Sender or Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    Dispute {},
+
+    /// Settles a disputed escrow in favour of one party, paying the arbiter
+    /// its fee first: in lamports for a native escrow, and its
+    /// `arbiter_fee_bps` share in tokens for a token escrow.
+    #[account(0, writable, signer, name = "arbiter", desc = "Escrow arbiter, receives its fee")]
+    #[account(1, writable, name = "initializer", desc = "Sender")]
+    #[account(2, writable, name = "taker", desc = "Receiver")]
+    #[account(3, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(4, writable, name = "vault", desc = "Vault PDA")]
+    #[account(5, writable, optional, name = "recipient_token", desc = "Winning party's token account, for token escrows")]
+    #[account(6, optional, name = "mint", desc = "Escrowed mint, for token escrows")]
+    #[account(7, optional, name = "token_program", desc = "Token program, for token escrows")]
+    #[account(8, writable, optional, name = "arbiter_token", desc = "Arbiter's token account, receives its fee, for token escrows with an arbiter fee")]
+    #[account(9, writable, optional, name = "split_recipients", desc = "For split escrows released to the taker, the split recipients in order, in place of the token accounts")]
+    Resolve { release_to_taker: bool },
+
+    /// Pays one tranche of `amount` to the taker, less the protocol fee for
+    /// native escrows.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    #[account(5, writable, name = "treasury", desc = "Fee recipient recorded in the config")]
+    #[account(6, writable, optional, name = "taker_token", desc = "Receiver's token account, for token escrows")]
+    #[account(7, optional, name = "mint", desc = "Escrowed mint, for token escrows")]
+    #[account(8, optional, name = "token_program", desc = "Token program, for token escrows")]
+    WithdrawPartial { amount: u64 },
+
+    /// Adds `amount` lamports towards a native escrow's total.
+    #[account(0, writable, optional_signer, name = "initializer", desc = "Sender, signs and pays unless a funder does")]
+    #[account(1, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, signer, optional, name = "funder", desc = "Third party paying on the Sender's behalf")]
+    DepositAdditional { amount: u64 },
+
+    /// Claims an open escrow for the signer, or records the consent of a
+    /// named taker who didn't sign its Initialize.
+    #[account(0, signer, name = "taker", desc = "New or named Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    AcceptEscrow {},
+
+    /// Unwinds a live escrow with both signatures and closes it.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, receives everything back")]
+    #[account(1, writable, signer, name = "taker", desc = "Receiver, gets their bond back")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, writable, name = "registry", desc = "Initializer's registry PDA")]
+    #[account(5, writable, optional, name = "initializer_token", desc = "Sender's token account, for token escrows")]
+    #[account(6, optional, name = "mint", desc = "Escrowed mint, for token escrows")]
+    #[account(7, optional, name = "token_program", desc = "Token program, for token escrows")]
+    MutualCancel {},
+
+    /// Creates an escrow for the single token of an NFT mint in `collection`.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(4, name = "mint", desc = "NFT mint")]
+    #[account(5, name = "token_program", desc = "SPL Token or Token-2022")]
+    #[account(6, name = "system_program", desc = "System program")]
+    #[account(7, name = "config", desc = "Config PDA")]
+    #[account(8, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(9, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes")]
+    InitializeNft { expiry_ts: i64, collection: Pubkey },
+
+    /// Moves the NFT into the vault after checking its Metaplex metadata.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "initializer_token", desc = "Sender's token account")]
+    #[account(4, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(5, name = "mint", desc = "NFT mint")]
+    #[account(6, name = "token_program", desc = "SPL Token or Token-2022")]
+    #[account(7, name = "config", desc = "Config PDA")]
+    #[account(8, name = "metadata", desc = "Metaplex metadata PDA of the mint")]
+    DepositNft {},
+
+    /// Creates a swap escrow: `amount` of `mint` against `taker_amount` of `taker_mint`.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA for the Sender's leg")]
+    #[account(4, name = "mint", desc = "Mint of the Sender's leg")]
+    #[account(5, name = "token_program", desc = "SPL Token or Token-2022")]
+    #[account(6, name = "system_program", desc = "System program")]
+    #[account(7, name = "config", desc = "Config PDA")]
+    #[account(8, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    InitializeSwap { amount: u64, expiry_ts: i64, taker_mint: Pubkey, taker_amount: u64 },
+
+    /// Moves the taker's leg into the taker vault, creating it.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver, pays for the taker vault")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "taker_token", desc = "Receiver's token account")]
+    #[account(3, writable, name = "taker_vault", desc = "Taker vault token account PDA")]
+    #[account(4, name = "taker_mint", desc = "Mint of the Receiver's leg")]
+    #[account(5, name = "taker_token_program", desc = "Token program of the taker mint")]
+    #[account(6, name = "system_program", desc = "System program")]
+    #[account(7, name = "config", desc = "Config PDA")]
+    DepositSwap {},
+
+    /// Crosses both legs of a swap atomically.
+    #[account(0, optional_signer, name = "initializer", desc = "Sender; either party signs")]
+    #[account(1, writable, optional_signer, name = "taker", desc = "Receiver, gets the taker vault rent back")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA for the Sender's leg")]
+    #[account(4, writable, name = "taker_vault", desc = "Taker vault token account PDA")]
+    #[account(5, writable, name = "initializer_receive", desc = "Sender's account for the taker mint")]
+    #[account(6, writable, name = "taker_receive", desc = "Receiver's account for the escrowed mint")]
+    #[account(7, name = "mint", desc = "Mint of the Sender's leg")]
+    #[account(8, name = "token_program", desc = "Token program of the escrowed mint")]
+    #[account(9, name = "taker_mint", desc = "Mint of the Receiver's leg")]
+    #[account(10, name = "taker_token_program", desc = "Token program of the taker mint")]
+    ExecuteSwap {},
+
+    /// Returns the taker's leg of an unexecuted swap.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "taker_vault", desc = "Taker vault token account PDA")]
+    #[account(3, writable, name = "taker_token", desc = "Receiver's token account")]
+    #[account(4, name = "taker_mint", desc = "Mint of the Receiver's leg")]
+    #[account(5, name = "taker_token_program", desc = "Token program of the taker mint")]
+    ReclaimSwap {},
+
+    /// Creates the config PDA; the signer becomes the admin.
+    #[account(0, writable, signer, name = "admin", desc = "Admin, pays for the config")]
+    #[account(1, writable, name = "config", desc = "Config PDA")]
+    #[account(2, name = "system_program", desc = "System program")]
+    InitializeConfig { fee_bps: u16, treasury: Pubkey },
+
+    /// Replaces the config settings.
+    #[account(0, signer, name = "admin", desc = "Current admin")]
+    #[account(1, writable, name = "config", desc = "Config PDA")]
+    UpdateConfig {
+        admin: Pubkey,
+        fee_bps: u16,
+        treasury: Pubkey,
+        paused: bool,
+        max_expiry_horizon: i64,
+        referral_bps: u16,
+        crank_bounty_bps: u16,
+        require_deposit: bool,
+        allowed_caller: Pubkey,
+        yield_beneficiary: Pubkey,
+        min_amount: u64,
+        max_amount: u64,
+        creation_fee: u64,
+        max_escrows_per_epoch: u32,
+        arbiter_fee: u64,
+        arbiter_fee_bps: u16,
+        appeal_window_secs: i64,
+        appeal_arbiter: Pubkey,
+    },
+
+    /// Creates a hash time-locked native escrow.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes")]
+    InitializeHtlc { amount: u64, timeout: i64, hash_lock: [u8; 32] },
+
+    /// Pays an HTLC escrow to the taker against the hash lock preimage.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    #[account(4, writable, name = "treasury", desc = "Fee recipient recorded in the config")]
+    Claim { preimage: [u8; 32] },
+
+    /// Creates a native escrow that vests linearly.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes")]
+    InitializeVesting { amount: u64, start_ts: i64, cliff_ts: i64, end_ts: i64 },
+
+    /// Pays whatever has vested so far to the taker, less the protocol fee.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    #[account(4, writable, name = "treasury", desc = "Fee recipient recorded in the config")]
+    WithdrawVested {},
+
+    /// Creates a native escrow paid out in milestone tranches.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA, sized for the milestones")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes")]
+    InitializeMilestones { expiry_ts: i64, milestones: Vec<u64> },
+
+    /// Unlocks the milestone at `index` for the taker.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    ApproveMilestone { index: u8 },
+
+    /// Pays every approved milestone not yet released to the taker, less the
+    /// protocol fee.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    #[account(4, writable, name = "treasury", desc = "Fee recipient recorded in the config")]
+    WithdrawMilestones {},
+
+    /// Creates a native crowdfund collecting `goal` lamports until `deadline`.
+    #[account(0, writable, signer, name = "initializer", desc = "Campaign creator, pays for the accounts")]
+    #[account(1, signer, name = "taker", desc = "Receiver of the funds if the goal is met")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the contributions")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    InitializeCrowdfund { goal: u64, deadline: i64 },
+
+    /// Adds `amount` lamports to a crowdfund before its deadline.
+    #[account(0, writable, signer, name = "contributor", desc = "Funder, pays for the receipt")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, writable, name = "receipt", desc = "Contributor's receipt PDA")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    Contribute { amount: u64 },
+
+    /// Returns a contribution once the deadline passed without reaching the goal.
+    #[account(0, writable, signer, name = "contributor", desc = "Funder, receives the refund and receipt rent")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, writable, name = "receipt", desc = "Contributor's receipt PDA")]
+    ClaimRefund {},
+
+    /// Makes Withdraw of a native escrow depend on an oracle price.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, name = "oracle", desc = "Pyth PriceUpdateV2 or Switchboard aggregator account")]
+    SetPriceCondition { threshold: i64, expo: i32, above: bool },
+
+    /// Releases a native escrow to the taker on the arbiter's off-chain
+    /// approval. The instruction right before it must be an ed25519 program
+    /// check of the arbiter's signature over `state::voucher_message`.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    #[account(4, writable, name = "treasury", desc = "Receives the protocol fee")]
+    #[account(5, name = "instructions", desc = "Instructions sysvar")]
+    ClaimWithVoucher {},
+
+    /// Lets the holder of Ethereum address `eth_address` release a native
+    /// escrow before it is funded; all zeros removes it.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    SetEthAuthority { eth_address: [u8; 20] },
+
+    /// Releases a native escrow to the taker with the release authority's
+    /// secp256k1 `signature` over `state::eth_release_message`, hashed as an
+    /// Ethereum `personal_sign` message.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    #[account(4, writable, name = "treasury", desc = "Receives the protocol fee")]
+    ClaimWithEthSignature { signature: [u8; 64], recovery_id: u8 },
+
+    /// Makes a native escrow releasable by a Wormhole message from
+    /// `emitter_address` on chain `emitter_chain`, before it is funded.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    SetWormholeEmitter { emitter_chain: u16, emitter_address: [u8; 32] },
+
+    /// Releases a native escrow to the taker once the core bridge has posted
+    /// a VAA from its emitter whose payload is `state::vaa_release_payload`.
+    /// Anyone can submit it, e.g. a relayer.
+    #[account(0, writable, name = "taker", desc = "Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    #[account(4, writable, name = "treasury", desc = "Receives the protocol fee")]
+    #[account(5, name = "posted_vaa", desc = "Wormhole PostedVAA account")]
+    ReleaseWithVaa {},
+
+    /// Starts the `DISPUTE_WINDOW_SECS` countdown after which the taker alone
+    /// can FinalizeWithdraw a native escrow. It needs an arbiter, so the
+    /// initializer can Dispute in the meantime.
+    #[account(0, signer, name = "taker", desc = "Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    RequestWithdraw {},
+
+    /// Pays the taker once the dispute window of their RequestWithdraw has
+    /// passed without a Dispute.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    #[account(4, writable, name = "treasury", desc = "Receives the protocol fee")]
+    FinalizeWithdraw {},
+
+    /// Asks the taker of a plain native escrow for `amount` lamports of
+    /// collateral, before it is funded.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    SetTakerBond { amount: u64 },
+
+    /// Locks the taker's bond in the vault of a funded escrow; it must be in
+    /// before the escrow can be released to them.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver, pays the bond")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    DepositBond {},
+
+    /// Redirects the escrow to `new_taker`, who signs unless it is
+    /// `Pubkey::default()` (reopening it for AcceptEscrow). Once funded the
+    /// current taker must sign too.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, name = "taker", desc = "Current receiver, signs once funded")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, name = "new_taker", desc = "New receiver")]
+    SetTaker { new_taker: Pubkey },
+
+    /// Changes the agreed amount of an escrow that is not funded yet.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    UpdateAmount { new_amount: u64 },
+
+    /// Pushes back the expiry of a live escrow that has one, to no more than
+    /// the config's `max_expiry_horizon` from now.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    ExtendDeadline { new_expiry: i64 },
+
+    /// Restricts an unfunded open escrow to `takers`: only they can
+    /// AcceptEscrow it, and any one of them can ClaimAllowed it once funded.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the larger account")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, name = "system_program", desc = "System program")]
+    SetAllowedTakers { takers: Vec<Pubkey> },
+
+    /// Pays a funded native escrow to one member of its allowlist, who
+    /// becomes its taker; no initializer signature is needed.
+    #[account(0, writable, signer, name = "claimant", desc = "Allowlist member")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    #[account(4, writable, name = "treasury", desc = "Receives the protocol fee")]
+    ClaimAllowed {},
+
+    /// Creates a native escrow paying `total` lamports out to many claimants,
+    /// whose allocations are the leaves of `merkle_root`, along with the
+    /// bitmap tracking `num_claims` claims. Fund it with Deposit; after
+    /// `expiry_ts`, if set, Refund returns whatever was not claimed.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    #[account(5, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(6, writable, name = "claimed", desc = "Claimed bitmap PDA")]
+    InitializeDistribution { total: u64, merkle_root: [u8; 32], num_claims: u32, expiry_ts: i64 },
+
+    /// Pays the claimant their allocation at `index`, once, given the proof
+    /// of `merkle::distribution_leaf(index, claimant, amount)`.
+    #[account(0, writable, signer, name = "claimant", desc = "Recipient of the allocation")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault PDA")]
+    #[account(3, writable, name = "claimed", desc = "Claimed bitmap PDA")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    #[account(5, writable, name = "treasury", desc = "Receives the protocol fee")]
+    ClaimDistribution { index: u32, amount: u64, proof: Vec<[u8; 32]> },
+
+    /// Splits the payout of a plain native escrow between `splits`, before
+    /// it is funded; an empty list pays it all to the taker again.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the extra space")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    SetSplits { splits: Vec<Split> },
+
+    /// Upgrades an escrow of an older layout version, down to v1 which has
+    /// no `version` field, to the current layout in place. Anyone can run it; the payer covers the extra rent.
+    #[account(0, writable, signer, name = "payer", desc = "Pays for the extra space")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, name = "system_program", desc = "System program")]
+    MigrateState {},
+
+    /// Appends a tranche of `amount` lamports to a milestone escrow before
+    /// it is funded, growing the state account.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the extra space")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    AddMilestone { amount: u64 },
+
+    /// Does nothing: the program invokes it on itself to record an event's
+    /// `Event::data` in the transaction's inner instructions, which RPC
+    /// nodes keep in full even when they truncate the logs. Only the event
+    /// authority can sign it, so indexers can trust what it carries.
+    #[account(0, signer, name = "event_authority", desc = "Event authority PDA")]
+    EmitEvent { data: Vec<u8> },
+
+    /// Writes the escrow's `EscrowState` header and lists, as stored in the
+    /// account, as return data, for reads through simulation or by a calling
+    /// program. Fails when they are larger than `MAX_RETURN_DATA`.
+    #[account(0, name = "escrow", desc = "Escrow state PDA")]
+    GetState {},
+
+    /// Refund for keeper bots: once a funded native escrow has expired,
+    /// anyone can return it to the initializer, keeping the config's
+    /// `crank_bounty_bps` of the refund for their trouble.
+    #[account(0, writable, signer, name = "cranker", desc = "Anyone, receives the bounty")]
+    #[account(1, writable, name = "initializer", desc = "Sender, receives the rest of the refund")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    CrankRefund {},
+
+    /// Creates one native escrow per `(taker, amount, id)` item, as
+    /// Initialize would, for bulk payouts such as payroll. Each `id` must
+    /// be the next one in the initializer's registry, so they run on
+    /// consecutively. After the fixed accounts come `[taker, escrow, vault]`
+    /// for each item, in order; takers sign as for Initialize.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, name = "system_program", desc = "System program")]
+    #[account(2, name = "config", desc = "Config PDA")]
+    #[account(3, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow ids")]
+    #[account(4, optional, name = "instructions", desc = "Instructions sysvar, in strict mode, after the items' accounts")]
+    InitializeBatch { items: Vec<(Pubkey, u64, u64)> },
+
+    /// Withdraws up to `MAX_BATCH_LEN` funded native escrows addressed to
+    /// the taker in one go, each with the checks and fee of Withdraw. After
+    /// the fixed accounts come `[initializer, escrow, vault]` for each
+    /// escrow; initializers sign as for Withdraw. Escrows with a referrer,
+    /// splits or a price condition are rejected.
+    #[account(0, writable, signer, name = "taker", desc = "Receiver of every escrow")]
+    #[account(1, name = "config", desc = "Config PDA")]
+    #[account(2, writable, name = "treasury", desc = "Fee recipient recorded in the config")]
+    WithdrawBatch {},
+
+    /// Funds a wrapped SOL token escrow straight from the initializer's
+    /// lamports: they are moved into the vault, which is then synced, so
+    /// no wSOL account is needed on the way in.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender")]
+    #[account(1, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(4, name = "mint", desc = "Native mint of the token program")]
+    #[account(5, name = "token_program", desc = "SPL Token or Token-2022")]
+    #[account(6, name = "config", desc = "Config PDA")]
+    #[account(7, name = "system_program", desc = "System program")]
+    DepositSol {},
+
+    /// Pays a wrapped SOL token escrow out to the taker as native SOL,
+    /// closing the vault. Its rent stays with the escrow until Close.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(4, name = "mint", desc = "Native mint of the token program")]
+    #[account(5, name = "token_program", desc = "SPL Token or Token-2022")]
+    WithdrawSol {},
+
+    /// Escrows a stake account, which keeps earning meanwhile: the
+    /// initializer, its staker and withdrawer, hands both authorities to
+    /// the escrow PDA and the escrow is funded with the account's balance
+    /// at once.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "stake", desc = "Stake account to escrow")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, name = "clock", desc = "Clock sysvar")]
+    #[account(8, name = "stake_program", desc = "Stake program")]
+    InitializeStake { expiry_ts: i64 },
+
+    /// Hands the escrowed stake account's authorities to the taker.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "stake", desc = "Escrowed stake account")]
+    #[account(4, name = "clock", desc = "Clock sysvar")]
+    #[account(5, name = "stake_program", desc = "Stake program")]
+    WithdrawStake {},
+
+    /// Hands the escrowed stake account's authorities back to the
+    /// initializer after `expiry_ts`.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "stake", desc = "Escrowed stake account")]
+    #[account(3, name = "clock", desc = "Clock sysvar")]
+    #[account(4, name = "stake_program", desc = "Stake program")]
+    ReclaimStake {},
+
+    /// Funds a token escrow on a stake pool's mint with `amount` lamports,
+    /// which DepositSol stakes into the pool so they earn while locked. The
+    /// vault holds the pool tokens received.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender")]
+    #[account(1, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    #[account(5, name = "system_program", desc = "System program")]
+    #[account(6, name = "stake_pool_program", desc = "SPL stake pool program")]
+    #[account(7, writable, name = "stake_pool", desc = "Stake pool")]
+    #[account(8, name = "withdraw_authority", desc = "Stake pool withdraw authority PDA")]
+    #[account(9, writable, name = "reserve_stake", desc = "Stake pool reserve")]
+    #[account(10, writable, name = "manager_fee_account", desc = "Stake pool manager's fee account")]
+    #[account(11, writable, name = "pool_mint", desc = "Pool token mint, the escrowed mint")]
+    #[account(12, name = "token_program", desc = "Token program of the pool mint")]
+    DepositStaked {},
+
+    /// Redeems the pool tokens with WithdrawSol and pays the taker up to the
+    /// deposited lamports; what the stake earned on top goes to the yield
+    /// beneficiary.
+    #[account(0, signer, name = "initializer", desc = "Sender")]
+    #[account(1, writable, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    #[account(5, writable, name = "beneficiary", desc = "Yield beneficiary from the config, or the Sender")]
+    #[account(6, name = "stake_pool_program", desc = "SPL stake pool program")]
+    #[account(7, writable, name = "stake_pool", desc = "Stake pool")]
+    #[account(8, name = "withdraw_authority", desc = "Stake pool withdraw authority PDA")]
+    #[account(9, writable, name = "reserve_stake", desc = "Stake pool reserve")]
+    #[account(10, writable, name = "manager_fee_account", desc = "Stake pool manager's fee account")]
+    #[account(11, writable, name = "pool_mint", desc = "Pool token mint, the escrowed mint")]
+    #[account(12, name = "token_program", desc = "Token program of the pool mint")]
+    #[account(13, name = "clock", desc = "Clock sysvar")]
+    #[account(14, name = "stake_history", desc = "Stake history sysvar")]
+    #[account(15, name = "stake_program", desc = "Stake program")]
+    WithdrawStaked {},
+
+    /// WithdrawStaked back to the initializer after `expiry_ts`.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(3, name = "config", desc = "Config PDA")]
+    #[account(4, writable, name = "beneficiary", desc = "Yield beneficiary from the config, or the Sender")]
+    #[account(5, name = "stake_pool_program", desc = "SPL stake pool program")]
+    #[account(6, writable, name = "stake_pool", desc = "Stake pool")]
+    #[account(7, name = "withdraw_authority", desc = "Stake pool withdraw authority PDA")]
+    #[account(8, writable, name = "reserve_stake", desc = "Stake pool reserve")]
+    #[account(9, writable, name = "manager_fee_account", desc = "Stake pool manager's fee account")]
+    #[account(10, writable, name = "pool_mint", desc = "Pool token mint, the escrowed mint")]
+    #[account(11, name = "token_program", desc = "Token program of the pool mint")]
+    #[account(12, name = "clock", desc = "Clock sysvar")]
+    #[account(13, name = "stake_history", desc = "Stake history sysvar")]
+    #[account(14, name = "stake_program", desc = "Stake program")]
+    ReclaimStaked {},
+
+    /// Creates a token escrow sold by Dutch auction: once funded with
+    /// DepositToken, anyone (or an allowed taker) can buy it with BuyAuction
+    /// at the current price of `auction`.
+    #[account(0, writable, signer, name = "initializer", desc = "Seller, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Only allowed buyer, or the default pubkey for an open auction")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(4, name = "mint", desc = "Auctioned mint")]
+    #[account(5, name = "token_program", desc = "SPL Token or Token-2022")]
+    #[account(6, name = "system_program", desc = "System program")]
+    #[account(7, name = "config", desc = "Config PDA")]
+    #[account(8, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(9, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes")]
+    InitializeAuction { amount: u64, auction: Auction },
+
+    /// Pays the seller the auction price at the current Clock time and
+    /// releases the escrowed tokens to the buyer.
+    #[account(0, writable, signer, name = "buyer", desc = "Pays the price, becomes the taker")]
+    #[account(1, writable, name = "initializer", desc = "Seller, receives the price")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(4, writable, name = "buyer_token", desc = "Buyer's token account")]
+    #[account(5, name = "mint", desc = "Auctioned mint")]
+    #[account(6, name = "token_program", desc = "SPL Token or Token-2022")]
+    #[account(7, name = "system_program", desc = "System program")]
+    BuyAuction {},
+
+    /// Returns the tokens of an unsold auction to the seller.
+    #[account(0, writable, signer, name = "initializer", desc = "Seller")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, writable, name = "vault", desc = "Vault token account PDA")]
+    #[account(3, writable, name = "initializer_token", desc = "Seller's token account")]
+    #[account(4, name = "mint", desc = "Auctioned mint")]
+    #[account(5, name = "token_program", desc = "SPL Token or Token-2022")]
+    CancelAuction {},
+
+    /// Puts a revised `amount` and `expiry_ts` (0 for none) for an escrow
+    /// that is not funded yet on-chain, replacing any pending proposal.
+    /// Nothing changes until the other party runs AcceptTerms.
+    #[account(0, signer, name = "proposer", desc = "Sender or Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(2, name = "config", desc = "Config PDA")]
+    ProposeTerms { amount: u64, expiry_ts: i64 },
+
+    /// Applies the terms pending from ProposeTerms; only the party who
+    /// didn't propose them can accept.
+    #[account(0, signer, name = "accepter", desc = "Sender or Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    AcceptTerms {},
+
+    /// Creates the reputation stats PDA of `party`. Instructions given it
+    /// with `with_stats` count the escrows of `party` they settle.
+    #[account(0, writable, signer, name = "payer", desc = "Pays for the stats account")]
+    #[account(1, writable, name = "stats", desc = "Stats PDA of the party")]
+    #[account(2, name = "system_program", desc = "System program")]
+    InitializeStats { party: Pubkey },
+
+    /// Initialize submitted by a relayer, who pays for everything, on the
+    /// initializer's off-chain authorization: the instruction right before
+    /// it must be an ed25519 program check of the initializer's signature
+    /// over `state::relayed_initialize_message`, valid until `deadline`.
+    #[account(0, name = "initializer", desc = "Sender, who signed the message")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, writable, signer, name = "payer", desc = "Relayer, pays for the accounts")]
+    #[account(8, name = "instructions", desc = "Instructions sysvar")]
+    InitializeRelayed { amount: u64, expiry_ts: i64, deadline: i64 },
+
+    /// Creates a native escrow prepaying `intervals` tranches of
+    /// `subscription.amount_per_interval`, released one per elapsed
+    /// interval by WithdrawSubscription.
+    #[account(0, writable, signer, name = "initializer", desc = "Subscriber, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Provider, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes")]
+    InitializeSubscription { intervals: u64, subscription: Subscription },
+
+    /// Pays the taker the subscription tranches elapsed since the last
+    /// withdrawal, less the protocol fee. Anyone can crank it, the funds only
+    /// go to the taker.
+    #[account(0, signer, name = "caller", desc = "Taker or any crank")]
+    #[account(1, writable, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    #[account(5, writable, name = "treasury", desc = "Fee recipient recorded in the config")]
+    WithdrawSubscription {},
+
+    /// Creates a native escrow that authorizes, like a card hold, a charge
+    /// of up to `amount`: once funded, the taker can Capture part or all of
+    /// it until `expiry_ts`, after which Refund returns it all.
+    #[account(0, writable, signer, name = "initializer", desc = "Payer of the charge, pays for the accounts")]
+    #[account(1, optional_signer, name = "taker", desc = "Merchant, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes")]
+    InitializeAuthorization { amount: u64, expiry_ts: i64 },
+
+    /// Settles an authorization: pays the taker `amount`, less the protocol
+    /// fee, and refunds the uncaptured remainder to the initializer.
+    #[account(0, writable, signer, name = "taker", desc = "Merchant")]
+    #[account(1, writable, name = "initializer", desc = "Payer of the charge, receives the remainder")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, name = "config", desc = "Config PDA")]
+    #[account(5, writable, name = "treasury", desc = "Fee recipient recorded in the config")]
+    #[account(6, writable, optional, name = "referrer", desc = "Escrow's referrer, for referred escrows")]
+    Capture { amount: u64 },
+
+    /// Invoice: the payee creates, and pays for, an escrow of `amount` that
+    /// the named payer owes. The payer is its initializer and funds it with
+    /// Deposit as usual, then may release it with Withdraw without the
+    /// taker's signature, since issuing the invoice was the taker's consent.
+    #[account(0, name = "initializer", desc = "Payer, who owes the amount")]
+    #[account(1, writable, signer, name = "taker", desc = "Payee, pays for the accounts")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Payer's registry PDA, assigns the escrow id")]
+    InitializeInvoice { amount: u64, expiry_ts: i64 },
+
+    /// Withdraws an invoice nobody funded yet, returning its rent to the
+    /// payee who issued it.
+    #[account(0, writable, signer, name = "taker", desc = "Payee who issued the invoice")]
+    #[account(1, writable, name = "initializer", desc = "Payer")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, writable, name = "registry", desc = "Payer's registry PDA")]
+    CancelInvoice {},
+
+    /// Creates or replaces the escrow template registered under `name`.
+    #[account(0, writable, signer, name = "admin", desc = "Config admin, pays for a new template")]
+    #[account(1, name = "config", desc = "Config PDA")]
+    #[account(2, writable, name = "template", desc = "Template PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    SetTemplate {
+        name: [u8; 32],
+        fee_bps: u16,
+        dispute_window_secs: i64,
+        default_expiry_secs: i64,
+        arbiter: Pubkey,
+    },
+
+    /// Creates the authorities account, with no grants. Until roles are
+    /// granted, the config admin alone runs the role-gated instructions.
+    #[account(0, writable, signer, name = "admin", desc = "Config admin, pays for the account")]
+    #[account(1, name = "config", desc = "Config PDA")]
+    #[account(2, writable, name = "authorities", desc = "Authorities PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    InitializeAuthorities {},
+
+    /// Grants `role` to `authority`. The Arbiter role takes an Admin or
+    /// ArbiterRegistry signer, every other role an Admin.
+    #[account(0, writable, signer, name = "signer", desc = "Config admin or role holder, pays for a new grant")]
+    #[account(1, name = "config", desc = "Config PDA")]
+    #[account(2, writable, name = "authorities", desc = "Authorities PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    GrantRole { authority: Pubkey, role: Role },
+
+    /// Revokes `role` from `authority`, with the same signers as GrantRole.
+    #[account(0, writable, signer, name = "signer", desc = "Config admin or role holder, receives freed rent")]
+    #[account(1, name = "config", desc = "Config PDA")]
+    #[account(2, writable, name = "authorities", desc = "Authorities PDA")]
+    RevokeRole { authority: Pubkey, role: Role },
+
+    /// Pauses or unpauses new escrows and deposits.
+    #[account(0, signer, name = "signer", desc = "Config admin or Pauser")]
+    #[account(1, writable, name = "config", desc = "Config PDA")]
+    #[account(2, name = "authorities", desc = "Authorities PDA")]
+    SetPaused { paused: bool },
+
+    /// Replaces the protocol fee and the referral and crank bounty shares.
+    #[account(0, signer, name = "signer", desc = "Config admin or FeeManager")]
+    #[account(1, writable, name = "config", desc = "Config PDA")]
+    #[account(2, name = "authorities", desc = "Authorities PDA")]
+    SetFees { fee_bps: u16, referral_bps: u16, crank_bounty_bps: u16 },
+
+    /// Starts the timelock of an emergency recovery of a funded or disputed
+    /// native escrow's funds to `recipient`, for when a bug leaves them
+    /// stuck. Replaces any pending proposal.
+    #[account(0, signer, name = "admin", desc = "Config admin")]
+    #[account(1, name = "config", desc = "Config PDA")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    ProposeEmergencyWithdraw { recipient: Pubkey },
+
+    /// Cancels the pending emergency withdrawal of an escrow.
+    #[account(0, signer, name = "party", desc = "Sender or Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    VetoEmergencyWithdraw {},
+
+    /// Pays the escrow's funds to the proposed recipient once
+    /// `EMERGENCY_DELAY_SECS` have passed without a veto.
+    #[account(0, signer, name = "admin", desc = "Config admin")]
+    #[account(1, name = "config", desc = "Config PDA")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA")]
+    #[account(4, writable, name = "recipient", desc = "Recipient named by the proposal")]
+    ExecuteEmergencyWithdraw {},
+
+    /// Switches `feature` on or off, creating the feature flags account with
+    /// `DEFAULT_FEATURES` on first use.
+    #[account(0, writable, signer, name = "admin", desc = "Config admin, pays for the account")]
+    #[account(1, name = "config", desc = "Config PDA")]
+    #[account(2, writable, name = "features", desc = "Feature flags PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    SetFeature { feature: Feature, enabled: bool },
+
+    /// Initialize, also recording the client's `nonce` in the escrow and its
+    /// `EscrowInitialized` event.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts unless a payer is given")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes, or the default pubkey for none")]
+    #[account(8, optional, name = "referrer", desc = "Receives the config's referral share of the fee on Withdraw, or the default pubkey for none")]
+    #[account(9, optional, name = "instructions", desc = "Instructions sysvar, in strict mode, or the default pubkey")]
+    #[account(10, writable, optional_signer, name = "payer", desc = "Pays the rent in the Sender's place, or the default pubkey")]
+    #[account(11, optional, name = "template", desc = "Template PDA whose fee, dispute window, default expiry and arbiter the escrow takes")]
+    InitializeWithNonce { amount: u64, expiry_ts: i64, nonce: u64 },
+
+    /// Initialize, also recording a 32-byte off-chain `reference`, such as
+    /// the hash of an order id, in the escrow and its `EscrowInitialized`
+    /// event.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the accounts unless a payer is given")]
+    #[account(1, optional_signer, name = "taker", desc = "Receiver, or the default pubkey for an open escrow")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, writable, name = "vault", desc = "Vault PDA holding the lamports")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA")]
+    #[account(6, writable, name = "registry", desc = "Initializer's registry PDA, assigns the escrow id")]
+    #[account(7, optional, name = "arbiter", desc = "Arbiter allowed to resolve disputes, or the default pubkey for none")]
+    #[account(8, optional, name = "referrer", desc = "Receives the config's referral share of the fee on Withdraw, or the default pubkey for none")]
+    #[account(9, optional, name = "instructions", desc = "Instructions sysvar, in strict mode, or the default pubkey")]
+    #[account(10, writable, optional_signer, name = "payer", desc = "Pays the rent in the Sender's place, or the default pubkey")]
+    #[account(11, optional, name = "template", desc = "Template PDA whose fee, dispute window, default expiry and arbiter the escrow takes")]
+    InitializeWithReference { amount: u64, expiry_ts: i64, reference: [u8; 32] },
+
+    /// Both parties fix the escrow's terms: a URI of the human-readable
+    /// document and its `hash`, which can't change afterwards.
+    #[account(0, writable, signer, name = "initializer", desc = "Sender, pays for the larger account")]
+    #[account(1, signer, name = "taker", desc = "Receiver")]
+    #[account(2, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    SetMetadata { uri: String, hash: [u8; 32] },
+
+    /// Settles a disputed native escrow between both parties: `taker_bps`
+    /// of it goes to the taker, or their splits, and the rest back to the
+    /// initializer, once the arbiter took its fee.
+    #[account(0, writable, signer, name = "arbiter", desc = "Escrow arbiter, receives its fee")]
+    #[account(1, writable, name = "initializer", desc = "Sender")]
+    #[account(2, writable, name = "taker", desc = "Receiver")]
+    #[account(3, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(4, writable, name = "vault", desc = "Vault PDA")]
+    #[account(5, writable, optional, name = "split_recipients", desc = "For split escrows, the split recipients in order")]
+    ResolvePartial { taker_bps: u16 },
+
+    /// Either party challenges a ruling held in its appeal window, handing
+    /// the dispute to the escrow's appeal arbiter, whose ruling is final.
+    #[account(0, signer, name = "party", desc = "Sender or Receiver")]
+    #[account(1, writable, name = "escrow", desc = "Escrow state PDA")]
+    Appeal {},
+
+    /// Carries out a ruling whose appeal window closed unchallenged; anyone
+    /// can send it.
+    #[account(0, writable, name = "arbiter", desc = "Escrow arbiter, receives its fee")]
+    #[account(1, writable, name = "initializer", desc = "Sender")]
+    #[account(2, writable, name = "taker", desc = "Receiver")]
+    #[account(3, writable, name = "escrow", desc = "Escrow state PDA")]
+    #[account(4, writable, name = "vault", desc = "Vault PDA")]
+    #[account(5, writable, optional, name = "recipient_token", desc = "Winning party's token account, for token escrows")]
+    #[account(6, optional, name = "mint", desc = "Escrowed mint, for token escrows")]
+    #[account(7, optional, name = "token_program", desc = "Token program, for token escrows")]
+    #[account(8, writable, optional, name = "arbiter_token", desc = "Arbiter's token account, receives its fee, for token escrows with an arbiter fee")]
+    #[account(9, writable, optional, name = "split_recipients", desc = "Split recipients of a split escrow, in place of the token accounts")]
+    FinalizeRuling {},
+}
+
+impl EscrowInstruction {
+    /// Decodes `input`, rejecting trailing bytes, then checks the arguments.
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let mut data = input;
+        let instr    = Self::deserialize(&mut data).map_err(|_| EscrowError::MalformedInstruction)?;
+        if !data.is_empty() {
+            return Err(EscrowError::TrailingBytes.into());
+        }
+        instr.validate()?;
+        Ok(instr)
+    }
+
+    /// Instructions only the config admin, or a holder of one of its roles,
+    /// can run. They skip the `allowed_caller` check, so a multisig or
+    /// governance program holding the admin key as a PDA can invoke them
+    /// directly, without the caller accounts.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Self::InitializeConfig { .. }
+                | Self::UpdateConfig { .. }
+                | Self::SetTemplate { .. }
+                | Self::InitializeAuthorities {}
+                | Self::GrantRole { .. }
+                | Self::RevokeRole { .. }
+                | Self::SetPaused { .. }
+                | Self::SetFees { .. }
+                | Self::ProposeEmergencyWithdraw { .. }
+                | Self::ExecuteEmergencyWithdraw {}
+                | Self::SetFeature { .. }
+        )
+    }
+
+    /// Features the instruction needs enabled, as a bitmask of
+    /// `Feature::bit`s. When it needs any, the feature flags PDA must follow
+    /// its accounts (see `with_features`).
+    pub fn required_features(&self) -> u64 {
+        match self {
+            Self::InitializeToken { .. }
+            | Self::InitializeNft { .. }
+            | Self::InitializeSwap { .. }
+            | Self::InitializeAuction { .. } => Feature::TokenEscrows.bit(),
+            Self::InitializeConfig { fee_bps, .. } if *fee_bps > 0 => Feature::Fees.bit(),
+            Self::UpdateConfig { fee_bps, require_deposit, .. } => {
+                let fees   = if *fee_bps > 0 { Feature::Fees.bit() } else { 0 };
+                let strict = if *require_deposit { Feature::StrictMode.bit() } else { 0 };
+                fees | strict
+            }
+            Self::SetFees { fee_bps, .. } | Self::SetTemplate { fee_bps, .. } if *fee_bps > 0 => Feature::Fees.bit(),
+            _ => 0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), EscrowError> {
+        match self {
+            Self::Initialize { amount, .. }
+            | Self::InitializeWithNonce { amount, .. }
+            | Self::InitializeWithReference { amount, .. }
+            | Self::InitializeToken { amount, .. }
+            | Self::InitializeAndDeposit { amount, .. }
+            | Self::WithdrawPartial { amount }
+            | Self::DepositAdditional { amount }
+            | Self::InitializeHtlc { amount, .. }
+            | Self::InitializeVesting { amount, .. }
+            | Self::InitializeAuction { amount, .. }
+            | Self::InitializeCrowdfund { goal: amount, .. }
+            | Self::SetTakerBond { amount }
+            | Self::UpdateAmount { new_amount: amount }
+            | Self::ProposeTerms { amount, .. }
+            | Self::InitializeRelayed { amount, .. }
+            | Self::InitializeAuthorization { amount, .. }
+            | Self::Capture { amount }
+            | Self::InitializeInvoice { amount, .. }
+            | Self::AddMilestone { amount }
+            | Self::Contribute { amount } => nonzero(*amount),
+            Self::InitializeSwap { amount, taker_amount, .. } => {
+                nonzero(*amount)?;
+                nonzero(*taker_amount)
+            }
+            Self::InitializeSubscription { intervals, subscription } => {
+                nonzero(*intervals)?;
+                nonzero(subscription.amount_per_interval)
+            }
+            Self::InitializeMilestones { milestones, .. } => {
+                if milestones.is_empty() || milestones.len() > MAX_MILESTONES {
+                    return Err(EscrowError::InvalidMilestone);
+                }
+                milestones.iter().try_for_each(|m| nonzero(*m))
+            }
+            Self::ApproveMilestone { index } if usize::from(*index) >= MAX_MILESTONES => {
+                Err(EscrowError::InvalidMilestone)
+            }
+            Self::SetAllowedTakers { takers } if takers.is_empty() || takers.len() > MAX_ALLOWED_TAKERS => {
+                Err(EscrowError::InvalidAllowlist)
+            }
+            Self::ResolvePartial { taker_bps } if *taker_bps > 10_000 => Err(EscrowError::InvalidSplit),
+            Self::SetMetadata { uri, hash } if uri.is_empty() || uri.len() > MAX_METADATA_URI_LEN || *hash == [0; 32] => {
+                Err(EscrowError::InvalidTerms)
+            }
+            Self::InitializeDistribution { total, num_claims, .. } => {
+                nonzero(*total)?;
+                nonzero((*num_claims).into())
+            }
+            Self::ClaimDistribution { amount, proof, .. } => {
+                if proof.len() > MAX_PROOF_LEN {
+                    return Err(EscrowError::InvalidProof);
+                }
+                nonzero(*amount)
+            }
+            Self::InitializeBatch { items } => {
+                if items.is_empty() || items.len() > MAX_BATCH_LEN {
+                    return Err(EscrowError::InvalidBatch);
+                }
+                items.iter().try_for_each(|(_, amount, _)| nonzero(*amount))
+            }
+            Self::SetSplits { splits } if !splits.is_empty() => {
+                let total: u32 = splits.iter().map(|s| u32::from(s.bps)).sum();
+                if splits.len() > MAX_SPLITS || total != 10_000 || splits.iter().any(|s| s.bps == 0) {
+                    return Err(EscrowError::InvalidSplit);
+                }
+                Ok(())
+            }
+            Self::InitializeConfig { fee_bps, .. }
+            | Self::UpdateConfig { fee_bps, .. }
+            | Self::SetTemplate { fee_bps, .. }
+            | Self::SetFees { fee_bps, .. }
+                if *fee_bps > MAX_FEE_BPS =>
+            {
+                Err(EscrowError::InvalidFee)
+            }
+            Self::UpdateConfig { referral_bps, crank_bounty_bps, .. } | Self::SetFees { referral_bps, crank_bounty_bps, .. }
+                if *referral_bps > 10_000 || *crank_bounty_bps > 10_000 =>
+            {
+                Err(EscrowError::InvalidFee)
+            }
+            Self::SetTemplate { dispute_window_secs, default_expiry_secs, .. }
+                if *dispute_window_secs <= 0 || *default_expiry_secs < 0 =>
+            {
+                Err(EscrowError::InvalidTemplate)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn nonzero(amount: u64) -> Result<(), EscrowError> {
+    if amount == 0 {
+        return Err(EscrowError::InvalidAmount);
+    }
+    Ok(())
+}
+
+/// Builds an `Initialize` instruction; both parties must sign the transaction.
+/// Pass `Pubkey::default()` as `taker` to create an open escrow instead.
+pub fn initialize(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    amount: u64,
+    id: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        arbiter,
+        &EscrowInstruction::Initialize { amount, expiry_ts },
+    )
+}
+
+/// Builds an `InitializeWithNonce` instruction, tagging the escrow with
+/// `nonce` so a retry can be matched to the escrow it created.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_with_nonce(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    amount: u64,
+    id: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+    nonce: u64,
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        arbiter,
+        &EscrowInstruction::InitializeWithNonce { amount, expiry_ts, nonce },
+    )
+}
+
+/// Builds an `InitializeWithReference` instruction, tagging the escrow with
+/// `reference` so off-chain systems can match it to their own records.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_with_reference(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    amount: u64,
+    id: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+    reference: [u8; 32],
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        arbiter,
+        &EscrowInstruction::InitializeWithReference { amount, expiry_ts, reference },
+    )
+}
+
+/// Builds an `Initialize` instruction for an escrow brought in by `referrer`.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_referred(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    amount: u64,
+    id: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+    referrer: &Pubkey,
+) -> Instruction {
+    let mut ix = initialize(program_id, initializer, taker, amount, id, expiry_ts, None);
+    ix.accounts.extend([
+        AccountMeta::new_readonly(arbiter.copied().unwrap_or_default(), false),
+        AccountMeta::new_readonly(*referrer, false),
+    ]);
+    ix
+}
+
+/// Builds an `InitializeAndDeposit` instruction; both parties must sign.
+pub fn initialize_and_deposit(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    amount: u64,
+    id: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        arbiter,
+        &EscrowInstruction::InitializeAndDeposit { amount, expiry_ts },
+    )
+}
+
+/// Builds an `InitializeHtlc` instruction. The taker can Claim with the
+/// preimage of `hash_lock` until `timeout`; afterwards the initializer can Refund.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_htlc(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    amount: u64,
+    id: u64,
+    timeout: i64,
+    hash_lock: [u8; 32],
+    arbiter: Option<&Pubkey>,
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        arbiter,
+        &EscrowInstruction::InitializeHtlc { amount, timeout, hash_lock },
+    )
+}
+
+/// Builds a `Claim` instruction; only the taker signs, revealing `preimage`.
+pub fn claim(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+    preimage: [u8; 32],
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::Claim { preimage },
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds an `InitializeVesting` instruction for a native escrow that unlocks
+/// linearly from `start_ts` to `end_ts`, with nothing before `cliff_ts`.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_vesting(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    amount: u64,
+    id: u64,
+    start_ts: i64,
+    cliff_ts: i64,
+    end_ts: i64,
+    arbiter: Option<&Pubkey>,
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        arbiter,
+        &EscrowInstruction::InitializeVesting { amount, start_ts, cliff_ts, end_ts },
+    )
+}
+
+/// Builds an `InitializeSubscription` instruction for a native escrow of
+/// `intervals` tranches released on the `subscription` schedule.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_subscription(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    intervals: u64,
+    subscription: Subscription,
+    arbiter: Option<&Pubkey>,
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        arbiter,
+        &EscrowInstruction::InitializeSubscription { intervals, subscription },
+    )
+}
+
+/// Builds an `InitializeAuthorization` instruction holding up to `amount`
+/// for the taker to Capture until `expiry_ts`.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_authorization(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    amount: u64,
+    id: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        arbiter,
+        &EscrowInstruction::InitializeAuthorization { amount, expiry_ts },
+    )
+}
+
+/// Builds an `InitializeInvoice` instruction in which `taker` bills
+/// `initializer` for `amount`; only the taker signs.
+pub fn initialize_invoice(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    amount: u64,
+    id: u64,
+    expiry_ts: i64,
+) -> Instruction {
+    let mut ix = create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        None,
+        &EscrowInstruction::InitializeInvoice { amount, expiry_ts },
+    );
+    ix.accounts[0] = AccountMeta::new_readonly(*initializer, false);
+    ix.accounts[1] = AccountMeta::new(*taker, true);
+    ix
+}
+
+/// Builds a `CancelInvoice` instruction; only the taker signs.
+pub fn cancel_invoice(program_id: &Pubkey, taker: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _)   = find_escrow_address(program_id, initializer, id);
+    let (vault, _)    = find_vault_address(program_id, &escrow);
+    let (registry, _) = find_registry_address(program_id, initializer);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::CancelInvoice {},
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(*initializer, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(registry, false),
+        ],
+    )
+}
+
+/// Builds an `InitializeMilestones` instruction; the escrow amount is the sum
+/// of the `milestones` tranches.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_milestones(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    expiry_ts: i64,
+    milestones: Vec<u64>,
+    arbiter: Option<&Pubkey>,
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        arbiter,
+        &EscrowInstruction::InitializeMilestones { expiry_ts, milestones },
+    )
+}
+
+/// Builds an `ApproveMilestone` instruction signed by the initializer.
+pub fn approve_milestone(program_id: &Pubkey, initializer: &Pubkey, id: u64, index: u8) -> Instruction {
+    initializer_only(program_id, initializer, id, &EscrowInstruction::ApproveMilestone { index })
+}
+
+/// Builds an `AddMilestone` instruction; both parties must sign.
+pub fn add_milestone(program_id: &Pubkey, initializer: &Pubkey, taker: &Pubkey, id: u64, amount: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::AddMilestone { amount },
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `WithdrawMilestones` instruction; only the taker signs.
+pub fn withdraw_milestones(program_id: &Pubkey, taker: &Pubkey, initializer: &Pubkey, treasury: &Pubkey, id: u64) -> Instruction {
+    let mut ix = withdraw_vested(program_id, taker, initializer, treasury, id);
+    ix.data = borsh::to_vec(&EscrowInstruction::WithdrawMilestones {}).unwrap();
+    ix
+}
+
+/// Builds an `InitializeCrowdfund` instruction; both the creator and the
+/// receiver sign.
+pub fn initialize_crowdfund(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    goal: u64,
+    id: u64,
+    deadline: i64,
+) -> Instruction {
+    create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        None,
+        &EscrowInstruction::InitializeCrowdfund { goal, deadline },
+    )
+}
+
+/// Builds a `Contribute` instruction signed by `contributor`.
+pub fn contribute(
+    program_id: &Pubkey,
+    contributor: &Pubkey,
+    initializer: &Pubkey,
+    id: u64,
+    amount: u64,
+) -> Instruction {
+    let mut ix = claim_refund(program_id, contributor, initializer, id);
+    ix.accounts.extend([
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+    ]);
+    ix.data = borsh::to_vec(&EscrowInstruction::Contribute { amount }).unwrap();
+    ix
+}
+
+/// Builds a `ClaimRefund` instruction signed by `contributor`.
+pub fn claim_refund(program_id: &Pubkey, contributor: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _)  = find_escrow_address(program_id, initializer, id);
+    let (vault, _)   = find_vault_address(program_id, &escrow);
+    let (receipt, _) = find_receipt_address(program_id, &escrow, contributor);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ClaimRefund {},
+        vec![
+            AccountMeta::new(*contributor, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(receipt, false),
+        ],
+    )
+}
+
+/// Builds a `Withdraw` instruction that pays `recipient` instead of the
+/// taker's signing key; both parties still sign.
+pub fn withdraw_to(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    treasury: &Pubkey,
+    recipient: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = withdraw(program_id, initializer, taker, treasury, id);
+    ix.accounts.push(AccountMeta::new(*recipient, false));
+    ix
+}
+
+/// Builds a `SetPriceCondition` instruction; both parties sign. The escrow
+/// is then withdrawn with `withdraw_conditioned`.
+#[allow(clippy::too_many_arguments)]
+pub fn set_price_condition(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    oracle: &Pubkey,
+    id: u64,
+    threshold: i64,
+    expo: i32,
+    above: bool,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetPriceCondition { threshold, expo, above },
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new_readonly(*oracle, false),
+        ],
+    )
+}
+
+/// Builds a `Withdraw` instruction for an escrow with a price condition,
+/// paying the taker and passing the oracle it was set up with.
+pub fn withdraw_conditioned(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    treasury: &Pubkey,
+    oracle: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = withdraw(program_id, initializer, taker, treasury, id);
+    ix.accounts.push(AccountMeta::new_readonly(*oracle, false));
+    ix
+}
+
+/// Builds a `Withdraw` instruction for an escrow with a price condition that
+/// pays `recipient` instead of the taker's signing key.
+pub fn withdraw_conditioned_to(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    treasury: &Pubkey,
+    oracle: &Pubkey,
+    recipient: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = withdraw_conditioned(program_id, initializer, taker, treasury, oracle, id);
+    ix.accounts.push(AccountMeta::new(*recipient, false));
+    ix
+}
+
+/// Builds a `ClaimWithVoucher` instruction. Put the arbiter's ed25519
+/// signature check right before it in the same transaction, e.g. with
+/// `new_ed25519_instruction_with_signature` over `state::voucher_message`.
+pub fn claim_with_voucher(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ClaimWithVoucher {},
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+            AccountMeta::new_readonly(sysvar::instructions::ID, false),
+        ],
+    )
+}
+
+/// Builds a `SetEthAuthority` instruction; both parties must sign.
+pub fn set_eth_authority(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    eth_address: [u8; 20],
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetEthAuthority { eth_address },
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds a `ClaimWithEthSignature` instruction from a 64-byte `(r, s)`
+/// signature and its recovery id (0 or 1, not Ethereum's 27 or 28).
+pub fn claim_with_eth_signature(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+    signature: [u8; 64],
+    recovery_id: u8,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ClaimWithEthSignature { signature, recovery_id },
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds a `SetWormholeEmitter` instruction; both parties must sign.
+pub fn set_wormhole_emitter(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    emitter_chain: u16,
+    emitter_address: [u8; 32],
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetWormholeEmitter { emitter_chain, emitter_address },
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds a `ReleaseWithVaa` instruction; no signature is needed beyond the
+/// fee payer's.
+pub fn release_with_vaa(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    posted_vaa: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ReleaseWithVaa {},
+        vec![
+            AccountMeta::new(*taker, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+            AccountMeta::new_readonly(*posted_vaa, false),
+        ],
+    )
+}
+
+/// Builds a `RequestWithdraw` instruction signed by the taker.
+pub fn request_withdraw(program_id: &Pubkey, taker: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::RequestWithdraw {},
+        vec![
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds a `FinalizeWithdraw` instruction signed by the taker.
+pub fn finalize_withdraw(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::FinalizeWithdraw {},
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds a `SetTakerBond` instruction; both parties must sign.
+pub fn set_taker_bond(program_id: &Pubkey, initializer: &Pubkey, taker: &Pubkey, id: u64, amount: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetTakerBond { amount },
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds a `DepositBond` instruction signed and paid by the taker.
+pub fn deposit_bond(program_id: &Pubkey, taker: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::DepositBond {},
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Builds a `SetTaker` instruction for an unfunded escrow, signed by the
+/// initializer and `new_taker`.
+pub fn set_taker(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    new_taker: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetTaker { new_taker: *new_taker },
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new_readonly(*new_taker, *new_taker != Pubkey::default()),
+        ],
+    )
+}
+
+/// Builds a `SetTaker` instruction for a funded escrow, which the current
+/// taker signs as well.
+pub fn set_taker_funded(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    new_taker: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = set_taker(program_id, initializer, taker, new_taker, id);
+    ix.accounts[1].is_signer = true;
+    ix
+}
+
+/// Builds an `UpdateAmount` instruction; both parties must sign.
+pub fn update_amount(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    new_amount: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::UpdateAmount { new_amount },
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds an `ExtendDeadline` instruction; both parties must sign.
+pub fn extend_deadline(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    new_expiry: i64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ExtendDeadline { new_expiry },
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Builds a `SetAllowedTakers` instruction signed by the initializer.
+pub fn set_allowed_takers(program_id: &Pubkey, initializer: &Pubkey, id: u64, takers: Vec<Pubkey>) -> Instruction {
+    let mut ix = initializer_only(program_id, initializer, id, &EscrowInstruction::SetAllowedTakers { takers });
+    ix.accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    ix
+}
+
+/// Builds a `SetMetadata` instruction; both parties must sign.
+pub fn set_metadata(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    uri: String,
+    hash: [u8; 32],
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetMetadata { uri, hash },
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `ClaimAllowed` instruction signed by `claimant`.
+pub fn claim_allowed(
+    program_id: &Pubkey,
+    claimant: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ClaimAllowed {},
+        vec![
+            AccountMeta::new(*claimant, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds an `InitializeDistribution` instruction signed by the initializer.
+pub fn initialize_distribution(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    id: u64,
+    total: u64,
+    merkle_root: [u8; 32],
+    num_claims: u32,
+    expiry_ts: i64,
+) -> Instruction {
+    let (escrow, _)  = find_escrow_address(program_id, initializer, id);
+    let (vault, _)   = find_vault_address(program_id, &escrow);
+    let (claimed, _) = find_claimed_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::InitializeDistribution { total, merkle_root, num_claims, expiry_ts },
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_registry_address(program_id, initializer).0, false),
+            AccountMeta::new(claimed, false),
+        ],
+    )
+}
+
+/// Builds a `ClaimDistribution` instruction signed by `claimant`.
+#[allow(clippy::too_many_arguments)]
+pub fn claim_distribution(
+    program_id: &Pubkey,
+    claimant: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+    index: u32,
+    amount: u64,
+    proof: Vec<[u8; 32]>,
+) -> Instruction {
+    let (escrow, _)  = find_escrow_address(program_id, initializer, id);
+    let (vault, _)   = find_vault_address(program_id, &escrow);
+    let (claimed, _) = find_claimed_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ClaimDistribution { index, amount, proof },
+        vec![
+            AccountMeta::new(*claimant, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(claimed, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds a `Withdraw` instruction for a referred escrow, paying `referrer`
+/// its share of the fee.
+pub fn withdraw_referred(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    treasury: &Pubkey,
+    referrer: &Pubkey,
+    id: u64,
+) -> Instruction {
+    withdraw_to(program_id, initializer, taker, treasury, referrer, id)
+}
+
+/// Builds a `SetSplits` instruction; both parties must sign.
+pub fn set_splits(program_id: &Pubkey, initializer: &Pubkey, taker: &Pubkey, id: u64, splits: Vec<Split>) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetSplits { splits },
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `Withdraw` instruction for a split escrow, paying `recipients`,
+/// which must match its splits in order.
+pub fn withdraw_split(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+    recipients: &[Pubkey],
+) -> Instruction {
+    let mut ix = withdraw(program_id, initializer, taker, treasury, id);
+    ix.accounts.extend(recipients.iter().map(|r| AccountMeta::new(*r, false)));
+    ix
+}
+
+/// Builds a `Resolve` instruction for a split escrow; a ruling for the taker
+/// pays `recipients`, which must match its splits in order.
+pub fn resolve_split(
+    program_id: &Pubkey,
+    arbiter: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    release_to_taker: bool,
+    recipients: &[Pubkey],
+) -> Instruction {
+    let mut ix = resolve(program_id, arbiter, initializer, taker, id, release_to_taker);
+    ix.accounts.extend(recipients.iter().map(|r| AccountMeta::new(*r, false)));
+    ix
+}
+
+/// Builds a `MigrateState` instruction for `escrow`, paid by `payer`.
+pub fn migrate_state(program_id: &Pubkey, payer: &Pubkey, escrow: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::MigrateState {},
+        vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `GetState` instruction for `escrow`; it needs no signer.
+pub fn get_state(program_id: &Pubkey, escrow: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::GetState {},
+        vec![AccountMeta::new_readonly(*escrow, false)],
+    )
+}
+
+/// Builds a `Withdraw` instruction for a crowdfund that reached its goal;
+/// only the taker signs.
+pub fn withdraw_crowdfund(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = withdraw(program_id, initializer, taker, treasury, id);
+    ix.accounts[0].is_signer = false;
+    ix
+}
+
+/// Builds a `Deposit` instruction signed by the initializer.
+pub fn deposit(program_id: &Pubkey, initializer: &Pubkey, taker: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::Deposit {},
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new_readonly(*taker, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Builds a `DepositAdditional` instruction adding `amount` lamports towards the total.
+pub fn deposit_additional(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    amount: u64,
+) -> Instruction {
+    let mut ix = deposit(program_id, initializer, taker, id);
+    ix.data = borsh::to_vec(&EscrowInstruction::DepositAdditional { amount }).unwrap();
+    ix
+}
+
+/// Builds a `Deposit` instruction paid and signed by `funder` on the
+/// initializer's behalf; the initializer does not sign.
+pub fn deposit_from(
+    program_id: &Pubkey,
+    funder: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+) -> Instruction {
+    with_funder(funder, deposit(program_id, initializer, taker, id))
+}
+
+/// Builds a `DepositAdditional` instruction paid and signed by `funder`.
+pub fn deposit_additional_from(
+    program_id: &Pubkey,
+    funder: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    amount: u64,
+) -> Instruction {
+    with_funder(funder, deposit_additional(program_id, initializer, taker, id, amount))
+}
+
+/// Builds a `Withdraw` instruction; both parties must sign the transaction.
+/// `treasury` must be the one recorded in the config and receives the fee.
+pub fn withdraw(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::Withdraw {},
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds a `Capture` instruction taking `amount` of an authorization;
+/// only the taker signs.
+pub fn capture(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+    amount: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::Capture { amount },
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(*initializer, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds a `Withdraw` instruction in which the payer of an invoice
+/// releases it alone.
+pub fn release_invoice(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = withdraw(program_id, initializer, taker, treasury, id);
+    ix.accounts[1].is_signer = false;
+    ix
+}
+
+/// Builds a `WithdrawPartial` instruction for a native escrow; both parties must sign.
+pub fn withdraw_partial(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+    amount: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::WithdrawPartial { amount },
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds a `WithdrawPartial` instruction for a token escrow paying `taker_token`.
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_partial_token(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    treasury: &Pubkey,
+    taker_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+    amount: u64,
+) -> Instruction {
+    let mut ix = withdraw_partial(program_id, initializer, taker, treasury, id, amount);
+    ix.accounts.extend([
+        AccountMeta::new(*taker_token, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+    ]);
+    ix
+}
+
+/// Builds a `WithdrawVested` instruction; only the taker signs.
+pub fn withdraw_vested(program_id: &Pubkey, taker: &Pubkey, initializer: &Pubkey, treasury: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::WithdrawVested {},
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds a `WithdrawSubscription` instruction; `caller` signs and the
+/// tranches go to `taker`.
+pub fn withdraw_subscription(
+    program_id: &Pubkey,
+    caller: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    treasury: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::WithdrawSubscription {},
+        vec![
+            AccountMeta::new_readonly(*caller, true),
+            AccountMeta::new(*taker, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(*treasury, false),
+        ],
+    )
+}
+
+/// Builds a `MutualCancel` instruction for a native escrow; both parties must sign.
+pub fn mutual_cancel(program_id: &Pubkey, initializer: &Pubkey, taker: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::MutualCancel {},
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(find_registry_address(program_id, initializer).0, false),
+        ],
+    )
+}
+
+/// Builds a `MutualCancel` instruction for a token escrow, refunding to `initializer_token`.
+pub fn mutual_cancel_token(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    initializer_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = mutual_cancel(program_id, initializer, taker, id);
+    ix.accounts.extend([
+        AccountMeta::new(*initializer_token, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+    ]);
+    ix
+}
+
+/// Builds a `Cancel` instruction signed by the initializer.
+pub fn cancel(program_id: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let ix = with_vault(program_id, initializer_only(program_id, initializer, id, &EscrowInstruction::Cancel {}));
+    with_registry(program_id, ix)
+}
+
+/// Builds a `Refund` instruction signed by the initializer.
+pub fn refund(program_id: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    with_vault(program_id, initializer_only(program_id, initializer, id, &EscrowInstruction::Refund {}))
+}
+
+/// Builds a `CrankRefund` instruction signed by `cranker`.
+pub fn crank_refund(program_id: &Pubkey, cranker: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let (config, _) = find_config_address(program_id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::CrankRefund {},
+        vec![
+            AccountMeta::new(*cranker, true),
+            AccountMeta::new(*initializer, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(config, false),
+        ],
+    )
+}
+
+/// Builds a `Close` instruction for a native escrow, signed by the initializer.
+pub fn close(program_id: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let ix = with_vault(program_id, initializer_only(program_id, initializer, id, &EscrowInstruction::Close {}));
+    with_registry(program_id, ix)
+}
+
+/// Builds a `Close` instruction for a token escrow, also closing its vault.
+pub fn close_token(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = close(program_id, initializer, id);
+    ix.accounts.push(AccountMeta::new_readonly(*token_program, false));
+    ix
+}
+
+/// Builds a `Cancel` instruction for a distribution, also closing its claimed bitmap.
+pub fn cancel_distribution(program_id: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    with_claimed(program_id, cancel(program_id, initializer, id))
+}
+
+/// Builds a `Close` instruction for a distribution, also closing its claimed bitmap.
+pub fn close_distribution(program_id: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    with_claimed(program_id, close(program_id, initializer, id))
+}
+
+/// Builds an `InitializeToken` instruction; both parties must sign.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_token(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+    id: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let mut accounts = vec![
+        AccountMeta::new(*initializer, true),
+        AccountMeta::new_readonly(*taker, *taker != Pubkey::default()),
+        AccountMeta::new(escrow, false),
+        AccountMeta::new(vault, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        AccountMeta::new(find_registry_address(program_id, initializer).0, false),
+    ];
+    if let Some(arbiter) = arbiter {
+        accounts.push(AccountMeta::new_readonly(*arbiter, false));
+    }
+    let ix = Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::InitializeToken { amount, expiry_ts },
+        accounts,
+    );
+    with_features(program_id, ix)
+}
+
+/// Builds an `InitializeNft` instruction escrowing the single token of `mint`,
+/// which must be a verified member of `collection`.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_nft(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    collection: &Pubkey,
+    id: u64,
+    expiry_ts: i64,
+    arbiter: Option<&Pubkey>,
+) -> Instruction {
+    let mut ix = initialize_token(
+        program_id, initializer, taker, mint, token_program, 1, id, expiry_ts, arbiter,
+    );
+    ix.data = borsh::to_vec(&EscrowInstruction::InitializeNft {
+        expiry_ts,
+        collection: *collection,
+    })
+    .unwrap();
+    ix
+}
+
+/// Builds a `DepositNft` instruction; same accounts as `DepositToken` plus the
+/// mint's Metaplex metadata account.
+pub fn deposit_nft(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    initializer_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = deposit_token(program_id, initializer, taker, initializer_token, mint, token_program, id);
+    ix.accounts.push(AccountMeta::new_readonly(find_metadata_address(mint).0, false));
+    ix.data = borsh::to_vec(&EscrowInstruction::DepositNft {}).unwrap();
+    ix
+}
+
+/// Builds an `InitializeSwap` instruction: the initializer escrows `amount` of
+/// `mint` (funded afterwards with `deposit_token`) against `taker_amount` of
+/// `taker_mint` from the taker.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_swap(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+    taker_mint: &Pubkey,
+    taker_amount: u64,
+    id: u64,
+    expiry_ts: i64,
+) -> Instruction {
+    let mut ix = initialize_token(
+        program_id, initializer, taker, mint, token_program, amount, id, expiry_ts, None,
+    );
+    ix.data = borsh::to_vec(&EscrowInstruction::InitializeSwap {
+        amount,
+        expiry_ts,
+        taker_mint: *taker_mint,
+        taker_amount,
+    })
+    .unwrap();
+    ix
+}
+
+/// Builds a `DepositSwap` instruction moving the taker's leg from `taker_token`.
+pub fn deposit_swap(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    taker_token: &Pubkey,
+    taker_mint: &Pubkey,
+    taker_token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _)      = find_escrow_address(program_id, initializer, id);
+    let (taker_vault, _) = find_taker_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::DepositSwap {},
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(*taker_token, false),
+            AccountMeta::new(taker_vault, false),
+            AccountMeta::new_readonly(*taker_mint, false),
+            AccountMeta::new_readonly(*taker_token_program, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Builds an `ExecuteSwap` instruction signed by `signer`, which must be the
+/// initializer or the taker. `initializer_receive` holds `taker_mint`,
+/// `taker_receive` holds `mint`.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_swap(
+    program_id: &Pubkey,
+    signer: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    initializer_receive: &Pubkey,
+    taker_receive: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    taker_mint: &Pubkey,
+    taker_token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _)      = find_escrow_address(program_id, initializer, id);
+    let (vault, _)       = find_vault_address(program_id, &escrow);
+    let (taker_vault, _) = find_taker_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ExecuteSwap {},
+        vec![
+            AccountMeta::new_readonly(*initializer, initializer == signer),
+            AccountMeta::new(*taker, taker == signer),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(taker_vault, false),
+            AccountMeta::new(*initializer_receive, false),
+            AccountMeta::new(*taker_receive, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(*taker_mint, false),
+            AccountMeta::new_readonly(*taker_token_program, false),
+        ],
+    )
+}
+
+/// Builds a `ReclaimSwap` instruction returning the taker's leg to `taker_token`.
+pub fn reclaim_swap(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    initializer: &Pubkey,
+    taker_token: &Pubkey,
+    taker_mint: &Pubkey,
+    taker_token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _)      = find_escrow_address(program_id, initializer, id);
+    let (taker_vault, _) = find_taker_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ReclaimSwap {},
+        vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(taker_vault, false),
+            AccountMeta::new(*taker_token, false),
+            AccountMeta::new_readonly(*taker_mint, false),
+            AccountMeta::new_readonly(*taker_token_program, false),
+        ],
+    )
+}
+
+/// Builds an `AcceptEscrow` instruction claiming an open escrow for `taker`.
+pub fn accept_escrow(program_id: &Pubkey, taker: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::AcceptEscrow {},
+        vec![
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds a `Dispute` instruction signed by either party.
+pub fn dispute(program_id: &Pubkey, party: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::Dispute {},
+        vec![
+            AccountMeta::new_readonly(*party, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds a `Resolve` instruction for a native escrow, signed by the arbiter.
+pub fn resolve(
+    program_id: &Pubkey,
+    arbiter: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    release_to_taker: bool,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::Resolve { release_to_taker },
+        vec![
+            AccountMeta::new(*arbiter, true),
+            AccountMeta::new(*initializer, false),
+            AccountMeta::new(*taker, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+        ],
+    )
+}
+
+/// Builds a `ResolvePartial` instruction for a native escrow, signed by the
+/// arbiter.
+pub fn resolve_partial(
+    program_id: &Pubkey,
+    arbiter: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    taker_bps: u16,
+) -> Instruction {
+    let mut ix = resolve(program_id, arbiter, initializer, taker, id, true);
+    ix.data = borsh::to_vec(&EscrowInstruction::ResolvePartial { taker_bps }).unwrap();
+    ix
+}
+
+/// Builds an `Appeal` instruction signed by `party`.
+pub fn appeal(program_id: &Pubkey, party: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::Appeal {},
+        vec![
+            AccountMeta::new_readonly(*party, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds a `FinalizeRuling` instruction for a native escrow; for a split
+/// one, append its recipients in order.
+pub fn finalize_ruling(
+    program_id: &Pubkey,
+    arbiter: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = resolve(program_id, arbiter, initializer, taker, id, true);
+    ix.accounts[0].is_signer = false;
+    ix.data = borsh::to_vec(&EscrowInstruction::FinalizeRuling {}).unwrap();
+    ix
+}
+
+/// Builds a `Resolve` instruction for a token escrow. `recipient_token` must
+/// belong to the taker when `release_to_taker`, otherwise to the initializer;
+/// `arbiter_token` must belong to the arbiter and receives its fee.
+#[allow(clippy::too_many_arguments)]
+pub fn resolve_token(
+    program_id: &Pubkey,
+    arbiter: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    recipient_token: &Pubkey,
+    arbiter_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+    release_to_taker: bool,
+) -> Instruction {
+    let mut ix = resolve(program_id, arbiter, initializer, taker, id, release_to_taker);
+    ix.accounts.extend(token_ruling_accounts(recipient_token, arbiter_token, mint, token_program));
+    ix
+}
+
+/// Builds a `FinalizeRuling` instruction for a token escrow, with the same
+/// token accounts as the `resolve_token` it carries out.
+#[allow(clippy::too_many_arguments)]
+pub fn finalize_ruling_token(
+    program_id: &Pubkey,
+    arbiter: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    recipient_token: &Pubkey,
+    arbiter_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let mut ix = finalize_ruling(program_id, arbiter, initializer, taker, id);
+    ix.accounts.extend(token_ruling_accounts(recipient_token, arbiter_token, mint, token_program));
+    ix
+}
+
+fn token_ruling_accounts(
+    recipient_token: &Pubkey,
+    arbiter_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> [AccountMeta; 4] {
+    [
+        AccountMeta::new(*recipient_token, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new(*arbiter_token, false),
+    ]
+}
+
+/// Builds a `DepositToken` instruction moving tokens from `initializer_token`.
+pub fn deposit_token(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    initializer_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::DepositToken {},
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(*initializer_token, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Builds a `WithdrawToken` instruction paying out to `taker_token`.
+pub fn withdraw_token(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    taker_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::WithdrawToken {},
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(*taker_token, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+    )
+}
+
+/// Builds a `WithdrawToken` instruction paying out to the taker's associated
+/// token account, which the taker pays to create if it doesn't exist.
+pub fn withdraw_token_to_ata(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let taker_token = find_associated_token_address(taker, mint, token_program);
+    let mut ix = withdraw_token(program_id, initializer, taker, &taker_token, mint, token_program, id);
+    ix.accounts[1].is_writable = true;
+    ix.accounts.extend([
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(ASSOCIATED_TOKEN_PROGRAM_ID, false),
+    ]);
+    ix
+}
+
+/// Builds an `InitializeConfig` instruction; `admin` signs and pays for the
+/// config account.
+pub fn initialize_config(program_id: &Pubkey, admin: &Pubkey, fee_bps: u16, treasury: &Pubkey) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    let ix = Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::InitializeConfig { fee_bps, treasury: *treasury },
+        vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(config, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    with_features(program_id, ix)
+}
+
+/// Builds an `UpdateConfig` instruction signed by the current `admin`,
+/// replacing every setting (pass the same `new_admin` to keep the admin).
+#[allow(clippy::too_many_arguments)]
+pub fn update_config(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    new_admin: &Pubkey,
+    fee_bps: u16,
+    treasury: &Pubkey,
+    paused: bool,
+    max_expiry_horizon: i64,
+    referral_bps: u16,
+    crank_bounty_bps: u16,
+    require_deposit: bool,
+    allowed_caller: &Pubkey,
+    yield_beneficiary: &Pubkey,
+    min_amount: u64,
+    max_amount: u64,
+    creation_fee: u64,
+    max_escrows_per_epoch: u32,
+    arbiter_fee: u64,
+    arbiter_fee_bps: u16,
+    appeal_window_secs: i64,
+    appeal_arbiter: &Pubkey,
+) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    let ix = Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::UpdateConfig {
+            admin: *new_admin,
+            fee_bps,
+            treasury: *treasury,
+            paused,
+            max_expiry_horizon,
+            referral_bps,
+            crank_bounty_bps,
+            require_deposit,
+            allowed_caller: *allowed_caller,
+            yield_beneficiary: *yield_beneficiary,
+            min_amount,
+            max_amount,
+            creation_fee,
+            max_escrows_per_epoch,
+            arbiter_fee,
+            arbiter_fee_bps,
+            appeal_window_secs,
+            appeal_arbiter: *appeal_arbiter,
+        },
+        vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(config, false),
+        ],
+    );
+    with_features(program_id, ix)
+}
+
+/// Builds an `InitializeBatch` instruction creating an escrow for each
+/// `(taker, amount, id)`; the initializer and every taker must sign.
+pub fn initialize_batch(program_id: &Pubkey, initializer: &Pubkey, items: Vec<(Pubkey, u64, u64)>) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*initializer, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        AccountMeta::new(find_registry_address(program_id, initializer).0, false),
+    ];
+    for (taker, _, id) in &items {
+        let (escrow, _) = find_escrow_address(program_id, initializer, *id);
+        let (vault, _)  = find_vault_address(program_id, &escrow);
+        accounts.extend([
+            AccountMeta::new_readonly(*taker, *taker != Pubkey::default()),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+        ]);
+    }
+    Instruction::new_with_borsh(*program_id, &EscrowInstruction::InitializeBatch { items }, accounts)
+}
+
+/// Builds a `WithdrawBatch` instruction paying `taker` the escrows with the
+/// given `(initializer, id)`; the taker and every initializer must sign.
+pub fn withdraw_batch(program_id: &Pubkey, taker: &Pubkey, treasury: &Pubkey, escrows: &[(Pubkey, u64)]) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*taker, true),
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        AccountMeta::new(*treasury, false),
+    ];
+    for (initializer, id) in escrows {
+        let (escrow, _) = find_escrow_address(program_id, initializer, *id);
+        let (vault, _)  = find_vault_address(program_id, &escrow);
+        accounts.extend([
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+        ]);
+    }
+    Instruction::new_with_borsh(*program_id, &EscrowInstruction::WithdrawBatch {}, accounts)
+}
+
+/// Builds a `DepositSol` instruction funding a wrapped SOL escrow with the
+/// initializer's lamports.
+pub fn deposit_sol(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::DepositSol {},
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new_readonly(*taker, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(native_mint(token_program), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `WithdrawSol` instruction unwrapping a wrapped SOL escrow to
+/// the taker.
+pub fn withdraw_sol(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::WithdrawSol {},
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(native_mint(token_program), false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+    )
+}
+
+/// Builds an `InitializeStake` instruction escrowing `stake_account`, of which
+/// `initializer` is the staker and withdrawer; both parties must sign.
+pub fn initialize_stake(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    stake_account: &Pubkey,
+    id: u64,
+    expiry_ts: i64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::InitializeStake { expiry_ts },
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new_readonly(*taker, *taker != Pubkey::default()),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(*stake_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_registry_address(program_id, initializer).0, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(stake::program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `WithdrawStake` instruction handing `stake_account` to the taker.
+pub fn withdraw_stake(program_id: &Pubkey, initializer: &Pubkey, taker: &Pubkey, stake_account: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::WithdrawStake {},
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(*stake_account, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(stake::program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `ReclaimStake` instruction handing `stake_account` back to the initializer.
+pub fn reclaim_stake(program_id: &Pubkey, initializer: &Pubkey, stake_account: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ReclaimStake {},
+        vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(*stake_account, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(stake::program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `DepositStaked` instruction staking the escrow's `amount`
+/// lamports into `pool`.
+pub fn deposit_staked(program_id: &Pubkey, initializer: &Pubkey, taker: &Pubkey, pool: &StakePool, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let mut accounts = vec![
+        AccountMeta::new(*initializer, true),
+        AccountMeta::new_readonly(*taker, false),
+        AccountMeta::new(escrow, false),
+        AccountMeta::new(vault, false),
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    accounts.extend(stake_pool_accounts(pool));
+    Instruction::new_with_borsh(*program_id, &EscrowInstruction::DepositStaked {}, accounts)
+}
+
+/// Builds a `WithdrawStaked` instruction paying the taker out of `pool`,
+/// and the yield to `beneficiary`.
+pub fn withdraw_staked(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    beneficiary: &Pubkey,
+    pool: &StakePool,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*initializer, true),
+        AccountMeta::new(*taker, true),
+        AccountMeta::new(escrow, false),
+        AccountMeta::new(vault, false),
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        AccountMeta::new(*beneficiary, false),
+    ];
+    accounts.extend(stake_pool_accounts(pool));
+    accounts.extend(stake_pool_sysvars());
+    Instruction::new_with_borsh(*program_id, &EscrowInstruction::WithdrawStaked {}, accounts)
+}
+
+/// Builds a `ReclaimStaked` instruction paying the initializer out of
+/// `pool`, and the yield to `beneficiary`.
+pub fn reclaim_staked(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    beneficiary: &Pubkey,
+    pool: &StakePool,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let mut accounts = vec![
+        AccountMeta::new(*initializer, true),
+        AccountMeta::new(escrow, false),
+        AccountMeta::new(vault, false),
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        AccountMeta::new(*beneficiary, false),
+    ];
+    accounts.extend(stake_pool_accounts(pool));
+    accounts.extend(stake_pool_sysvars());
+    Instruction::new_with_borsh(*program_id, &EscrowInstruction::ReclaimStaked {}, accounts)
+}
+
+fn stake_pool_accounts(pool: &StakePool) -> [AccountMeta; 7] {
+    [
+        AccountMeta::new_readonly(STAKE_POOL_PROGRAM_ID, false),
+        AccountMeta::new(pool.address, false),
+        AccountMeta::new_readonly(pool.withdraw_authority(), false),
+        AccountMeta::new(pool.reserve_stake, false),
+        AccountMeta::new(pool.manager_fee_account, false),
+        AccountMeta::new(pool.pool_mint, false),
+        AccountMeta::new_readonly(pool.token_program, false),
+    ]
+}
+
+fn stake_pool_sysvars() -> [AccountMeta; 3] {
+    [
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sysvar::stake_history::id(), false),
+        AccountMeta::new_readonly(stake::program::id(), false),
+    ]
+}
+
+/// The wrapped SOL mint of `token_program`.
+pub fn native_mint(token_program: &Pubkey) -> Pubkey {
+    if *token_program == spl_token_2022::id() {
+        spl_token_2022::native_mint::id()
+    } else {
+        SPL_TOKEN_NATIVE_MINT
+    }
+}
+
+fn create_escrow(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    id: u64,
+    arbiter: Option<&Pubkey>,
+    instruction: &EscrowInstruction,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    let mut accounts = vec![
+        AccountMeta::new(*initializer, true),
+        AccountMeta::new_readonly(*taker, *taker != Pubkey::default()),
+        AccountMeta::new(escrow, false),
+        AccountMeta::new(vault, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        AccountMeta::new(find_registry_address(program_id, initializer).0, false),
+    ];
+    if let Some(arbiter) = arbiter {
+        accounts.push(AccountMeta::new_readonly(*arbiter, false));
+    }
+    Instruction::new_with_borsh(*program_id, instruction, accounts)
+}
+
+fn initializer_only(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    id: u64,
+    instruction: &EscrowInstruction,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        instruction,
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Appends the Instructions sysvar that strict mode needs to an Initialize
+/// (or HTLC, vesting or milestone Initialize) instruction, padding its
+/// optional arbiter and referrer with the default pubkey, or to an
+/// InitializeBatch instruction. Every escrow it creates must then be funded
+/// by a later Deposit in the same transaction.
+pub fn with_deposit_check(mut ix: Instruction) -> Instruction {
+    if !matches!(EscrowInstruction::try_from_slice(&ix.data), Ok(EscrowInstruction::InitializeBatch { .. })) {
+        ix.accounts.resize(9, AccountMeta::new_readonly(Pubkey::default(), false));
+    }
+    ix.accounts.push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+    ix
+}
+
+/// Moves the rent of an Initialize (or HTLC, vesting, milestone or
+/// crowdfund Initialize) instruction to `payer`, padding its optional
+/// accounts with the default pubkey; the initializer still signs. Apply it
+/// after `with_deposit_check`.
+pub fn with_payer(payer: &Pubkey, mut ix: Instruction) -> Instruction {
+    ix.accounts.resize(10, AccountMeta::new_readonly(Pubkey::default(), false));
+    ix.accounts.push(AccountMeta::new(*payer, true));
+    ix
+}
+
+/// Makes an Initialize (or HTLC, vesting, milestone or crowdfund
+/// Initialize) instruction create its escrow from the template registered
+/// under `name`, padding its optional accounts with the default pubkey.
+/// Apply it after `with_payer`.
+pub fn with_template(program_id: &Pubkey, name: &[u8; 32], mut ix: Instruction) -> Instruction {
+    let (template, _) = find_template_address(program_id, name);
+    ix.accounts.resize(11, AccountMeta::new_readonly(Pubkey::default(), false));
+    ix.accounts.push(AccountMeta::new_readonly(template, false));
+    ix
+}
+
+/// Drops the taker's signature from an Initialize variant built for a named
+/// taker, who then consents later with AcceptEscrow or by signing for the
+/// funds.
+pub fn without_taker_signature(mut ix: Instruction) -> Instruction {
+    ix.accounts[1].is_signer = false;
+    ix
+}
+
+/// Appends the stats PDAs of `parties`, whose counters then record the
+/// escrows of theirs the instruction settles; parties left out, or whose
+/// stats aren't created yet, go uncounted. Apply it before
+/// `with_event_cpi` and `with_caller_accounts`.
+pub fn with_stats(program_id: &Pubkey, mut ix: Instruction, parties: &[Pubkey]) -> Instruction {
+    ix.accounts.extend(
+        parties.iter().map(|party| AccountMeta::new(find_stats_address(program_id, party).0, false)),
+    );
+    ix
+}
+
+/// Appends the SPL Memo program to a Withdraw or Refund, which then logs
+/// the escrow and its outcome as a memo for systems that attribute
+/// transfers by memo. Apply it after `with_stats` and before
+/// `with_event_cpi` and `with_caller_accounts`.
+pub fn with_memo(mut ix: Instruction) -> Instruction {
+    ix.accounts.push(AccountMeta::new_readonly(MEMO_PROGRAM_ID, false));
+    ix
+}
+
+/// Appends the feature flags PDA, which instructions with
+/// `required_features` need; their builders already do it. A release that
+/// charges a protocol fee, and an Initialize variant while the config
+/// requires a deposit, need it too. Apply it after `with_deposit_check`,
+/// `with_payer` and `with_template`, and before `with_stats`,
+/// `with_event_cpi` and `with_caller_accounts`.
+pub fn with_features(program_id: &Pubkey, mut ix: Instruction) -> Instruction {
+    ix.accounts.push(AccountMeta::new_readonly(find_features_address(program_id).0, false));
+    ix
+}
+
+/// Appends the config and `treasury` to a Cancel, CancelInvoice,
+/// MutualCancel or Close of an escrow that put up a creation fee, which goes
+/// to the treasury unless the escrow was released. Apply it before
+/// `with_stats`, `with_event_cpi` and `with_caller_accounts`.
+pub fn with_creation_fee(program_id: &Pubkey, mut ix: Instruction, treasury: &Pubkey) -> Instruction {
+    ix.accounts.extend([
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        AccountMeta::new(*treasury, false),
+    ]);
+    ix
+}
+
+/// Appends the event authority and the program itself to any instruction,
+/// so the events it emits are also recorded through an EmitEvent self-CPI.
+pub fn with_event_cpi(program_id: &Pubkey, mut ix: Instruction) -> Instruction {
+    let (event_authority, _) = find_event_authority_address(program_id);
+    ix.accounts.push(AccountMeta::new_readonly(event_authority, false));
+    ix.accounts.push(AccountMeta::new_readonly(*program_id, false));
+    ix
+}
+
+/// Appends the config and the Instructions sysvar, which every instruction
+/// but EmitEvent and the admin ones needs when invoked through CPI, so the
+/// program can check the caller against the config's `allowed_caller`.
+/// Apply it after `with_event_cpi`.
+pub fn with_caller_accounts(program_id: &Pubkey, mut ix: Instruction) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    ix.accounts.push(AccountMeta::new_readonly(config, false));
+    ix.accounts.push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+    ix
+}
+
+/// Moves the signature and payment of a native deposit to `funder`.
+fn with_funder(funder: &Pubkey, mut ix: Instruction) -> Instruction {
+    ix.accounts[0].is_signer = false;
+    ix.accounts.push(AccountMeta::new(*funder, true));
+    ix
+}
+
+/// Appends the initializer's registry, for paths that close the escrow.
+fn with_registry(program_id: &Pubkey, mut ix: Instruction) -> Instruction {
+    let (registry, _) = find_registry_address(program_id, &ix.accounts[0].pubkey);
+    ix.accounts.push(AccountMeta::new(registry, false));
+    ix
+}
+
+/// Appends the escrow's claimed bitmap after the `[initializer, escrow]` accounts.
+fn with_claimed(program_id: &Pubkey, mut ix: Instruction) -> Instruction {
+    let (claimed, _) = find_claimed_address(program_id, &ix.accounts[1].pubkey);
+    ix.accounts.push(AccountMeta::new(claimed, false));
+    ix
+}
+
+/// Appends the escrow's vault after the `[initializer, escrow]` accounts.
+fn with_vault(program_id: &Pubkey, mut ix: Instruction) -> Instruction {
+    let (vault, _) = find_vault_address(program_id, &ix.accounts[1].pubkey);
+    ix.accounts.push(AccountMeta::new(vault, false));
+    ix
+}
+
+/// Builds an `InitializeAuction` instruction selling `amount` tokens of
+/// `mint` on the `auction` price schedule.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_auction(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+    id: u64,
+    auction: Auction,
+) -> Instruction {
+    let mut ix = initialize_token(program_id, initializer, taker, mint, token_program, amount, id, 0, None);
+    ix.data = borsh::to_vec(&EscrowInstruction::InitializeAuction { amount, auction }).unwrap();
+    ix
+}
+
+/// Builds a `BuyAuction` instruction paying for escrow `id` of `initializer`
+/// and receiving its tokens into `buyer_token`.
+pub fn buy_auction(
+    program_id: &Pubkey,
+    buyer: &Pubkey,
+    initializer: &Pubkey,
+    buyer_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::BuyAuction {},
+        vec![
+            AccountMeta::new(*buyer, true),
+            AccountMeta::new(*initializer, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(*buyer_token, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `CancelAuction` instruction returning the unsold tokens to
+/// `initializer_token`.
+pub fn cancel_auction(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    initializer_token: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    id: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::CancelAuction {},
+        vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(*initializer_token, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+    )
+}
+
+/// Builds a `ProposeTerms` instruction from `proposer`, either party of
+/// escrow `id` of `initializer`.
+pub fn propose_terms(
+    program_id: &Pubkey,
+    proposer: &Pubkey,
+    initializer: &Pubkey,
+    id: u64,
+    amount: u64,
+    expiry_ts: i64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ProposeTerms { amount, expiry_ts },
+        vec![
+            AccountMeta::new_readonly(*proposer, true),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Builds an `AcceptTerms` instruction from `accepter`, the party of escrow
+/// `id` who didn't propose the pending terms.
+pub fn accept_terms(program_id: &Pubkey, accepter: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::AcceptTerms {},
+        vec![AccountMeta::new_readonly(*accepter, true), AccountMeta::new(escrow, false)],
+    )
+}
+
+/// Builds an `InitializeStats` instruction creating the stats PDA of `party`.
+pub fn initialize_stats(program_id: &Pubkey, payer: &Pubkey, party: &Pubkey) -> Instruction {
+    let (stats, _) = find_stats_address(program_id, party);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::InitializeStats { party: *party },
+        vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(stats, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `SetTemplate` instruction registering `name`'s parameters,
+/// signed by the config admin.
+pub fn set_template(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    name: &[u8; 32],
+    fee_bps: u16,
+    dispute_window_secs: i64,
+    default_expiry_secs: i64,
+    arbiter: &Pubkey,
+) -> Instruction {
+    let (template, _) = find_template_address(program_id, name);
+    let ix = Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetTemplate {
+            name: *name,
+            fee_bps,
+            dispute_window_secs,
+            default_expiry_secs,
+            arbiter: *arbiter,
+        },
+        vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(template, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    with_features(program_id, ix)
+}
+
+/// Builds an `InitializeAuthorities` instruction signed by the config admin.
+pub fn initialize_authorities(program_id: &Pubkey, admin: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::InitializeAuthorities {},
+        vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_authorities_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `GrantRole` instruction giving `role` to `authority`.
+pub fn grant_role(program_id: &Pubkey, signer: &Pubkey, authority: &Pubkey, role: Role) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::GrantRole { authority: *authority, role },
+        vec![
+            AccountMeta::new(*signer, true),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_authorities_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `RevokeRole` instruction taking `role` from `authority`.
+pub fn revoke_role(program_id: &Pubkey, signer: &Pubkey, authority: &Pubkey, role: Role) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::RevokeRole { authority: *authority, role },
+        vec![
+            AccountMeta::new(*signer, true),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_authorities_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Builds a `SetPaused` instruction signed by the admin or a Pauser.
+pub fn set_paused(program_id: &Pubkey, signer: &Pubkey, paused: bool) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetPaused { paused },
+        role_gated_accounts(program_id, signer),
+    )
+}
+
+/// Builds a `SetFees` instruction signed by the admin or a FeeManager.
+pub fn set_fees(
+    program_id: &Pubkey,
+    signer: &Pubkey,
+    fee_bps: u16,
+    referral_bps: u16,
+    crank_bounty_bps: u16,
+) -> Instruction {
+    let ix = Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetFees { fee_bps, referral_bps, crank_bounty_bps },
+        role_gated_accounts(program_id, signer),
+    );
+    with_features(program_id, ix)
+}
+
+fn role_gated_accounts(program_id: &Pubkey, signer: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(*signer, true),
+        AccountMeta::new(find_config_address(program_id).0, false),
+        AccountMeta::new_readonly(find_authorities_address(program_id).0, false),
+    ]
+}
+
+/// Builds a `ProposeEmergencyWithdraw` instruction for escrow `id` of
+/// `initializer`, signed by the config admin.
+pub fn propose_emergency_withdraw(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    initializer: &Pubkey,
+    id: u64,
+    recipient: &Pubkey,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ProposeEmergencyWithdraw { recipient: *recipient },
+        vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds a `VetoEmergencyWithdraw` instruction signed by either party.
+pub fn veto_emergency_withdraw(program_id: &Pubkey, party: &Pubkey, initializer: &Pubkey, id: u64) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::VetoEmergencyWithdraw {},
+        vec![
+            AccountMeta::new_readonly(*party, true),
+            AccountMeta::new(escrow, false),
+        ],
+    )
+}
+
+/// Builds an `ExecuteEmergencyWithdraw` instruction paying `recipient`,
+/// who must be the one proposed.
+pub fn execute_emergency_withdraw(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    initializer: &Pubkey,
+    id: u64,
+    recipient: &Pubkey,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, initializer, id);
+    let (vault, _)  = find_vault_address(program_id, &escrow);
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::ExecuteEmergencyWithdraw {},
+        vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(*recipient, false),
+        ],
+    )
+}
+
+/// Builds an `InitializeRelayed` instruction creating escrow `id` of
+/// `initializer` at `payer`'s expense. Submit it right after an ed25519
+/// program instruction checking the initializer's signature over
+/// `state::relayed_initialize_message`.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_relayed(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    taker: &Pubkey,
+    payer: &Pubkey,
+    amount: u64,
+    id: u64,
+    expiry_ts: i64,
+    deadline: i64,
+) -> Instruction {
+    let mut ix = create_escrow(
+        program_id,
+        initializer,
+        taker,
+        id,
+        None,
+        &EscrowInstruction::InitializeRelayed { amount, expiry_ts, deadline },
+    );
+    ix.accounts[0] = AccountMeta::new_readonly(*initializer, false);
+    ix.accounts.push(AccountMeta::new(*payer, true));
+    ix.accounts.push(AccountMeta::new_readonly(sysvar::instructions::ID, false));
+    ix
+}
+
+/// Builds a `SetFeature` instruction signed by the config admin.
+pub fn set_feature(program_id: &Pubkey, admin: &Pubkey, feature: Feature, enabled: bool) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &EscrowInstruction::SetFeature { feature, enabled },
+        vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_features_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
@@ -0,0 +1,21 @@
+//! Minimal client of the SPL Memo program, enough to attach a settlement
+//! memo to the transaction that pays out an escrow.
+
+use solana_program::{instruction::Instruction, pubkey, pubkey::Pubkey};
+
+pub const MEMO_PROGRAM_ID: Pubkey = pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
+/// A memo of `text` with no signers, which the Memo program only logs.
+pub fn memo(text: &str) -> Instruction {
+    Instruction {
+        program_id: MEMO_PROGRAM_ID,
+        accounts:   Vec::new(),
+        data:       text.as_bytes().to_vec(),
+    }
+}
+
+/// The memo Withdraw and Refund log for an escrow: its address, id and
+/// `outcome`, for systems that attribute incoming transfers by memo.
+pub fn settlement_memo(escrow: &Pubkey, id: u64, outcome: &str) -> String {
+    format!("flo escrow {escrow} #{id} {outcome}")
+}
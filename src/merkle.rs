@@ -0,0 +1,25 @@
+//! Merkle proofs for distribution escrows. Leaves and inner nodes are
+//! SHA-256 hashes with distinct one-byte prefixes, so a node can never pass
+//! for a leaf, and pairs are hashed in sorted order, so proofs need no
+//! left/right flags.
+
+use solana_program::{hash::hashv, pubkey::Pubkey};
+
+/// Longest proof ClaimDistribution accepts, enough for 2^32 leaves.
+pub const MAX_PROOF_LEN: usize = 32;
+
+/// Leaf for `claimant`'s allocation of `amount` lamports at `index`.
+pub fn distribution_leaf(index: u32, claimant: &Pubkey, amount: u64) -> [u8; 32] {
+    hashv(&[&[0], &index.to_le_bytes(), claimant.as_ref(), &amount.to_le_bytes()]).to_bytes()
+}
+
+/// Parent of two nodes, in either order.
+pub fn hash_nodes(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    hashv(&[&[1], low, high]).to_bytes()
+}
+
+/// Whether `proof` leads from `leaf` up to `root`.
+pub fn verify(proof: &[[u8; 32]], root: &[u8; 32], leaf: [u8; 32]) -> bool {
+    proof.iter().fold(leaf, |node, sibling| hash_nodes(&node, sibling)) == *root
+}
@@ -0,0 +1,65 @@
+//! Minimal read-only view of Metaplex Token Metadata accounts, enough to
+//! check which mint and collection an NFT belongs to.
+
+use borsh::BorshDeserialize;
+use solana_program::{program_error::ProgramError, pubkey, pubkey::Pubkey};
+
+pub const METADATA_PROGRAM_ID: Pubkey = pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+const METADATA_SEED: &[u8]          = b"metadata";
+const KEY_METADATA_V1: u8           = 4;
+
+/// Derives the metadata PDA for `mint`.
+pub fn find_metadata_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[METADATA_SEED, METADATA_PROGRAM_ID.as_ref(), mint.as_ref()],
+        &METADATA_PROGRAM_ID,
+    )
+}
+
+#[derive(BorshDeserialize, Debug)]
+pub struct Creator {
+    pub address:  Pubkey,
+    pub verified: bool,
+    pub share:    u8,
+}
+
+#[derive(BorshDeserialize, Debug)]
+pub struct Collection {
+    pub verified: bool,
+    pub key:      Pubkey,
+}
+
+/// Leading fields of the on-chain `Metadata` account, up to `collection`.
+/// Later fields are ignored.
+#[derive(BorshDeserialize, Debug)]
+pub struct Metadata {
+    pub key:                     u8,
+    pub update_authority:        Pubkey,
+    pub mint:                    Pubkey,
+    pub name:                    String,
+    pub symbol:                  String,
+    pub uri:                     String,
+    pub seller_fee_basis_points: u16,
+    pub creators:                Option<Vec<Creator>>,
+    pub primary_sale_happened:   bool,
+    pub is_mutable:              bool,
+    pub edition_nonce:           Option<u8>,
+    pub token_standard:          Option<u8>,
+    pub collection:              Option<Collection>,
+}
+
+impl Metadata {
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let metadata = Self::deserialize(&mut &data[..])
+            .map_err(|_| ProgramError::InvalidAccountData)?;
+        if metadata.key != KEY_METADATA_V1 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(metadata)
+    }
+
+    /// True when the NFT is a verified member of `collection`.
+    pub fn in_collection(&self, collection: &Pubkey) -> bool {
+        matches!(&self.collection, Some(c) if c.verified && c.key == *collection)
+    }
+}
@@ -0,0 +1,5836 @@
+use std::{cell::RefMut, mem::offset_of};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT},
+    msg, program_error::ProgramError,
+    program::{invoke, invoke_signed, set_return_data, MAX_RETURN_DATA},
+    pubkey::Pubkey,
+    clock::Clock,
+    rent::Rent,
+    stake::{self, state::StakeAuthorize},
+    system_instruction, system_program,
+};
+use solana_program::{
+    ed25519_program,
+    hash::hash,
+    keccak,
+    secp256k1_recover::secp256k1_recover,
+    sysvar::{
+        self,
+        instructions::{load_current_index_checked, load_instruction_at_checked},
+        Sysvar,
+    },
+};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType,
+        StateWithExtensions,
+    },
+    state::{Account as TokenAccount, Mint},
+};
+
+use crate::{
+    associated_token::{create_idempotent, find_associated_token_address, ASSOCIATED_TOKEN_PROGRAM_ID},
+    error::EscrowError,
+    event::{EmergencyWithdrawProposed, EscrowCancelled, EscrowFunded, EscrowInitialized, EscrowReleased, Event, EventCpi},
+    instruction::EscrowInstruction,
+    memo::{self, settlement_memo, MEMO_PROGRAM_ID},
+    merkle,
+    metadata::{find_metadata_address, Metadata, METADATA_PROGRAM_ID},
+    pyth::{PriceUpdateV2, VerificationLevel, PYTH_RECEIVER_PROGRAM_ID},
+    stake_pool::{self, StakePool, STAKE_POOL_PROGRAM_ID},
+    state::{
+        eth_release_message, find_claimed_address, find_config_address, find_escrow_address,
+        find_authorities_address, find_event_authority_address, find_features_address, find_receipt_address,
+        find_registry_address, find_stats_address, find_taker_vault_address, find_template_address, find_vault_address, migrate_escrow_data, relayed_initialize_message,
+        vaa_release_payload, voucher_message,
+        Auction, ClaimedState, ConfigState, EscrowData, EscrowState, EscrowStatus, Milestone, ReceiptState, RegistryState, Split, StatsState,
+        AuthoritiesState, Feature, FeaturesState, Role, RoleGrant, Subscription, TemplateState, AUTHORITIES_DISCRIMINATOR, AUTHORITIES_PDA_SEED,
+        AUTHORITIES_STATE_LEN, MAX_AUTHORITIES, CLAIMED_DISCRIMINATOR, CLAIMED_PDA_SEED, CLAIMED_STATE_LEN,
+        CONFIG_DISCRIMINATOR, CONFIG_PDA_SEED, CONFIG_STATE_LEN, DEFAULT_FEATURES, DEFAULT_MAX_EXPIRY_HORIZON, DISPUTE_WINDOW_SECS, EMERGENCY_DELAY_SECS, ESCROW_DISCRIMINATOR,
+        ESCROW_PDA_SEED, ESCROW_STATE_LEN, ESCROW_STATE_LENS, ESCROW_STATE_VERSION, FEATURES_DISCRIMINATOR, FEATURES_PDA_SEED,
+        FEATURES_STATE_LEN, MAX_ALLOWED_TAKERS, MAX_BATCH_LEN, MAX_FEE_BPS, SPL_TOKEN_NATIVE_MINT, MAX_MILESTONES, MAX_PRICE_AGE_SECS, MILESTONE_LEN,
+        RECEIPT_DISCRIMINATOR, RECEIPT_PDA_SEED, RECEIPT_STATE_LEN, REGISTRY_DISCRIMINATOR, REGISTRY_PDA_SEED,
+        REGISTRY_STATE_LEN, REGISTRY_TAIL_LEN, SPLIT_LEN, STATS_DISCRIMINATOR, STATS_PDA_SEED, STATS_STATE_LEN,
+        TAKER_VAULT_PDA_SEED, TEMPLATE_DISCRIMINATOR, TEMPLATE_PDA_SEED, TEMPLATE_STATE_LEN, VAULT_PDA_SEED,
+    },
+    switchboard::{AggregatorAccountData, SWITCHBOARD_V2_PROGRAM_ID},
+    wormhole::{PostedVaa, WORMHOLE_CORE_BRIDGE_PROGRAM_ID},
+};
+
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    input: &[u8],
+) -> ProgramResult {
+    let instr              = EscrowInstruction::unpack(input)?;
+    let accounts           = split_caller_accounts(program_id, accounts, &instr)?;
+    let (accounts, events) = split_event_accounts(program_id, accounts);
+    let (accounts, memo)   = split_memo_account(accounts);
+    let (accounts, stats)  = split_stats_accounts(program_id, accounts)?;
+    let (accounts, features) = split_features_account(program_id, accounts, &instr)?;
+    let before             = if stats.is_empty() { Vec::new() } else { escrow_snapshots(program_id, accounts) };
+    dispatch(program_id, accounts, instr, events, memo, features)?;
+    record_stats(program_id, accounts, stats, &before)
+}
+
+fn dispatch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instr: EscrowInstruction,
+    events: Option<EventCpi>,
+    memo: Option<&AccountInfo>,
+    features: Features,
+) -> ProgramResult {
+    match instr {
+        EscrowInstruction::Initialize { amount, expiry_ts } => {
+            msg!("Initialize {} lamports", amount);
+            process_initialize(program_id, accounts, amount, expiry_ts, Vec::new(), true, 0, [0; 32], events, features)
+        }
+        EscrowInstruction::InitializeWithNonce { amount, expiry_ts, nonce } => {
+            msg!("InitializeWithNonce {} lamports, nonce {}", amount, nonce);
+            process_initialize(program_id, accounts, amount, expiry_ts, Vec::new(), true, nonce, [0; 32], events, features)
+        }
+        EscrowInstruction::InitializeWithReference { amount, expiry_ts, reference } => {
+            msg!("InitializeWithReference {} lamports", amount);
+            process_initialize(program_id, accounts, amount, expiry_ts, Vec::new(), true, 0, reference, events, features)
+        }
+        EscrowInstruction::Deposit {} => {
+            msg!("Deposit");
+            process_deposit_additional(program_id, accounts, None, events)
+        }
+        EscrowInstruction::Withdraw {} => {
+            msg!("Withdraw");
+            process_withdraw(program_id, accounts, events, memo, features)
+        }
+        EscrowInstruction::Cancel {} => {
+            msg!("Cancel");
+            process_cancel(program_id, accounts, events)
+        }
+        EscrowInstruction::InitializeToken { amount, expiry_ts } => {
+            msg!("InitializeToken {} tokens", amount);
+            process_initialize_token(program_id, accounts, amount, expiry_ts, None, events)
+        }
+        EscrowInstruction::DepositToken {} => {
+            msg!("DepositToken");
+            process_deposit_token(program_id, accounts, false, events)
+        }
+        EscrowInstruction::WithdrawToken {} => {
+            msg!("WithdrawToken");
+            process_withdraw_token(program_id, accounts, events)
+        }
+        EscrowInstruction::Refund {} => {
+            msg!("Refund");
+            process_refund(program_id, accounts, events, memo)
+        }
+        EscrowInstruction::Close {} => {
+            msg!("Close");
+            process_close(program_id, accounts)
+        }
+        EscrowInstruction::InitializeAndDeposit { amount, expiry_ts } => {
+            msg!("InitializeAndDeposit {} lamports", amount);
+            process_initialize_and_deposit(program_id, accounts, amount, expiry_ts, events)
+        }
+        EscrowInstruction::Dispute {} => {
+            msg!("Dispute");
+            process_dispute(program_id, accounts)
+        }
+        EscrowInstruction::Resolve { release_to_taker } => {
+            msg!("Resolve, release to taker: {}", release_to_taker);
+            process_resolve(program_id, accounts, release_to_taker, events)
+        }
+        EscrowInstruction::WithdrawPartial { amount } => {
+            msg!("WithdrawPartial {}", amount);
+            process_withdraw_partial(program_id, accounts, amount, events, features)
+        }
+        EscrowInstruction::DepositAdditional { amount } => {
+            msg!("DepositAdditional {} lamports", amount);
+            process_deposit_additional(program_id, accounts, Some(amount), events)
+        }
+        EscrowInstruction::AcceptEscrow {} => {
+            msg!("AcceptEscrow");
+            process_accept_escrow(program_id, accounts)
+        }
+        EscrowInstruction::MutualCancel {} => {
+            msg!("MutualCancel");
+            process_mutual_cancel(program_id, accounts, events)
+        }
+        EscrowInstruction::InitializeNft { expiry_ts, collection } => {
+            msg!("InitializeNft, collection {}", collection);
+            process_initialize_token(program_id, accounts, 1, expiry_ts, Some(collection), events)
+        }
+        EscrowInstruction::DepositNft {} => {
+            msg!("DepositNft");
+            process_deposit_token(program_id, accounts, true, events)
+        }
+        EscrowInstruction::InitializeSwap { amount, expiry_ts, taker_mint, taker_amount } => {
+            msg!("InitializeSwap {} for {} of {}", amount, taker_amount, taker_mint);
+            process_initialize_swap(program_id, accounts, amount, expiry_ts, taker_mint, taker_amount, events)
+        }
+        EscrowInstruction::DepositSwap {} => {
+            msg!("DepositSwap");
+            process_deposit_swap(program_id, accounts)
+        }
+        EscrowInstruction::ExecuteSwap {} => {
+            msg!("ExecuteSwap");
+            process_execute_swap(program_id, accounts, events)
+        }
+        EscrowInstruction::ReclaimSwap {} => {
+            msg!("ReclaimSwap");
+            process_reclaim_swap(program_id, accounts)
+        }
+        EscrowInstruction::InitializeConfig { fee_bps, treasury } => {
+            msg!("InitializeConfig, fee {} bps, treasury {}", fee_bps, treasury);
+            process_initialize_config(program_id, accounts, fee_bps, treasury)
+        }
+        EscrowInstruction::UpdateConfig {
+            admin,
+            fee_bps,
+            treasury,
+            paused,
+            max_expiry_horizon,
+            referral_bps,
+            crank_bounty_bps,
+            require_deposit,
+            allowed_caller,
+            yield_beneficiary,
+            min_amount,
+            max_amount,
+            creation_fee,
+            max_escrows_per_epoch,
+            arbiter_fee,
+            arbiter_fee_bps,
+            appeal_window_secs,
+            appeal_arbiter,
+        } => {
+            msg!("UpdateConfig, fee {} bps, paused: {}", fee_bps, paused);
+            process_update_config(
+                program_id, accounts, admin, fee_bps, treasury, paused, max_expiry_horizon, referral_bps, crank_bounty_bps,
+                require_deposit, allowed_caller, yield_beneficiary, min_amount, max_amount, creation_fee, max_escrows_per_epoch,
+                arbiter_fee, arbiter_fee_bps, appeal_window_secs, appeal_arbiter,
+            )
+        }
+        EscrowInstruction::InitializeHtlc { amount, timeout, hash_lock } => {
+            msg!("InitializeHtlc {} lamports, timeout {}", amount, timeout);
+            process_initialize_htlc(program_id, accounts, amount, timeout, hash_lock, events, features)
+        }
+        EscrowInstruction::Claim { preimage } => {
+            msg!("Claim");
+            process_claim(program_id, accounts, preimage, events, features)
+        }
+        EscrowInstruction::InitializeVesting { amount, start_ts, cliff_ts, end_ts } => {
+            msg!("InitializeVesting {} lamports, {}..{}", amount, start_ts, end_ts);
+            process_initialize_vesting(program_id, accounts, amount, start_ts, cliff_ts, end_ts, events, features)
+        }
+        EscrowInstruction::WithdrawVested {} => {
+            msg!("WithdrawVested");
+            process_withdraw_vested(program_id, accounts, events, features)
+        }
+        EscrowInstruction::InitializeMilestones { expiry_ts, milestones } => {
+            msg!("InitializeMilestones, {} milestones", milestones.len());
+            process_initialize_milestones(program_id, accounts, expiry_ts, milestones, events, features)
+        }
+        EscrowInstruction::ApproveMilestone { index } => {
+            msg!("ApproveMilestone {}", index);
+            process_approve_milestone(program_id, accounts, index)
+        }
+        EscrowInstruction::WithdrawMilestones {} => {
+            msg!("WithdrawMilestones");
+            process_withdraw_milestones(program_id, accounts, events, features)
+        }
+        EscrowInstruction::InitializeCrowdfund { goal, deadline } => {
+            msg!("InitializeCrowdfund {} lamports, deadline {}", goal, deadline);
+            process_initialize_crowdfund(program_id, accounts, goal, deadline, events, features)
+        }
+        EscrowInstruction::Contribute { amount } => {
+            msg!("Contribute {} lamports", amount);
+            process_contribute(program_id, accounts, amount, events)
+        }
+        EscrowInstruction::ClaimRefund {} => {
+            msg!("ClaimRefund");
+            process_claim_refund(program_id, accounts, events)
+        }
+        EscrowInstruction::SetPriceCondition { threshold, expo, above } => {
+            msg!("SetPriceCondition {}e{}, above {}", threshold, expo, above);
+            process_set_price_condition(program_id, accounts, threshold, expo, above)
+        }
+        EscrowInstruction::ClaimWithVoucher {} => {
+            msg!("ClaimWithVoucher");
+            process_claim_with_voucher(program_id, accounts, events, features)
+        }
+        EscrowInstruction::SetEthAuthority { eth_address } => {
+            msg!("SetEthAuthority");
+            process_set_eth_authority(program_id, accounts, eth_address)
+        }
+        EscrowInstruction::ClaimWithEthSignature { signature, recovery_id } => {
+            msg!("ClaimWithEthSignature");
+            process_claim_with_eth_signature(program_id, accounts, signature, recovery_id, events, features)
+        }
+        EscrowInstruction::SetWormholeEmitter { emitter_chain, emitter_address } => {
+            msg!("SetWormholeEmitter chain {}", emitter_chain);
+            process_set_wormhole_emitter(program_id, accounts, emitter_chain, emitter_address)
+        }
+        EscrowInstruction::ReleaseWithVaa {} => {
+            msg!("ReleaseWithVaa");
+            process_release_with_vaa(program_id, accounts, events, features)
+        }
+        EscrowInstruction::RequestWithdraw {} => {
+            msg!("RequestWithdraw");
+            process_request_withdraw(program_id, accounts)
+        }
+        EscrowInstruction::FinalizeWithdraw {} => {
+            msg!("FinalizeWithdraw");
+            process_finalize_withdraw(program_id, accounts, events, features)
+        }
+        EscrowInstruction::SetTakerBond { amount } => {
+            msg!("SetTakerBond {}", amount);
+            process_set_taker_bond(program_id, accounts, amount)
+        }
+        EscrowInstruction::DepositBond {} => {
+            msg!("DepositBond");
+            process_deposit_bond(program_id, accounts)
+        }
+        EscrowInstruction::SetTaker { new_taker } => {
+            msg!("SetTaker {}", new_taker);
+            process_set_taker(program_id, accounts, new_taker)
+        }
+        EscrowInstruction::UpdateAmount { new_amount } => {
+            msg!("UpdateAmount {}", new_amount);
+            process_update_amount(program_id, accounts, new_amount, events)
+        }
+        EscrowInstruction::ExtendDeadline { new_expiry } => {
+            msg!("ExtendDeadline to {}", new_expiry);
+            process_extend_deadline(program_id, accounts, new_expiry)
+        }
+        EscrowInstruction::SetAllowedTakers { takers } => {
+            msg!("SetAllowedTakers, {} members", takers.len());
+            process_set_allowed_takers(program_id, accounts, takers)
+        }
+        EscrowInstruction::ClaimAllowed {} => {
+            msg!("ClaimAllowed");
+            process_claim_allowed(program_id, accounts, events, features)
+        }
+        EscrowInstruction::InitializeDistribution { total, merkle_root, num_claims, expiry_ts } => {
+            msg!("InitializeDistribution {} lamports, {} claims", total, num_claims);
+            process_initialize_distribution(program_id, accounts, total, merkle_root, num_claims, expiry_ts, events)
+        }
+        EscrowInstruction::ClaimDistribution { index, amount, proof } => {
+            msg!("ClaimDistribution {} lamports at index {}", amount, index);
+            process_claim_distribution(program_id, accounts, index, amount, proof, events, features)
+        }
+        EscrowInstruction::SetSplits { splits } => {
+            msg!("SetSplits, {} recipients", splits.len());
+            process_set_splits(program_id, accounts, splits)
+        }
+        EscrowInstruction::MigrateState {} => {
+            msg!("MigrateState");
+            process_migrate_state(program_id, accounts)
+        }
+        EscrowInstruction::AddMilestone { amount } => {
+            msg!("AddMilestone {} lamports", amount);
+            process_add_milestone(program_id, accounts, amount)
+        }
+        EscrowInstruction::EmitEvent { .. } => process_emit_event(program_id, accounts),
+        EscrowInstruction::GetState {} => {
+            msg!("GetState");
+            process_get_state(program_id, accounts)
+        }
+        EscrowInstruction::CrankRefund {} => {
+            msg!("CrankRefund");
+            process_crank_refund(program_id, accounts, events)
+        }
+        EscrowInstruction::WithdrawBatch {} => {
+            msg!("WithdrawBatch");
+            process_withdraw_batch(program_id, accounts, events, features)
+        }
+        EscrowInstruction::InitializeBatch { items } => {
+            msg!("InitializeBatch of {} escrows", items.len());
+            process_initialize_batch(program_id, accounts, items, events, features)
+        }
+        EscrowInstruction::DepositSol {} => {
+            msg!("DepositSol");
+            process_deposit_sol(program_id, accounts, events)
+        }
+        EscrowInstruction::WithdrawSol {} => {
+            msg!("WithdrawSol");
+            process_withdraw_sol(program_id, accounts, events)
+        }
+        EscrowInstruction::InitializeStake { expiry_ts } => {
+            msg!("InitializeStake, expiry {}", expiry_ts);
+            process_initialize_stake(program_id, accounts, expiry_ts, events)
+        }
+        EscrowInstruction::WithdrawStake {} => {
+            msg!("WithdrawStake");
+            process_withdraw_stake(program_id, accounts, events)
+        }
+        EscrowInstruction::ReclaimStake {} => {
+            msg!("ReclaimStake");
+            process_reclaim_stake(program_id, accounts, events)
+        }
+        EscrowInstruction::DepositStaked {} => {
+            msg!("DepositStaked");
+            process_deposit_staked(program_id, accounts, events)
+        }
+        EscrowInstruction::WithdrawStaked {} => {
+            msg!("WithdrawStaked");
+            process_withdraw_staked(program_id, accounts, events)
+        }
+        EscrowInstruction::ReclaimStaked {} => {
+            msg!("ReclaimStaked");
+            process_reclaim_staked(program_id, accounts, events)
+        }
+        EscrowInstruction::InitializeAuction { amount, auction } => {
+            msg!("InitializeAuction");
+            process_initialize_auction(program_id, accounts, amount, auction, events)
+        }
+        EscrowInstruction::BuyAuction {} => {
+            msg!("BuyAuction");
+            process_buy_auction(program_id, accounts, events)
+        }
+        EscrowInstruction::CancelAuction {} => {
+            msg!("CancelAuction");
+            process_cancel_auction(program_id, accounts, events)
+        }
+        EscrowInstruction::ProposeTerms { amount, expiry_ts } => {
+            msg!("ProposeTerms");
+            process_propose_terms(program_id, accounts, amount, expiry_ts)
+        }
+        EscrowInstruction::AcceptTerms {} => {
+            msg!("AcceptTerms");
+            process_accept_terms(program_id, accounts, events)
+        }
+        EscrowInstruction::InitializeStats { party } => {
+            msg!("InitializeStats for {}", party);
+            process_initialize_stats(program_id, accounts, party)
+        }
+        EscrowInstruction::InitializeRelayed { amount, expiry_ts, deadline } => {
+            msg!("InitializeRelayed {} lamports", amount);
+            process_initialize_relayed(program_id, accounts, amount, expiry_ts, deadline, events, features)
+        }
+        EscrowInstruction::InitializeSubscription { intervals, subscription } => {
+            msg!("InitializeSubscription {} x {} lamports", intervals, subscription.amount_per_interval);
+            process_initialize_subscription(program_id, accounts, intervals, subscription, events, features)
+        }
+        EscrowInstruction::WithdrawSubscription {} => {
+            msg!("WithdrawSubscription");
+            process_withdraw_subscription(program_id, accounts, events, features)
+        }
+        EscrowInstruction::InitializeAuthorization { amount, expiry_ts } => {
+            msg!("InitializeAuthorization {} lamports until {}", amount, expiry_ts);
+            process_initialize_authorization(program_id, accounts, amount, expiry_ts, events, features)
+        }
+        EscrowInstruction::Capture { amount } => {
+            msg!("Capture {} lamports", amount);
+            process_capture(program_id, accounts, amount, events, features)
+        }
+        EscrowInstruction::InitializeInvoice { amount, expiry_ts } => {
+            msg!("InitializeInvoice {} lamports", amount);
+            process_initialize_invoice(program_id, accounts, amount, expiry_ts, events)
+        }
+        EscrowInstruction::CancelInvoice {} => {
+            msg!("CancelInvoice");
+            process_cancel_invoice(program_id, accounts, events)
+        }
+        EscrowInstruction::SetTemplate { name, fee_bps, dispute_window_secs, default_expiry_secs, arbiter } => {
+            msg!("SetTemplate, fee {} bps", fee_bps);
+            process_set_template(program_id, accounts, name, fee_bps, dispute_window_secs, default_expiry_secs, arbiter)
+        }
+        EscrowInstruction::InitializeAuthorities {} => {
+            msg!("InitializeAuthorities");
+            process_initialize_authorities(program_id, accounts)
+        }
+        EscrowInstruction::GrantRole { authority, role } => {
+            msg!("GrantRole {:?} to {}", role, authority);
+            process_set_role(program_id, accounts, authority, role, true)
+        }
+        EscrowInstruction::RevokeRole { authority, role } => {
+            msg!("RevokeRole {:?} from {}", role, authority);
+            process_set_role(program_id, accounts, authority, role, false)
+        }
+        EscrowInstruction::SetPaused { paused } => {
+            msg!("SetPaused {}", paused);
+            process_set_paused(program_id, accounts, paused)
+        }
+        EscrowInstruction::SetFees { fee_bps, referral_bps, crank_bounty_bps } => {
+            msg!("SetFees, fee {} bps", fee_bps);
+            process_set_fees(program_id, accounts, fee_bps, referral_bps, crank_bounty_bps)
+        }
+        EscrowInstruction::ProposeEmergencyWithdraw { recipient } => {
+            msg!("ProposeEmergencyWithdraw to {}", recipient);
+            process_propose_emergency_withdraw(program_id, accounts, recipient, events)
+        }
+        EscrowInstruction::VetoEmergencyWithdraw {} => {
+            msg!("VetoEmergencyWithdraw");
+            process_veto_emergency_withdraw(program_id, accounts)
+        }
+        EscrowInstruction::ExecuteEmergencyWithdraw {} => {
+            msg!("ExecuteEmergencyWithdraw");
+            process_execute_emergency_withdraw(program_id, accounts, events)
+        }
+        EscrowInstruction::SetFeature { feature, enabled } => {
+            msg!("SetFeature {:?}: {}", feature, enabled);
+            process_set_feature(program_id, accounts, feature, enabled)
+        }
+        EscrowInstruction::SetMetadata { uri, hash } => {
+            msg!("SetMetadata {}", uri);
+            process_set_metadata(program_id, accounts, uri, hash)
+        }
+        EscrowInstruction::ResolvePartial { taker_bps } => {
+            msg!("ResolvePartial, {} bps to taker", taker_bps);
+            process_resolve_partial(program_id, accounts, taker_bps, events)
+        }
+        EscrowInstruction::Appeal {} => {
+            msg!("Appeal");
+            process_appeal(program_id, accounts)
+        }
+        EscrowInstruction::FinalizeRuling {} => {
+            msg!("FinalizeRuling");
+            process_finalize_ruling(program_id, accounts, events)
+        }
+    }
+}
+
+fn process_get_state(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let escrow_account = next_account_info(&mut accounts.iter())?;
+    let state          = load_escrow(program_id, escrow_account)?;
+    if state.as_bytes().len() > MAX_RETURN_DATA {
+        return Err(EscrowError::StateTooLarge.into());
+    }
+    set_return_data(state.as_bytes());
+    Ok(())
+}
+
+/// Under CPI, splits off the `[config, instructions sysvar]` pair
+/// `with_caller_accounts` appends and, when the config names an
+/// `allowed_caller`, requires it to be the top-level instruction's program
+/// invoking the escrow directly. Every CPI must carry the pair, or an
+/// instruction that takes no config could get around the check; it only
+/// goes unchecked while the config does not exist yet. Top-level calls,
+/// event self-CPIs and admin instructions, which a multisig may invoke, are
+/// left alone.
+fn split_caller_accounts<'a, 'info>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'info>],
+    instr: &EscrowInstruction,
+) -> Result<&'a [AccountInfo<'info>], ProgramError> {
+    let height = get_stack_height();
+    if height == TRANSACTION_LEVEL_STACK_HEIGHT || matches!(instr, EscrowInstruction::EmitEvent { .. }) || instr.is_admin() {
+        return Ok(accounts);
+    }
+    let (config_address, _) = find_config_address(program_id);
+    let [rest @ .., config_account, instructions] = accounts else {
+        return Err(EscrowError::CallerNotAllowed.into());
+    };
+    if *config_account.key != config_address || *instructions.key != sysvar::instructions::id() {
+        return Err(EscrowError::CallerNotAllowed.into());
+    }
+    if config_account.data_is_empty() {
+        return Ok(rest);
+    }
+    let config = load_config(program_id, config_account)?;
+    if config.allowed_caller == Pubkey::default() {
+        return Ok(rest);
+    }
+    let current = load_current_index_checked(instructions)?;
+    let top     = load_instruction_at_checked(current as usize, instructions)?;
+    if height != TRANSACTION_LEVEL_STACK_HEIGHT + 1 || top.program_id != config.allowed_caller {
+        return Err(EscrowError::CallerNotAllowed.into());
+    }
+    Ok(rest)
+}
+
+/// Splits off the `[event_authority, program]` pair `with_event_cpi` appends,
+/// so handlers never mistake them for optional trailing accounts.
+fn split_event_accounts<'a, 'info>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'info>],
+) -> (&'a [AccountInfo<'info>], Option<EventCpi<'a, 'info>>) {
+    let [rest @ .., authority, program] = accounts else { return (accounts, None) };
+    if program.key != program_id {
+        return (accounts, None);
+    }
+    let (pda, bump) = find_event_authority_address(program_id);
+    if *authority.key != pda {
+        return (accounts, None);
+    }
+    (rest, Some(EventCpi { authority, program, bump }))
+}
+
+/// Splits off the Memo program `with_memo` appends.
+fn split_memo_account<'a, 'info>(
+    accounts: &'a [AccountInfo<'info>],
+) -> (&'a [AccountInfo<'info>], Option<&'a AccountInfo<'info>>) {
+    match accounts {
+        [rest @ .., program] if *program.key == MEMO_PROGRAM_ID => (rest, Some(program)),
+        _ => (accounts, None),
+    }
+}
+
+/// Logs the settlement of `state` through the Memo program, when the
+/// instruction was given it.
+fn log_settlement_memo(
+    memo_program: Option<&AccountInfo>,
+    escrow_account: &AccountInfo,
+    state: &EscrowState,
+    outcome: &str,
+) -> ProgramResult {
+    let Some(memo_program) = memo_program else { return Ok(()) };
+    invoke(&memo::memo(&settlement_memo(escrow_account.key, state.id, outcome)), std::slice::from_ref(memo_program))
+}
+
+/// A stats PDA the instruction was given.
+struct StatsAccount<'a, 'info> {
+    account:  &'a AccountInfo<'info>,
+    party:    Pubkey,
+    /// `None` while InitializeStats hasn't created the account
+    counters: Option<StatsState>,
+}
+
+/// Splits off the stats PDAs `with_stats` appends, each account once. A
+/// party who has none yet is given as its uncreated PDA, recognized by
+/// deriving the PDAs of the escrows' parties and the signers.
+fn split_stats_accounts<'a, 'info>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<(&'a [AccountInfo<'info>], Vec<StatsAccount<'a, 'info>>), ProgramError> {
+    let mut derived: Option<Vec<(Pubkey, Pubkey)>> = None;
+    let mut stats: Vec<StatsAccount> = Vec::new();
+    let mut end = accounts.len();
+    while let Some(account) = accounts[..end].last() {
+        let (party, counters) = if account.owner == program_id && account.data.borrow().starts_with(&STATS_DISCRIMINATOR) {
+            let counters = load_stats(program_id, account)?;
+            (counters.party, Some(counters))
+        } else if account.data_is_empty() && *account.owner == system_program::id() {
+            let derived = derived.get_or_insert_with(|| stats_candidates(program_id, &accounts[..end]));
+            match derived.iter().find(|(pda, _)| pda == account.key) {
+                Some(&(_, party)) => (party, None),
+                None => break,
+            }
+        } else {
+            break;
+        };
+        if !stats.iter().any(|known| known.account.key == account.key) {
+            stats.push(StatsAccount { account, party, counters });
+        }
+        end -= 1;
+    }
+    Ok((&accounts[..end], stats))
+}
+
+/// Stats PDAs, with their parties, that an instruction over `accounts` may
+/// have to update: those of the escrows' parties and of the signers, who
+/// may become a taker.
+fn stats_candidates(program_id: &Pubkey, accounts: &[AccountInfo]) -> Vec<(Pubkey, Pubkey)> {
+    let mut parties: Vec<Pubkey> = Vec::new();
+    for snapshot in escrow_snapshots(program_id, accounts) {
+        parties.extend([snapshot.initializer, snapshot.taker]);
+    }
+    parties.extend(accounts.iter().filter(|account| account.is_signer).map(|account| *account.key));
+    parties.retain(|party| *party != Pubkey::default());
+    parties.sort_unstable();
+    parties.dedup();
+    parties.into_iter().map(|party| (find_stats_address(program_id, &party).0, party)).collect()
+}
+
+/// Splits off the feature flags PDA that `with_features` appends, and
+/// checks that the instruction's `required_features` are enabled. Gated
+/// behaviors check their flag in the returned `Features` where they happen.
+fn split_features_account<'a, 'info>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'info>],
+    instr: &EscrowInstruction,
+) -> Result<(&'a [AccountInfo<'info>], Features), ProgramError> {
+    let (accounts, enabled) = match accounts {
+        [rest @ .., features_account] if *features_account.key == find_features_address(program_id).0 => {
+            (rest, Some(load_features(program_id, features_account)?))
+        }
+        _ => (accounts, None),
+    };
+    let required = instr.required_features();
+    if required != 0 && enabled.ok_or(ProgramError::NotEnoughAccountKeys)? & required != required {
+        return Err(EscrowError::FeatureDisabled.into());
+    }
+    Ok((accounts, Features(enabled)))
+}
+
+/// The enabled features, if the instruction was given the feature flags PDA.
+#[derive(Clone, Copy)]
+struct Features(Option<u64>);
+
+impl Features {
+    /// Whether `feature` is enabled. An instruction about to do what it
+    /// gates must have been given the flags.
+    fn enabled(self, feature: Feature) -> Result<bool, ProgramError> {
+        let enabled = self.0.ok_or(EscrowError::FeaturesAccountMissing)?;
+        Ok(enabled & feature.bit() != 0)
+    }
+}
+
+/// The enabled features, `DEFAULT_FEATURES` until SetFeature creates the
+/// account.
+fn load_features(program_id: &Pubkey, features_account: &AccountInfo) -> Result<u64, ProgramError> {
+    if features_account.data_is_empty() {
+        return Ok(DEFAULT_FEATURES);
+    }
+    if features_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let features = FeaturesState::try_from_slice(&features_account.data.borrow())?;
+    if features.discriminator != FEATURES_DISCRIMINATOR {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(features.enabled)
+}
+
+/// What the stats need of an escrow the instruction was given.
+struct EscrowSnapshot {
+    index:       usize,
+    status:      EscrowStatus,
+    initializer: Pubkey,
+    taker:       Pubkey,
+    amount:      u64,
+}
+
+/// Reads the escrows among `accounts`.
+fn escrow_snapshots(program_id: &Pubkey, accounts: &[AccountInfo]) -> Vec<EscrowSnapshot> {
+    let mut seen: Vec<&Pubkey> = Vec::new();
+    accounts
+        .iter()
+        .enumerate()
+        .filter(|(_, account)| {
+            let new = account.owner == program_id && !seen.contains(&account.key);
+            seen.push(account.key);
+            new
+        })
+        .filter_map(|(index, account)| escrow_snapshot(index, account))
+        .collect()
+}
+
+fn escrow_snapshot(index: usize, account: &AccountInfo) -> Option<EscrowSnapshot> {
+    let data  = account.data.borrow();
+    let state = EscrowState::unpack(&data).ok()?;
+    if state.discriminator != ESCROW_DISCRIMINATOR {
+        return None;
+    }
+    Some(EscrowSnapshot {
+        index,
+        status:      state.status,
+        initializer: state.initializer_pubkey,
+        taker:       state.taker_pubkey,
+        amount:      state.amount,
+    })
+}
+
+/// Counts the escrows that left Created, Funded, Disputed or Ruled, or went
+/// into dispute, in the stats of those of their parties that were given.
+fn record_stats(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mut stats: Vec<StatsAccount>,
+    before: &[EscrowSnapshot],
+) -> ProgramResult {
+    for old in before {
+        // An escrow that no longer reads as one was closed
+        let account = &accounts[old.index];
+        let new     = Some(account).filter(|a| a.owner == program_id).and_then(|a| escrow_snapshot(old.index, a));
+        let (status, initializer, taker, amount) = match &new {
+            Some(new) => (new.status, new.initializer, new.taker, new.amount),
+            None => (EscrowStatus::Closed, old.initializer, old.taker, old.amount),
+        };
+        let live = matches!(
+            old.status,
+            EscrowStatus::Created | EscrowStatus::Funded | EscrowStatus::Disputed | EscrowStatus::Ruled
+        );
+        let count: fn(&mut StatsState, u64) = match status {
+            EscrowStatus::Released if live => |stats, amount| {
+                stats.completed = stats.completed.saturating_add(1);
+                stats.volume    = stats.volume.saturating_add(amount);
+            },
+            EscrowStatus::Cancelled | EscrowStatus::Closed if live => {
+                |stats, _| stats.cancelled = stats.cancelled.saturating_add(1)
+            }
+            // An appeal takes a ruled escrow back to Disputed, still the same dispute
+            EscrowStatus::Disputed if !matches!(old.status, EscrowStatus::Disputed | EscrowStatus::Ruled) => {
+                |stats, _| stats.disputed = stats.disputed.saturating_add(1)
+            }
+            _ => continue,
+        };
+        let parties = [Some(initializer), (taker != initializer).then_some(taker)];
+        for party in parties.into_iter().flatten().filter(|party| *party != Pubkey::default()) {
+            let counters = stats.iter_mut().find(|stats| stats.party == party).and_then(|stats| stats.counters.as_mut());
+            if let Some(counters) = counters {
+                count(counters, amount);
+            }
+        }
+    }
+    for party_stats in stats {
+        if let Some(counters) = party_stats.counters {
+            counters.serialize(&mut &mut party_stats.account.data.borrow_mut()[..])?;
+        }
+    }
+    Ok(())
+}
+
+fn load_stats(program_id: &Pubkey, stats_account: &AccountInfo) -> Result<StatsState, ProgramError> {
+    let stats = StatsState::deserialize(&mut &stats_account.data.borrow()[..])?;
+    let pda   = Pubkey::create_program_address(&[STATS_PDA_SEED, stats.party.as_ref(), &[stats.bump]], program_id)?;
+    if pda != *stats_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(stats)
+}
+
+/// Creates the stats PDA of any `party`, paid by the signer.
+fn process_initialize_stats(program_id: &Pubkey, accounts: &[AccountInfo], party: Pubkey) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let payer           = next_account_info(a)?;
+    let stats_account   = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+
+    if !payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (pda, bump) = find_stats_address(program_id, &party);
+    if pda != *stats_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let rent     = Rent::get()?;
+    let lamports = rent.minimum_balance(STATS_STATE_LEN);
+    create_pda_account(
+        payer,
+        stats_account,
+        lamports,
+        STATS_STATE_LEN as u64,
+        program_id,
+        system_program,
+        &[STATS_PDA_SEED, party.as_ref(), &[bump]],
+    )?;
+
+    let stats = StatsState { discriminator: STATS_DISCRIMINATOR, party, bump, ..StatsState::default() };
+    stats.serialize(&mut &mut stats_account.data.borrow_mut()[..])?;
+    msg!("Stats initialized at {}", pda);
+    Ok(())
+}
+
+/// Creates the template PDA of `name` on first use, then writes its
+/// parameters; only the config admin can.
+fn process_set_template(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    name: [u8; 32],
+    fee_bps: u16,
+    dispute_window_secs: i64,
+    default_expiry_secs: i64,
+    arbiter: Pubkey,
+) -> ProgramResult {
+    let a                = &mut accounts.iter();
+    let admin            = next_account_info(a)?;
+    let config_account   = next_account_info(a)?;
+    let template_account = next_account_info(a)?;
+    let system_program   = next_system_program(a)?;
+
+    if !admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let config = load_config(program_id, config_account)?;
+    if config.admin != *admin.key {
+        return Err(EscrowError::WrongAdmin.into());
+    }
+    let (pda, bump) = find_template_address(program_id, &name);
+    if pda != *template_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if template_account.data_is_empty() {
+        let rent = Rent::get()?;
+        create_pda_account(
+            admin,
+            template_account,
+            rent.minimum_balance(TEMPLATE_STATE_LEN),
+            TEMPLATE_STATE_LEN as u64,
+            program_id,
+            system_program,
+            &[TEMPLATE_PDA_SEED, &name, &[bump]],
+        )?;
+    }
+
+    let template = TemplateState {
+        discriminator: TEMPLATE_DISCRIMINATOR,
+        name,
+        bump,
+        fee_bps,
+        dispute_window_secs,
+        default_expiry_secs,
+        arbiter,
+    };
+    template.serialize(&mut &mut template_account.data.borrow_mut()[..])?;
+    msg!("Template set at {}", pda);
+    Ok(())
+}
+
+fn process_initialize_authorities(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a                   = &mut accounts.iter();
+    let admin               = next_account_info(a)?;
+    let config_account      = next_account_info(a)?;
+    let authorities_account = next_account_info(a)?;
+    let system_program      = next_system_program(a)?;
+
+    if !admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let config = load_config(program_id, config_account)?;
+    if config.admin != *admin.key {
+        return Err(EscrowError::WrongAdmin.into());
+    }
+    let (pda, bump) = find_authorities_address(program_id);
+    if pda != *authorities_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let rent = Rent::get()?;
+    create_pda_account(
+        admin,
+        authorities_account,
+        rent.minimum_balance(AUTHORITIES_STATE_LEN),
+        AUTHORITIES_STATE_LEN as u64,
+        program_id,
+        system_program,
+        &[AUTHORITIES_PDA_SEED, &[bump]],
+    )?;
+
+    let authorities = AuthoritiesState { discriminator: AUTHORITIES_DISCRIMINATOR, bump, grants: Vec::new() };
+    authorities.serialize(&mut &mut authorities_account.data.borrow_mut()[..])?;
+    msg!("Authorities initialized at {}", pda);
+    Ok(())
+}
+
+fn load_authorities(program_id: &Pubkey, authorities_account: &AccountInfo) -> Result<AuthoritiesState, ProgramError> {
+    if authorities_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    if find_authorities_address(program_id).0 != *authorities_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let authorities = AuthoritiesState::try_from_slice(&authorities_account.data.borrow())?;
+    if authorities.discriminator != AUTHORITIES_DISCRIMINATOR {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(authorities)
+}
+
+/// Passes when `signer` signed and is the config admin, who holds every
+/// role, or was granted `role`.
+fn check_role(
+    config: &ConfigState,
+    authorities: &AuthoritiesState,
+    signer: &AccountInfo,
+    role: Role,
+) -> ProgramResult {
+    if !signer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if config.admin == *signer.key || authorities.has_role(signer.key, role) {
+        return Ok(());
+    }
+    Err(EscrowError::MissingRole.into())
+}
+
+/// Grants (`grant`) or revokes `role`, resizing the account as `authority`
+/// gains its first role or loses its last.
+fn process_set_role(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    authority: Pubkey,
+    role: Role,
+    grant: bool,
+) -> ProgramResult {
+    let a                   = &mut accounts.iter();
+    let signer              = next_account_info(a)?;
+    let config_account      = next_account_info(a)?;
+    let authorities_account = next_account_info(a)?;
+
+    let config          = load_config(program_id, config_account)?;
+    let mut authorities = load_authorities(program_id, authorities_account)?;
+    // ArbiterRegistry holders manage the arbiter list, Admins every role
+    if role != Role::Arbiter || check_role(&config, &authorities, signer, Role::ArbiterRegistry).is_err() {
+        check_role(&config, &authorities, signer, Role::Admin)?;
+    }
+
+    let index = authorities.grants.iter().position(|g| g.authority == authority);
+    match (index, grant) {
+        (Some(i), true) => authorities.grants[i].roles |= role.bit(),
+        (None, true) => {
+            if authorities.grants.len() >= MAX_AUTHORITIES {
+                return Err(EscrowError::InvalidRoleChange.into());
+            }
+            authorities.grants.push(RoleGrant { authority, roles: role.bit() });
+        }
+        (Some(i), false) => {
+            authorities.grants[i].roles &= !role.bit();
+            if authorities.grants[i].roles == 0 {
+                authorities.grants.remove(i);
+            }
+        }
+        (None, false) => {}
+    }
+
+    if authorities.space() > authorities_account.data_len() {
+        grow_account(authorities_account, authorities.space(), 0, signer, next_account_info(a)?)?;
+    } else {
+        shrink_account(authorities_account, authorities.space(), 0, signer)?;
+    }
+    authorities.serialize(&mut &mut authorities_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+fn process_set_paused(program_id: &Pubkey, accounts: &[AccountInfo], paused: bool) -> ProgramResult {
+    let a                   = &mut accounts.iter();
+    let signer              = next_account_info(a)?;
+    let config_account      = next_account_info(a)?;
+    let authorities_account = next_account_info(a)?;
+
+    let mut config = load_config(program_id, config_account)?;
+    check_role(&config, &load_authorities(program_id, authorities_account)?, signer, Role::Pauser)?;
+    config.paused = paused;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    msg!("Config paused: {}", paused);
+    Ok(())
+}
+
+fn process_set_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    fee_bps: u16,
+    referral_bps: u16,
+    crank_bounty_bps: u16,
+) -> ProgramResult {
+    let a                   = &mut accounts.iter();
+    let signer              = next_account_info(a)?;
+    let config_account      = next_account_info(a)?;
+    let authorities_account = next_account_info(a)?;
+
+    let mut config = load_config(program_id, config_account)?;
+    check_role(&config, &load_authorities(program_id, authorities_account)?, signer, Role::FeeManager)?;
+    if fee_bps > MAX_FEE_BPS || referral_bps > 10_000 || crank_bounty_bps > 10_000 {
+        return Err(EscrowError::InvalidFee.into());
+    }
+    config.fee_bps          = fee_bps;
+    config.referral_bps     = referral_bps;
+    config.crank_bounty_bps = crank_bounty_bps;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    msg!("Fees set to {} bps", fee_bps);
+    Ok(())
+}
+
+fn process_set_feature(program_id: &Pubkey, accounts: &[AccountInfo], feature: Feature, enabled: bool) -> ProgramResult {
+    let a                = &mut accounts.iter();
+    let admin            = next_account_info(a)?;
+    let config_account   = next_account_info(a)?;
+    let features_account = next_account_info(a)?;
+    let system_program   = next_system_program(a)?;
+
+    check_admin(program_id, config_account, admin)?;
+    let (pda, bump) = find_features_address(program_id);
+    if pda != *features_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let current = load_features(program_id, features_account)?;
+    if features_account.data_is_empty() {
+        let rent = Rent::get()?;
+        create_pda_account(
+            admin,
+            features_account,
+            rent.minimum_balance(FEATURES_STATE_LEN),
+            FEATURES_STATE_LEN as u64,
+            program_id,
+            system_program,
+            &[FEATURES_PDA_SEED, &[bump]],
+        )?;
+    }
+
+    let features = FeaturesState {
+        discriminator: FEATURES_DISCRIMINATOR,
+        bump,
+        enabled: if enabled { current | feature.bit() } else { current & !feature.bit() },
+    };
+    features.serialize(&mut &mut features_account.data.borrow_mut()[..])?;
+    msg!("Features now {:#b}", features.enabled);
+    Ok(())
+}
+
+/// Requires `admin` to be the config admin, signing.
+fn check_admin(program_id: &Pubkey, config_account: &AccountInfo, admin: &AccountInfo) -> ProgramResult {
+    if !admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if load_config(program_id, config_account)?.admin != *admin.key {
+        return Err(EscrowError::WrongAdmin.into());
+    }
+    Ok(())
+}
+
+/// Emergency recovery only reaches native escrows still holding funds.
+fn check_emergency_recoverable(state: &EscrowState) -> ProgramResult {
+    check_mint(state, &Pubkey::default())?;
+    check_not_stake(state)?;
+    if !matches!(state.status, EscrowStatus::Disputed | EscrowStatus::Ruled) {
+        check_status(state, EscrowStatus::Funded)?;
+    }
+    Ok(())
+}
+
+fn process_propose_emergency_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    recipient: Pubkey,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let admin           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    check_admin(program_id, config_account, admin)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_emergency_recoverable(&state)?;
+    if recipient == Pubkey::default() {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    state.emergency_recipient   = recipient;
+    state.emergency_proposed_at = Clock::get()?.unix_timestamp;
+    let executable_at = state.emergency_proposed_at + EMERGENCY_DELAY_SECS;
+    msg!("Emergency withdraw executable from {}", executable_at);
+    EmergencyWithdrawProposed { escrow: *escrow_account.key, recipient, executable_at }.emit(events)
+}
+
+fn process_veto_emergency_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let party           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !party.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_party(&state, party.key)?;
+    if state.emergency_recipient == Pubkey::default() {
+        return Err(EscrowError::NoEmergency.into());
+    }
+
+    state.emergency_recipient   = Pubkey::default();
+    state.emergency_proposed_at = 0;
+    msg!("Emergency withdraw vetoed");
+    Ok(())
+}
+
+fn process_execute_emergency_withdraw(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let admin           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let recipient       = next_account_info(a)?;
+
+    check_admin(program_id, config_account, admin)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_emergency_recoverable(&state)?;
+    if state.emergency_recipient == Pubkey::default()
+        || Clock::get()?.unix_timestamp < state.emergency_proposed_at + EMERGENCY_DELAY_SECS
+    {
+        return Err(EscrowError::NoEmergency.into());
+    }
+    if *recipient.key != state.emergency_recipient {
+        return Err(ProgramError::InvalidArgument);
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    // The vault keeps its rent for Close, as after a Refund
+    let recovered = state.remaining;
+    move_lamports(vault, recipient, recovered)?;
+    release_bond(&mut state, vault, recipient)?;
+    state.remaining             = 0;
+    state.status                = EscrowStatus::Cancelled;
+    state.emergency_recipient   = Pubkey::default();
+    state.emergency_proposed_at = 0;
+    msg!("Emergency withdrew {} lamports", recovered);
+    EscrowCancelled { escrow: *escrow_account.key, refunded: recovered }.emit(events)
+}
+
+/// Accepts an event self-CPI, which is only valid when the program signed
+/// it with its event authority. It stays silent so the event is not logged
+/// twice.
+fn process_emit_event(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let authority = next_account_info(&mut accounts.iter())?;
+    if !authority.is_signer || *authority.key != find_event_authority_address(program_id).0 {
+        return Err(EscrowError::EventAuthority.into());
+    }
+    Ok(())
+}
+
+/// Creates the escrow PDA owned by this program and writes its initial state.
+/// A `taker` of `Pubkey::default()` leaves the escrow open for AcceptEscrow.
+#[allow(clippy::too_many_arguments)]
+fn create_escrow_account<'a, 'b>(
+    program_id: &Pubkey,
+    initializer: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    taker: &AccountInfo<'a>,
+    escrow_account: &'b AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    registry_account: &AccountInfo<'a>,
+    amount: u64,
+    mint: Pubkey,
+    expiry_ts: i64,
+    arbiter: Pubkey,
+    milestones: Vec<Milestone>,
+    nonce: u64,
+    reference: [u8; 32],
+    events: Option<EventCpi>,
+) -> Result<Escrow<'b>, ProgramError> {
+    // A named taker who doesn't sign now consents later
+    let open = *taker.key == Pubkey::default();
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_paused(program_id, config_account)?;
+    let config = load_config(program_id, config_account)?;
+    let native = mint == Pubkey::default();
+    if native {
+        check_amount_limits(&config, amount)?;
+    }
+    let id          = register_escrow(program_id, initializer, payer, registry_account, system_program, &config)?;
+    let (pda, bump) = find_escrow_address(program_id, initializer.key, id);
+    if pda != *escrow_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    check_uninitialized(escrow_account)?;
+    let space    = ESCROW_STATE_LEN + milestones.len() * MILESTONE_LEN;
+    let rent     = Rent::get()?;
+    // The creation fee rides along with the rent
+    let lamports = rent.minimum_balance(space) + config.creation_fee;
+    create_pda_account(
+        payer,
+        escrow_account,
+        lamports,
+        space as u64,
+        program_id,
+        system_program,
+        &[ESCROW_PDA_SEED, initializer.key.as_ref(), &id.to_le_bytes(), &[bump]],
+    )?;
+
+    // A new account's data is zeroed, so only the fields set here aren't
+    let mut state = escrow_state(escrow_account)?;
+    state.discriminator      = ESCROW_DISCRIMINATOR;
+    state.is_initialized     = true.into();
+    state.initializer_pubkey = *initializer.key;
+    state.taker_pubkey       = *taker.key;
+    state.amount             = amount;
+    state.bump               = bump;
+    state.id                 = id;
+    state.mint               = mint;
+    state.expiry_ts          = expiry_ts;
+    state.status             = EscrowStatus::Created;
+    state.arbiter_pubkey     = arbiter;
+    state.taker_pending      = (!open && !taker.is_signer).into();
+    state.created_by         = *initializer.key;
+    state.nonce              = nonce;
+    state.creation_fee       = config.creation_fee;
+    state.reference          = reference;
+    // Fixed now so neither party can change what a ruling costs or how long
+    // it waits; the flat fee is in lamports, so token escrows only pay the
+    // share
+    if native {
+        state.arbiter_fee    = config.arbiter_fee;
+    }
+    state.arbiter_fee_bps    = config.arbiter_fee_bps;
+    state.appeal_window_secs = config.appeal_window_secs;
+    state.appeal_arbiter     = config.appeal_arbiter;
+    state.version            = ESCROW_STATE_VERSION;
+    state.set_milestones(&milestones);
+    msg!("Escrow {} initialized at {}", id, pda);
+    EscrowInitialized {
+        escrow:      pda,
+        initializer: *initializer.key,
+        taker:       *taker.key,
+        id,
+        amount,
+        mint,
+        nonce,
+        reference,
+    }
+    .emit(events)?;
+    Ok(state)
+}
+
+/// Refuses to create an account at an address that already holds data or
+/// belongs to another program, so creation never writes over a live account.
+fn check_uninitialized(account: &AccountInfo) -> ProgramResult {
+    if !account.data_is_empty() || !system_program::check_id(account.owner) {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    Ok(())
+}
+
+/// A new native escrow's amount must lie within the config's limits.
+fn check_amount_limits(config: &ConfigState, amount: u64) -> ProgramResult {
+    if amount < config.min_amount || (config.max_amount != 0 && amount > config.max_amount) {
+        return Err(EscrowError::AmountOutOfLimits.into());
+    }
+    Ok(())
+}
+
+/// Hands out the initializer's next escrow id and records it as active,
+/// creating their registry PDA on their first escrow.
+fn register_escrow<'a>(
+    program_id: &Pubkey,
+    initializer: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    registry_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    config: &ConfigState,
+) -> Result<u64, ProgramError> {
+    let (pda, bump) = find_registry_address(program_id, initializer.key);
+    if pda != *registry_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut registry = if registry_account.data_is_empty() {
+        let rent = Rent::get()?;
+        create_pda_account(
+            payer,
+            registry_account,
+            rent.minimum_balance(REGISTRY_STATE_LEN),
+            REGISTRY_STATE_LEN as u64,
+            program_id,
+            system_program,
+            &[REGISTRY_PDA_SEED, initializer.key.as_ref(), &[bump]],
+        )?;
+        RegistryState { discriminator: REGISTRY_DISCRIMINATOR, next_id: 0, bump, ..RegistryState::default() }
+    } else {
+        load_registry(program_id, initializer, registry_account)?
+    };
+
+    // The window restarts with every epoch
+    let epoch = Clock::get()?.epoch;
+    if registry.epoch != epoch {
+        registry.epoch            = epoch;
+        registry.created_in_epoch = 0;
+    }
+    if config.max_escrows_per_epoch != 0 && registry.created_in_epoch >= config.max_escrows_per_epoch {
+        return Err(EscrowError::RateLimited.into());
+    }
+    registry.created_in_epoch += 1;
+
+    let id = registry.next_id;
+    registry.next_id = id.checked_add(1).ok_or(EscrowError::AmountOverflow)?;
+    registry.active.push(id);
+
+    // Grow the account by one id, with the payer covering the extra rent
+    grow_account(registry_account, registry.space(), 0, payer, system_program)?;
+    registry.serialize(&mut &mut registry_account.data.borrow_mut()[..])?;
+    Ok(id)
+}
+
+/// Drops a closed escrow from the registry, shrinking the account and
+/// returning the freed rent to the initializer.
+fn unregister_escrow(
+    program_id: &Pubkey,
+    initializer: &AccountInfo,
+    registry_account: &AccountInfo,
+    id: u64,
+) -> ProgramResult {
+    let mut registry = load_registry(program_id, initializer, registry_account)?;
+    // A registry from before the rate limit stays without its window, which
+    // would need rent nobody here pays, until its next escrow
+    let legacy = registry_account.data_len() < registry.space();
+    registry.active.retain(|active| *active != id);
+    let data = borsh::to_vec(&registry)?;
+    let len  = if legacy { data.len() - REGISTRY_TAIL_LEN } else { data.len() };
+    shrink_account(registry_account, len, 0, initializer)?;
+    registry_account.data.borrow_mut()[..len].copy_from_slice(&data[..len]);
+    Ok(())
+}
+
+/// Creates the PDA `account` signed by `seeds`, owned by `owner`, with
+/// `payer` funding it up to `lamports`. Anyone can send lamports to the
+/// address beforehand, which would make `create_account` fail, so an account
+/// that already holds some is only topped up, allocated and assigned.
+fn create_pda_account<'a>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    lamports: u64,
+    space: u64,
+    owner: &Pubkey,
+    system_program: &AccountInfo<'a>,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    if account.lamports() == 0 {
+        return invoke_signed(
+            &system_instruction::create_account(payer.key, account.key, lamports, space, owner),
+            &[payer.clone(), account.clone(), system_program.clone()],
+            &[seeds],
+        );
+    }
+    let shortfall = lamports.saturating_sub(account.lamports());
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, shortfall),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::allocate(account.key, space),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(account.key, owner),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
+
+/// Reallocs a program-owned account up to `len` bytes, with `payer` topping
+/// it up to the new rent-exempt minimum plus the `held` lamports it keeps on
+/// top. The new bytes are zeroed.
+fn grow_account<'a>(
+    account: &AccountInfo<'a>,
+    len: usize,
+    held: u64,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    account.realloc(len, true)?;
+    let shortfall = (Rent::get()?.minimum_balance(len) + held).saturating_sub(account.lamports());
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, shortfall),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Reallocs a program-owned account down to `len` bytes and returns the
+/// rent it no longer needs, beyond the `held` lamports it keeps on top, to
+/// `destination`.
+fn shrink_account(account: &AccountInfo, len: usize, held: u64, destination: &AccountInfo) -> ProgramResult {
+    account.realloc(len, false)?;
+    let surplus = account.lamports().saturating_sub(Rent::get()?.minimum_balance(len) + held);
+    move_lamports(account, destination, surplus)
+}
+
+fn load_registry(
+    program_id: &Pubkey,
+    initializer: &AccountInfo,
+    registry_account: &AccountInfo,
+) -> Result<RegistryState, ProgramError> {
+    if registry_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    if find_registry_address(program_id, initializer.key).0 != *registry_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let registry = RegistryState::unpack(&registry_account.data.borrow())?;
+    if registry.discriminator != REGISTRY_DISCRIMINATOR {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(registry)
+}
+
+/// Key of an optional trailing account, `Pubkey::default()` when it is omitted.
+fn optional_key(a: &mut std::slice::Iter<AccountInfo>) -> Pubkey {
+    a.next().map(|account| *account.key).unwrap_or_default()
+}
+
+/// Moves lamports out of a program-owned account. Unless the move drains it
+/// completely, which only happens when the account is being closed, `from`
+/// must stay rent exempt so the runtime never reclaims live state.
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+    let rent_floor = Rent::get()?.minimum_balance(from.data_len());
+    let new_from   = from.lamports().checked_sub(amount).ok_or(ProgramError::InsufficientFunds)?;
+    if new_from != 0 && new_from < rent_floor {
+        return Err(EscrowError::BelowRentFloor.into());
+    }
+    transfer_lamports(from, to, amount)
+}
+
+/// The only place lamports are written: debits `from` and credits `to` by
+/// `amount`, failing on underflow or overflow and unless the two accounts
+/// hold the same total afterwards. Moving lamports from an account to itself
+/// changes nothing.
+fn transfer_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+    if from.key == to.key {
+        return Ok(());
+    }
+    let mut from_lamports = from.lamports.borrow_mut();
+    let mut to_lamports   = to.lamports.borrow_mut();
+    let total    = **from_lamports as u128 + **to_lamports as u128;
+    let new_from = from_lamports
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    let new_to   = to_lamports
+        .checked_add(amount)
+        .ok_or(EscrowError::AmountOverflow)?;
+    if new_from as u128 + new_to as u128 != total {
+        return Err(EscrowError::LamportsNotConserved.into());
+    }
+    **from_lamports = new_from;
+    **to_lamports   = new_to;
+    Ok(())
+}
+
+/// An escrow borrowed in place from its account's data.
+type Escrow<'a> = EscrowData<RefMut<'a, [u8]>>;
+
+/// Loads the escrow state after checking the account is owned by this program
+/// and sits at the PDA derived from its own stored id and bump. The account's
+/// data stays borrowed until the escrow is dropped, which a CPI naming the
+/// account or a realloc of it needs first.
+fn load_escrow<'a>(program_id: &Pubkey, escrow_account: &'a AccountInfo) -> Result<Escrow<'a>, ProgramError> {
+    if escrow_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    // A v1 header is shorter than the current one, so it is turned away
+    // before being borrowed as one
+    let version = escrow_account.try_borrow_data()?.get(offset_of!(EscrowState, version)).copied();
+    if version.is_some_and(|version| version != ESCROW_STATE_VERSION) {
+        return Err(EscrowError::StateVersion.into());
+    }
+    let state = escrow_state(escrow_account)?;
+    check_escrow_state(program_id, escrow_account, &state)?;
+    Ok(state)
+}
+
+fn check_escrow_state(program_id: &Pubkey, escrow_account: &AccountInfo, state: &EscrowState) -> ProgramResult {
+    if state.discriminator != ESCROW_DISCRIMINATOR || !bool::from(state.is_initialized) {
+        return Err(EscrowError::NotInitialized.into());
+    }
+    check_escrow_address(program_id, escrow_account, &EscrowSigner::of(state))
+}
+
+/// Borrows the account data as an escrow without checking it, to get back
+/// an escrow `load_escrow` already checked once a CPI is done with it.
+fn escrow_state<'a>(escrow_account: &'a AccountInfo) -> Result<Escrow<'a>, ProgramError> {
+    let data = RefMut::map(escrow_account.try_borrow_mut_data()?, |data| &mut **data);
+    EscrowData::new(data).ok_or_else(|| EscrowError::NotInitialized.into())
+}
+
+fn check_escrow_address(program_id: &Pubkey, escrow_account: &AccountInfo, signer: &EscrowSigner) -> ProgramResult {
+    if Pubkey::create_program_address(&signer.seeds(), program_id)? != *escrow_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(())
+}
+
+/// The seeds of the escrow PDA, copied out of its state so the account's
+/// data can be released for a CPI the escrow signs.
+#[derive(Clone, Copy)]
+struct EscrowSigner {
+    initializer: Pubkey,
+    id:          [u8; 8],
+    bump:        [u8; 1],
+}
+
+impl EscrowSigner {
+    fn of(state: &EscrowState) -> Self {
+        Self { initializer: state.initializer_pubkey, id: state.id.to_le_bytes(), bump: [state.bump] }
+    }
+
+    fn seeds(&self) -> [&[u8]; 4] {
+        [ESCROW_PDA_SEED, self.initializer.as_ref(), &self.id, &self.bump]
+    }
+}
+
+fn check_initializer(state: &EscrowState, initializer: &AccountInfo) -> ProgramResult {
+    if state.initializer_pubkey != *initializer.key {
+        return Err(EscrowError::WrongInitializer.into());
+    }
+    Ok(())
+}
+
+fn check_taker(state: &EscrowState, taker: &AccountInfo) -> ProgramResult {
+    if state.taker_pubkey != *taker.key {
+        return Err(EscrowError::WrongTaker.into());
+    }
+    Ok(())
+}
+
+/// `mint` is `Pubkey::default()` when the caller handles native lamports.
+fn check_mint(state: &EscrowState, mint: &Pubkey) -> ProgramResult {
+    if state.mint != *mint {
+        return Err(EscrowError::WrongMint.into());
+    }
+    Ok(())
+}
+
+/// Rejects the instruction unless the escrow is in the `expected` status.
+fn check_status(state: &EscrowState, expected: EscrowStatus) -> ProgramResult {
+    match (expected, state.status) {
+        (e, s) if e == s => Ok(()),
+        (_, EscrowStatus::Released | EscrowStatus::Cancelled | EscrowStatus::Closed) => {
+            Err(EscrowError::AlreadySettled.into())
+        }
+        (_, EscrowStatus::Disputed | EscrowStatus::Ruled) => Err(EscrowError::Disputed.into()),
+        (EscrowStatus::Created, _) => Err(EscrowError::AlreadyFunded.into()),
+        _ => Err(EscrowError::NotFunded.into()),
+    }
+}
+
+fn check_not_expired(state: &EscrowState) -> ProgramResult {
+    if state.expiry_ts != 0 && Clock::get()?.unix_timestamp > state.expiry_ts {
+        return Err(EscrowError::Expired.into());
+    }
+    Ok(())
+}
+
+fn load_config(program_id: &Pubkey, config_account: &AccountInfo) -> Result<ConfigState, ProgramError> {
+    if config_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    if find_config_address(program_id).0 != *config_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let config = ConfigState::try_from_slice(&config_account.data.borrow())?;
+    if config.discriminator != CONFIG_DISCRIMINATOR || !config.is_initialized {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(config)
+}
+
+/// New escrows and deposits are refused while the admin has paused the program.
+fn check_not_paused(program_id: &Pubkey, config_account: &AccountInfo) -> ProgramResult {
+    if load_config(program_id, config_account)?.paused {
+        return Err(EscrowError::Paused.into());
+    }
+    Ok(())
+}
+
+/// The protocol fee on `amount`, at the rate of the escrow's template if
+/// it has one, and none while the `Fees` feature is off.
+fn fee_for(config: &ConfigState, features: Features, state: &EscrowState, amount: u64) -> Result<u64, ProgramError> {
+    let bps = if state.template == Pubkey::default() { config.fee_bps } else { state.fee_bps };
+    if bps == 0 || !features.enabled(Feature::Fees)? {
+        return Ok(0);
+    }
+    let fee = amount as u128 * bps as u128 / 10_000;
+    u64::try_from(fee).map_err(|_| EscrowError::AmountOverflow.into())
+}
+
+fn check_vault(program_id: &Pubkey, escrow_account: &AccountInfo, vault: &AccountInfo) -> ProgramResult {
+    if find_vault_address(program_id, escrow_account.key).0 != *vault.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(())
+}
+
+/// Creates the data-less vault PDA that holds a native escrow's lamports.
+/// It is owned by this program so payouts can debit it directly, and its
+/// rent stays put until Close.
+fn create_native_vault<'a>(
+    program_id: &Pubkey,
+    escrow_account: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let (vault_pda, vault_bump) = find_vault_address(program_id, escrow_account.key);
+    if vault_pda != *vault.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    check_uninitialized(vault)?;
+    let rent = Rent::get()?;
+    create_pda_account(
+        payer,
+        vault,
+        rent.minimum_balance(0),
+        0,
+        program_id,
+        system_program,
+        &[VAULT_PDA_SEED, escrow_account.key.as_ref(), &[vault_bump]],
+    )
+}
+
+/// Transfers `amount` lamports from `funder` into a native vault and
+/// returns what the vault's balance grew by, refusing a transfer that
+/// credited it anything else.
+fn deposit_lamports<'a>(
+    funder: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    amount: u64,
+) -> Result<u64, ProgramError> {
+    let balance_before = vault.lamports();
+    invoke(
+        &system_instruction::transfer(funder.key, vault.key, amount),
+        &[funder.clone(), vault.clone(), system_program.clone()],
+    )?;
+    let received = vault.lamports().checked_sub(balance_before).ok_or(EscrowError::DepositMismatch)?;
+    if received != amount {
+        return Err(EscrowError::DepositMismatch.into());
+    }
+    Ok(received)
+}
+
+/// Crowdfund deposits belong to their contributors until the goal is met, so
+/// the usual initializer paths must not touch them.
+fn check_not_crowdfund(state: &EscrowState) -> ProgramResult {
+    if bool::from(state.crowdfund) {
+        return Err(EscrowError::Crowdfund.into());
+    }
+    Ok(())
+}
+
+/// A distribution pays its claimants by proof, so it never gets a taker.
+fn check_not_distribution(state: &EscrowState) -> ProgramResult {
+    if state.merkle_root != [0; 32] {
+        return Err(EscrowError::Distribution.into());
+    }
+    Ok(())
+}
+
+/// The escrow PDA must not lose its stake authorities, nor the vault its
+/// pool tokens, so stake escrows stay off every path but their own
+/// Withdraw and Reclaim instructions.
+fn check_not_stake(state: &EscrowState) -> ProgramResult {
+    if state.stake_account != Pubkey::default() || state.stake_pool != Pubkey::default() {
+        return Err(EscrowError::StakeEscrow.into());
+    }
+    Ok(())
+}
+
+/// Swap escrows only pay out through ExecuteSwap, which crosses both legs.
+fn check_not_swap(state: &EscrowState) -> ProgramResult {
+    if state.taker_mint != Pubkey::default() {
+        return Err(EscrowError::SwapEscrow.into());
+    }
+    Ok(())
+}
+
+/// `deposited_later` says whether the config's `require_deposit` applies;
+/// crowdfunds are funded by Contribute instead.
+#[allow(clippy::too_many_arguments)]
+fn process_initialize(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    expiry_ts: i64,
+    milestones: Vec<Milestone>,
+    deposited_later: bool,
+    nonce: u64,
+    reference: [u8; 32],
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+    let arbiter          = optional_key(a);
+    let referrer         = optional_key(a);
+    let instructions     = a.next();
+    let payer            = rent_payer(initializer, a.next())?;
+    let template         = a.next().filter(|t| *t.key != Pubkey::default());
+    let template         = template.map(|t| load_template(program_id, t).map(|state| (*t.key, state))).transpose()?;
+
+    let (expiry_ts, arbiter) = match &template {
+        Some((_, template)) => apply_template(template, expiry_ts, arbiter)?,
+        None => (expiry_ts, arbiter),
+    };
+    let mut state = create_escrow_account(
+        program_id,
+        initializer,
+        payer,
+        taker,
+        escrow_account,
+        system_program,
+        config_account,
+        registry_account,
+        amount,
+        Pubkey::default(),
+        expiry_ts,
+        arbiter,
+        milestones,
+        nonce,
+        reference,
+        events,
+    )?;
+    if let Some((key, template)) = template {
+        state.template            = key;
+        state.fee_bps             = template.fee_bps;
+        state.dispute_window_secs = template.dispute_window_secs;
+    }
+    if referrer != Pubkey::default() {
+        // The parties can't pay themselves a cut of the protocol fee
+        if referrer == *initializer.key || referrer == *taker.key {
+            return Err(EscrowError::WrongReferrer.into());
+        }
+        state.referrer = referrer;
+    }
+    if deposited_later {
+        check_deposit_follows(program_id, config_account, escrow_account, instructions, features)?;
+    }
+    create_native_vault(program_id, escrow_account, vault, payer, system_program)
+}
+
+fn load_template(program_id: &Pubkey, template_account: &AccountInfo) -> Result<TemplateState, ProgramError> {
+    if template_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let template = TemplateState::deserialize(&mut &template_account.data.borrow()[..])?;
+    if template.discriminator != TEMPLATE_DISCRIMINATOR {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let pda = Pubkey::create_program_address(&[TEMPLATE_PDA_SEED, &template.name, &[template.bump]], program_id)?;
+    if pda != *template_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(template)
+}
+
+/// The expiry and arbiter of an escrow created from `template`: its
+/// default expiry when none was given, and its arbiter, which an explicit
+/// one must match.
+fn apply_template(template: &TemplateState, expiry_ts: i64, arbiter: Pubkey) -> Result<(i64, Pubkey), ProgramError> {
+    let expiry_ts = if expiry_ts == 0 && template.default_expiry_secs > 0 {
+        Clock::get()?.unix_timestamp.checked_add(template.default_expiry_secs).ok_or(EscrowError::InvalidExpiry)?
+    } else {
+        expiry_ts
+    };
+    if template.arbiter == Pubkey::default() {
+        return Ok((expiry_ts, arbiter));
+    }
+    if arbiter != Pubkey::default() && arbiter != template.arbiter {
+        return Err(EscrowError::InvalidTemplate.into());
+    }
+    Ok((expiry_ts, template.arbiter))
+}
+
+/// The optional payer covering the rent in the initializer's place, so a
+/// relayer can onboard initializers who hold no SOL; the initializer, who
+/// still signs as the party, gets the rent back when the escrow closes.
+fn rent_payer<'a, 'info>(
+    initializer: &'a AccountInfo<'info>,
+    payer: Option<&'a AccountInfo<'info>>,
+) -> Result<&'a AccountInfo<'info>, ProgramError> {
+    match payer {
+        Some(payer) if *payer.key != Pubkey::default() => {
+            if !payer.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            Ok(payer)
+        }
+        _ => Ok(initializer),
+    }
+}
+
+/// In strict mode (`require_deposit` in the config, while the `StrictMode`
+/// feature is on), an escrow may only be
+/// created by a transaction that also funds it: a Deposit of that escrow
+/// must come later in the same transaction, found through the Instructions
+/// sysvar. Without one, the escrow would sit unfunded, holding the taker's
+/// slot and the initializer's registry id.
+fn check_deposit_follows(
+    program_id: &Pubkey,
+    config_account: &AccountInfo,
+    escrow_account: &AccountInfo,
+    instructions: Option<&AccountInfo>,
+    features: Features,
+) -> ProgramResult {
+    if !load_config(program_id, config_account)?.require_deposit || !features.enabled(Feature::StrictMode)? {
+        return Ok(());
+    }
+    let instructions = instructions.ok_or(EscrowError::DepositMissing)?;
+    let current      = usize::from(load_current_index_checked(instructions)?);
+    for index in current + 1.. {
+        let Ok(ix) = load_instruction_at_checked(index, instructions) else { break };
+        let deposit = ix.program_id == *program_id
+            && matches!(EscrowInstruction::unpack(&ix.data), Ok(EscrowInstruction::Deposit {}))
+            && ix.accounts.get(2).is_some_and(|meta| meta.pubkey == *escrow_account.key);
+        if deposit {
+            return Ok(());
+        }
+    }
+    Err(EscrowError::DepositMissing.into())
+}
+
+fn process_initialize_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    items: Vec<(Pubkey, u64, u64)>,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+
+    // In strict mode the Instructions sysvar follows the items
+    let instructions = accounts.get(4 + items.len() * 3);
+    if a.len() != items.len() * 3 + usize::from(instructions.is_some()) {
+        return Err(EscrowError::InvalidBatch.into());
+    }
+    for (taker_key, amount, id) in items {
+        let taker          = next_account_info(a)?;
+        let escrow_account = next_account_info(a)?;
+        let vault          = next_account_info(a)?;
+        if *taker.key != taker_key {
+            return Err(EscrowError::InvalidBatch.into());
+        }
+        let state = create_escrow_account(
+            program_id,
+            initializer,
+            initializer,
+            taker,
+            escrow_account,
+            system_program,
+            config_account,
+            registry_account,
+            amount,
+            Pubkey::default(),
+            0,
+            Pubkey::default(),
+            Vec::new(),
+            0,
+            [0; 32],
+            events,
+        )?;
+        // The registry hands out the ids; the item's id only confirms it
+        if state.id != id {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        check_deposit_follows(program_id, config_account, escrow_account, instructions, features)?;
+        create_native_vault(program_id, escrow_account, vault, initializer, system_program)?;
+    }
+    Ok(())
+}
+
+fn process_initialize_and_deposit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    expiry_ts: i64,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+
+    let mut state = create_escrow_account(
+        program_id,
+        initializer,
+        initializer,
+        taker,
+        escrow_account,
+        system_program,
+        config_account,
+        registry_account,
+        amount,
+        Pubkey::default(),
+        expiry_ts,
+        optional_key(a),
+        Vec::new(),
+        0,
+        [0; 32],
+        events,
+    )?;
+    create_native_vault(program_id, escrow_account, vault, initializer, system_program)?;
+    // Fund in the same handler so an unfunded escrow never exists
+    state.deposited = deposit_lamports(initializer, vault, system_program, state.amount)?;
+    state.remaining = state.deposited;
+    state.status    = EscrowStatus::Funded;
+    msg!("Deposited {} lamports", state.amount);
+    EscrowFunded { escrow: *escrow_account.key, deposited: state.deposited }.emit(events)?;
+    Ok(())
+}
+
+/// Adds lamports towards the agreed amount; `None` tops up whatever is missing.
+/// The escrow becomes Funded once `deposited` reaches `amount`.
+fn process_deposit_additional(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    top_up: Option<u64>,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+    // Anyone may fund on the initializer's behalf by passing themselves here
+    let funder          = next_account_info(a).unwrap_or(initializer);
+
+    // Only the paying account must sign
+    if !funder.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_paused(program_id, config_account)?;
+    // The escrow stays bound to its stored parties whoever pays
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_not_crowdfund(&state)?;
+    check_status(&state, EscrowStatus::Created)?;
+    check_not_expired(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+    let missing = state.amount.saturating_sub(state.deposited);
+    let amount  = top_up.unwrap_or(missing);
+    if amount == 0 || amount > missing {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    // Transfer amount lamports from funder → vault
+    state.deposited += deposit_lamports(funder, vault, system_program, amount)?;
+    if state.deposited == state.amount {
+        state.remaining = state.deposited;
+        state.status    = EscrowStatus::Funded;
+        EscrowFunded { escrow: *escrow_account.key, deposited: state.deposited }.emit(events)?;
+    }
+    msg!("Deposited {} lamports, {} of {} funded", amount, state.deposited, state.amount);
+    Ok(())
+}
+
+fn process_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    events: Option<EventCpi>,
+    memo: Option<&AccountInfo>,
+    features: Features,
+) -> ProgramResult {
+    let a       = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_withdraw(program_id, &state, initializer, taker, escrow_account, vault)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+    let referrer        = if state.referrer == Pubkey::default() {
+        None
+    } else {
+        let referrer = next_account_info(a)?;
+        if *referrer.key != state.referrer {
+            return Err(EscrowError::WrongReferrer.into());
+        }
+        Some(referrer)
+    };
+    if !state.splits().is_empty() {
+        release_split(program_id, &mut state, escrow_account, vault, taker, config_account, treasury, referrer, a, events, features)?;
+    } else {
+        // The oracle, when the escrow has one, comes before the optional
+        // recipient so that leaving the recipient out can't shift it there
+        check_price_condition(&state, a)?;
+        // The taker's signature authorizes the release wherever it is sent
+        let recipient   = if taker.is_signer { next_account_info(a).unwrap_or(taker) } else { taker };
+        release_with_fee(program_id, &mut state, escrow_account, vault, recipient, config_account, treasury, referrer, events, features)?;
+    }
+    log_settlement_memo(memo, escrow_account, &state, "released")
+}
+
+/// Signatures and state a native Withdraw needs, before any of the
+/// escrow's optional accounts.
+fn check_withdraw(
+    program_id: &Pubkey,
+    state: &EscrowState,
+    initializer: &AccountInfo,
+    taker: &AccountInfo,
+    escrow_account: &AccountInfo,
+    vault: &AccountInfo,
+) -> ProgramResult {
+    // Reaching a crowdfund's goal is the funders' consent, so the taker signs
+    // alone; issuing an invoice was the taker's, so its payer does
+    if (!taker.is_signer && !state.is_invoice()) || (!initializer.is_signer && !bool::from(state.crowdfund)) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_initializer(state, initializer)?;
+    check_taker(state, taker)?;
+    check_mint(state, &Pubkey::default())?;
+    check_not_stake(state)?;
+    check_status(state, EscrowStatus::Funded)?;
+    check_vault(program_id, escrow_account, vault)
+}
+
+/// Withdraws every escrow in the trailing `[initializer, escrow, vault]`
+/// triples to the taker. Escrows that need more accounts to release (a
+/// referrer, splits or a price condition) must go through Withdraw.
+fn process_withdraw_batch(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    if a.len() == 0 || !a.len().is_multiple_of(3) || a.len() > MAX_BATCH_LEN * 3 {
+        return Err(EscrowError::InvalidBatch.into());
+    }
+    while let Some(initializer) = a.next() {
+        let escrow_account = next_account_info(a)?;
+        let vault          = next_account_info(a)?;
+        let mut state = load_escrow(program_id, escrow_account)?;
+        check_withdraw(program_id, &state, initializer, taker, escrow_account, vault)?;
+        if state.referrer != Pubkey::default() || state.oracle != Pubkey::default() {
+            return Err(EscrowError::InvalidBatch.into());
+        }
+        release_with_fee(program_id, &mut state, escrow_account, vault, taker, config_account, treasury, None, events, features)?;
+    }
+    Ok(())
+}
+
+/// Moves the protocol fee out of the vault: the config's referral share to
+/// `referrer`, if any, and the rest to the treasury.
+fn pay_fee(
+    config: &ConfigState,
+    vault: &AccountInfo,
+    treasury: &AccountInfo,
+    referrer: Option<&AccountInfo>,
+    fee: u64,
+) -> ProgramResult {
+    let referral = match referrer {
+        Some(referrer) => {
+            let referral = (fee as u128 * config.referral_bps as u128 / 10_000) as u64;
+            move_lamports(vault, referrer, referral)?;
+            referral
+        }
+        None => 0,
+    };
+    move_lamports(vault, treasury, fee - referral)
+}
+
+/// `release_with_fee` for a split escrow: the payout, less the fee, is
+/// shared between the split recipients that follow in `a`, and the taker's
+/// bond goes back to the taker.
+#[allow(clippy::too_many_arguments)]
+fn release_split(
+    program_id: &Pubkey,
+    state: &mut Escrow,
+    escrow_account: &AccountInfo,
+    vault: &AccountInfo,
+    taker: &AccountInfo,
+    config_account: &AccountInfo,
+    treasury: &AccountInfo,
+    referrer: Option<&AccountInfo>,
+    a: &mut std::slice::Iter<AccountInfo>,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    let config = load_config(program_id, config_account)?;
+    if config.treasury != *treasury.key {
+        return Err(EscrowError::WrongTreasury.into());
+    }
+
+    check_bond(state)?;
+    let payout = state.remaining;
+    let fee    = fee_for(&config, features, state, payout)?;
+    pay_fee(&config, vault, treasury, referrer, fee)?;
+    pay_splits(state.splits(), vault, payout - fee, a)?;
+    check_price_condition(state, a)?;
+    release_bond(state, vault, taker)?;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Released;
+    msg!("Withdrew {} lamports to {} recipients, fee {}", payout - fee, state.splits().len(), fee);
+    EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: payout - fee, fee }.emit(events)?;
+    Ok(())
+}
+
+/// Shares `amount` between `splits`, whose recipients must come next in `a`
+/// in the same order. Shares round down and the last recipient gets the dust.
+fn pay_splits(
+    splits: &[Split],
+    vault: &AccountInfo,
+    amount: u64,
+    a: &mut std::slice::Iter<AccountInfo>,
+) -> ProgramResult {
+    let mut left = amount;
+    for (i, split) in splits.iter().enumerate() {
+        let recipient = next_account_info(a)?;
+        if *recipient.key != split.recipient {
+            return Err(EscrowError::InvalidSplit.into());
+        }
+        let share = if i + 1 == splits.len() {
+            left
+        } else {
+            (amount as u128 * split.bps as u128 / 10_000) as u64
+        };
+        move_lamports(vault, recipient, share)?;
+        left -= share;
+    }
+    Ok(())
+}
+
+fn check_no_splits(state: &EscrowState) -> ProgramResult {
+    if state.split_count > 0 {
+        return Err(EscrowError::Split.into());
+    }
+    Ok(())
+}
+
+/// Pays everything that remains in the vault to `recipient`, less the
+/// protocol fee, and marks the escrow Released. The taker's bond, which
+/// must be in, goes along fee-free. Only Withdraw passes the `referrer`;
+/// on the other release paths the whole fee goes to the treasury.
+#[allow(clippy::too_many_arguments)]
+fn release_with_fee(
+    program_id: &Pubkey,
+    state: &mut EscrowState,
+    escrow_account: &AccountInfo,
+    vault: &AccountInfo,
+    recipient: &AccountInfo,
+    config_account: &AccountInfo,
+    treasury: &AccountInfo,
+    referrer: Option<&AccountInfo>,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    check_no_splits(state)?;
+    let config = load_config(program_id, config_account)?;
+    if config.treasury != *treasury.key {
+        return Err(EscrowError::WrongTreasury.into());
+    }
+
+    check_bond(state)?;
+    let payout = state.remaining;
+    let fee    = fee_for(&config, features, state, payout)?;
+    pay_fee(&config, vault, treasury, referrer, fee)?;
+    move_lamports(vault, recipient, payout - fee)?;
+    release_bond(state, vault, recipient)?;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Released;
+    msg!("Withdrew {} lamports, fee {}", payout - fee, fee);
+    EscrowReleased { escrow: *escrow_account.key, recipient: *recipient.key, amount: payout - fee, fee }.emit(events)?;
+    Ok(())
+}
+
+/// Pays `amount` from a native vault to `recipient`, less the protocol fee,
+/// which all goes to the treasury, and returns the fee. For the paths that
+/// release an escrow tranche by tranche.
+#[allow(clippy::too_many_arguments)]
+fn pay_tranche(
+    program_id: &Pubkey,
+    state: &EscrowState,
+    vault: &AccountInfo,
+    recipient: &AccountInfo,
+    config_account: &AccountInfo,
+    treasury: &AccountInfo,
+    amount: u64,
+    features: Features,
+) -> Result<u64, ProgramError> {
+    let config = load_config(program_id, config_account)?;
+    if config.treasury != *treasury.key {
+        return Err(EscrowError::WrongTreasury.into());
+    }
+    let fee = fee_for(&config, features, state, amount)?;
+    pay_fee(&config, vault, treasury, None, fee)?;
+    move_lamports(vault, recipient, amount - fee)?;
+    Ok(fee)
+}
+
+/// Pays out one tranche to the taker. Native escrows pay lamports from the
+/// vault, less the protocol fee as Withdraw charges it; token escrows also
+/// pass the taker token account, mint and token program, and pay no fee, as
+/// WithdrawToken doesn't.
+fn process_withdraw_partial(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_swap(&state)?;
+    check_not_crowdfund(&state)?;
+    if state.oracle != Pubkey::default() {
+        return Err(EscrowError::PriceConditioned.into());
+    }
+    if amount == 0 || amount > state.remaining {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    check_no_splits(&state)?;
+    check_bond(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+    let config = load_config(program_id, config_account)?;
+    if config.treasury != *treasury.key {
+        return Err(EscrowError::WrongTreasury.into());
+    }
+
+    let mut fee = 0;
+    if state.mint == Pubkey::default() {
+        fee = fee_for(&config, features, &state, amount)?;
+        pay_fee(&config, vault, treasury, None, fee)?;
+        move_lamports(vault, taker, amount - fee)?;
+    } else {
+        let taker_token   = next_account_info(a)?;
+        let mint          = next_account_info(a)?;
+        let token_program = next_token_program(a)?;
+        check_token_program(token_program, mint)?;
+        check_mint(&state, mint.key)?;
+        let signer = EscrowSigner::of(&state);
+        drop(state);
+        release_tokens(signer, escrow_account, vault, taker_token, mint, token_program, amount)?;
+        state = escrow_state(escrow_account)?;
+    }
+    state.remaining -= amount;
+    if state.remaining == 0 {
+        release_bond(&mut state, vault, taker)?;
+        state.status = EscrowStatus::Released;
+        EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: amount - fee, fee }.emit(events)?;
+    }
+    msg!("Withdrew {}, fee {}, {} remaining", amount - fee, fee, state.remaining);
+    Ok(())
+}
+
+fn process_cancel(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+
+    // Only initializer must sign
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // A copy, as the account is closed below
+    let state = *load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    // Token escrows keep their funds in the vault, which this path does not close
+    check_mint(&state, &Pubkey::default())?;
+    // Until the taker consents, the funds aren't committed to them
+    if !(state.status == EscrowStatus::Funded && bool::from(state.taker_pending)) {
+        check_status(&state, EscrowStatus::Created)?;
+    }
+    // A crowdfund can only be cancelled before anyone contributed
+    if bool::from(state.crowdfund) && state.deposited > 0 {
+        return Err(EscrowError::Crowdfund.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    // Partial deposits and the vault's rent go back along with the state rent
+    move_lamports(vault, initializer, vault.lamports())?;
+    EscrowCancelled { escrow: *escrow_account.key, refunded: state.deposited }.emit(events)?;
+    if state.merkle_root != [0; 32] {
+        close_claimed(program_id, escrow_account, next_account_info(a)?, initializer)?;
+    }
+    forfeit_creation_fee(program_id, &state, escrow_account, a)?;
+    unregister_escrow(program_id, initializer, registry_account, state.id)?;
+    close_escrow_account(escrow_account, initializer)
+}
+
+fn process_refund(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    events: Option<EventCpi>,
+    memo: Option<&AccountInfo>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+
+    // Only initializer must sign
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_expired_refund(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    // The vault keeps its rent until Close; the taker defaulted, so their
+    // bond is forfeited to the initializer
+    let refund = state.remaining;
+    move_lamports(vault, initializer, refund)?;
+    release_bond(&mut state, vault, initializer)?;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Cancelled;
+    msg!("Refunded {} lamports after expiry", refund);
+    EscrowCancelled { escrow: *escrow_account.key, refunded: refund }.emit(events)?;
+    log_settlement_memo(memo, escrow_account, &state, "refunded")
+}
+
+/// Refund, and CrankRefund, apply to funded native escrows past their expiry.
+fn check_expired_refund(state: &EscrowState) -> ProgramResult {
+    check_mint(state, &Pubkey::default())?;
+    check_not_crowdfund(state)?;
+    check_not_stake(state)?;
+    check_status(state, EscrowStatus::Funded)?;
+    check_past_expiry(state)
+}
+
+fn check_past_expiry(state: &EscrowState) -> ProgramResult {
+    let clock = Clock::get()?;
+    if state.expiry_ts == 0 || clock.unix_timestamp <= state.expiry_ts {
+        return Err(EscrowError::NotExpired.into());
+    }
+    Ok(())
+}
+
+fn process_crank_refund(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let cranker         = next_account_info(a)?;
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+
+    if !cranker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_expired_refund(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+    let config = load_config(program_id, config_account)?;
+
+    // As with Refund, the bond is forfeited to the initializer, who bears
+    // the bounty out of the refund
+    let bounty = (state.remaining as u128 * config.crank_bounty_bps as u128 / 10_000) as u64;
+    let refund = state.remaining - bounty;
+    move_lamports(vault, cranker, bounty)?;
+    move_lamports(vault, initializer, refund)?;
+    release_bond(&mut state, vault, initializer)?;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Cancelled;
+    msg!("Refunded {} lamports after expiry, bounty {}", refund, bounty);
+    EscrowCancelled { escrow: *escrow_account.key, refunded: refund }.emit(events)?;
+    Ok(())
+}
+
+fn process_close(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+
+    // Only initializer must sign
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // A copy, as the account is closed below
+    let state = *load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    match state.status {
+        EscrowStatus::Released | EscrowStatus::Cancelled => {}
+        EscrowStatus::Closed => return Err(EscrowError::AlreadySettled.into()),
+        _ => return Err(EscrowError::NotSettled.into()),
+    }
+    if state.taker_deposited > 0 {
+        return Err(EscrowError::TakerDeposit.into());
+    }
+
+    // Hand back the (now empty) vault's rent as well
+    check_vault(program_id, escrow_account, vault)?;
+    if state.mint == Pubkey::default() {
+        move_lamports(vault, initializer, vault.lamports())?;
+        if state.merkle_root != [0; 32] {
+            close_claimed(program_id, escrow_account, next_account_info(a)?, initializer)?;
+        }
+    } else {
+        let token_program = next_token_program(a)?;
+        spl_token_2022::check_spl_token_program_account(token_program.key)?;
+        // WithdrawSol already closed a wrapped SOL vault
+        if vault.lamports() > 0 {
+            close_vault(EscrowSigner::of(&state), escrow_account, vault, initializer, token_program)?;
+        }
+    }
+    forfeit_creation_fee(program_id, &state, escrow_account, a)?;
+    unregister_escrow(program_id, initializer, registry_account, state.id)?;
+    close_escrow_account(escrow_account, initializer)
+}
+
+/// Closes the vault token account, sending its rent to `destination`.
+fn close_vault<'a>(
+    signer: EscrowSigner,
+    escrow_account: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    invoke_signed(
+        &spl_token_2022::instruction::close_account(
+            token_program.key,
+            vault.key,
+            destination.key,
+            escrow_account.key,
+            &[],
+        )?,
+        &[vault.clone(), destination.clone(), escrow_account.clone()],
+        &[&signer.seeds()],
+    )
+}
+
+/// Pays the creation fee of an escrow closing without having been released
+/// to the treasury, taken with the config from the next accounts
+/// (`with_creation_fee`). A released escrow keeps it, to be reclaimed with
+/// its rent.
+fn forfeit_creation_fee(
+    program_id: &Pubkey,
+    state: &EscrowState,
+    escrow_account: &AccountInfo,
+    a: &mut std::slice::Iter<AccountInfo>,
+) -> ProgramResult {
+    if state.creation_fee == 0 || state.status == EscrowStatus::Released {
+        return Ok(());
+    }
+    let config_account = next_account_info(a)?;
+    let treasury       = next_account_info(a)?;
+    if load_config(program_id, config_account)?.treasury != *treasury.key {
+        return Err(EscrowError::WrongTreasury.into());
+    }
+    move_lamports(escrow_account, treasury, state.creation_fee)?;
+    msg!("Creation fee of {} lamports forfeited", state.creation_fee);
+    Ok(())
+}
+
+/// Zeroes everything but the Closed marker, so the account can't be reused,
+/// and moves all of its lamports to `destination`.
+fn close_escrow_account(escrow_account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    {
+        let mut data = escrow_account.data.borrow_mut();
+        data.fill(0);
+        EscrowState::from_bytes_mut(&mut data).ok_or(ProgramError::InvalidAccountData)?.status = EscrowStatus::Closed;
+    }
+    let reclaimed = escrow_account.lamports();
+    move_lamports(escrow_account, destination, reclaimed)?;
+    msg!("Closed escrow, reclaimed {} lamports", reclaimed);
+    Ok(())
+}
+
+/// Unwinds a live escrow with both parties' consent: whatever is still held
+/// goes back to the initializer and the escrow (and its vault) is closed.
+fn process_mutual_cancel(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // A copy, as the account is closed below
+    let mut state = *load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    match state.status {
+        EscrowStatus::Created | EscrowStatus::Funded => {}
+        EscrowStatus::Disputed | EscrowStatus::Ruled => return Err(EscrowError::Disputed.into()),
+        _ => return Err(EscrowError::AlreadySettled.into()),
+    }
+    // The taker must ReclaimSwap their leg before the escrow goes away
+    if state.taker_deposited > 0 {
+        return Err(EscrowError::TakerDeposit.into());
+    }
+    check_not_crowdfund(&state)?;
+    check_not_stake(&state)?;
+
+    check_vault(program_id, escrow_account, vault)?;
+    if state.mint == Pubkey::default() {
+        // Deposits and the vault's rent go back together, the bond to the taker
+        release_bond(&mut state, vault, taker)?;
+        let held = vault.lamports();
+        move_lamports(vault, initializer, held)?;
+        msg!("Refunded {} lamports", held);
+        // Before funding, deposits have not reached `remaining` yet
+        let refunded = if state.status == EscrowStatus::Funded { state.remaining } else { state.deposited };
+        EscrowCancelled { escrow: *escrow_account.key, refunded }.emit(events)?;
+    } else {
+        let initializer_token = next_account_info(a)?;
+        let mint              = next_account_info(a)?;
+        let token_program     = next_token_program(a)?;
+        check_token_program(token_program, mint)?;
+        check_mint(&state, mint.key)?;
+        let held   = token_balance(vault)?;
+        let signer = EscrowSigner::of(&state);
+        if held > 0 {
+            release_tokens(signer, escrow_account, vault, initializer_token, mint, token_program, held)?;
+        }
+        close_vault(signer, escrow_account, vault, initializer, token_program)?;
+        msg!("Refunded {} tokens", held);
+        EscrowCancelled { escrow: *escrow_account.key, refunded: held }.emit(events)?;
+    }
+    forfeit_creation_fee(program_id, &state, escrow_account, a)?;
+    unregister_escrow(program_id, initializer, registry_account, state.id)?;
+    close_escrow_account(escrow_account, initializer)
+}
+
+fn process_accept_escrow(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    // The named taker consents to an escrow created without their signature
+    let confirm = bool::from(state.taker_pending) && state.taker_pubkey == *taker.key;
+    if state.taker_pubkey != Pubkey::default() && !confirm {
+        return Err(EscrowError::TakerAlreadySet.into());
+    }
+    if *taker.key == state.initializer_pubkey {
+        return Err(EscrowError::WrongTaker.into());
+    }
+    check_allowed(&state, taker.key)?;
+    check_not_distribution(&state)?;
+    match state.status {
+        EscrowStatus::Created | EscrowStatus::Funded => {}
+        _ => return Err(EscrowError::AlreadySettled.into()),
+    }
+    check_not_expired(&state)?;
+
+    state.taker_pubkey  = *taker.key;
+    state.taker_pending = false.into();
+    msg!("Escrow accepted by {}", taker.key);
+    Ok(())
+}
+
+/// Hands the escrow to another taker without moving it to a new PDA.
+fn process_set_taker(program_id: &Pubkey, accounts: &[AccountInfo], new_taker: Pubkey) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let new_taker_info  = next_account_info(a)?;
+
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_not_distribution(&state)?;
+    match state.status {
+        EscrowStatus::Created => {}
+        // Funds are committed to the current taker, who has to agree
+        EscrowStatus::Funded if state.taker_pubkey == Pubkey::default() || taker.is_signer => {}
+        EscrowStatus::Funded => return Err(ProgramError::MissingRequiredSignature),
+        EscrowStatus::Disputed | EscrowStatus::Ruled => return Err(EscrowError::Disputed.into()),
+        _ => return Err(EscrowError::AlreadySettled.into()),
+    }
+    if *new_taker_info.key != new_taker || new_taker == state.initializer_pubkey {
+        return Err(EscrowError::WrongTaker.into());
+    }
+    if new_taker != Pubkey::default() && !new_taker_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // Whatever the current taker put in is theirs to take back first
+    if state.taker_deposited > 0 {
+        return Err(EscrowError::TakerDeposit.into());
+    }
+    if bool::from(state.bond_held) {
+        return Err(EscrowError::BondPosted.into());
+    }
+    if bool::from(state.crowdfund) && state.deposited > 0 {
+        return Err(EscrowError::Crowdfund.into());
+    }
+
+    state.taker_pubkey          = new_taker;
+    state.taker_pending         = false.into();
+    state.withdraw_requested_at = 0;
+    Ok(())
+}
+
+/// Renegotiates the amount before funding. Partial deposits count towards
+/// the new amount, so it cannot drop below them; meeting them exactly funds
+/// the escrow.
+fn process_update_amount(program_id: &Pubkey, accounts: &[AccountInfo], new_amount: u64, events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_new_amount(&state, new_amount)?;
+
+    set_amount(&mut state, escrow_account, new_amount, events)?;
+    Ok(())
+}
+
+/// UpdateAmount and AcceptTerms change the amount of unfunded escrows only.
+fn check_new_amount(state: &EscrowState, new_amount: u64) -> ProgramResult {
+    check_status(state, EscrowStatus::Created)?;
+    check_not_crowdfund(state)?;
+    // A milestone escrow's amount is the sum of its tranches
+    if state.milestone_count > 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if new_amount < state.deposited {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    Ok(())
+}
+
+/// Lowering the amount to what was deposited funds the escrow.
+fn set_amount(state: &mut EscrowState, escrow_account: &AccountInfo, new_amount: u64, events: Option<EventCpi>) -> ProgramResult {
+    msg!("Amount changed from {} to {}", state.amount, new_amount);
+    state.amount = new_amount;
+    if state.deposited == state.amount {
+        state.remaining = state.deposited;
+        state.status    = EscrowStatus::Funded;
+        EscrowFunded { escrow: *escrow_account.key, deposited: state.deposited }.emit(events)?;
+    }
+    Ok(())
+}
+
+fn process_extend_deadline(program_id: &Pubkey, accounts: &[AccountInfo], new_expiry: i64) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    match state.status {
+        EscrowStatus::Created | EscrowStatus::Funded => {}
+        EscrowStatus::Disputed | EscrowStatus::Ruled => return Err(EscrowError::Disputed.into()),
+        _ => return Err(EscrowError::AlreadySettled.into()),
+    }
+    // Contributors agreed to the crowdfund's deadline, not just the parties
+    check_not_crowdfund(&state)?;
+    let config = load_config(program_id, config_account)?;
+    let now    = Clock::get()?.unix_timestamp;
+    if state.expiry_ts == 0
+        || new_expiry <= state.expiry_ts
+        || new_expiry > now.saturating_add(config.max_expiry_horizon)
+    {
+        return Err(EscrowError::InvalidExpiry.into());
+    }
+
+    state.expiry_ts = new_expiry;
+    Ok(())
+}
+
+/// Either party records a revised amount and expiry, replacing any
+/// pending proposal, so the other party can counter with their own.
+fn process_propose_terms(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, expiry_ts: i64) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let proposer        = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+
+    if !proposer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_party(&state, proposer.key)?;
+    check_new_amount(&state, amount)?;
+    let config = load_config(program_id, config_account)?;
+    let now    = Clock::get()?.unix_timestamp;
+    if expiry_ts != 0 && (expiry_ts <= now || expiry_ts > now.saturating_add(config.max_expiry_horizon)) {
+        return Err(EscrowError::InvalidExpiry.into());
+    }
+
+    state.proposed_by     = *proposer.key;
+    state.proposed_amount = amount;
+    state.proposed_expiry = expiry_ts;
+    msg!("Proposed {} until {}", amount, expiry_ts);
+    Ok(())
+}
+
+/// The party who didn't propose the pending terms applies them.
+fn process_accept_terms(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let accepter        = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !accepter.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_party(&state, accepter.key)?;
+    if state.proposed_by == Pubkey::default() || state.proposed_by == *accepter.key {
+        return Err(EscrowError::NoProposal.into());
+    }
+    check_new_amount(&state, state.proposed_amount)?;
+    let expiry_ts = state.proposed_expiry;
+    if expiry_ts != 0 && expiry_ts <= Clock::get()?.unix_timestamp {
+        return Err(EscrowError::InvalidExpiry.into());
+    }
+
+    let amount = state.proposed_amount;
+    state.expiry_ts       = expiry_ts;
+    state.proposed_by     = Pubkey::default();
+    state.proposed_amount = 0;
+    state.proposed_expiry = 0;
+    set_amount(&mut state, escrow_account, amount, events)?;
+    Ok(())
+}
+
+fn check_party(state: &EscrowState, key: &Pubkey) -> ProgramResult {
+    if *key != state.initializer_pubkey && *key != state.taker_pubkey {
+        return Err(EscrowError::NotParty.into());
+    }
+    Ok(())
+}
+
+/// Open escrows with an allowlist can only be taken by its members.
+fn check_allowed(state: &Escrow, taker: &Pubkey) -> ProgramResult {
+    if !state.allowed_takers().is_empty() && !state.allowed_takers().contains(taker) {
+        return Err(EscrowError::NotAllowed.into());
+    }
+    Ok(())
+}
+
+fn process_set_allowed_takers(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    takers: Vec<Pubkey>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_status(&state, EscrowStatus::Created)?;
+    check_not_distribution(&state)?;
+    if state.taker_pubkey != Pubkey::default() {
+        return Err(EscrowError::InvalidAllowlist.into());
+    }
+    if takers.is_empty() || takers.len() > MAX_ALLOWED_TAKERS || takers.contains(&state.initializer_pubkey) {
+        return Err(EscrowError::InvalidAllowlist.into());
+    }
+
+    let space = state.space() - state.allowed_takers().len() * 32 + takers.len() * 32;
+    drop(state);
+    resize_escrow(escrow_account, space, initializer, system_program, |state| state.set_allowed_takers(&takers))
+}
+
+/// Both parties fix the terms document once, before the escrow settles;
+/// its hash then pins the content behind the URI.
+fn process_set_metadata(program_id: &Pubkey, accounts: &[AccountInfo], uri: String, hash: [u8; 32]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    match state.status {
+        EscrowStatus::Created | EscrowStatus::Funded => {}
+        EscrowStatus::Disputed | EscrowStatus::Ruled => return Err(EscrowError::Disputed.into()),
+        _ => return Err(EscrowError::AlreadySettled.into()),
+    }
+    if state.metadata_hash != [0; 32] {
+        return Err(EscrowError::TermsAlreadySet.into());
+    }
+
+    let space = state.space() - state.metadata_uri().len() + uri.len();
+    drop(state);
+    resize_escrow(escrow_account, space, initializer, system_program, |state| {
+        state.metadata_hash = hash;
+        state.set_metadata_uri(uri.as_bytes());
+    })
+}
+
+/// Rewrites the lists of the escrow with `update`, resizing the account to
+/// the `space` they take afterwards, with the initializer covering extra
+/// rent and getting back any surplus.
+fn resize_escrow<'a>(
+    escrow_account: &AccountInfo<'a>,
+    space: usize,
+    initializer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    update: impl FnOnce(&mut Escrow),
+) -> ProgramResult {
+    let held = escrow_state(escrow_account)?.creation_fee;
+    // Replacing a list moves the ones after it, so grow first and shrink last
+    if space > escrow_account.data_len() {
+        grow_account(escrow_account, space, held, initializer, system_program)?;
+        update(&mut escrow_state(escrow_account)?);
+        Ok(())
+    } else {
+        update(&mut escrow_state(escrow_account)?);
+        shrink_account(escrow_account, space, held, initializer)
+    }
+}
+
+/// Grows an escrow of an older layout version to the current header,
+/// moving its lists after it, and stamps it with the current version.
+fn process_migrate_state(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let payer           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+
+    if !payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if escrow_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    // A v1 header has no version and reads as version 0
+    let version = escrow_account.try_borrow_data()?.get(offset_of!(EscrowState, version)).copied();
+    let old_len = match version {
+        Some(version) if version < ESCROW_STATE_VERSION => ESCROW_STATE_LENS[usize::from(version.max(1)) - 1],
+        _ => return Err(EscrowError::StateVersion.into()),
+    };
+    // The fields added since read as zeros
+    let mut header = escrow_account.try_borrow_data()?.get(..old_len).ok_or(EscrowError::NotInitialized)?.to_vec();
+    header.resize(ESCROW_STATE_LEN, 0);
+    let state = EscrowState::unpack(&header)?;
+    check_escrow_state(program_id, escrow_account, &state)?;
+
+    let len = escrow_account.data_len();
+    grow_account(escrow_account, len + ESCROW_STATE_LEN - old_len, 0, payer, system_program)?;
+    migrate_escrow_data(&mut escrow_account.try_borrow_mut_data()?, old_len);
+    msg!("Escrow migrated to version {}", ESCROW_STATE_VERSION);
+    Ok(())
+}
+
+fn process_claim_allowed(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let claimant        = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    // Being listed by the initializer stands in for their signature
+    if !claimant.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    if state.allowed_takers().is_empty() {
+        return Err(EscrowError::InvalidAllowlist.into());
+    }
+    // A member who already accepted the escrow can still claim it
+    if state.taker_pubkey != Pubkey::default() && state.taker_pubkey != *claimant.key {
+        return Err(EscrowError::TakerAlreadySet.into());
+    }
+    check_allowed(&state, claimant.key)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_expired(&state)?;
+    if state.oracle != Pubkey::default() {
+        return Err(EscrowError::PriceConditioned.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    state.taker_pubkey = *claimant.key;
+    release_with_fee(program_id, &mut state, escrow_account, vault, claimant, config_account, treasury, None, events, features)
+}
+
+/// Creates a native escrow without a taker whose `total` is paid out by
+/// ClaimDistribution, plus the bitmap PDA recording which of the
+/// `num_claims` leaves of `merkle_root` were claimed.
+fn process_initialize_distribution(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    total: u64,
+    merkle_root: [u8; 32],
+    num_claims: u32,
+    expiry_ts: i64,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+    let claimed_account = next_account_info(a)?;
+
+    if merkle_root == [0; 32] {
+        return Err(EscrowError::InvalidProof.into());
+    }
+    // The system program's id is the default pubkey, so it stands in for
+    // the taker and leaves the escrow open
+    let mut state = create_escrow_account(
+        program_id,
+        initializer,
+        initializer,
+        system_program,
+        escrow_account,
+        system_program,
+        config_account,
+        registry_account,
+        total,
+        Pubkey::default(),
+        expiry_ts,
+        Pubkey::default(),
+        Vec::new(),
+        0,
+        [0; 32],
+        events,
+    )?;
+    create_native_vault(program_id, escrow_account, vault, initializer, system_program)?;
+    state.merkle_root = merkle_root;
+
+    let (claimed_pda, claimed_bump) = find_claimed_address(program_id, escrow_account.key);
+    if claimed_pda != *claimed_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let claimed = ClaimedState {
+        discriminator: CLAIMED_DISCRIMINATOR,
+        escrow:        *escrow_account.key,
+        bump:          claimed_bump,
+        claimed:       vec![0; (num_claims as usize).div_ceil(8)],
+    };
+    let space = CLAIMED_STATE_LEN + claimed.claimed.len();
+    create_pda_account(
+        initializer,
+        claimed_account,
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        program_id,
+        system_program,
+        &[CLAIMED_PDA_SEED, escrow_account.key.as_ref(), &[claimed_bump]],
+    )?;
+    claimed.serialize(&mut &mut claimed_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+/// Pays one allocation of a funded distribution to its claimant, less the
+/// protocol fee, and marks its leaf as claimed. The last claim releases
+/// the escrow.
+fn process_claim_distribution(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    index: u32,
+    amount: u64,
+    proof: Vec<[u8; 32]>,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let claimant        = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let claimed_account = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    if !claimant.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    if state.merkle_root == [0; 32] {
+        return Err(EscrowError::NotDistribution.into());
+    }
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_expired(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+    let mut claimed = load_claimed(program_id, escrow_account, claimed_account)?;
+    let (byte, bit) = (index as usize / 8, 1u8 << (index % 8));
+    match claimed.claimed.get(byte) {
+        None => return Err(EscrowError::InvalidProof.into()),
+        Some(flags) if flags & bit != 0 => return Err(EscrowError::AlreadyClaimed.into()),
+        Some(_) => {}
+    }
+    let leaf = merkle::distribution_leaf(index, claimant.key, amount);
+    if !merkle::verify(&proof, &state.merkle_root, leaf) {
+        return Err(EscrowError::InvalidProof.into());
+    }
+    if amount > state.remaining {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+
+    let config = load_config(program_id, config_account)?;
+    if config.treasury != *treasury.key {
+        return Err(EscrowError::WrongTreasury.into());
+    }
+    let fee = fee_for(&config, features, &state, amount)?;
+    move_lamports(vault, treasury, fee)?;
+    move_lamports(vault, claimant, amount - fee)?;
+    claimed.claimed[byte] |= bit;
+    claimed.serialize(&mut &mut claimed_account.data.borrow_mut()[..])?;
+
+    state.remaining -= amount;
+    if state.remaining == 0 {
+        state.status = EscrowStatus::Released;
+        EscrowReleased { escrow: *escrow_account.key, recipient: *claimant.key, amount: amount - fee, fee }.emit(events)?;
+    }
+    msg!("Claimed {} lamports, fee {}, {} remaining", amount - fee, fee, state.remaining);
+    Ok(())
+}
+
+fn load_claimed(
+    program_id: &Pubkey,
+    escrow_account: &AccountInfo,
+    claimed_account: &AccountInfo,
+) -> Result<ClaimedState, ProgramError> {
+    if claimed_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let claimed = ClaimedState::try_from_slice(&claimed_account.data.borrow())?;
+    if claimed.discriminator != CLAIMED_DISCRIMINATOR {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if claimed.escrow != *escrow_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(claimed)
+}
+
+/// Closes a distribution's claimed bitmap, sending its rent to `destination`.
+fn close_claimed(
+    program_id: &Pubkey,
+    escrow_account: &AccountInfo,
+    claimed_account: &AccountInfo,
+    destination: &AccountInfo,
+) -> ProgramResult {
+    load_claimed(program_id, escrow_account, claimed_account)?;
+    claimed_account.data.borrow_mut().fill(0);
+    move_lamports(claimed_account, destination, claimed_account.lamports())
+}
+
+fn process_dispute(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let party           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !party.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    if *party.key != state.initializer_pubkey && *party.key != state.taker_pubkey {
+        return Err(EscrowError::NotParty.into());
+    }
+    if state.arbiter_pubkey == Pubkey::default() {
+        return Err(EscrowError::NoArbiter.into());
+    }
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_swap(&state)?;
+    check_not_crowdfund(&state)?;
+    check_not_stake(&state)?;
+
+    state.status = EscrowStatus::Disputed;
+    msg!("Escrow disputed by {}", party.key);
+    Ok(())
+}
+
+fn process_request_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_swap(&state)?;
+    check_not_crowdfund(&state)?;
+    if state.oracle != Pubkey::default() {
+        return Err(EscrowError::PriceConditioned.into());
+    }
+    // Without an arbiter the initializer would have no way to object
+    if state.arbiter_pubkey == Pubkey::default() {
+        return Err(EscrowError::NoArbiter.into());
+    }
+    if state.withdraw_requested_at != 0 {
+        return Err(EscrowError::WithdrawRequested.into());
+    }
+
+    state.withdraw_requested_at = Clock::get()?.unix_timestamp;
+    state.taker_pending         = false.into();
+    msg!("Withdraw requested, final after {}", state.withdraw_requested_at + dispute_window(&state));
+    Ok(())
+}
+
+/// `DISPUTE_WINDOW_SECS`, or the window of the escrow's template.
+fn dispute_window(state: &EscrowState) -> i64 {
+    if state.template == Pubkey::default() { DISPUTE_WINDOW_SECS } else { state.dispute_window_secs }
+}
+
+fn process_finalize_withdraw(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    // A Dispute moves the escrow out of Funded, which blocks this
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    if state.withdraw_requested_at == 0 {
+        return Err(EscrowError::WithdrawNotRequested.into());
+    }
+    if Clock::get()?.unix_timestamp < state.withdraw_requested_at + dispute_window(&state) {
+        return Err(EscrowError::DisputeWindowOpen.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    release_with_fee(program_id, &mut state, escrow_account, vault, taker, config_account, treasury, None, events, features)
+}
+
+fn process_set_taker_bond(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Created)?;
+    check_not_crowdfund(&state)?;
+    // Vesting, subscription and milestone payouts never release the escrow in one go
+    if state.end_ts != 0 || state.subscription.is_set() || state.milestone_count > 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    state.taker_bond = amount;
+    Ok(())
+}
+
+fn process_set_splits(program_id: &Pubkey, accounts: &[AccountInfo], splits: Vec<Split>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Created)?;
+    check_not_crowdfund(&state)?;
+    // Vesting, subscription and milestone payouts never release the escrow in one go
+    if state.end_ts != 0 || state.subscription.is_set() || state.milestone_count > 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let space = state.space() - state.splits().len() * SPLIT_LEN + splits.len() * SPLIT_LEN;
+    drop(state);
+    resize_escrow(escrow_account, space, initializer, system_program, |state| state.set_splits(&splits))
+}
+
+fn process_deposit_bond(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_paused(program_id, config_account)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    // Only once funded, so Cancel never has a bond to hand back
+    check_status(&state, EscrowStatus::Funded)?;
+    if state.taker_bond == 0 {
+        return Err(EscrowError::NoBond.into());
+    }
+    if bool::from(state.bond_held) {
+        return Err(EscrowError::BondPosted.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    deposit_lamports(taker, vault, system_program, state.taker_bond)?;
+    state.bond_held     = true.into();
+    state.taker_pending = false.into();
+    msg!("Taker bond of {} lamports posted", state.taker_bond);
+    Ok(())
+}
+
+/// Paying out to the taker requires their bond, if the escrow asks for one.
+fn check_bond(state: &EscrowState) -> ProgramResult {
+    if state.taker_bond > 0 && !bool::from(state.bond_held) {
+        return Err(EscrowError::BondNotPosted.into());
+    }
+    Ok(())
+}
+
+/// Moves the taker's bond, if it is in the vault, to `to`.
+fn release_bond(state: &mut EscrowState, vault: &AccountInfo, to: &AccountInfo) -> ProgramResult {
+    if bool::from(state.bond_held) {
+        move_lamports(vault, to, state.taker_bond)?;
+        state.bond_held = false.into();
+    }
+    Ok(())
+}
+
+fn process_resolve(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    release_to_taker: bool,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let arbiter         = next_account_info(a)?;
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+
+    let state = check_resolve(program_id, arbiter, initializer, taker, escrow_account, vault)?;
+    let taker_bps = if release_to_taker { 10_000 } else { 0 };
+    rule(state, arbiter, initializer, taker, escrow_account, vault, taker_bps, a, events)
+}
+
+/// Settles a disputed native escrow between both parties: `taker_bps` of
+/// what it holds goes to the taker's side, the rest back to the initializer.
+fn process_resolve_partial(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    taker_bps: u16,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let arbiter         = next_account_info(a)?;
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+
+    let state = check_resolve(program_id, arbiter, initializer, taker, escrow_account, vault)?;
+    check_mint(&state, &Pubkey::default())?;
+    rule(state, arbiter, initializer, taker, escrow_account, vault, taker_bps, a, events)
+}
+
+/// Carries out a ruling, or holds it for the escrow's appeal window.
+#[allow(clippy::too_many_arguments)]
+fn rule<'a, 'info>(
+    mut state: Escrow,
+    arbiter: &AccountInfo<'info>,
+    initializer: &AccountInfo<'info>,
+    taker: &AccountInfo<'info>,
+    escrow_account: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+    taker_bps: u16,
+    a: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    if state.appeal_window_secs > 0 {
+        state.ruling_taker_bps = taker_bps;
+        state.ruled_at         = Clock::get()?.unix_timestamp;
+        state.status           = EscrowStatus::Ruled;
+        msg!("Ruled {} bps to the taker, open to appeal for {} seconds", taker_bps, state.appeal_window_secs);
+        return Ok(());
+    }
+    execute_ruling(state, arbiter, initializer, taker, escrow_account, vault, taker_bps, a, events)
+}
+
+/// Pays the arbiter its fee, then `taker_bps` of the rest to the taker's
+/// side and the remainder back to the initializer.
+#[allow(clippy::too_many_arguments)]
+fn execute_ruling<'a, 'info>(
+    mut state: Escrow,
+    arbiter: &AccountInfo<'info>,
+    initializer: &AccountInfo<'info>,
+    taker: &AccountInfo<'info>,
+    escrow_account: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+    taker_bps: u16,
+    a: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    if state.mint != Pubkey::default() {
+        return execute_token_ruling(state, arbiter, initializer, taker, escrow_account, vault, taker_bps > 0, a, events);
+    }
+    let fee         = arbiter_fee(&state);
+    move_lamports(vault, arbiter, fee)?;
+    let payout      = state.remaining - fee;
+    let taker_share = (u128::from(payout) * u128::from(taker_bps) / 10_000) as u64;
+    let refund      = payout - taker_share;
+    if state.splits().is_empty() {
+        move_lamports(vault, taker, taker_share)?;
+    } else if taker_share > 0 {
+        pay_splits(state.splits(), vault, taker_share, a)?;
+    }
+    move_lamports(vault, initializer, refund)?;
+    // Ruling wholly for the initializer means the taker defaulted; any other
+    // ruling finds neither party in default, so the taker keeps the bond
+    release_bond(&mut state, vault, if taker_bps == 0 { initializer } else { taker })?;
+    state.remaining = 0;
+    state.status    = if taker_share > 0 { EscrowStatus::Released } else { EscrowStatus::Cancelled };
+    msg!("Dispute resolved, {} released to the taker and {} refunded", taker_share, refund);
+    if taker_share > 0 {
+        EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: taker_share, fee }.emit(events)?;
+    } else {
+        EscrowCancelled { escrow: *escrow_account.key, refunded: refund }.emit(events)?;
+    }
+    Ok(())
+}
+
+/// Carries out a ruling on a token escrow, which goes wholly to one party:
+/// the arbiter's fee to its token account, the rest to the winner's.
+#[allow(clippy::too_many_arguments)]
+fn execute_token_ruling<'a, 'info>(
+    state: Escrow,
+    arbiter: &AccountInfo<'info>,
+    initializer: &AccountInfo<'info>,
+    taker: &AccountInfo<'info>,
+    escrow_account: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+    release_to_taker: bool,
+    a: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let recipient       = if release_to_taker { taker } else { initializer };
+    let recipient_token = next_account_info(a)?;
+    let mint            = next_account_info(a)?;
+    let token_program   = next_token_program(a)?;
+    check_token_program(token_program, mint)?;
+    check_mint(&state, mint.key)?;
+    // The sender picks the accounts, so make sure the tokens reach the party ruled for
+    check_token_owner(recipient_token, recipient.key)?;
+    let fee    = arbiter_fee(&state);
+    let payout = state.remaining - fee;
+    let signer = EscrowSigner::of(&state);
+    drop(state);
+    if fee > 0 {
+        let arbiter_token = next_account_info(a)?;
+        check_token_owner(arbiter_token, arbiter.key)?;
+        release_tokens(signer, escrow_account, vault, arbiter_token, mint, token_program, fee)?;
+    }
+    release_tokens(signer, escrow_account, vault, recipient_token, mint, token_program, payout)?;
+    let mut state = escrow_state(escrow_account)?;
+    state.remaining = 0;
+    state.status    = if release_to_taker { EscrowStatus::Released } else { EscrowStatus::Cancelled };
+    msg!("Dispute resolved, {} released to {}, arbiter fee {}", payout, recipient.key, fee);
+    if release_to_taker {
+        EscrowReleased { escrow: *escrow_account.key, recipient: *recipient.key, amount: payout, fee }.emit(events)?;
+    } else {
+        EscrowCancelled { escrow: *escrow_account.key, refunded: payout }.emit(events)?;
+    }
+    Ok(())
+}
+
+/// Either party challenges a held ruling before its window closes. The
+/// appeal arbiter takes over with a final say, so there is no second appeal.
+fn process_appeal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let party           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !party.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_party(&state, party.key)?;
+    check_status(&state, EscrowStatus::Ruled)?;
+    if Clock::get()?.unix_timestamp > state.ruled_at.saturating_add(state.appeal_window_secs) {
+        return Err(EscrowError::AppealWindowClosed.into());
+    }
+
+    state.arbiter_pubkey     = state.appeal_arbiter;
+    state.appeal_arbiter     = Pubkey::default();
+    state.appeal_window_secs = 0;
+    state.ruling_taker_bps   = 0;
+    state.ruled_at           = 0;
+    state.status             = EscrowStatus::Disputed;
+    msg!("Ruling appealed to {}", state.arbiter_pubkey);
+    Ok(())
+}
+
+/// Carries out a held ruling once its appeal window closed unchallenged.
+/// Every account is checked against the escrow, so anyone can crank it.
+fn process_finalize_ruling(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let arbiter         = next_account_info(a)?;
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+
+    let state = load_escrow(program_id, escrow_account)?;
+    if state.arbiter_pubkey != *arbiter.key {
+        return Err(EscrowError::WrongArbiter.into());
+    }
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_status(&state, EscrowStatus::Ruled)?;
+    check_vault(program_id, escrow_account, vault)?;
+    if Clock::get()?.unix_timestamp <= state.ruled_at.saturating_add(state.appeal_window_secs) {
+        return Err(EscrowError::AppealWindowOpen.into());
+    }
+
+    let taker_bps = state.ruling_taker_bps;
+    execute_ruling(state, arbiter, initializer, taker, escrow_account, vault, taker_bps, a, events)
+}
+
+/// What the arbiter earns for ruling on `state`: the flat fee plus the bps
+/// share of what the escrow holds, both fixed at Initialize, capped at it.
+fn arbiter_fee(state: &EscrowState) -> u64 {
+    let share = (u128::from(state.remaining) * u128::from(state.arbiter_fee_bps) / 10_000) as u64;
+    state.arbiter_fee.saturating_add(share).min(state.remaining)
+}
+
+/// Signatures and state both rulings need: the escrow's arbiter settles its
+/// dispute between the recorded parties.
+fn check_resolve<'a>(
+    program_id: &Pubkey,
+    arbiter: &AccountInfo,
+    initializer: &AccountInfo,
+    taker: &AccountInfo,
+    escrow_account: &'a AccountInfo,
+    vault: &AccountInfo,
+) -> Result<Escrow<'a>, ProgramError> {
+    if !arbiter.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let state = load_escrow(program_id, escrow_account)?;
+    if state.arbiter_pubkey != *arbiter.key {
+        return Err(EscrowError::WrongArbiter.into());
+    }
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_status(&state, EscrowStatus::Disputed)?;
+    check_vault(program_id, escrow_account, vault)?;
+    Ok(state)
+}
+
+/// The next account, which must be the system program.
+fn next_system_program<'a, 'info>(
+    a: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+) -> Result<&'a AccountInfo<'info>, ProgramError> {
+    let account = next_account_info(a)?;
+    if !system_program::check_id(account.key) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(account)
+}
+
+/// The next account, which must be SPL Token or Token-2022.
+fn next_token_program<'a, 'info>(
+    a: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+) -> Result<&'a AccountInfo<'info>, ProgramError> {
+    let account = next_account_info(a)?;
+    spl_token_2022::check_spl_token_program_account(account.key)?;
+    Ok(account)
+}
+
+/// The next account, which must be the stake program.
+fn next_stake_program<'a, 'info>(
+    a: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+) -> Result<&'a AccountInfo<'info>, ProgramError> {
+    let account = next_account_info(a)?;
+    if !stake::program::check_id(account.key) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(account)
+}
+
+/// Accepts either SPL Token or Token-2022, as long as it owns the mint.
+fn check_token_program(token_program: &AccountInfo, mint: &AccountInfo) -> ProgramResult {
+    spl_token_2022::check_spl_token_program_account(token_program.key)?;
+    if mint.owner != token_program.key {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+fn token_balance(token_account: &AccountInfo) -> Result<u64, ProgramError> {
+    let data = token_account.data.borrow();
+    Ok(StateWithExtensions::<TokenAccount>::unpack(&data)?.base.amount)
+}
+
+fn check_token_owner(token_account: &AccountInfo, owner: &Pubkey) -> ProgramResult {
+    let data = token_account.data.borrow();
+    if StateWithExtensions::<TokenAccount>::unpack(&data)?.base.owner != *owner {
+        return Err(ProgramError::IllegalOwner);
+    }
+    Ok(())
+}
+
+/// Transfers `amount` out of the vault, signed for by the escrow PDA.
+fn release_tokens<'a>(
+    signer: EscrowSigner,
+    escrow_account: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    let decimals = StateWithExtensions::<Mint>::unpack(&mint.data.borrow())?.base.decimals;
+    invoke_signed(
+        &spl_token_2022::instruction::transfer_checked(
+            token_program.key,
+            vault.key,
+            mint.key,
+            destination.key,
+            escrow_account.key,
+            &[],
+            amount,
+            decimals,
+        )?,
+        &[vault.clone(), mint.clone(), destination.clone(), escrow_account.clone()],
+        &[&signer.seeds()],
+    )
+}
+
+/// Also backs InitializeNft: with a `collection` the mint must be an NFT
+/// mint and the deposit must later go through DepositNft.
+fn process_initialize_token(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    expiry_ts: i64,
+    collection: Option<Pubkey>,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let mint            = next_account_info(a)?;
+    let token_program   = next_token_program(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+
+    check_token_program(token_program, mint)?;
+    if collection.is_some() {
+        let mint_data  = mint.data.borrow();
+        let mint_state = StateWithExtensions::<Mint>::unpack(&mint_data)?;
+        if mint_state.base.decimals != 0 || mint_state.base.supply != 1 {
+            return Err(EscrowError::NotAnNft.into());
+        }
+    }
+    let (vault_pda, vault_bump) = find_vault_address(program_id, escrow_account.key);
+    if vault_pda != *vault.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut state = create_escrow_account(
+        program_id,
+        initializer,
+        initializer,
+        taker,
+        escrow_account,
+        system_program,
+        config_account,
+        registry_account,
+        amount,
+        *mint.key,
+        expiry_ts,
+        optional_key(a),
+        Vec::new(),
+        0,
+        [0; 32],
+        events,
+    )?;
+    if let Some(collection) = collection {
+        state.collection = collection;
+    }
+
+    create_token_vault(
+        escrow_account,
+        vault,
+        initializer,
+        mint,
+        token_program,
+        system_program,
+        &[VAULT_PDA_SEED, escrow_account.key.as_ref(), &[vault_bump]],
+    )?;
+    msg!("Token vault initialized at {}", vault_pda);
+    Ok(())
+}
+
+/// Creates a token account at the `vault_seeds` PDA, with the escrow PDA as
+/// authority, paid for by `payer`.
+fn create_token_vault<'a>(
+    escrow_account: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    vault_seeds: &[&[u8]],
+) -> ProgramResult {
+    // Token-2022 mints may require account extensions, so size it from the mint.
+    let mint_extensions = StateWithExtensions::<Mint>::unpack(&mint.data.borrow())?
+        .get_extension_types()?;
+    let vault_len = ExtensionType::try_calculate_account_len::<TokenAccount>(
+        &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+    )?;
+    let rent     = Rent::get()?;
+    let lamports = rent.minimum_balance(vault_len);
+    create_pda_account(
+        payer,
+        vault,
+        lamports,
+        vault_len as u64,
+        token_program.key,
+        system_program,
+        vault_seeds,
+    )?;
+    invoke(
+        &spl_token_2022::instruction::initialize_account3(
+            token_program.key,
+            vault.key,
+            mint.key,
+            escrow_account.key,
+        )?,
+        &[vault.clone(), mint.clone()],
+    )
+}
+
+/// With `nft`, also takes the mint's Metaplex metadata account and checks
+/// it against the collection recorded at InitializeNft.
+fn process_deposit_token(program_id: &Pubkey, accounts: &[AccountInfo], nft: bool, events: Option<EventCpi>) -> ProgramResult {
+    let a                 = &mut accounts.iter();
+    let initializer       = next_account_info(a)?;
+    let taker             = next_account_info(a)?;
+    let escrow_account    = next_account_info(a)?;
+    let initializer_token = next_account_info(a)?;
+    let vault             = next_account_info(a)?;
+    let mint              = next_account_info(a)?;
+    let token_program     = next_token_program(a)?;
+    let config_account    = next_account_info(a)?;
+
+    // Only initializer must sign
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_paused(program_id, config_account)?;
+    check_token_program(token_program, mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, mint.key)?;
+    check_status(&state, EscrowStatus::Created)?;
+    check_not_expired(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+    if nft {
+        check_nft_metadata(&state, mint, next_account_info(a)?)?;
+    } else if state.collection != Pubkey::default() {
+        return Err(EscrowError::InvalidMetadata.into());
+    }
+
+    let (decimals, has_transfer_fee) = {
+        let mint_data  = mint.data.borrow();
+        let mint_state = StateWithExtensions::<Mint>::unpack(&mint_data)?;
+        (
+            mint_state.base.decimals,
+            mint_state.get_extension::<TransferFeeConfig>().is_ok(),
+        )
+    };
+    let balance_before = token_balance(vault)?;
+    invoke(
+        &spl_token_2022::instruction::transfer_checked(
+            token_program.key,
+            initializer_token.key,
+            mint.key,
+            vault.key,
+            initializer.key,
+            &[],
+            state.amount,
+            decimals,
+        )?,
+        &[initializer_token.clone(), mint.clone(), vault.clone(), initializer.clone()],
+    )?;
+    // Fee-bearing mints withhold part of the transfer, so record what the vault received
+    state.deposited = token_balance(vault)?
+        .checked_sub(balance_before)
+        .ok_or(EscrowError::AmountOverflow)?;
+    state.remaining = state.deposited;
+    state.status    = EscrowStatus::Funded;
+    EscrowFunded { escrow: *escrow_account.key, deposited: state.deposited }.emit(events)?;
+    if has_transfer_fee {
+        msg!("Transfer fee withheld {} tokens", state.amount.saturating_sub(state.deposited));
+    }
+    msg!("Deposited {} tokens", state.deposited);
+    Ok(())
+}
+
+fn check_nft_metadata(state: &EscrowState, mint: &AccountInfo, metadata: &AccountInfo) -> ProgramResult {
+    if state.collection == Pubkey::default() {
+        return Err(EscrowError::NotAnNft.into());
+    }
+    if *metadata.owner != METADATA_PROGRAM_ID || find_metadata_address(mint.key).0 != *metadata.key {
+        return Err(EscrowError::InvalidMetadata.into());
+    }
+    let metadata = Metadata::unpack(&metadata.data.borrow())?;
+    if metadata.mint != *mint.key {
+        return Err(EscrowError::InvalidMetadata.into());
+    }
+    if !metadata.in_collection(&state.collection) {
+        return Err(EscrowError::CollectionMismatch.into());
+    }
+    Ok(())
+}
+
+fn process_withdraw_token(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let taker_token     = next_account_info(a)?;
+    let mint            = next_account_info(a)?;
+    let token_program   = next_token_program(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_token_program(token_program, mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, mint.key)?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_swap(&state)?;
+    check_not_stake(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    if taker_token.data_is_empty() {
+        create_associated_token_account(taker, taker_token, mint, token_program, a)?;
+    }
+
+    let payout = state.remaining;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Released;
+    let signer = EscrowSigner::of(&state);
+    drop(state);
+    release_tokens(signer, escrow_account, vault, taker_token, mint, token_program, payout)?;
+    msg!("Withdrew {} tokens", payout);
+    EscrowReleased { escrow: *escrow_account.key, recipient: *taker_token.key, amount: payout, fee: 0 }.emit(events)?;
+    Ok(())
+}
+
+/// Creates `owner`'s associated token account for `mint` at
+/// `token_account`, paid for by the owner, with the trailing
+/// `[system_program, associated_token_program]` accounts.
+fn create_associated_token_account<'a>(
+    owner: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    a: &mut std::slice::Iter<AccountInfo<'a>>,
+) -> ProgramResult {
+    let system_program = next_system_program(a)?;
+    let ata_program    = next_account_info(a)?;
+    if *ata_program.key != ASSOCIATED_TOKEN_PROGRAM_ID {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if find_associated_token_address(owner.key, mint.key, token_program.key) != *token_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    invoke(
+        &create_idempotent(owner.key, owner.key, mint.key, token_program.key),
+        &[
+            owner.clone(),
+            token_account.clone(),
+            mint.clone(),
+            system_program.clone(),
+            token_program.clone(),
+            ata_program.clone(),
+        ],
+    )?;
+    msg!("Created associated token account {}", token_account.key);
+    Ok(())
+}
+
+fn check_native_mint(mint: &AccountInfo) -> ProgramResult {
+    if !spl_token_2022::native_mint::check_id(mint.key) && *mint.key != SPL_TOKEN_NATIVE_MINT {
+        return Err(EscrowError::NotNativeMint.into());
+    }
+    Ok(())
+}
+
+/// DepositToken for a wrapped SOL escrow, paid in lamports: SyncNative
+/// credits the vault with what was moved in.
+fn process_deposit_sol(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let mint            = next_account_info(a)?;
+    let token_program   = next_token_program(a)?;
+    let config_account  = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_paused(program_id, config_account)?;
+    check_token_program(token_program, mint)?;
+    check_native_mint(mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, mint.key)?;
+    check_status(&state, EscrowStatus::Created)?;
+    check_not_expired(&state)?;
+    check_not_swap(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    let balance_before = token_balance(vault)?;
+    invoke(
+        &system_instruction::transfer(initializer.key, vault.key, state.amount),
+        &[initializer.clone(), vault.clone(), system_program.clone()],
+    )?;
+    invoke(
+        &spl_token_2022::instruction::sync_native(token_program.key, vault.key)?,
+        std::slice::from_ref(vault),
+    )?;
+    state.deposited = token_balance(vault)?
+        .checked_sub(balance_before)
+        .ok_or(EscrowError::AmountOverflow)?;
+    state.remaining = state.deposited;
+    state.status    = EscrowStatus::Funded;
+    EscrowFunded { escrow: *escrow_account.key, deposited: state.deposited }.emit(events)?;
+    msg!("Deposited {} lamports as wrapped SOL", state.deposited);
+    Ok(())
+}
+
+/// WithdrawToken for a wrapped SOL escrow, paid in lamports: the vault is
+/// closed into the escrow account, which passes the payout on and keeps
+/// the vault's rent for Close to return.
+fn process_withdraw_sol(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let mint            = next_account_info(a)?;
+    let token_program   = next_token_program(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_token_program(token_program, mint)?;
+    check_native_mint(mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, mint.key)?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_swap(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    let payout = state.remaining;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Released;
+    let signer = EscrowSigner::of(&state);
+    drop(state);
+    close_vault(signer, escrow_account, vault, escrow_account, token_program)?;
+    move_lamports(escrow_account, taker, payout)?;
+    msg!("Withdrew {} lamports from wrapped SOL", payout);
+    EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: payout, fee: 0 }.emit(events)?;
+    Ok(())
+}
+
+/// Escrows a whole stake account: the initializer, as its staker and
+/// withdrawer, hands both authorities to the escrow PDA, so the escrow is
+/// funded at once and the stake keeps earning.
+fn process_initialize_stake(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    expiry_ts: i64,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let stake_account   = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+    let clock           = next_account_info(a)?;
+    let stake_program   = next_stake_program(a)?;
+
+    check_stake_program(stake_account, stake_program)?;
+    let amount    = stake_account.lamports();
+    let mut state = create_escrow_account(
+        program_id,
+        initializer,
+        initializer,
+        taker,
+        escrow_account,
+        system_program,
+        config_account,
+        registry_account,
+        amount,
+        Pubkey::default(),
+        expiry_ts,
+        Pubkey::default(),
+        Vec::new(),
+        0,
+        [0; 32],
+        events,
+    )?;
+    authorize_stake(stake_account, clock, initializer, escrow_account.key, &[])?;
+
+    state.stake_account = *stake_account.key;
+    state.deposited     = amount;
+    state.remaining     = amount;
+    state.status        = EscrowStatus::Funded;
+    EscrowFunded { escrow: *escrow_account.key, deposited: amount }.emit(events)?;
+    msg!("Escrowed stake account {} holding {} lamports", stake_account.key, amount);
+    Ok(())
+}
+
+/// Hands the stake account's authorities to the taker.
+fn process_withdraw_stake(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let stake_account   = next_account_info(a)?;
+    let clock           = next_account_info(a)?;
+    let stake_program   = next_stake_program(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_escrowed_stake(&state, stake_account, stake_program)?;
+
+    let released    = state.remaining;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Released;
+    let signer = EscrowSigner::of(&state);
+    drop(state);
+    release_stake(signer, escrow_account, stake_account, clock, taker.key)?;
+    msg!("Released stake account {} to {}", stake_account.key, taker.key);
+    EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: released, fee: 0 }.emit(events)?;
+    Ok(())
+}
+
+/// Refund for a stake escrow: after `expiry_ts` the initializer takes the
+/// stake account's authorities back.
+fn process_reclaim_stake(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let stake_account   = next_account_info(a)?;
+    let clock           = next_account_info(a)?;
+    let stake_program   = next_stake_program(a)?;
+
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_past_expiry(&state)?;
+    check_escrowed_stake(&state, stake_account, stake_program)?;
+
+    let refunded    = state.remaining;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Cancelled;
+    let signer = EscrowSigner::of(&state);
+    drop(state);
+    release_stake(signer, escrow_account, stake_account, clock, initializer.key)?;
+    msg!("Returned stake account {} after expiry", stake_account.key);
+    EscrowCancelled { escrow: *escrow_account.key, refunded }.emit(events)?;
+    Ok(())
+}
+
+fn check_stake_program(stake_account: &AccountInfo, stake_program: &AccountInfo) -> ProgramResult {
+    if *stake_program.key != stake::program::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *stake_account.owner != stake::program::id() {
+        return Err(EscrowError::WrongStakeAccount.into());
+    }
+    Ok(())
+}
+
+fn check_escrowed_stake(state: &EscrowState, stake_account: &AccountInfo, stake_program: &AccountInfo) -> ProgramResult {
+    check_stake_program(stake_account, stake_program)?;
+    if state.stake_account == Pubkey::default() || state.stake_account != *stake_account.key {
+        return Err(EscrowError::WrongStakeAccount.into());
+    }
+    Ok(())
+}
+
+/// Moves both of `stake_account`'s authorities from `authority` to
+/// `new_authority`, signing with `signer_seeds` when the authority is a PDA.
+fn authorize_stake<'a>(
+    stake_account: &AccountInfo<'a>,
+    clock: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    new_authority: &Pubkey,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    for kind in [StakeAuthorize::Staker, StakeAuthorize::Withdrawer] {
+        invoke_signed(
+            &stake::instruction::authorize(stake_account.key, authority.key, new_authority, kind, None),
+            &[stake_account.clone(), clock.clone(), authority.clone()],
+            signer_seeds,
+        )?;
+    }
+    Ok(())
+}
+
+/// Hands the escrowed stake account to `recipient`, signed for by the escrow PDA.
+fn release_stake<'a>(
+    signer: EscrowSigner,
+    escrow_account: &AccountInfo<'a>,
+    stake_account: &AccountInfo<'a>,
+    clock: &AccountInfo<'a>,
+    recipient: &Pubkey,
+) -> ProgramResult {
+    authorize_stake(
+        stake_account,
+        clock,
+        escrow_account,
+        recipient,
+        &[&signer.seeds()],
+    )
+}
+
+/// A stake pool's accounts, in `stake_pool::StakePool` order after the
+/// program. WithdrawSol also takes `sysvars`: clock, stake history and the
+/// stake program.
+struct PoolAccounts<'a, 'info> {
+    pool:               &'a AccountInfo<'info>,
+    withdraw_authority: &'a AccountInfo<'info>,
+    reserve_stake:      &'a AccountInfo<'info>,
+    manager_fee:        &'a AccountInfo<'info>,
+    pool_mint:          &'a AccountInfo<'info>,
+    token_program:      &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> PoolAccounts<'a, 'info> {
+    fn next(a: &mut std::slice::Iter<'a, AccountInfo<'info>>) -> Result<Self, ProgramError> {
+        if *next_account_info(a)?.key != STAKE_POOL_PROGRAM_ID {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        Ok(Self {
+            pool:               next_account_info(a)?,
+            withdraw_authority: next_account_info(a)?,
+            reserve_stake:      next_account_info(a)?,
+            manager_fee:        next_account_info(a)?,
+            pool_mint:          next_account_info(a)?,
+            token_program:      next_token_program(a)?,
+        })
+    }
+
+    fn keys(&self) -> StakePool {
+        StakePool {
+            address:             *self.pool.key,
+            reserve_stake:       *self.reserve_stake.key,
+            pool_mint:           *self.pool_mint.key,
+            manager_fee_account: *self.manager_fee.key,
+            token_program:       *self.token_program.key,
+        }
+    }
+}
+
+/// DepositToken for an escrow on a stake pool's mint, paid in lamports: the
+/// escrow's `amount` is staked through the pool's DepositSol, and the vault
+/// holds the pool tokens it mints.
+fn process_deposit_staked(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let pool            = PoolAccounts::next(a)?;
+
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_paused(program_id, config_account)?;
+    check_token_program(pool.token_program, pool.pool_mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, pool.pool_mint.key)?;
+    check_status(&state, EscrowStatus::Created)?;
+    check_not_expired(&state)?;
+    check_not_swap(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    let balance_before = token_balance(vault)?;
+    invoke(
+        &stake_pool::deposit_sol(&pool.keys(), initializer.key, vault.key, state.amount),
+        &[
+            pool.pool.clone(),
+            pool.withdraw_authority.clone(),
+            pool.reserve_stake.clone(),
+            initializer.clone(),
+            vault.clone(),
+            pool.manager_fee.clone(),
+            pool.pool_mint.clone(),
+            system_program.clone(),
+            pool.token_program.clone(),
+        ],
+    )?;
+    state.deposited = token_balance(vault)?
+        .checked_sub(balance_before)
+        .ok_or(EscrowError::AmountOverflow)?;
+    state.remaining  = state.deposited;
+    state.stake_pool = *pool.pool.key;
+    state.status     = EscrowStatus::Funded;
+    EscrowFunded { escrow: *escrow_account.key, deposited: state.deposited }.emit(events)?;
+    msg!("Staked {} lamports for {} pool tokens", state.amount, state.deposited);
+    Ok(())
+}
+
+/// Pays a stake pool escrow out to the taker.
+fn process_withdraw_staked(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let beneficiary     = next_account_info(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_status(&state, EscrowStatus::Funded)?;
+
+    // A copy, as the escrow account takes part in the unstaking
+    let header = *state;
+    drop(state);
+    let (paid, earned) = unstake(program_id, &header, escrow_account, vault, config_account, taker, beneficiary, a)?;
+    let mut state = escrow_state(escrow_account)?;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Released;
+    msg!("Unstaked {} lamports to the taker, {} of yield", paid, earned);
+    EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: paid, fee: 0 }.emit(events)?;
+    Ok(())
+}
+
+/// Refund for a stake pool escrow, after `expiry_ts`.
+fn process_reclaim_staked(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let beneficiary     = next_account_info(a)?;
+
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_past_expiry(&state)?;
+
+    // A copy, as the escrow account takes part in the unstaking
+    let header = *state;
+    drop(state);
+    let (refunded, earned) =
+        unstake(program_id, &header, escrow_account, vault, config_account, initializer, beneficiary, a)?;
+    let mut state = escrow_state(escrow_account)?;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Cancelled;
+    msg!("Unstaked {} lamports after expiry, {} of yield", refunded, earned);
+    EscrowCancelled { escrow: *escrow_account.key, refunded }.emit(events)?;
+    Ok(())
+}
+
+/// InitializeToken with an auction price schedule; the taker, if any, is
+/// the only allowed buyer.
+fn process_initialize_auction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    auction: Auction,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    if !auction.is_set()
+        || auction.floor_price > auction.start_price
+        || auction.start_ts >= auction.end_ts
+        || auction.step_secs < 0
+    {
+        return Err(EscrowError::InvalidAuction.into());
+    }
+    process_initialize_token(program_id, accounts, amount, 0, None, events)?;
+
+    let escrow_account = &accounts[2];
+    let mut state = load_escrow(program_id, escrow_account)?;
+    state.auction = auction;
+    Ok(())
+}
+
+fn check_auction(state: &EscrowState) -> ProgramResult {
+    if !state.auction.is_set() {
+        return Err(EscrowError::NotAuction.into());
+    }
+    Ok(())
+}
+
+/// The buyer pays the price read from the Clock now straight to the
+/// seller, so nothing but the tokens ever sits in the escrow.
+fn process_buy_auction(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let buyer           = next_account_info(a)?;
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let buyer_token     = next_account_info(a)?;
+    let mint            = next_account_info(a)?;
+    let token_program   = next_token_program(a)?;
+    let system_program  = next_system_program(a)?;
+
+    if !buyer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_token_program(token_program, mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_auction(&state)?;
+    check_initializer(&state, initializer)?;
+    check_mint(&state, mint.key)?;
+    check_status(&state, EscrowStatus::Funded)?;
+    if state.taker_pubkey != Pubkey::default() && state.taker_pubkey != *buyer.key {
+        return Err(EscrowError::WrongTaker.into());
+    }
+    check_allowed(&state, buyer.key)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    let price = state.auction.price_at(Clock::get()?.unix_timestamp);
+    invoke(
+        &system_instruction::transfer(buyer.key, initializer.key, price),
+        &[buyer.clone(), initializer.clone(), system_program.clone()],
+    )?;
+    let payout = state.remaining;
+    state.taker_pubkey = *buyer.key;
+    state.remaining    = 0;
+    state.status       = EscrowStatus::Released;
+    let signer = EscrowSigner::of(&state);
+    drop(state);
+    release_tokens(signer, escrow_account, vault, buyer_token, mint, token_program, payout)?;
+    msg!("Auction sold {} tokens for {} lamports", payout, price);
+    EscrowReleased { escrow: *escrow_account.key, recipient: *buyer_token.key, amount: payout, fee: 0 }.emit(events)?;
+    Ok(())
+}
+
+fn process_cancel_auction(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a                 = &mut accounts.iter();
+    let initializer       = next_account_info(a)?;
+    let escrow_account    = next_account_info(a)?;
+    let vault             = next_account_info(a)?;
+    let initializer_token = next_account_info(a)?;
+    let mint              = next_account_info(a)?;
+    let token_program     = next_token_program(a)?;
+
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_token_program(token_program, mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_auction(&state)?;
+    check_initializer(&state, initializer)?;
+    check_mint(&state, mint.key)?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    let refund = state.remaining;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Cancelled;
+    let signer = EscrowSigner::of(&state);
+    drop(state);
+    release_tokens(signer, escrow_account, vault, initializer_token, mint, token_program, refund)?;
+    msg!("Auction cancelled, returned {} tokens", refund);
+    EscrowCancelled { escrow: *escrow_account.key, refunded: refund }.emit(events)?;
+    Ok(())
+}
+
+/// Redeems all of the vault's pool tokens with WithdrawSol into the escrow
+/// account, then pays `recipient` up to the deposited lamports and the
+/// config's yield beneficiary the rest. Returns both amounts.
+#[allow(clippy::too_many_arguments)]
+fn unstake<'a, 'info>(
+    program_id: &Pubkey,
+    state: &EscrowState,
+    escrow_account: &'a AccountInfo<'info>,
+    vault: &'a AccountInfo<'info>,
+    config_account: &AccountInfo,
+    recipient: &AccountInfo<'info>,
+    beneficiary: &AccountInfo<'info>,
+    a: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+) -> Result<(u64, u64), ProgramError> {
+    let pool          = PoolAccounts::next(a)?;
+    let clock         = next_account_info(a)?;
+    let stake_history = next_account_info(a)?;
+    let stake_program = next_stake_program(a)?;
+
+    check_token_program(pool.token_program, pool.pool_mint)?;
+    check_mint(state, pool.pool_mint.key)?;
+    if state.stake_pool == Pubkey::default() || state.stake_pool != *pool.pool.key {
+        return Err(EscrowError::WrongStakeAccount.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+    let config = load_config(program_id, config_account)?;
+    let expected = if config.yield_beneficiary == Pubkey::default() {
+        state.initializer_pubkey
+    } else {
+        config.yield_beneficiary
+    };
+    if *beneficiary.key != expected {
+        return Err(EscrowError::WrongBeneficiary.into());
+    }
+
+    let lamports_before = escrow_account.lamports();
+    invoke_signed(
+        &stake_pool::withdraw_sol(&pool.keys(), escrow_account.key, vault.key, escrow_account.key, state.remaining),
+        &[
+            pool.pool.clone(),
+            pool.withdraw_authority.clone(),
+            escrow_account.clone(),
+            vault.clone(),
+            pool.reserve_stake.clone(),
+            pool.manager_fee.clone(),
+            pool.pool_mint.clone(),
+            clock.clone(),
+            stake_history.clone(),
+            stake_program.clone(),
+            pool.token_program.clone(),
+        ],
+        &[&EscrowSigner::of(state).seeds()],
+    )?;
+    let received = escrow_account
+        .lamports()
+        .checked_sub(lamports_before)
+        .ok_or(EscrowError::AmountOverflow)?;
+    // A pool withdrawal fee can leave less than was deposited
+    let paid   = received.min(state.amount);
+    let earned = received - paid;
+    move_lamports(escrow_account, recipient, paid)?;
+    move_lamports(escrow_account, beneficiary, earned)?;
+    Ok((paid, earned))
+}
+
+/// Creates a token escrow for the initializer's leg, which is then funded
+/// with DepositToken, and records the taker's leg for DepositSwap.
+fn process_initialize_swap(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    expiry_ts: i64,
+    taker_mint: Pubkey,
+    taker_amount: u64,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    if taker_mint == Pubkey::default() || taker_amount == 0 {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    process_initialize_token(program_id, accounts, amount, expiry_ts, None, events)?;
+
+    let escrow_account = &accounts[2];
+    let mut state = load_escrow(program_id, escrow_account)?;
+    state.taker_mint   = taker_mint;
+    state.taker_amount = taker_amount;
+    Ok(())
+}
+
+/// The taker funds their leg into a taker vault created here at their
+/// expense. The vault only exists while it holds the deposit.
+fn process_deposit_swap(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a                 = &mut accounts.iter();
+    let taker             = next_account_info(a)?;
+    let escrow_account    = next_account_info(a)?;
+    let taker_token       = next_account_info(a)?;
+    let taker_vault       = next_account_info(a)?;
+    let taker_mint        = next_account_info(a)?;
+    let token_program     = next_token_program(a)?;
+    let system_program    = next_system_program(a)?;
+    let config_account    = next_account_info(a)?;
+
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_paused(program_id, config_account)?;
+    check_token_program(token_program, taker_mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    if state.taker_mint == Pubkey::default() || state.taker_mint != *taker_mint.key {
+        return Err(EscrowError::WrongMint.into());
+    }
+    match state.status {
+        EscrowStatus::Created | EscrowStatus::Funded => {}
+        _ => return Err(EscrowError::AlreadySettled.into()),
+    }
+    check_not_expired(&state)?;
+    if state.taker_deposited > 0 {
+        return Err(EscrowError::AlreadyFunded.into());
+    }
+    let (vault_pda, vault_bump) = find_taker_vault_address(program_id, escrow_account.key);
+    if vault_pda != *taker_vault.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    create_token_vault(
+        escrow_account,
+        taker_vault,
+        taker,
+        taker_mint,
+        token_program,
+        system_program,
+        &[TAKER_VAULT_PDA_SEED, escrow_account.key.as_ref(), &[vault_bump]],
+    )?;
+    let decimals = StateWithExtensions::<Mint>::unpack(&taker_mint.data.borrow())?.base.decimals;
+    invoke(
+        &spl_token_2022::instruction::transfer_checked(
+            token_program.key,
+            taker_token.key,
+            taker_mint.key,
+            taker_vault.key,
+            taker.key,
+            &[],
+            state.taker_amount,
+            decimals,
+        )?,
+        &[taker_token.clone(), taker_mint.clone(), taker_vault.clone(), taker.clone()],
+    )?;
+    state.taker_deposited = token_balance(taker_vault)?;
+    msg!("Taker deposited {} tokens", state.taker_deposited);
+    Ok(())
+}
+
+/// Crosses both legs in one instruction: the initializer's vault pays the
+/// taker and the taker vault pays the initializer. Either party may sign;
+/// the receiving token accounts must belong to the right party.
+fn process_execute_swap(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a                   = &mut accounts.iter();
+    let initializer         = next_account_info(a)?;
+    let taker               = next_account_info(a)?;
+    let escrow_account      = next_account_info(a)?;
+    let vault               = next_account_info(a)?;
+    let taker_vault         = next_account_info(a)?;
+    let initializer_receive = next_account_info(a)?;
+    let taker_receive       = next_account_info(a)?;
+    let mint                = next_account_info(a)?;
+    let token_program       = next_token_program(a)?;
+    let taker_mint          = next_account_info(a)?;
+    let taker_token_program = next_token_program(a)?;
+
+    if !initializer.is_signer && !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_token_program(token_program, mint)?;
+    check_token_program(taker_token_program, taker_mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, mint.key)?;
+    if state.taker_mint == Pubkey::default() || state.taker_mint != *taker_mint.key {
+        return Err(EscrowError::WrongMint.into());
+    }
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_expired(&state)?;
+    if state.taker_deposited == 0 {
+        return Err(EscrowError::TakerDeposit.into());
+    }
+    if find_vault_address(program_id, escrow_account.key).0 != *vault.key
+        || find_taker_vault_address(program_id, escrow_account.key).0 != *taker_vault.key
+    {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    check_token_owner(initializer_receive, initializer.key)?;
+    check_token_owner(taker_receive, taker.key)?;
+
+    let initializer_leg = state.remaining;
+    let taker_leg       = state.taker_deposited;
+    state.remaining       = 0;
+    state.taker_deposited = 0;
+    state.status          = EscrowStatus::Released;
+    let signer = EscrowSigner::of(&state);
+    drop(state);
+    release_tokens(signer, escrow_account, vault, taker_receive, mint, token_program, initializer_leg)?;
+    release_tokens(
+        signer,
+        escrow_account,
+        taker_vault,
+        initializer_receive,
+        taker_mint,
+        taker_token_program,
+        taker_leg,
+    )?;
+    // The taker paid for their vault, so its rent goes back to them
+    close_vault(signer, escrow_account, taker_vault, taker, taker_token_program)?;
+    msg!("Swapped {} tokens for {} tokens", initializer_leg, taker_leg);
+    EscrowReleased { escrow: *escrow_account.key, recipient: *taker_receive.key, amount: initializer_leg, fee: 0 }.emit(events)?;
+    Ok(())
+}
+
+/// Hands the taker's deposit back and closes the taker vault, as long as the
+/// swap has not been executed.
+fn process_reclaim_swap(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let taker_vault     = next_account_info(a)?;
+    let taker_token     = next_account_info(a)?;
+    let taker_mint      = next_account_info(a)?;
+    let token_program   = next_token_program(a)?;
+
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_token_program(token_program, taker_mint)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    if state.taker_mint != *taker_mint.key {
+        return Err(EscrowError::WrongMint.into());
+    }
+    if state.taker_deposited == 0 {
+        return Err(EscrowError::TakerDeposit.into());
+    }
+    if find_taker_vault_address(program_id, escrow_account.key).0 != *taker_vault.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let held = state.taker_deposited;
+    state.taker_deposited = 0;
+    let signer = EscrowSigner::of(&state);
+    drop(state);
+    release_tokens(signer, escrow_account, taker_vault, taker_token, taker_mint, token_program, held)?;
+    close_vault(signer, escrow_account, taker_vault, taker, token_program)?;
+    msg!("Taker reclaimed {} tokens", held);
+    Ok(())
+}
+
+/// Creates the config PDA. It can only be created once, and its signer
+/// becomes the admin.
+fn process_initialize_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    fee_bps: u16,
+    treasury: Pubkey,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let admin           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+
+    if !admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if fee_bps > MAX_FEE_BPS {
+        return Err(EscrowError::InvalidFee.into());
+    }
+    let (pda, bump) = find_config_address(program_id);
+    if pda != *config_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let rent     = Rent::get()?;
+    let lamports = rent.minimum_balance(CONFIG_STATE_LEN);
+    create_pda_account(
+        admin,
+        config_account,
+        lamports,
+        CONFIG_STATE_LEN as u64,
+        program_id,
+        system_program,
+        &[CONFIG_PDA_SEED, &[bump]],
+    )?;
+
+    let config = ConfigState {
+        discriminator:  CONFIG_DISCRIMINATOR,
+        is_initialized: true,
+        admin:          *admin.key,
+        fee_bps,
+        treasury,
+        bump,
+        paused:         false,
+        max_expiry_horizon: DEFAULT_MAX_EXPIRY_HORIZON,
+        referral_bps:   0,
+        crank_bounty_bps: 0,
+        require_deposit: false,
+        allowed_caller: Pubkey::default(),
+        yield_beneficiary: Pubkey::default(),
+        min_amount:     0,
+        max_amount:     0,
+        creation_fee:   0,
+        max_escrows_per_epoch: 0,
+        arbiter_fee:    0,
+        arbiter_fee_bps: 0,
+        appeal_window_secs: 0,
+        appeal_arbiter: Pubkey::default(),
+    };
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    msg!("Config initialized at {}", pda);
+    Ok(())
+}
+
+/// Replaces the config settings; only the current admin may sign. Passing a
+/// different `admin` hands over control.
+#[allow(clippy::too_many_arguments)]
+fn process_update_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    admin: Pubkey,
+    fee_bps: u16,
+    treasury: Pubkey,
+    paused: bool,
+    max_expiry_horizon: i64,
+    referral_bps: u16,
+    crank_bounty_bps: u16,
+    require_deposit: bool,
+    allowed_caller: Pubkey,
+    yield_beneficiary: Pubkey,
+    min_amount: u64,
+    max_amount: u64,
+    creation_fee: u64,
+    max_escrows_per_epoch: u32,
+    arbiter_fee: u64,
+    arbiter_fee_bps: u16,
+    appeal_window_secs: i64,
+    appeal_arbiter: Pubkey,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let signer          = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+
+    if !signer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut config = load_config(program_id, config_account)?;
+    if config.admin != *signer.key {
+        return Err(EscrowError::WrongAdmin.into());
+    }
+    if fee_bps > MAX_FEE_BPS || referral_bps > 10_000 || crank_bounty_bps > 10_000 || arbiter_fee_bps > 10_000 {
+        return Err(EscrowError::InvalidFee.into());
+    }
+    if max_expiry_horizon < 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if max_amount != 0 && min_amount > max_amount {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    // A window nobody can appeal in would only delay the ruling
+    if appeal_window_secs < 0 || (appeal_window_secs > 0 && appeal_arbiter == Pubkey::default()) {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    config.admin    = admin;
+    config.fee_bps  = fee_bps;
+    config.treasury = treasury;
+    config.paused   = paused;
+    config.max_expiry_horizon = max_expiry_horizon;
+    config.referral_bps       = referral_bps;
+    config.crank_bounty_bps   = crank_bounty_bps;
+    config.require_deposit    = require_deposit;
+    config.allowed_caller     = allowed_caller;
+    config.yield_beneficiary  = yield_beneficiary;
+    config.min_amount         = min_amount;
+    config.max_amount         = max_amount;
+    config.creation_fee       = creation_fee;
+    config.max_escrows_per_epoch = max_escrows_per_epoch;
+    config.arbiter_fee        = arbiter_fee;
+    config.arbiter_fee_bps    = arbiter_fee_bps;
+    config.appeal_window_secs = appeal_window_secs;
+    config.appeal_arbiter     = appeal_arbiter;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    msg!("Config updated");
+    Ok(())
+}
+
+/// A native escrow whose `expiry_ts` doubles as the HTLC timeout: Claim pays
+/// the taker before it, Refund returns the funds after it.
+fn process_initialize_htlc(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    timeout: i64,
+    hash_lock: [u8; 32],
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    if timeout == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    process_initialize(program_id, accounts, amount, timeout, Vec::new(), true, 0, [0; 32], events, features)?;
+
+    let escrow_account = &accounts[2];
+    let mut state = load_escrow(program_id, escrow_account)?;
+    state.hash_lock = hash_lock;
+    Ok(())
+}
+
+fn process_claim(program_id: &Pubkey, accounts: &[AccountInfo], preimage: [u8; 32], events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    // Knowing the preimage stands in for the initializer's signature
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_expired(&state)?;
+    if state.hash_lock == [0; 32] || hash(&preimage).to_bytes() != state.hash_lock {
+        return Err(EscrowError::InvalidPreimage.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    release_with_fee(program_id, &mut state, escrow_account, vault, taker, config_account, treasury, None, events, features)
+}
+
+fn process_claim_with_voucher(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+    let instructions    = next_account_info(a)?;
+
+    // The arbiter's voucher stands in for the initializer's signature
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_swap(&state)?;
+    if state.oracle != Pubkey::default() {
+        return Err(EscrowError::PriceConditioned.into());
+    }
+    if state.arbiter_pubkey == Pubkey::default() {
+        return Err(EscrowError::NoArbiter.into());
+    }
+    let message = voucher_message(escrow_account.key, state.remaining);
+    check_voucher(instructions, &state.arbiter_pubkey, &message)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    release_with_fee(program_id, &mut state, escrow_account, vault, taker, config_account, treasury, None, events, features)
+}
+
+fn process_initialize_relayed(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    expiry_ts: i64,
+    deadline: i64,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+    let payer           = next_account_info(a)?;
+    let instructions    = next_account_info(a)?;
+
+    if !payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if Clock::get()?.unix_timestamp > deadline {
+        return Err(EscrowError::RelayExpired.into());
+    }
+    let message = relayed_initialize_message(escrow_account.key, taker.key, amount, expiry_ts, deadline);
+    check_voucher(instructions, initializer.key, &message)?;
+
+    // The signed message stands in for the initializer's signature; the
+    // account is only read from here on, never passed to a CPI
+    let mut authorized = initializer.clone();
+    authorized.is_signer = true;
+    create_escrow_account(
+        program_id,
+        &authorized,
+        payer,
+        taker,
+        escrow_account,
+        system_program,
+        config_account,
+        registry_account,
+        amount,
+        Pubkey::default(),
+        expiry_ts,
+        Pubkey::default(),
+        Vec::new(),
+        0,
+        [0; 32],
+        events,
+    )?;
+    check_deposit_follows(program_id, config_account, escrow_account, Some(instructions), features)?;
+    create_native_vault(program_id, escrow_account, vault, payer, system_program)
+}
+
+/// An escrow the taker creates for the payer to fund: the payer's
+/// signature comes with its Deposit, so the escrow is opened on the taker's
+/// alone, under the payer's registry and at the taker's expense.
+fn process_initialize_invoice(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    expiry_ts: i64,
+    events: Option<EventCpi>,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // Nothing is committed until the payer deposits, so as with a relayed
+    // Initialize the account is only read from here on
+    let mut authorized = initializer.clone();
+    authorized.is_signer = true;
+    let mut state = create_escrow_account(
+        program_id,
+        &authorized,
+        taker,
+        taker,
+        escrow_account,
+        system_program,
+        config_account,
+        registry_account,
+        amount,
+        Pubkey::default(),
+        expiry_ts,
+        Pubkey::default(),
+        Vec::new(),
+        0,
+        [0; 32],
+        events,
+    )?;
+    // Strict mode's same-transaction Deposit doesn't apply: an invoice is
+    // meant to wait for its payer
+    state.created_by = *taker.key;
+    create_native_vault(program_id, escrow_account, vault, taker, system_program)
+}
+
+/// Lets the taker withdraw an invoice before it is funded, taking back the
+/// rent it paid; any partial deposit goes back to the payer.
+fn process_cancel_invoice(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let registry_account = next_account_info(a)?;
+
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // A copy, as the account is closed below
+    let state = *load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    if !state.is_invoice() {
+        return Err(EscrowError::NotInvoice.into());
+    }
+    check_status(&state, EscrowStatus::Created)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    move_lamports(vault, initializer, state.deposited)?;
+    move_lamports(vault, taker, vault.lamports())?;
+    EscrowCancelled { escrow: *escrow_account.key, refunded: state.deposited }.emit(events)?;
+    forfeit_creation_fee(program_id, &state, escrow_account, a)?;
+    unregister_escrow(program_id, initializer, registry_account, state.id)?;
+    close_escrow_account(escrow_account, taker)
+}
+
+/// Requires the instruction before this one to be an ed25519 program check
+/// of a single signature by `authority` over `message`, with the key,
+/// signature and message all inside that instruction's own data. The ed25519
+/// program fails the whole transaction if the signature itself is bad.
+fn check_voucher(instructions: &AccountInfo, authority: &Pubkey, message: &[u8]) -> ProgramResult {
+    let current = load_current_index_checked(instructions)?;
+    let index   = current.checked_sub(1).ok_or(EscrowError::InvalidVoucher)?;
+    let verify  = load_instruction_at_checked(usize::from(index), instructions)?;
+    if verify.program_id != ed25519_program::ID {
+        return Err(EscrowError::InvalidVoucher.into());
+    }
+
+    let data   = &verify.data;
+    let u16_at = |offset: usize| -> Option<usize> {
+        let bytes = data.get(offset..offset + 2)?;
+        Some(usize::from(u16::from_le_bytes([bytes[0], bytes[1]])))
+    };
+    let slice_at = |offset: usize, len: usize| data.get(offset..offset.checked_add(len)?);
+    // [num_signatures, padding, Ed25519SignatureOffsets { 7 x u16 }, ..]
+    let signed = || -> Option<(&[u8], &[u8])> {
+        if *data.first()? != 1 {
+            return None;
+        }
+        let own = usize::from(u16::MAX);
+        if u16_at(4)? != own || u16_at(8)? != own || u16_at(14)? != own {
+            return None;
+        }
+        Some((slice_at(u16_at(6)?, 32)?, slice_at(u16_at(10)?, u16_at(12)?)?))
+    };
+    match signed() {
+        Some((key, signed_message)) if key == authority.as_ref() && signed_message == message => Ok(()),
+        _ => Err(EscrowError::InvalidVoucher.into()),
+    }
+}
+
+fn process_set_eth_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    eth_address: [u8; 20],
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Created)?;
+
+    state.eth_authority = eth_address;
+    Ok(())
+}
+
+fn process_claim_with_eth_signature(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    signature: [u8; 64],
+    recovery_id: u8,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    // The Ethereum authority's signature stands in for the initializer's
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_swap(&state)?;
+    if state.oracle != Pubkey::default() {
+        return Err(EscrowError::PriceConditioned.into());
+    }
+    if state.eth_authority == [0; 20] {
+        return Err(EscrowError::NoEthAuthority.into());
+    }
+    let message = eth_release_message(escrow_account.key, state.eth_nonce);
+    if eth_signer(&message, &signature, recovery_id)? != state.eth_authority {
+        return Err(EscrowError::InvalidEthSignature.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    state.eth_nonce += 1;
+    release_with_fee(program_id, &mut state, escrow_account, vault, taker, config_account, treasury, None, events, features)
+}
+
+/// Recovers the Ethereum address that `personal_sign`ed `message`.
+fn eth_signer(message: &[u8], signature: &[u8; 64], recovery_id: u8) -> Result<[u8; 20], ProgramError> {
+    let prefix = format!("\x19Ethereum Signed Message:\n{}", message.len());
+    let digest = keccak::hashv(&[prefix.as_bytes(), message]);
+    let pubkey = secp256k1_recover(&digest.to_bytes(), recovery_id, signature)
+        .map_err(|_| EscrowError::InvalidEthSignature)?;
+    let hashed = keccak::hash(&pubkey.to_bytes()).to_bytes();
+    let mut address = [0; 20];
+    address.copy_from_slice(&hashed[12..]);
+    Ok(address)
+}
+
+fn process_set_wormhole_emitter(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    emitter_chain: u16,
+    emitter_address: [u8; 32],
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Created)?;
+
+    state.emitter_chain   = emitter_chain;
+    state.emitter_address = emitter_address;
+    Ok(())
+}
+
+fn process_release_with_vaa(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+    let posted_vaa      = next_account_info(a)?;
+
+    // The guardian-verified message is the authorization, so nobody signs
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_not_swap(&state)?;
+    if state.oracle != Pubkey::default() {
+        return Err(EscrowError::PriceConditioned.into());
+    }
+    if state.emitter_address == [0; 32] {
+        return Err(EscrowError::NoWormholeEmitter.into());
+    }
+    // Only the core bridge can write accounts it owns, and it only posts
+    // VAAs that carry a quorum of guardian signatures
+    if *posted_vaa.owner != WORMHOLE_CORE_BRIDGE_PROGRAM_ID {
+        return Err(EscrowError::InvalidVaa.into());
+    }
+    let vaa = PostedVaa::unpack(&posted_vaa.data.borrow()).map_err(|_| EscrowError::InvalidVaa)?;
+    if vaa.emitter_chain != state.emitter_chain
+        || vaa.emitter_address != state.emitter_address
+        || vaa.payload != vaa_release_payload(&state.initializer_pubkey, state.id)
+    {
+        return Err(EscrowError::InvalidVaa.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    release_with_fee(program_id, &mut state, escrow_account, vault, taker, config_account, treasury, None, events, features)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_initialize_vesting(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    start_ts: i64,
+    cliff_ts: i64,
+    end_ts: i64,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    if start_ts >= end_ts || cliff_ts < start_ts || cliff_ts > end_ts {
+        return Err(EscrowError::InvalidSchedule.into());
+    }
+    process_initialize(program_id, accounts, amount, 0, Vec::new(), true, 0, [0; 32], events, features)?;
+
+    let escrow_account = &accounts[2];
+    let mut state = load_escrow(program_id, escrow_account)?;
+    state.start_ts = start_ts;
+    state.cliff_ts = cliff_ts;
+    state.end_ts   = end_ts;
+    Ok(())
+}
+
+/// Portion of `deposited` unlocked at `now`: nothing before the cliff, then
+/// linear between `start_ts` and `end_ts`.
+fn vested_amount(state: &EscrowState, now: i64) -> u64 {
+    if now < state.cliff_ts {
+        return 0;
+    }
+    if now >= state.end_ts {
+        return state.deposited;
+    }
+    let elapsed  = (now - state.start_ts) as u128;
+    let duration = (state.end_ts - state.start_ts) as u128;
+    (state.deposited as u128 * elapsed / duration) as u64
+}
+
+/// Pays the taker whatever has vested since the last withdrawal, less the
+/// protocol fee.
+fn process_withdraw_vested(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    // The schedule is the initializer's consent, so the taker signs alone
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    if state.end_ts == 0 {
+        return Err(EscrowError::InvalidSchedule.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    let released = state.deposited - state.remaining;
+    let payout   = vested_amount(&state, Clock::get()?.unix_timestamp).saturating_sub(released);
+    if payout == 0 {
+        return Err(EscrowError::NothingVested.into());
+    }
+    let fee = pay_tranche(program_id, &state, vault, taker, config_account, treasury, payout, features)?;
+    state.remaining -= payout;
+    if state.remaining == 0 {
+        state.status = EscrowStatus::Released;
+        EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: payout - fee, fee }.emit(events)?;
+    }
+    msg!("Withdrew {} vested lamports, fee {}, {} remaining", payout - fee, fee, state.remaining);
+    Ok(())
+}
+
+fn process_initialize_subscription(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    intervals: u64,
+    subscription: Subscription,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    if subscription.interval_secs <= 0 {
+        return Err(EscrowError::InvalidSchedule.into());
+    }
+    let amount = intervals.checked_mul(subscription.amount_per_interval).ok_or(EscrowError::AmountOverflow)?;
+    process_initialize(program_id, accounts, amount, 0, Vec::new(), true, 0, [0; 32], events, features)?;
+
+    let escrow_account = &accounts[2];
+    let mut state = load_escrow(program_id, escrow_account)?;
+    state.subscription = subscription;
+    Ok(())
+}
+
+/// Pays the taker the subscription tranches elapsed since the last
+/// withdrawal, less the protocol fee, on anyone's signature.
+fn process_withdraw_subscription(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let caller          = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    // The funds can only go to the taker, so a crank needs no authority
+    if !caller.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    if !state.subscription.is_set() {
+        return Err(EscrowError::InvalidSchedule.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+
+    let released = state.deposited - state.remaining;
+    let unlocked = state.subscription.unlocked_at(Clock::get()?.unix_timestamp, state.deposited);
+    let payout   = unlocked.saturating_sub(released);
+    if payout == 0 {
+        return Err(EscrowError::NothingVested.into());
+    }
+    let fee = pay_tranche(program_id, &state, vault, taker, config_account, treasury, payout, features)?;
+    state.remaining -= payout;
+    if state.remaining == 0 {
+        state.status = EscrowStatus::Released;
+        EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: payout - fee, fee }.emit(events)?;
+    }
+    msg!("Withdrew {} subscription lamports, fee {}, {} remaining", payout - fee, fee, state.remaining);
+    Ok(())
+}
+
+/// A native escrow the taker settles with Capture; the expiry is the end
+/// of the capture window, so it is required.
+fn process_initialize_authorization(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    expiry_ts: i64,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    if expiry_ts == 0 {
+        return Err(EscrowError::InvalidExpiry.into());
+    }
+    process_initialize(program_id, accounts, amount, expiry_ts, Vec::new(), true, 0, [0; 32], events, features)?;
+
+    let escrow_account = &accounts[2];
+    let mut state = load_escrow(program_id, escrow_account)?;
+    state.authorization = true.into();
+    Ok(())
+}
+
+/// Pays the taker the captured `amount`, less the protocol fee, and hands
+/// the uncaptured remainder straight back to the initializer.
+fn process_capture(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    // The authorization is the initializer's consent, so the taker signs alone
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    if !bool::from(state.authorization) {
+        return Err(EscrowError::NotAuthorization.into());
+    }
+    check_not_expired(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+    check_no_splits(&state)?;
+    check_bond(&state)?;
+    if amount > state.remaining {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    let config = load_config(program_id, config_account)?;
+    if config.treasury != *treasury.key {
+        return Err(EscrowError::WrongTreasury.into());
+    }
+    let referrer = if state.referrer == Pubkey::default() {
+        None
+    } else {
+        let referrer = next_account_info(a)?;
+        if *referrer.key != state.referrer {
+            return Err(EscrowError::WrongReferrer.into());
+        }
+        Some(referrer)
+    };
+
+    let fee    = fee_for(&config, features, &state, amount)?;
+    let refund = state.remaining - amount;
+    pay_fee(&config, vault, treasury, referrer, fee)?;
+    move_lamports(vault, taker, amount - fee)?;
+    move_lamports(vault, initializer, refund)?;
+    release_bond(&mut state, vault, taker)?;
+    state.captured  = amount;
+    state.remaining = 0;
+    state.status    = EscrowStatus::Released;
+    msg!("Captured {} lamports, fee {}, refunded {}", amount - fee, fee, refund);
+    EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: amount - fee, fee }.emit(events)?;
+    Ok(())
+}
+
+/// A native escrow for the sum of `milestones`, released tranche by tranche
+/// as the initializer approves them.
+fn process_initialize_milestones(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    expiry_ts: i64,
+    milestones: Vec<u64>,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    if milestones.is_empty() || milestones.len() > MAX_MILESTONES {
+        return Err(EscrowError::InvalidMilestone.into());
+    }
+    let mut amount: u64 = 0;
+    for &tranche in &milestones {
+        if tranche == 0 {
+            return Err(EscrowError::InvalidAmount.into());
+        }
+        amount = amount.checked_add(tranche).ok_or(EscrowError::AmountOverflow)?;
+    }
+    let milestones = milestones
+        .into_iter()
+        .map(Milestone::new)
+        .collect();
+    process_initialize(program_id, accounts, amount, expiry_ts, milestones, true, 0, [0; 32], events, features)
+}
+
+/// Appends a tranche to a milestone escrow that is not funded yet, growing
+/// the state account and raising the amount by `amount`.
+fn process_add_milestone(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_status(&state, EscrowStatus::Created)?;
+    if state.milestone_count == 0 || usize::from(state.milestone_count) >= MAX_MILESTONES {
+        return Err(EscrowError::InvalidMilestone.into());
+    }
+
+    state.amount = state.amount.checked_add(amount).ok_or(EscrowError::AmountOverflow)?;
+    let mut milestones = state.milestones().to_vec();
+    milestones.push(Milestone::new(amount));
+    let space = state.space() + MILESTONE_LEN;
+    drop(state);
+    resize_escrow(escrow_account, space, initializer, system_program, |state| state.set_milestones(&milestones))?;
+    msg!("Added milestone {} of {} lamports", milestones.len() - 1, amount);
+    Ok(())
+}
+
+fn process_approve_milestone(program_id: &Pubkey, accounts: &[AccountInfo], index: u8) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_status(&state, EscrowStatus::Funded)?;
+    let milestone = state
+        .milestones_mut()
+        .get_mut(index as usize)
+        .filter(|m| !bool::from(m.approved))
+        .ok_or(EscrowError::InvalidMilestone)?;
+
+    milestone.approved = true.into();
+    let amount = milestone.amount;
+    msg!("Milestone {} approved, {} lamports unlocked", index, amount);
+    Ok(())
+}
+
+/// Pays the taker every approved milestone not yet released, less the
+/// protocol fee.
+fn process_withdraw_milestones(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>, features: Features) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let config_account  = next_account_info(a)?;
+    let treasury        = next_account_info(a)?;
+
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Funded)?;
+    check_vault(program_id, escrow_account, vault)?;
+
+    let mut unlocked: u64 = 0;
+    for milestone in state.milestones_mut().iter_mut().filter(|m| bool::from(m.approved) && !bool::from(m.released)) {
+        milestone.released = true.into();
+        unlocked += milestone.amount;
+    }
+    // A mutual WithdrawPartial may already have paid part of it out
+    let payout = unlocked.min(state.remaining);
+    if payout == 0 {
+        return Err(EscrowError::NothingApproved.into());
+    }
+    let fee = pay_tranche(program_id, &state, vault, taker, config_account, treasury, payout, features)?;
+    state.remaining -= payout;
+    if state.remaining == 0 {
+        state.status = EscrowStatus::Released;
+        EscrowReleased { escrow: *escrow_account.key, recipient: *taker.key, amount: payout - fee, fee }.emit(events)?;
+    }
+    msg!("Withdrew {} lamports of approved milestones, fee {}, {} remaining", payout - fee, fee, state.remaining);
+    Ok(())
+}
+
+/// A native escrow for `goal` lamports that anyone can Contribute to until
+/// `deadline`. The receiver is fixed up front and signs the creation.
+fn process_initialize_crowdfund(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    goal: u64,
+    deadline: i64,
+    events: Option<EventCpi>,
+    features: Features,
+) -> ProgramResult {
+    if deadline == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if accounts.get(1).is_some_and(|taker| *taker.key == Pubkey::default()) {
+        return Err(EscrowError::WrongTaker.into());
+    }
+    process_initialize(program_id, accounts, goal, deadline, Vec::new(), false, 0, [0; 32], events, features)?;
+
+    let escrow_account = &accounts[2];
+    let mut state = load_escrow(program_id, escrow_account)?;
+    state.crowdfund = true.into();
+    Ok(())
+}
+
+/// Moves `amount` lamports from the contributor into the vault and records
+/// them on the contributor's receipt, creating it on their first contribution.
+fn process_contribute(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let contributor     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let receipt_account = next_account_info(a)?;
+    let system_program  = next_system_program(a)?;
+    let config_account  = next_account_info(a)?;
+
+    if !contributor.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_paused(program_id, config_account)?;
+    let mut state = load_escrow(program_id, escrow_account)?;
+    if !bool::from(state.crowdfund) {
+        return Err(EscrowError::NotCrowdfund.into());
+    }
+    check_status(&state, EscrowStatus::Created)?;
+    check_not_expired(&state)?;
+    check_vault(program_id, escrow_account, vault)?;
+    let missing = state.amount - state.deposited;
+    if amount == 0 || amount > missing {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+
+    let (receipt_pda, receipt_bump) = find_receipt_address(program_id, escrow_account.key, contributor.key);
+    if receipt_pda != *receipt_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut receipt = if receipt_account.data_is_empty() {
+        let rent = Rent::get()?;
+        create_pda_account(
+            contributor,
+            receipt_account,
+            rent.minimum_balance(RECEIPT_STATE_LEN),
+            RECEIPT_STATE_LEN as u64,
+            program_id,
+            system_program,
+            &[RECEIPT_PDA_SEED, escrow_account.key.as_ref(), contributor.key.as_ref(), &[receipt_bump]],
+        )?;
+        ReceiptState {
+            discriminator: RECEIPT_DISCRIMINATOR,
+            escrow:        *escrow_account.key,
+            contributor:   *contributor.key,
+            amount:        0,
+            bump:          receipt_bump,
+        }
+    } else {
+        load_receipt(program_id, receipt_account)?
+    };
+
+    let received = deposit_lamports(contributor, vault, system_program, amount)?;
+    receipt.amount = receipt.amount.checked_add(received).ok_or(EscrowError::AmountOverflow)?;
+    receipt.serialize(&mut &mut receipt_account.data.borrow_mut()[..])?;
+
+    state.deposited += received;
+    if state.deposited == state.amount {
+        state.remaining = state.deposited;
+        state.status    = EscrowStatus::Funded;
+        EscrowFunded { escrow: *escrow_account.key, deposited: state.deposited }.emit(events)?;
+    }
+    msg!("Contributed {} lamports, {} of {} raised", amount, state.deposited, state.amount);
+    Ok(())
+}
+
+fn load_receipt(program_id: &Pubkey, receipt_account: &AccountInfo) -> Result<ReceiptState, ProgramError> {
+    if receipt_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    let receipt = ReceiptState::try_from_slice(&receipt_account.data.borrow())?;
+    if receipt.discriminator != RECEIPT_DISCRIMINATOR {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(receipt)
+}
+
+/// After a deadline that passed short of the goal, pays a contributor back
+/// and closes their receipt. The last refund cancels the crowdfund so the
+/// initializer can Close it.
+fn process_claim_refund(program_id: &Pubkey, accounts: &[AccountInfo], events: Option<EventCpi>) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let contributor     = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let vault           = next_account_info(a)?;
+    let receipt_account = next_account_info(a)?;
+
+    if !contributor.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    if !bool::from(state.crowdfund) {
+        return Err(EscrowError::NotCrowdfund.into());
+    }
+    check_status(&state, EscrowStatus::Created)?;
+    if Clock::get()?.unix_timestamp <= state.expiry_ts {
+        return Err(EscrowError::NotExpired.into());
+    }
+    check_vault(program_id, escrow_account, vault)?;
+    let receipt = load_receipt(program_id, receipt_account)?;
+    if find_receipt_address(program_id, escrow_account.key, contributor.key).0 != *receipt_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    move_lamports(vault, contributor, receipt.amount)?;
+    receipt_account.data.borrow_mut().fill(0);
+    move_lamports(receipt_account, contributor, receipt_account.lamports())?;
+
+    state.deposited -= receipt.amount;
+    if state.deposited == 0 {
+        state.status = EscrowStatus::Cancelled;
+        EscrowCancelled { escrow: *escrow_account.key, refunded: receipt.amount }.emit(events)?;
+    }
+    msg!("Refunded {} lamports to contributor", receipt.amount);
+    Ok(())
+}
+
+/// Attaches a price condition to a native escrow before it is funded. The
+/// oracle must be a supported feed that already holds a usable price.
+fn process_set_price_condition(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    threshold: i64,
+    expo: i32,
+    above: bool,
+) -> ProgramResult {
+    let a               = &mut accounts.iter();
+    let initializer     = next_account_info(a)?;
+    let taker           = next_account_info(a)?;
+    let escrow_account  = next_account_info(a)?;
+    let oracle          = next_account_info(a)?;
+
+    if !initializer.is_signer || !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let mut state = load_escrow(program_id, escrow_account)?;
+    check_initializer(&state, initializer)?;
+    check_taker(&state, taker)?;
+    check_mint(&state, &Pubkey::default())?;
+    check_status(&state, EscrowStatus::Created)?;
+    load_price(oracle)?;
+
+    state.oracle          = *oracle.key;
+    state.price_threshold = threshold;
+    state.price_expo      = expo;
+    state.price_above     = above.into();
+    Ok(())
+}
+
+/// `value * 10^expo`, observed at `timestamp`.
+struct OraclePrice {
+    value:     i128,
+    expo:      i32,
+    timestamp: i64,
+}
+
+/// A price feed backend for price conditions. Backends are told apart by the
+/// program owning the oracle account, so adding one needs no new instruction
+/// data or accounts: implement this and list it in `load_price`.
+trait ConditionOracle {
+    const OWNER: Pubkey;
+
+    /// Parses the account and returns its latest trustworthy price.
+    fn read_price(data: &[u8]) -> Result<OraclePrice, ProgramError>;
+}
+
+impl ConditionOracle for PriceUpdateV2 {
+    const OWNER: Pubkey = PYTH_RECEIVER_PROGRAM_ID;
+
+    fn read_price(data: &[u8]) -> Result<OraclePrice, ProgramError> {
+        let update = Self::unpack(data)?;
+        if update.verification_level != VerificationLevel::Full {
+            return Err(EscrowError::InvalidOracle.into());
+        }
+        let message = update.price_message;
+        Ok(OraclePrice {
+            value:     message.price.into(),
+            expo:      message.exponent,
+            timestamp: message.publish_time,
+        })
+    }
+}
+
+impl ConditionOracle for AggregatorAccountData {
+    const OWNER: Pubkey = SWITCHBOARD_V2_PROGRAM_ID;
+
+    fn read_price(data: &[u8]) -> Result<OraclePrice, ProgramError> {
+        let aggregator = Self::unpack(data)?;
+        let round      = aggregator.latest_confirmed_round;
+        if round.num_success == 0 || round.num_success < aggregator.min_oracle_results {
+            return Err(EscrowError::InvalidOracle.into());
+        }
+        let expo = i32::try_from(round.result.scale).map_err(|_| EscrowError::InvalidOracle)?;
+        Ok(OraclePrice {
+            value:     round.result.mantissa,
+            expo:      -expo,
+            timestamp: round.round_open_timestamp,
+        })
+    }
+}
+
+/// Reads the price of whichever supported oracle owns `oracle`.
+fn load_price(oracle: &AccountInfo) -> Result<OraclePrice, ProgramError> {
+    let data = oracle.data.borrow();
+    let price = if *oracle.owner == PriceUpdateV2::OWNER {
+        PriceUpdateV2::read_price(&data)
+    } else if *oracle.owner == AggregatorAccountData::OWNER {
+        AggregatorAccountData::read_price(&data)
+    } else {
+        return Err(EscrowError::InvalidOracle.into());
+    };
+    price.map_err(|_| EscrowError::InvalidOracle.into())
+}
+
+/// Escrows with a price condition take the oracle as the next account and
+/// only release while its fresh price is on the agreed side of the threshold.
+fn check_price_condition(state: &EscrowState, a: &mut std::slice::Iter<AccountInfo>) -> ProgramResult {
+    if state.oracle == Pubkey::default() {
+        return Ok(());
+    }
+    let oracle = next_account_info(a)?;
+    if *oracle.key != state.oracle {
+        return Err(EscrowError::InvalidOracle.into());
+    }
+    let price = load_price(oracle)?;
+    if Clock::get()?.unix_timestamp.saturating_sub(price.timestamp) > MAX_PRICE_AGE_SECS {
+        return Err(EscrowError::StalePrice.into());
+    }
+
+    // Compare at the finer of the two exponents
+    let scale = |value: i128, from: i32| -> Result<i128, ProgramError> {
+        let shift = from.abs_diff(price.expo.min(state.price_expo));
+        10i128
+            .checked_pow(shift)
+            .and_then(|factor| value.checked_mul(factor))
+            .ok_or_else(|| EscrowError::InvalidOracle.into())
+    };
+    let threshold = scale(state.price_threshold.into(), state.price_expo)?;
+    let price     = scale(price.value, price.expo)?;
+    let met = if bool::from(state.price_above) { price > threshold } else { price < threshold };
+    if !met {
+        return Err(EscrowError::PriceConditionNotMet.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account<'a>(key: &'a Pubkey, lamports: &'a mut u64, owner: &'a Pubkey) -> AccountInfo<'a> {
+        AccountInfo::new(key, false, true, lamports, &mut [], owner, false, 0)
+    }
+
+    #[test]
+    fn transfer_lamports_moves_the_amount() {
+        let (a, b, owner) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (mut from_lamports, mut to_lamports) = (100, 5);
+        let from = account(&a, &mut from_lamports, &owner);
+        let to   = account(&b, &mut to_lamports, &owner);
+        transfer_lamports(&from, &to, 60).unwrap();
+        assert_eq!((from.lamports(), to.lamports()), (40, 65));
+    }
+
+    #[test]
+    fn transfer_lamports_rejects_overdraft_and_overflow() {
+        let (a, b, owner) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (mut from_lamports, mut to_lamports) = (100, u64::MAX - 10);
+        let from = account(&a, &mut from_lamports, &owner);
+        let to   = account(&b, &mut to_lamports, &owner);
+        assert_eq!(transfer_lamports(&from, &to, 101), Err(ProgramError::InsufficientFunds));
+        assert_eq!(transfer_lamports(&from, &to, 11), Err(EscrowError::AmountOverflow.into()));
+        assert_eq!((from.lamports(), to.lamports()), (100, u64::MAX - 10));
+    }
+
+    #[test]
+    fn auction_price_decays_to_the_floor() {
+        let linear = Auction { start_price: 1_000, floor_price: 100, start_ts: 10, end_ts: 110, step_secs: 0 };
+        assert_eq!(linear.price_at(0), 1_000);
+        assert_eq!(linear.price_at(35), 775);
+        assert_eq!(linear.price_at(110), 100);
+        assert_eq!(linear.price_at(i64::MAX), 100);
+        let stepped = Auction { step_secs: 20, ..linear };
+        assert_eq!(stepped.price_at(29), 1_000);
+        assert_eq!(stepped.price_at(35), 820);
+        assert_eq!(stepped.price_at(109), 280);
+    }
+
+    #[test]
+    fn transfer_lamports_to_itself_is_a_no_op() {
+        let (a, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut lamports = 100;
+        let from = account(&a, &mut lamports, &owner);
+        transfer_lamports(&from, &from.clone(), 60).unwrap();
+        assert_eq!(from.lamports(), 100);
+    }
+}
@@ -0,0 +1,49 @@
+//! Minimal read-only view of Pyth `PriceUpdateV2` accounts posted by the Pyth
+//! Solana receiver, enough to read a verified price and its age.
+
+use borsh::BorshDeserialize;
+use solana_program::{program_error::ProgramError, pubkey, pubkey::Pubkey};
+
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey = pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+/// Anchor discriminator of `PriceUpdateV2`, `sha256("account:PriceUpdateV2")[..8]`.
+const PRICE_UPDATE_V2_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+#[derive(BorshDeserialize, Debug, PartialEq)]
+pub enum VerificationLevel {
+    /// Checked against fewer Wormhole guardian signatures than a quorum
+    Partial { num_signatures: u8 },
+    Full,
+}
+
+#[derive(BorshDeserialize, Debug)]
+pub struct PriceFeedMessage {
+    pub feed_id:           [u8; 32],
+    pub price:             i64,
+    pub conf:              u64,
+    pub exponent:          i32,
+    pub publish_time:      i64,
+    pub prev_publish_time: i64,
+    pub ema_price:         i64,
+    pub ema_conf:          u64,
+}
+
+/// The on-chain `PriceUpdateV2` account, after its discriminator.
+#[derive(BorshDeserialize, Debug)]
+pub struct PriceUpdateV2 {
+    pub write_authority:    Pubkey,
+    pub verification_level: VerificationLevel,
+    pub price_message:      PriceFeedMessage,
+    pub posted_slot:        u64,
+}
+
+impl PriceUpdateV2 {
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let (discriminator, mut rest) = data
+            .split_first_chunk::<8>()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if *discriminator != PRICE_UPDATE_V2_DISCRIMINATOR {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Self::deserialize(&mut rest).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
@@ -0,0 +1,90 @@
+//! Minimal client of the SPL stake pool program, enough to turn lamports
+//! into pool tokens and back with DepositSol and WithdrawSol.
+
+use borsh::BorshSerialize;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+    stake, system_program, sysvar,
+};
+
+pub const STAKE_POOL_PROGRAM_ID: Pubkey = pubkey!("SPoo1Ku8WFXoNDMHPsrGSTSG1Y47rzgn41SLUNakuHy");
+const DEPOSIT_SOL: u8                    = 14;
+const WITHDRAW_SOL: u8                   = 16;
+
+/// The accounts of a stake pool that DepositSol and WithdrawSol touch.
+#[derive(Clone, Copy, Debug)]
+pub struct StakePool {
+    pub address:             Pubkey,
+    pub reserve_stake:       Pubkey,
+    pub pool_mint:           Pubkey,
+    pub manager_fee_account: Pubkey,
+    pub token_program:       Pubkey,
+}
+
+impl StakePool {
+    /// The pool's withdraw authority PDA, which mints and burns pool tokens.
+    pub fn withdraw_authority(&self) -> Pubkey {
+        Pubkey::find_program_address(&[self.address.as_ref(), b"withdraw"], &STAKE_POOL_PROGRAM_ID).0
+    }
+}
+
+fn data(tag: u8, amount: u64) -> Vec<u8> {
+    let mut data = vec![tag];
+    // Serializing into a Vec can't fail
+    let _ = amount.serialize(&mut data);
+    data
+}
+
+/// `DepositSol`: moves `lamports` from `from` into the pool's reserve and
+/// mints the pool tokens they are worth to `pool_tokens_to`.
+pub fn deposit_sol(pool: &StakePool, from: &Pubkey, pool_tokens_to: &Pubkey, lamports: u64) -> Instruction {
+    Instruction {
+        program_id: STAKE_POOL_PROGRAM_ID,
+        accounts:   vec![
+            AccountMeta::new(pool.address, false),
+            AccountMeta::new_readonly(pool.withdraw_authority(), false),
+            AccountMeta::new(pool.reserve_stake, false),
+            AccountMeta::new(*from, true),
+            AccountMeta::new(*pool_tokens_to, false),
+            AccountMeta::new(pool.manager_fee_account, false),
+            // The manager's fee account stands in for the referrer's
+            AccountMeta::new(pool.manager_fee_account, false),
+            AccountMeta::new(pool.pool_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(pool.token_program, false),
+        ],
+        data:       data(DEPOSIT_SOL, lamports),
+    }
+}
+
+/// `WithdrawSol`: burns `pool_tokens` from `pool_tokens_from`, owned by
+/// `authority`, and pays the lamports they are worth from the reserve to
+/// `lamports_to`.
+pub fn withdraw_sol(
+    pool: &StakePool,
+    authority: &Pubkey,
+    pool_tokens_from: &Pubkey,
+    lamports_to: &Pubkey,
+    pool_tokens: u64,
+) -> Instruction {
+    Instruction {
+        program_id: STAKE_POOL_PROGRAM_ID,
+        accounts:   vec![
+            AccountMeta::new(pool.address, false),
+            AccountMeta::new_readonly(pool.withdraw_authority(), false),
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new(*pool_tokens_from, false),
+            AccountMeta::new(pool.reserve_stake, false),
+            AccountMeta::new(*lamports_to, false),
+            AccountMeta::new(pool.manager_fee_account, false),
+            AccountMeta::new(pool.pool_mint, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(sysvar::stake_history::id(), false),
+            AccountMeta::new_readonly(stake::program::id(), false),
+            AccountMeta::new_readonly(pool.token_program, false),
+        ],
+        data:       data(WITHDRAW_SOL, pool_tokens),
+    }
+}
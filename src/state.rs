@@ -0,0 +1,1028 @@
+use std::ops::{Deref, DerefMut, Range};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
+use shank::ShankAccount;
+use solana_program::{pubkey, pubkey::Pubkey};
+use spl_pod::primitives::PodBool;
+
+pub const ESCROW_PDA_SEED: &[u8]      = b"escrow";
+pub const VAULT_PDA_SEED: &[u8]       = b"vault";
+pub const TAKER_VAULT_PDA_SEED: &[u8] = b"taker_vault";
+pub const CONFIG_PDA_SEED: &[u8]      = b"config";
+pub const REGISTRY_PDA_SEED: &[u8]    = b"registry";
+pub const RECEIPT_PDA_SEED: &[u8]     = b"receipt";
+pub const CLAIMED_PDA_SEED: &[u8]     = b"claimed";
+pub const EVENT_AUTHORITY_PDA_SEED: &[u8] = b"event_authority";
+pub const STATS_PDA_SEED: &[u8]       = b"stats";
+pub const TEMPLATE_PDA_SEED: &[u8]    = b"template";
+pub const AUTHORITIES_PDA_SEED: &[u8] = b"authorities";
+pub const FEATURES_PDA_SEED: &[u8]    = b"features";
+/// Size of the `EscrowState` header every escrow account starts with.
+pub const ESCROW_STATE_LEN: usize     = std::mem::size_of::<EscrowState>();
+pub const MILESTONE_LEN: usize        = std::mem::size_of::<Milestone>();
+pub const MAX_MILESTONES: usize       = 64;
+pub const MAX_ALLOWED_TAKERS: usize   = 16;
+/// Longest terms URI an escrow stores, in bytes.
+pub const MAX_METADATA_URI_LEN: usize = 200;
+pub const SPLIT_LEN: usize            = std::mem::size_of::<Split>();
+pub const MAX_SPLITS: usize           = 8;
+/// Most escrows one InitializeBatch creates, to stay within the compute budget.
+pub const MAX_BATCH_LEN: usize        = 8;
+
+/// Wrapped SOL mint of the original SPL Token program; Token-2022's is
+/// `spl_token_2022::native_mint::ID`.
+pub const SPL_TOKEN_NATIVE_MINT: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+/// Header size of each layout version, v1 first. v1 escrows have no
+/// version field, and each later version appends fields to the header.
+pub const ESCROW_STATE_LENS: [usize; ESCROW_STATE_VERSION as usize] = [
+    512,
+    512 + 64,
+    512 + 64 + 40,
+    512 + 64 + 40 + 48,
+    512 + 64 + 40 + 48 + 24,
+    512 + 64 + 40 + 48 + 24 + 8,
+    512 + 64 + 40 + 48 + 24 + 8 + 32,
+    512 + 64 + 40 + 48 + 24 + 8 + 32 + 48,
+    512 + 64 + 40 + 48 + 24 + 8 + 32 + 48 + 40,
+    512 + 64 + 40 + 48 + 24 + 8 + 32 + 48 + 40 + 8,
+    512 + 64 + 40 + 48 + 24 + 8 + 32 + 48 + 40 + 8 + 8,
+    512 + 64 + 40 + 48 + 24 + 8 + 32 + 48 + 40 + 8 + 8 + 32,
+    512 + 64 + 40 + 48 + 24 + 8 + 32 + 48 + 40 + 8 + 8 + 32 + 40,
+    512 + 64 + 40 + 48 + 24 + 8 + 32 + 48 + 40 + 8 + 8 + 32 + 40 + 16,
+    512 + 64 + 40 + 48 + 24 + 8 + 32 + 48 + 40 + 8 + 8 + 32 + 40 + 16 + 56,
+];
+
+/// Layout version written into new escrows. Escrows created before the
+/// `version` field existed are v1; each later version appends fields to
+/// the header, so older escrows must go through MigrateState.
+pub const ESCROW_STATE_VERSION: u8    = 15;
+
+/// First 8 bytes of every account type this program owns, for
+/// `getProgramAccounts` memcmp filters at offset 0. Never change them.
+pub const ESCROW_DISCRIMINATOR: [u8; 8] = *b"flo:escr";
+pub const CONFIG_DISCRIMINATOR: [u8; 8] = *b"flo:conf";
+pub const REGISTRY_DISCRIMINATOR: [u8; 8] = *b"flo:rgst";
+pub const RECEIPT_DISCRIMINATOR: [u8; 8]  = *b"flo:rcpt";
+pub const CLAIMED_DISCRIMINATOR: [u8; 8]  = *b"flo:clmd";
+pub const STATS_DISCRIMINATOR: [u8; 8]    = *b"flo:stat";
+pub const TEMPLATE_DISCRIMINATOR: [u8; 8] = *b"flo:tmpl";
+pub const AUTHORITIES_DISCRIMINATOR: [u8; 8] = *b"flo:auth";
+pub const FEATURES_DISCRIMINATOR: [u8; 8] = *b"flo:feat";
+
+/// Derives the escrow state PDA for `initializer` and escrow `id`.
+pub fn find_escrow_address(program_id: &Pubkey, initializer: &Pubkey, id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ESCROW_PDA_SEED, initializer.as_ref(), &id.to_le_bytes()], program_id)
+}
+
+/// Derives the registry PDA that hands out and lists `initializer`'s escrow ids.
+pub fn find_registry_address(program_id: &Pubkey, initializer: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REGISTRY_PDA_SEED, initializer.as_ref()], program_id)
+}
+
+/// Derives the vault PDA holding `escrow`'s lamports or tokens.
+pub fn find_vault_address(program_id: &Pubkey, escrow: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_PDA_SEED, escrow.as_ref()], program_id)
+}
+
+/// Derives the program-wide config PDA.
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_PDA_SEED], program_id)
+}
+
+/// Derives the receipt PDA recording `contributor`'s share of a crowdfund.
+pub fn find_receipt_address(program_id: &Pubkey, escrow: &Pubkey, contributor: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RECEIPT_PDA_SEED, escrow.as_ref(), contributor.as_ref()], program_id)
+}
+
+/// Derives the PDA that signs the self-CPI carrying an event.
+pub fn find_event_authority_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[EVENT_AUTHORITY_PDA_SEED], program_id)
+}
+
+/// Derives the bitmap PDA recording which allocations of a distribution
+/// escrow were claimed.
+pub fn find_claimed_address(program_id: &Pubkey, escrow: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CLAIMED_PDA_SEED, escrow.as_ref()], program_id)
+}
+
+/// Derives the vault holding the taker's leg of a swap escrow.
+pub fn find_taker_vault_address(program_id: &Pubkey, escrow: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[TAKER_VAULT_PDA_SEED, escrow.as_ref()], program_id)
+}
+
+/// Derives the reputation stats PDA of `party`.
+pub fn find_stats_address(program_id: &Pubkey, party: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STATS_PDA_SEED, party.as_ref()], program_id)
+}
+
+/// Derives the PDA of the escrow template registered under `name`.
+pub fn find_template_address(program_id: &Pubkey, name: &[u8; 32]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[TEMPLATE_PDA_SEED, name], program_id)
+}
+
+/// Derives the program-wide authorities PDA holding the role grants.
+pub fn find_authorities_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[AUTHORITIES_PDA_SEED], program_id)
+}
+
+/// Derives the program-wide feature flags PDA.
+pub fn find_features_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FEATURES_PDA_SEED], program_id)
+}
+
+// The parties stay where `getProgramAccounts` filters expect them, and the
+// lists that follow the header stay aligned. A field added to `EscrowState`
+// changes the size and moves the lists of the escrows already on chain, so
+// it needs a new version, with its header size in `ESCROW_STATE_LENS`.
+const _: () = assert!(std::mem::offset_of!(EscrowState, initializer_pubkey) == 8 + 1);
+const _: () = assert!(std::mem::offset_of!(EscrowState, taker_pubkey) == 8 + 1 + 32);
+const _: () = assert!(ESCROW_STATE_LENS[ESCROW_STATE_VERSION as usize - 1] == ESCROW_STATE_LEN);
+const _: () = assert!(ESCROW_STATE_LEN.is_multiple_of(std::mem::align_of::<Milestone>()));
+
+// Each version's header ends where the first field of the next one starts,
+// so a field that lands in an older version's part doesn't compile either.
+const _: () = {
+    let next_starts = [
+        std::mem::offset_of!(EscrowState, stake_account),
+        std::mem::offset_of!(EscrowState, auction),
+        std::mem::offset_of!(EscrowState, proposed_by),
+        std::mem::offset_of!(EscrowState, subscription),
+        std::mem::offset_of!(EscrowState, captured),
+        std::mem::offset_of!(EscrowState, created_by),
+        std::mem::offset_of!(EscrowState, template),
+        std::mem::offset_of!(EscrowState, emergency_recipient),
+        std::mem::offset_of!(EscrowState, nonce),
+        std::mem::offset_of!(EscrowState, creation_fee),
+        std::mem::offset_of!(EscrowState, reference),
+        std::mem::offset_of!(EscrowState, metadata_hash),
+        std::mem::offset_of!(EscrowState, arbiter_fee),
+        std::mem::offset_of!(EscrowState, appeal_window_secs),
+    ];
+    assert!(next_starts.len() == ESCROW_STATE_LENS.len() - 1);
+    let mut version = 0;
+    while version < next_starts.len() {
+        assert!(ESCROW_STATE_LENS[version] == next_starts[version]);
+        version += 1;
+    }
+};
+
+/// One escrow, stored at `["escrow", initializer, id]` with `id` as 8
+/// little-endian bytes.
+///
+/// The header is `#[repr(C)]` and Pod, so instructions read and write it in
+/// place in the account data instead of decoding a copy. Fields are grouped
+/// by alignment to leave no padding. The milestones, allowlist members and
+/// split recipients follow it in that order, as many of each as it counts;
+/// `EscrowData` reaches them.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable, ShankAccount)]
+pub struct EscrowState {
+    /// Always `ESCROW_DISCRIMINATOR` once initialized
+    pub discriminator:     [u8; 8],
+    pub is_initialized:    PodBool,
+    pub initializer_pubkey: Pubkey,
+    /// `Pubkey::default()` while an open escrow waits for AcceptEscrow
+    pub taker_pubkey:      Pubkey,
+    pub bump:              u8,
+    pub status:            EscrowStatus,
+    /// Funded by many contributors through Contribute, each holding a receipt
+    pub crowdfund:         PodBool,
+    /// Withdraw needs the oracle price strictly above (or below, if
+    /// `price_above` is false) `price_threshold * 10^price_expo`
+    pub price_above:       PodBool,
+    /// The bond is in the vault on top of `remaining`
+    pub bond_held:         PodBool,
+    pub emitter_chain:     u16,
+    pub price_expo:        i32,
+    /// Ethereum address whose secp256k1 signature releases the escrow through
+    /// ClaimWithEthSignature, all zeros for none
+    pub eth_authority:     [u8; 20],
+    /// SPL mint held by the vault, `Pubkey::default()` for native lamports
+    pub mint:              Pubkey,
+    /// Resolves disputes, `Pubkey::default()` when the escrow has no arbiter
+    pub arbiter_pubkey:    Pubkey,
+    /// Verified Metaplex collection the deposited NFT must belong to,
+    /// `Pubkey::default()` for escrows that are not NFT escrows
+    pub collection:        Pubkey,
+    /// Mint the taker pays with in a swap escrow, `Pubkey::default()` otherwise
+    pub taker_mint:        Pubkey,
+    /// SHA-256 hash the taker must open with Claim, all zeros for no hash lock
+    pub hash_lock:         [u8; 32],
+    /// Pyth or Switchboard price account gating Withdraw, `Pubkey::default()`
+    /// for no condition
+    pub oracle:            Pubkey,
+    /// Wormhole emitter whose message releases the escrow through
+    /// ReleaseWithVaa, on `emitter_chain`; an all-zero address means none
+    pub emitter_address:   [u8; 32],
+    /// Root of the allocations of a distribution escrow, all zeros otherwise
+    pub merkle_root:       [u8; 32],
+    /// Gets the config's referral share of the protocol fee on Withdraw,
+    /// `Pubkey::default()` when there is none
+    pub referrer:          Pubkey,
+    /// Tranches of a milestone escrow
+    pub milestone_count:   u8,
+    /// Members of an open escrow's allowlist, any one of whom may take it;
+    /// none when anyone can
+    pub allowed_taker_count: u8,
+    /// Recipients sharing the payout of Withdraw, none when it all goes to
+    /// the taker
+    pub split_count:       u8,
+    /// `ESCROW_STATE_VERSION` for current escrows. It takes a padding byte
+    /// of the v1 header, so a v1 escrow reads as version 0
+    pub version:           u8,
+    /// The named taker didn't co-sign the Initialize and hasn't consented
+    /// since with AcceptEscrow, DepositBond or RequestWithdraw; until then
+    /// the initializer can still Cancel the escrow once funded. Like
+    /// `version` it takes a padding byte, which older escrows leave false
+    pub taker_pending:     PodBool,
+    /// Authorize-and-capture escrow: the taker settles it with one Capture
+    /// of up to `amount` before `expiry_ts`
+    pub authorization:     PodBool,
+    _padding:              [u8; 2],
+    pub amount:            u64,
+    /// Assigned from the initializer's registry at creation
+    pub id:                u64,
+    /// Amount that actually landed in escrow, net of any transfer fee
+    pub deposited:         u64,
+    /// Unix timestamp after which the initializer may refund, 0 for no deadline
+    pub expiry_ts:         i64,
+    /// Part of `deposited` not yet paid out
+    pub remaining:         u64,
+    /// Amount of `taker_mint` the taker must deposit
+    pub taker_amount:      u64,
+    /// Taker leg currently held in the taker vault, net of any transfer fee
+    pub taker_deposited:   u64,
+    /// Linear vesting schedule for WithdrawVested; `end_ts` is 0 when the
+    /// escrow does not vest
+    pub start_ts:          i64,
+    pub cliff_ts:          i64,
+    pub end_ts:            i64,
+    pub price_threshold:   i64,
+    /// Part of the message `eth_authority` signs; bumped on every claim so a
+    /// signature only works once
+    pub eth_nonce:         u64,
+    /// When the taker called RequestWithdraw, 0 if they have not
+    pub withdraw_requested_at: i64,
+    /// Collateral in lamports the taker locks with DepositBond, 0 for none.
+    /// Completion pays it back with the funds, a taker default forfeits it
+    pub taker_bond:        u64,
+    /// Stake account whose authorities the escrow PDA holds, in place of a
+    /// vault, `Pubkey::default()` for escrows that are not stake escrows.
+    /// A v1 header ends before it, with its lists where this starts
+    pub stake_account:     Pubkey,
+    /// SPL stake pool whose tokens the vault holds for the deposited
+    /// lamports, `Pubkey::default()` for escrows that don't earn yield.
+    /// It fills the last of the reserve, so new fields from here on come
+    /// with a layout version
+    pub stake_pool:        Pubkey,
+    /// Dutch auction price schedule (v3), all zero when the escrow is not
+    /// an auction
+    pub auction:           Auction,
+    /// Party whose ProposeTerms awaits the other's AcceptTerms (v4),
+    /// `Pubkey::default()` when nothing is pending
+    pub proposed_by:       Pubkey,
+    pub proposed_amount:   u64,
+    pub proposed_expiry:   i64,
+    /// Release schedule of a subscription escrow (v5), all zero otherwise
+    pub subscription:      Subscription,
+    /// What Capture paid the taker (v6), fee included; the rest went back
+    /// to the initializer
+    pub captured:          u64,
+    /// Party who created the escrow (v7): the initializer, or the taker for
+    /// an invoice. `Pubkey::default()` for older escrows, which were all
+    /// created by their initializer
+    pub created_by:        Pubkey,
+    /// Template the escrow was created from (v8), `Pubkey::default()` for
+    /// none. The two fields below were copied from it and replace the
+    /// config's fee and `DISPUTE_WINDOW_SECS` when it is set
+    pub template:          Pubkey,
+    pub dispute_window_secs: i64,
+    pub fee_bps:           u16,
+    _padding_v8:           [u8; 6],
+    /// Where a pending ProposeEmergencyWithdraw (v9) would send the funds,
+    /// `Pubkey::default()` when none is pending
+    pub emergency_recipient: Pubkey,
+    /// When the admin proposed it; ExecuteEmergencyWithdraw waits
+    /// `EMERGENCY_DELAY_SECS` from then
+    pub emergency_proposed_at: i64,
+    /// Client-chosen tag given to InitializeWithNonce (v10) and echoed in
+    /// `EscrowInitialized`, so a retrying client can tell which of the
+    /// escrows in flight between the same parties is its own. 0 for none
+    pub nonce:             u64,
+    /// The config's `creation_fee` when the escrow was created (v11), held
+    /// in this account on top of its rent until it closes
+    pub creation_fee:      u64,
+    /// Off-chain reference given to InitializeWithReference (v12), such as
+    /// the hash of an order id, echoed in `EscrowInitialized`. Zero for none
+    pub reference:         [u8; 32],
+    /// Hash of the terms document at `metadata_uri`, fixed by both parties
+    /// with SetMetadata (v13). Zero until then
+    pub metadata_hash:     [u8; 32],
+    /// Bytes of the URI where the human-readable terms live, stored after
+    /// the split recipients, up to `MAX_METADATA_URI_LEN`
+    pub metadata_uri_len:  u8,
+    _padding_v13:          [u8; 7],
+    /// Flat lamports the arbiter earns for ruling on a dispute, from the
+    /// config at Initialize (v14); 0 for token escrows
+    pub arbiter_fee:       u64,
+    /// Share of the disputed funds the arbiter earns on top, in bps
+    pub arbiter_fee_bps:   u16,
+    _padding_v14:          [u8; 6],
+    /// How long a ruling waits for an Appeal before it can be carried out,
+    /// from the config at Initialize (v15); 0 for final rulings
+    pub appeal_window_secs: i64,
+    /// Arbiter an Appeal hands the dispute to, such as a DAO
+    pub appeal_arbiter:    Pubkey,
+    /// When the held ruling was made
+    pub ruled_at:          i64,
+    /// Share of the escrow a held ruling gives the taker, in bps
+    pub ruling_taker_bps:  u16,
+    _padding_v15:          [u8; 6],
+}
+
+impl Default for EscrowState {
+    fn default() -> Self {
+        Self::zeroed()
+    }
+}
+
+impl EscrowState {
+    /// Borrows the header at the start of escrow account `data`, which must
+    /// be aligned as account data is.
+    pub fn from_bytes(data: &[u8]) -> Option<&Self> {
+        bytemuck::try_from_bytes(data.get(..ESCROW_STATE_LEN)?).ok()
+    }
+
+    /// Borrows the header mutably; see `from_bytes`.
+    pub fn from_bytes_mut(data: &mut [u8]) -> Option<&mut Self> {
+        bytemuck::try_from_bytes_mut(data.get_mut(..ESCROW_STATE_LEN)?).ok()
+    }
+
+    /// Copies the header out of account data fetched off-chain, which need
+    /// not be aligned.
+    pub fn unpack(data: &[u8]) -> borsh::io::Result<Self> {
+        data.get(..ESCROW_STATE_LEN)
+            .and_then(|header| bytemuck::try_pod_read_unaligned(header).ok())
+            .ok_or_else(|| borsh::io::Error::new(borsh::io::ErrorKind::InvalidData, "not an escrow"))
+    }
+
+    /// Account size needed to store this escrow; it grows with milestones,
+    /// allowlist members, split recipients and the metadata URI.
+    pub fn space(&self) -> usize {
+        ESCROW_STATE_LEN + self.list_lens().iter().sum::<usize>()
+    }
+
+    /// Bytes of each list after the header, in the order they are stored.
+    fn list_lens(&self) -> [usize; 4] {
+        [
+            usize::from(self.milestone_count) * MILESTONE_LEN,
+            usize::from(self.allowed_taker_count) * 32,
+            usize::from(self.split_count) * SPLIT_LEN,
+            usize::from(self.metadata_uri_len),
+        ]
+    }
+
+    /// Whether the taker issued this escrow with InitializeInvoice.
+    pub fn is_invoice(&self) -> bool {
+        self.created_by == self.taker_pubkey && self.created_by != Pubkey::default()
+    }
+}
+
+/// An escrow account's data: derefs to its `EscrowState` header and reaches
+/// the lists stored after it, all in place.
+pub struct EscrowData<D>(D);
+
+impl<D: Deref<Target = [u8]>> EscrowData<D> {
+    /// Fails unless `data` starts with an aligned header and holds every list
+    /// it counts.
+    pub fn new(data: D) -> Option<Self> {
+        if data.len() < EscrowState::from_bytes(&data)?.space() {
+            return None;
+        }
+        Some(Self(data))
+    }
+
+    /// The header and lists, as stored at the start of the account.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0[..self.space()]
+    }
+
+    pub fn milestones(&self) -> &[Milestone] {
+        bytemuck::cast_slice(&self.0[self.list_range(0)])
+    }
+
+    pub fn allowed_takers(&self) -> &[Pubkey] {
+        bytemuck::cast_slice(&self.0[self.list_range(1)])
+    }
+
+    pub fn splits(&self) -> &[Split] {
+        bytemuck::cast_slice(&self.0[self.list_range(2)])
+    }
+
+    /// The terms URI SetMetadata stored, empty until then.
+    pub fn metadata_uri(&self) -> &[u8] {
+        &self.0[self.list_range(3)]
+    }
+
+    fn list_range(&self, list: usize) -> Range<usize> {
+        let lens  = self.list_lens();
+        let start = ESCROW_STATE_LEN + lens[..list].iter().sum::<usize>();
+        start..start + lens[list]
+    }
+}
+
+impl<D: DerefMut<Target = [u8]>> EscrowData<D> {
+    pub fn milestones_mut(&mut self) -> &mut [Milestone] {
+        let range = self.list_range(0);
+        bytemuck::cast_slice_mut(&mut self.0[range])
+    }
+
+    /// Replaces the tranches. Like the other setters, it moves the lists
+    /// stored after this one, so the data must already have room for the
+    /// new `space()`; the caller keeps to `MAX_MILESTONES`.
+    pub fn set_milestones(&mut self, milestones: &[Milestone]) {
+        self.replace_list(0, bytemuck::cast_slice(milestones));
+        self.milestone_count = milestones.len() as u8;
+    }
+
+    /// Replaces the allowlist; see `set_milestones`.
+    pub fn set_allowed_takers(&mut self, takers: &[Pubkey]) {
+        self.replace_list(1, bytemuck::cast_slice(takers));
+        self.allowed_taker_count = takers.len() as u8;
+    }
+
+    /// Replaces the split recipients; see `set_milestones`.
+    pub fn set_splits(&mut self, splits: &[Split]) {
+        self.replace_list(2, bytemuck::cast_slice(splits));
+        self.split_count = splits.len() as u8;
+    }
+
+    /// Replaces the terms URI; see `set_milestones`. The caller keeps to
+    /// `MAX_METADATA_URI_LEN`.
+    pub fn set_metadata_uri(&mut self, uri: &[u8]) {
+        self.replace_list(3, uri);
+        self.metadata_uri_len = uri.len() as u8;
+    }
+
+    fn replace_list(&mut self, list: usize, bytes: &[u8]) {
+        let range = self.list_range(list);
+        let end   = self.space();
+        let moved = range.start + bytes.len();
+        self.0.copy_within(range.end..end, moved);
+        self.0[range.start..moved].copy_from_slice(bytes);
+    }
+}
+
+impl<D: Deref<Target = [u8]>> Deref for EscrowData<D> {
+    type Target = EscrowState;
+
+    fn deref(&self) -> &EscrowState {
+        bytemuck::from_bytes(&self.0[..ESCROW_STATE_LEN])
+    }
+}
+
+impl<D: DerefMut<Target = [u8]>> DerefMut for EscrowData<D> {
+    fn deref_mut(&mut self) -> &mut EscrowState {
+        bytemuck::from_bytes_mut(&mut self.0[..ESCROW_STATE_LEN])
+    }
+}
+
+/// Upgrades escrow `data` with a header of `old_len` bytes, already grown
+/// by the bytes the current header adds, to the current layout: its lists
+/// move after the header, the fields added since read as zero and the
+/// version is stamped.
+pub fn migrate_escrow_data(data: &mut [u8], old_len: usize) {
+    let len = data.len() - (ESCROW_STATE_LEN - old_len);
+    data.copy_within(old_len..len, ESCROW_STATE_LEN);
+    data[old_len..ESCROW_STATE_LEN].fill(0);
+    data[std::mem::offset_of!(EscrowState, version)] = ESCROW_STATE_VERSION;
+}
+
+/// One recipient of a split payout and its share in basis points; the
+/// shares of an escrow add up to 10 000.
+#[repr(C)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct Split {
+    pub recipient: Pubkey,
+    pub bps:       u16,
+}
+
+/// Price schedule of a Dutch auction escrow: the buyer pays `start_price`
+/// lamports until `start_ts`, decaying to `floor_price` at `end_ts`.
+#[repr(C)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct Auction {
+    pub start_price: u64,
+    pub floor_price: u64,
+    pub start_ts:    i64,
+    /// 0 when the escrow is not an auction
+    pub end_ts:      i64,
+    /// The price drops every `step_secs` instead of continuously, 0 for a
+    /// linear decay
+    pub step_secs:   i64,
+}
+
+impl Auction {
+    pub fn is_set(&self) -> bool {
+        self.end_ts != 0
+    }
+
+    /// The price a buyer pays at `now`.
+    pub fn price_at(&self, now: i64) -> u64 {
+        if now <= self.start_ts {
+            return self.start_price;
+        }
+        if now >= self.end_ts {
+            return self.floor_price;
+        }
+        let mut elapsed = now - self.start_ts;
+        if self.step_secs > 0 {
+            elapsed -= elapsed % self.step_secs;
+        }
+        let range = u128::from(self.start_price.saturating_sub(self.floor_price));
+        let drop  = range * elapsed as u128 / (self.end_ts - self.start_ts) as u128;
+        self.start_price - drop as u64
+    }
+}
+
+/// Schedule of a subscription escrow: the taker can withdraw one
+/// `amount_per_interval` tranche per `interval_secs` elapsed since
+/// `start_ts`, until the deposit runs out.
+#[repr(C)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct Subscription {
+    pub amount_per_interval: u64,
+    /// 0 when the escrow is not a subscription
+    pub interval_secs:       i64,
+    pub start_ts:            i64,
+}
+
+impl Subscription {
+    pub fn is_set(&self) -> bool {
+        self.interval_secs != 0
+    }
+
+    /// Total of the tranches unlocked at `now` out of `deposited`.
+    pub fn unlocked_at(&self, now: i64, deposited: u64) -> u64 {
+        if now < self.start_ts || self.interval_secs <= 0 {
+            return 0;
+        }
+        let intervals = ((now - self.start_ts) / self.interval_secs) as u128;
+        (intervals * self.amount_per_interval as u128).min(deposited as u128) as u64
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+pub struct Milestone {
+    pub amount:   u64,
+    /// Set by ApproveMilestone, which unlocks the tranche for the taker
+    pub approved: PodBool,
+    pub released: PodBool,
+    _padding:     [u8; 6],
+}
+
+impl Milestone {
+    pub fn new(amount: u64) -> Self {
+        Self { amount, ..Self::default() }
+    }
+}
+
+/// Lifecycle of an escrow. Only the following transitions are valid:
+/// Created → Funded (Deposit, or DepositAdditional reaching `amount`),
+/// Created → Cancelled (Cancel), Funded → Released (Withdraw),
+/// Funded → Cancelled (Refund) and Released/Cancelled → Closed (Close).
+/// With an arbiter, Funded → Disputed (Dispute) and Disputed →
+/// Released/Cancelled (Resolve). With an appeal window, Resolve goes
+/// Disputed → Ruled, then Ruled → Disputed (Appeal) or Ruled →
+/// Released/Cancelled (FinalizeRuling). A swap escrow goes Funded → Released only
+/// through ExecuteSwap. A crowdfund goes Created → Funded once Contribute
+/// reaches its goal, or Created → Cancelled when ClaimRefund returns the
+/// last contribution after the deadline.
+///
+/// A byte rather than an enum so `EscrowState` stays Pod; it encodes as
+/// the enum's variant index would.
+#[repr(transparent)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Default, Eq, PartialEq, Pod, Zeroable)]
+pub struct EscrowStatus(u8);
+
+// Named like enum variants, so matches on a status read as they would
+#[allow(non_upper_case_globals)]
+impl EscrowStatus {
+    pub const Created: Self   = Self(0);
+    pub const Funded: Self    = Self(1);
+    pub const Released: Self  = Self(2);
+    pub const Cancelled: Self = Self(3);
+    pub const Closed: Self    = Self(4);
+    pub const Disputed: Self  = Self(5);
+    /// The arbiter ruled, and the ruling waits out the escrow's appeal window
+    pub const Ruled: Self     = Self(6);
+}
+
+impl std::fmt::Debug for EscrowStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        const NAMES: [&str; 7] = ["Created", "Funded", "Released", "Cancelled", "Closed", "Disputed", "Ruled"];
+        match NAMES.get(usize::from(self.0)) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "EscrowStatus({})", self.0),
+        }
+    }
+}
+
+pub const CONFIG_STATE_LEN: usize = 8 + 1 + 32 + 2 + 32 + 1 + 1 + 8 + 2 + 2 + 1 + 32 + 32 + 8 + 8 + 8 + 4 + 8 + 2
+    + 8 + 32;
+/// Fees are in basis points, so 10 000 takes the whole payout.
+pub const MAX_FEE_BPS: u16        = 10_000;
+/// Oldest oracle price, in seconds, a price condition will accept.
+pub const MAX_PRICE_AGE_SECS: i64 = 60;
+/// `max_expiry_horizon` of a newly initialized config, one year.
+pub const DEFAULT_MAX_EXPIRY_HORIZON: i64 = 365 * 24 * 60 * 60;
+/// How long the initializer has to Dispute after a RequestWithdraw.
+pub const DISPUTE_WINDOW_SECS: i64 = 3 * 24 * 60 * 60;
+/// How long an emergency withdrawal waits, for either party to Veto it.
+pub const EMERGENCY_DELAY_SECS: i64 = 7 * 24 * 60 * 60;
+/// Leads every voucher message so it cannot be mistaken for another payload.
+pub const VOUCHER_PREFIX: [u8; 8] = *b"flo:vchr";
+
+/// Message the arbiter signs off-chain to approve releasing `amount`
+/// lamports of `escrow` to its taker through ClaimWithVoucher.
+pub fn voucher_message(escrow: &Pubkey, amount: u64) -> [u8; 48] {
+    let mut message = [0; 48];
+    message[..8].copy_from_slice(&VOUCHER_PREFIX);
+    message[8..40].copy_from_slice(escrow.as_ref());
+    message[40..].copy_from_slice(&amount.to_le_bytes());
+    message
+}
+
+/// Leads every relayed Initialize message.
+pub const RELAY_PREFIX: [u8; 8] = *b"flo:rlay";
+
+/// Message the initializer signs off-chain so a relayer can create `escrow`
+/// for them through InitializeRelayed until `deadline`. The escrow address
+/// fixes the initializer and id, so each message creates one escrow at most.
+pub fn relayed_initialize_message(
+    escrow: &Pubkey,
+    taker: &Pubkey,
+    amount: u64,
+    expiry_ts: i64,
+    deadline: i64,
+) -> [u8; 96] {
+    let mut message = [0; 96];
+    message[..8].copy_from_slice(&RELAY_PREFIX);
+    message[8..40].copy_from_slice(escrow.as_ref());
+    message[40..72].copy_from_slice(taker.as_ref());
+    message[72..80].copy_from_slice(&amount.to_le_bytes());
+    message[80..88].copy_from_slice(&expiry_ts.to_le_bytes());
+    message[88..].copy_from_slice(&deadline.to_le_bytes());
+    message
+}
+
+/// Payload a Wormhole message must carry to release escrow `id` of
+/// `initializer` through ReleaseWithVaa: `initializer || id`, little-endian.
+pub fn vaa_release_payload(initializer: &Pubkey, id: u64) -> [u8; 40] {
+    let mut payload = [0; 40];
+    payload[..32].copy_from_slice(initializer.as_ref());
+    payload[32..].copy_from_slice(&id.to_le_bytes());
+    payload
+}
+
+/// Message `eth_authority` signs, EIP-191 style, to approve
+/// ClaimWithEthSignature: `escrow || nonce` with the nonce little-endian.
+pub fn eth_release_message(escrow: &Pubkey, nonce: u64) -> [u8; 40] {
+    let mut message = [0; 40];
+    message[..32].copy_from_slice(escrow.as_ref());
+    message[32..].copy_from_slice(&nonce.to_le_bytes());
+    message
+}
+
+/// Program-wide settings, stored once at the config PDA.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, ShankAccount)]
+pub struct ConfigState {
+    /// Always `CONFIG_DISCRIMINATOR` once initialized
+    pub discriminator:  [u8; 8],
+    pub is_initialized: bool,
+    /// Signer of InitializeConfig, allowed to manage the config
+    pub admin:          Pubkey,
+    /// Protocol fee taken on Withdraw, in basis points
+    pub fee_bps:        u16,
+    /// Receives the protocol fee
+    pub treasury:       Pubkey,
+    pub bump:           u8,
+    /// While set, new escrows and deposits are rejected
+    pub paused:         bool,
+    /// Furthest ExtendDeadline may push an expiry, in seconds from now
+    pub max_expiry_horizon: i64,
+    /// Share of the protocol fee paid to an escrow's referrer on Withdraw,
+    /// in basis points of the fee
+    pub referral_bps:   u16,
+    /// Share of an expired escrow's refund paid to whoever runs CrankRefund,
+    /// in basis points of the refund
+    pub crank_bounty_bps: u16,
+    /// Strict mode: native escrows must be funded by a Deposit in the
+    /// transaction that creates them
+    pub require_deposit: bool,
+    /// Only program allowed to invoke the escrow through CPI, or
+    /// `Pubkey::default()` for any
+    pub allowed_caller: Pubkey,
+    /// Receives the staking yield of escrows deposited into a stake pool,
+    /// or `Pubkey::default()` to leave it to each escrow's initializer
+    pub yield_beneficiary: Pubkey,
+    /// Smallest `amount` a new native escrow may ask for, in lamports
+    pub min_amount:     u64,
+    /// Largest `amount` a new native escrow may ask for, in lamports, 0 for
+    /// no cap
+    pub max_amount:     u64,
+    /// Lamports every new escrow puts up on top of its rent, given back when
+    /// it closes after a release and forfeited to the treasury otherwise
+    pub creation_fee:   u64,
+    /// Most escrows one initializer may create per epoch, 0 for no limit
+    pub max_escrows_per_epoch: u32,
+    /// Flat lamports the arbiter of a new native escrow earns per ruling
+    pub arbiter_fee:    u64,
+    /// Share of the disputed funds the arbiter earns on top, in bps
+    pub arbiter_fee_bps: u16,
+    /// How long the rulings on new native escrows wait for an Appeal, 0 for
+    /// final rulings
+    pub appeal_window_secs: i64,
+    /// Arbiter an Appeal hands the dispute to
+    pub appeal_arbiter: Pubkey,
+}
+
+/// Size of a registry with no active escrows; each one adds 8 bytes.
+pub const REGISTRY_STATE_LEN: usize = 8 + 8 + 1 + 4 + REGISTRY_TAIL_LEN;
+/// Bytes of the rate limit window after `active`, which registries created
+/// before it lack until their next escrow.
+pub const REGISTRY_TAIL_LEN: usize  = 8 + 4;
+
+/// Per-initializer escrow id allocator and index of live escrows, stored at
+/// `["registry", initializer]` and created by the initializer's first escrow.
+/// The account is resized as escrows are created and closed.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, ShankAccount)]
+pub struct RegistryState {
+    /// Always `REGISTRY_DISCRIMINATOR` once created
+    pub discriminator: [u8; 8],
+    /// Id the next escrow created by this initializer will get
+    pub next_id:       u64,
+    pub bump:          u8,
+    /// Ids of the initializer's escrows that have not been closed yet
+    pub active:        Vec<u64>,
+    /// Epoch `created_in_epoch` counts the escrows of
+    pub epoch:         u64,
+    /// Escrows created in `epoch`, held to the config's
+    /// `max_escrows_per_epoch`
+    pub created_in_epoch: u32,
+}
+
+impl RegistryState {
+    /// Account size needed to store this registry.
+    pub fn space(&self) -> usize {
+        REGISTRY_STATE_LEN + self.active.len() * 8
+    }
+
+    /// Decodes a registry account, reading the rate limit window of one
+    /// created before it as zero.
+    pub fn unpack(data: &[u8]) -> borsh::io::Result<Self> {
+        let mut padded = data.to_vec();
+        padded.resize(data.len() + REGISTRY_TAIL_LEN, 0);
+        Self::deserialize(&mut &padded[..])
+    }
+}
+
+pub const RECEIPT_STATE_LEN: usize = 8 + 32 + 32 + 8 + 1;
+
+/// One contributor's share of a crowdfund, stored at
+/// `["receipt", escrow, contributor]` and closed by ClaimRefund.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, ShankAccount)]
+pub struct ReceiptState {
+    /// Always `RECEIPT_DISCRIMINATOR` once created
+    pub discriminator: [u8; 8],
+    pub escrow:        Pubkey,
+    pub contributor:   Pubkey,
+    /// Lamports contributed so far
+    pub amount:        u64,
+    pub bump:          u8,
+}
+
+pub const STATS_STATE_LEN: usize = 8 + 32 + 1 + 8 + 8 + 8 + 8;
+
+/// Reputation counters of one party, stored at `["stats", party]` and
+/// created by InitializeStats. They count the escrows, as initializer or
+/// taker, that settled in an instruction given this account.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, ShankAccount)]
+pub struct StatsState {
+    /// Always `STATS_DISCRIMINATOR` once created
+    pub discriminator: [u8; 8],
+    pub party:         Pubkey,
+    pub bump:          u8,
+    /// Escrows released to their taker
+    pub completed:     u64,
+    /// Escrows cancelled, refunded or closed before release
+    pub cancelled:     u64,
+    /// Escrows that went into dispute
+    pub disputed:      u64,
+    /// Sum of the `amount` of completed escrows, in lamports or base units
+    /// of whichever mint each one held
+    pub volume:        u64,
+}
+
+pub const TEMPLATE_STATE_LEN: usize = 8 + 32 + 1 + 2 + 8 + 8 + 32;
+
+/// Escrow parameters the admin registers under a `name`, stored at
+/// `["template", name]`. An Initialize given the template gets them, so a
+/// marketplace can hold all of its escrows to the same terms.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, ShankAccount)]
+pub struct TemplateState {
+    /// Always `TEMPLATE_DISCRIMINATOR` once created
+    pub discriminator:       [u8; 8],
+    pub name:                [u8; 32],
+    pub bump:                u8,
+    /// Protocol fee of the escrow in place of the config's, in basis points
+    pub fee_bps:             u16,
+    /// How long the initializer has to Dispute after a RequestWithdraw
+    pub dispute_window_secs: i64,
+    /// Expiry, in seconds from creation, of escrows created without one;
+    /// 0 leaves them without
+    pub default_expiry_secs: i64,
+    /// Arbiter every escrow of the template gets, `Pubkey::default()` to
+    /// leave it to the initializer
+    pub arbiter:             Pubkey,
+}
+
+/// Size of an authorities account with no grants; each grant adds
+/// `ROLE_GRANT_LEN` bytes.
+pub const AUTHORITIES_STATE_LEN: usize = 8 + 1 + 4;
+pub const ROLE_GRANT_LEN: usize        = 32 + 1;
+/// Most keys the authorities account holds roles for.
+pub const MAX_AUTHORITIES: usize       = 16;
+
+/// Operational duties that can be handed to keys other than the config
+/// admin, who keeps them all.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Grants and revokes every role
+    Admin,
+    /// Changes the fees with SetFees
+    FeeManager,
+    /// Pauses and unpauses new escrows with SetPaused
+    Pauser,
+    /// Grants and revokes the Arbiter role
+    ArbiterRegistry,
+    /// Listed as an approved arbiter for integrators to pick from
+    Arbiter,
+}
+
+impl Role {
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Roles held by one key, as a bitmask of `Role::bit`s.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoleGrant {
+    pub authority: Pubkey,
+    pub roles:     u8,
+}
+
+/// Role grants of the program, stored at `["authorities"]` and created by
+/// the config admin with InitializeAuthorities. The account is resized as
+/// keys are granted their first role and revoked their last.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, ShankAccount)]
+pub struct AuthoritiesState {
+    /// Always `AUTHORITIES_DISCRIMINATOR` once created
+    pub discriminator: [u8; 8],
+    pub bump:          u8,
+    pub grants:        Vec<RoleGrant>,
+}
+
+impl AuthoritiesState {
+    pub fn space(&self) -> usize {
+        AUTHORITIES_STATE_LEN + self.grants.len() * ROLE_GRANT_LEN
+    }
+
+    pub fn has_role(&self, authority: &Pubkey, role: Role) -> bool {
+        self.grants.iter().any(|g| g.authority == *authority && g.roles & role.bit() != 0)
+    }
+}
+
+pub const FEATURES_STATE_LEN: usize = 8 + 1 + 8;
+
+/// Behaviors the config admin can switch on and off with SetFeature, so a
+/// program upgrade can ship them disabled and enable them later.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// A nonzero protocol fee can be set, with InitializeConfig,
+    /// UpdateConfig, SetFees or SetTemplate, and is charged on release
+    Fees,
+    /// Strict mode (`require_deposit`) can be turned on with UpdateConfig,
+    /// and is enforced on Initialize
+    StrictMode,
+    /// New token, NFT and swap escrows can be created
+    TokenEscrows,
+}
+
+impl Feature {
+    pub fn bit(self) -> u64 {
+        1 << self as u8
+    }
+}
+
+/// Features enabled until the flags account is created: none, so a program
+/// upgrade ships its gated behaviors off.
+pub const DEFAULT_FEATURES: u64 = 0;
+
+/// Enabled features of the program as a bitmask of `Feature::bit`s, stored
+/// at `["features"]` and created by the first SetFeature.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, ShankAccount)]
+pub struct FeaturesState {
+    /// Always `FEATURES_DISCRIMINATOR` once created
+    pub discriminator: [u8; 8],
+    pub bump:          u8,
+    pub enabled:       u64,
+}
+
+impl FeaturesState {
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        self.enabled & feature.bit() != 0
+    }
+}
+
+/// Size of a claimed bitmap with no bits; each 8 allocations add a byte.
+pub const CLAIMED_STATE_LEN: usize = 8 + 32 + 1 + 4;
+
+/// Which allocations of a distribution escrow were paid, one bit per leaf
+/// index, stored at `["claimed", escrow]`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, ShankAccount)]
+pub struct ClaimedState {
+    /// Always `CLAIMED_DISCRIMINATOR` once created
+    pub discriminator: [u8; 8],
+    pub escrow:        Pubkey,
+    pub bump:          u8,
+    /// Bit `index % 8` of byte `index / 8` is set once `index` is claimed
+    pub claimed:       Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replacing_a_list_moves_the_lists_after_it() {
+        // Account data is 8-byte aligned, and so are these words
+        let mut words = vec![0u64; (ESCROW_STATE_LEN + 2 * MILESTONE_LEN + 3 * 32 + 2 * SPLIT_LEN + 12).div_ceil(8)];
+        let mut escrow = EscrowData::new(bytemuck::cast_slice_mut::<u64, u8>(&mut words)).unwrap();
+        let takers = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let splits = [
+            Split { recipient: Pubkey::new_unique(), bps: 4_000 },
+            Split { recipient: Pubkey::new_unique(), bps: 6_000 },
+        ];
+        escrow.set_metadata_uri(b"ipfs://terms");
+        escrow.set_splits(&splits);
+        escrow.set_allowed_takers(&takers);
+        escrow.set_milestones(&[Milestone::new(5), Milestone::new(7)]);
+        assert_eq!(escrow.allowed_takers(), takers);
+        assert_eq!(escrow.splits(), splits);
+
+        escrow.set_allowed_takers(&takers[..1]);
+        assert_eq!(escrow.space(), ESCROW_STATE_LEN + 2 * MILESTONE_LEN + 32 + 2 * SPLIT_LEN + 12);
+        assert_eq!(escrow.milestones().iter().map(|m| m.amount).collect::<Vec<_>>(), [5, 7]);
+        assert_eq!(escrow.allowed_takers(), &takers[..1]);
+        assert_eq!(escrow.splits(), splits);
+        assert_eq!(escrow.metadata_uri(), b"ipfs://terms");
+    }
+
+    #[test]
+    fn every_version_round_trips_through_migration() {
+        // A current escrow with no zero byte in its header, so a misplaced
+        // byte shows, and an entry in each list
+        let mut words = vec![0u64; (ESCROW_STATE_LEN + MILESTONE_LEN + 32 + SPLIT_LEN + 12).div_ceil(8)];
+        let current = bytemuck::cast_slice_mut::<u64, u8>(&mut words);
+        for (i, byte) in current[..ESCROW_STATE_LEN].iter_mut().enumerate() {
+            *byte = (i % 255 + 1) as u8;
+        }
+        let header = EscrowState::from_bytes_mut(current).unwrap();
+        (header.milestone_count, header.allowed_taker_count, header.split_count, header.metadata_uri_len) = (0, 0, 0, 0);
+        header.version = ESCROW_STATE_VERSION;
+        let mut escrow = EscrowData::new(&mut *current).unwrap();
+        escrow.set_milestones(&[Milestone::new(1)]);
+        escrow.set_allowed_takers(&[Pubkey::new_unique()]);
+        escrow.set_splits(&[Split { recipient: Pubkey::new_unique(), bps: 10_000 }]);
+        escrow.set_metadata_uri(b"ipfs://terms");
+        let current = escrow.as_bytes().to_vec();
+
+        for version in 1..=ESCROW_STATE_VERSION {
+            // What an escrow of `version` holds: the current header cut where
+            // that version's ends, a v1 one not even holding the version byte
+            let old_len  = ESCROW_STATE_LENS[usize::from(version) - 1];
+            let mut data = [&current[..old_len], &current[ESCROW_STATE_LEN..]].concat();
+            data[std::mem::offset_of!(EscrowState, version)] = if version > 1 { version } else { 0 };
+            data.resize(current.len(), 0);
+            migrate_escrow_data(&mut data, old_len);
+
+            let mut expected = current.clone();
+            expected[old_len..ESCROW_STATE_LEN].fill(0);
+            assert_eq!(data, expected, "v{version} doesn't round-trip");
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Minimal read-only view of Switchboard V2 aggregator accounts, enough to
+//! read the latest confirmed result and when its round opened.
+
+use borsh::BorshDeserialize;
+use solana_program::{program_error::ProgramError, pubkey, pubkey::Pubkey};
+
+pub const SWITCHBOARD_V2_PROGRAM_ID: Pubkey = pubkey!("SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f");
+/// Anchor discriminator of `AggregatorAccountData`,
+/// `sha256("account:AggregatorAccountData")[..8]`.
+const AGGREGATOR_DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+
+/// `mantissa * 10^-scale`
+#[derive(BorshDeserialize, Debug)]
+pub struct SwitchboardDecimal {
+    pub mantissa: i128,
+    pub scale:    u32,
+}
+
+/// Leading fields of `AggregatorRound`, up to `result`.
+#[derive(BorshDeserialize, Debug)]
+pub struct AggregatorRound {
+    pub num_success:          u32,
+    pub num_error:            u32,
+    pub is_closed:            bool,
+    pub round_open_slot:      u64,
+    pub round_open_timestamp: i64,
+    pub result:               SwitchboardDecimal,
+}
+
+/// Leading fields of the packed on-chain `AggregatorAccountData`, after its
+/// discriminator and up to `latest_confirmed_round.result`. Later fields are
+/// ignored.
+#[derive(BorshDeserialize, Debug)]
+pub struct AggregatorAccountData {
+    pub name:                      [u8; 32],
+    pub metadata:                  [u8; 128],
+    pub reserved1:                 [u8; 32],
+    pub queue_pubkey:              Pubkey,
+    pub oracle_request_batch_size: u32,
+    pub min_oracle_results:        u32,
+    pub min_job_results:           u32,
+    pub min_update_delay_seconds:  u32,
+    pub start_after:               i64,
+    pub variance_threshold:        SwitchboardDecimal,
+    pub force_report_period:       i64,
+    pub expiration:                i64,
+    pub consecutive_failure_count: u64,
+    pub next_allowed_update_time:  i64,
+    pub is_locked:                 bool,
+    pub crank_pubkey:              Pubkey,
+    pub latest_confirmed_round:    AggregatorRound,
+}
+
+impl AggregatorAccountData {
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let (discriminator, mut rest) = data
+            .split_first_chunk::<8>()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if *discriminator != AGGREGATOR_DISCRIMINATOR {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Self::deserialize(&mut rest).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
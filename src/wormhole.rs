@@ -0,0 +1,38 @@
+//! Minimal read-only view of `PostedVAA` accounts written by the Wormhole
+//! core bridge once it has verified a VAA's guardian signatures.
+
+use borsh::BorshDeserialize;
+use solana_program::{program_error::ProgramError, pubkey, pubkey::Pubkey};
+
+pub const WORMHOLE_CORE_BRIDGE_PROGRAM_ID: Pubkey = pubkey!("worm2ZoG2kUd4vFXhvjh93UUH596ayRfgQ2MgjNMTth");
+/// Prefix of every posted message account; other core bridge accounts differ.
+const POSTED_VAA_MAGIC: [u8; 3] = *b"vaa";
+
+/// The on-chain `PostedVAA` account, after its magic.
+#[derive(BorshDeserialize, Debug)]
+pub struct PostedVaa {
+    pub vaa_version:           u8,
+    pub consistency_level:     u8,
+    pub vaa_time:              u32,
+    pub vaa_signature_account: Pubkey,
+    pub submission_time:       u32,
+    pub nonce:                 u32,
+    pub sequence:              u64,
+    /// Wormhole chain id of the emitting chain
+    pub emitter_chain:         u16,
+    /// Emitter contract, left-padded to 32 bytes
+    pub emitter_address:       [u8; 32],
+    pub payload:               Vec<u8>,
+}
+
+impl PostedVaa {
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let (magic, mut rest) = data
+            .split_first_chunk::<3>()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if *magic != POSTED_VAA_MAGIC {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Self::deserialize(&mut rest).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
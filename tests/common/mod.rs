@@ -0,0 +1,156 @@
+//! Stand-ins for the programs and accounts outside this one that the
+//! integration tests need: a stake pool, oracle price feeds, Wormhole VAAs,
+//! Metaplex NFT metadata and Ethereum signatures.
+
+#![allow(dead_code)]
+
+use escrow_program::{
+    metadata::{find_metadata_address, METADATA_PROGRAM_ID},
+    pyth::PYTH_RECEIVER_PROGRAM_ID,
+    switchboard::SWITCHBOARD_V2_PROGRAM_ID,
+    wormhole::WORMHOLE_CORE_BRIDGE_PROGRAM_ID,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    system_instruction,
+};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{account::Account, keccak, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey};
+
+/// Stands in for the SPL stake pool program: DepositSol mints pool tokens
+/// one for one, WithdrawSol pays 10% more lamports than it burns tokens for.
+pub fn mock_stake_pool(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let amount = u64::from_le_bytes(data[1..9].try_into().unwrap());
+    match data[0] {
+        // [pool, withdraw authority, reserve, from, pool tokens to, fee, referrer, mint, system, token program]
+        14 => {
+            invoke(&system_instruction::transfer(accounts[3].key, accounts[2].key, amount), accounts)?;
+            let (_, bump) = Pubkey::find_program_address(&[accounts[0].key.as_ref(), b"withdraw"], program_id);
+            let mint_to   = spl_token_2022::instruction::mint_to(
+                accounts[9].key, accounts[7].key, accounts[4].key, accounts[1].key, &[], amount,
+            )?;
+            invoke_signed(&mint_to, accounts, &[&[accounts[0].key.as_ref(), b"withdraw", &[bump]]])
+        }
+        // [pool, withdraw authority, user authority, pool tokens from, reserve, lamports to, fee, mint, sysvars.., token program]
+        16 => {
+            let burn = spl_token_2022::instruction::burn(
+                accounts[11].key, accounts[3].key, accounts[7].key, accounts[2].key, &[], amount,
+            )?;
+            invoke(&burn, accounts)?;
+            let lamports = amount / 10 * 11;
+            **accounts[4].try_borrow_mut_lamports()? -= lamports;
+            **accounts[5].try_borrow_mut_lamports()? += lamports;
+            Ok(())
+        }
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+/// Writes a fully verified Pyth `PriceUpdateV2` account at `oracle`.
+pub fn set_pyth_price(ctx: &mut ProgramTestContext, oracle: &Pubkey, price: i64, expo: i32, publish_time: i64) {
+    let mut data = vec![34, 241, 35, 99, 157, 126, 244, 205];
+    data.extend_from_slice(Pubkey::new_unique().as_ref());
+    data.push(1); // VerificationLevel::Full
+    data.extend_from_slice(&[7; 32]);
+    data.extend_from_slice(&price.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.extend_from_slice(&expo.to_le_bytes());
+    data.extend_from_slice(&publish_time.to_le_bytes());
+    data.extend_from_slice(&publish_time.to_le_bytes());
+    data.extend_from_slice(&price.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    let account = Account {
+        lamports: LAMPORTS_PER_SOL,
+        data,
+        owner: PYTH_RECEIVER_PROGRAM_ID,
+        ..Account::default()
+    };
+    ctx.set_account(oracle, &account.into());
+}
+
+/// Writes a Switchboard V2 aggregator at `oracle` whose latest confirmed
+/// round holds `mantissa * 10^-scale` and opened at `timestamp`.
+pub fn set_switchboard_price(ctx: &mut ProgramTestContext, oracle: &Pubkey, mantissa: i128, scale: u32, timestamp: i64) {
+    let mut data = vec![217, 230, 65, 101, 201, 162, 27, 125];
+    data.resize(8 + 228, 0);
+    data.extend_from_slice(&1u32.to_le_bytes()); // min_oracle_results
+    data.resize(8 + 333, 0);
+    data.extend_from_slice(&1u32.to_le_bytes()); // num_success
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.extend_from_slice(&timestamp.to_le_bytes());
+    data.extend_from_slice(&mantissa.to_le_bytes());
+    data.extend_from_slice(&scale.to_le_bytes());
+    data.resize(3851, 0);
+    let account = Account {
+        lamports: LAMPORTS_PER_SOL,
+        data,
+        owner: SWITCHBOARD_V2_PROGRAM_ID,
+        ..Account::default()
+    };
+    ctx.set_account(oracle, &account.into());
+}
+
+/// Signs `message` the way Ethereum's `personal_sign` does, returning `(r, s)`
+/// and the recovery id.
+pub fn eth_sign(key: &libsecp256k1::SecretKey, message: &[u8]) -> ([u8; 64], u8) {
+    let prefix = format!("\x19Ethereum Signed Message:\n{}", message.len());
+    let digest = keccak::hashv(&[prefix.as_bytes(), message]).to_bytes();
+    let (signature, recovery_id) = libsecp256k1::sign(&libsecp256k1::Message::parse(&digest), key);
+    (signature.serialize(), recovery_id.serialize())
+}
+
+pub fn eth_address(key: &libsecp256k1::SecretKey) -> [u8; 20] {
+    let pubkey = libsecp256k1::PublicKey::from_secret_key(key).serialize();
+    keccak::hash(&pubkey[1..]).to_bytes()[12..].try_into().unwrap()
+}
+
+/// Writes a core bridge `PostedVAA` account at `address`.
+pub fn set_posted_vaa(ctx: &mut ProgramTestContext, address: &Pubkey, emitter_chain: u16, emitter_address: [u8; 32], payload: &[u8]) {
+    let mut data = b"vaa".to_vec();
+    data.extend_from_slice(&[1, 1]);
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(Pubkey::new_unique().as_ref());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.extend_from_slice(&emitter_chain.to_le_bytes());
+    data.extend_from_slice(&emitter_address);
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    data.extend_from_slice(payload);
+    let account = Account {
+        lamports: LAMPORTS_PER_SOL,
+        data,
+        owner: WORMHOLE_CORE_BRIDGE_PROGRAM_ID,
+        ..Account::default()
+    };
+    ctx.set_account(address, &account.into());
+}
+
+/// Writes the Metaplex metadata of `mint`, naming it a member of
+/// `collection`, which has or hasn't `verified` it.
+pub fn set_nft_metadata(ctx: &mut ProgramTestContext, mint: &Pubkey, collection: &Pubkey, verified: bool) {
+    let mut data = vec![4]; // Key::MetadataV1
+    data.extend_from_slice(Pubkey::new_unique().as_ref());
+    data.extend_from_slice(mint.as_ref());
+    for field in ["Escrowed", "ESC", "https://example.com/nft.json"] {
+        data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        data.extend_from_slice(field.as_bytes());
+    }
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data.extend_from_slice(&[0, 0, 1, 0, 0]); // No creators, edition nonce or token standard
+    data.extend_from_slice(&[1, u8::from(verified)]);
+    data.extend_from_slice(collection.as_ref());
+    let account = Account {
+        lamports: LAMPORTS_PER_SOL,
+        data,
+        owner: METADATA_PROGRAM_ID,
+        ..Account::default()
+    };
+    ctx.set_account(&find_metadata_address(mint).0, &account.into());
+}
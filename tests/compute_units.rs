@@ -0,0 +1,997 @@
+//! Compute units each instruction consumes, metered against a budget so a
+//! new feature can't silently push an instruction towards the 200 000 CU
+//! default limit. Only the SBF build is metered, so this runs under
+//! `cargo test-sbf --features test-sbf` and fails anywhere else; plain
+//! `cargo test` ignores it.
+//!
+//! The budgets in `compute_units.txt` are measured, never written by hand:
+//! `RECORD_COMPUTE_UNITS=1 cargo test-sbf --features test-sbf --test
+//! compute_units` rewrites the file from the most each instruction
+//! consumed, plus headroom. Each transaction sets its compute unit limit to
+//! its instruction's budget, so the runtime itself stops one that grows
+//! past it.
+
+mod common;
+
+use std::collections::BTreeMap;
+
+use borsh::BorshDeserialize;
+use common::{eth_address, eth_sign, mock_stake_pool, set_posted_vaa, set_pyth_price};
+use escrow_program::{
+    associated_token::{create_idempotent, find_associated_token_address},
+    instruction::{self, EscrowInstruction},
+    merkle,
+    metadata::{find_metadata_address, METADATA_PROGRAM_ID},
+    processor::process_instruction,
+    stake_pool::{StakePool, STAKE_POOL_PROGRAM_ID},
+    state::{
+        eth_release_message, find_escrow_address, relayed_initialize_message, vaa_release_payload, voucher_message,
+        Auction, EscrowState, Feature, Role, Split, Subscription, DEFAULT_MAX_EXPIRY_HORIZON, DISPUTE_WINDOW_SECS,
+        EMERGENCY_DELAY_SECS, ESCROW_STATE_LENS, SPL_TOKEN_NATIVE_MINT,
+    },
+};
+use solana_ed25519_program::new_ed25519_instruction_with_signature;
+use solana_program::{program_option::COption, program_pack::Pack};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    clock::Clock,
+    compute_budget::ComputeBudgetInstruction,
+    hash::hash,
+    instruction::Instruction,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    stake::{
+        instruction as stake_instruction,
+        state::{Authorized, Lockup},
+    },
+    transaction::Transaction,
+};
+use spl_token_2022::state::{Account as TokenAccount, AccountState, Mint};
+
+const AMOUNT: u64 = LAMPORTS_PER_SOL;
+const SPL_TOKEN_PROGRAM_ID: Pubkey = solana_sdk::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+/// The most a transaction may request, which an unbudgeted one runs under
+const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// Instructions without a budget of their own: EmitEvent only runs as the
+/// self-CPI of another instruction, whose budget covers it.
+const UNMETERED: &[&str] = &["EmitEvent"];
+
+struct Bench {
+    ctx:         ProgramTestContext,
+    program_id:  Pubkey,
+    initializer: Keypair,
+    taker:       Keypair,
+    arbiter:     Keypair,
+    treasury:    Pubkey,
+    /// The stake pool's beneficiary of the yield on staked escrows
+    beneficiary: Pubkey,
+    /// The id the initializer's registry hands out next
+    next_id:     u64,
+    budgets:     BTreeMap<String, u32>,
+    /// Most compute units each instruction consumed
+    consumed:    BTreeMap<String, u64>,
+}
+
+impl Bench {
+    async fn start() -> Self {
+        let program_id = Pubkey::new_unique();
+        let (initializer, taker, arbiter) = (Keypair::new(), Keypair::new(), Keypair::new());
+        let treasury   = Pubkey::new_unique();
+
+        // Under cargo test-sbf the SBF build in SBF_OUT_DIR takes the
+        // processor's place
+        let mut program_test = ProgramTest::new("escrow_program", program_id, processor!(process_instruction));
+        program_test.add_program("stake_pool", STAKE_POOL_PROGRAM_ID, processor!(mock_stake_pool));
+        for key in [initializer.pubkey(), taker.pubkey(), arbiter.pubkey(), treasury] {
+            program_test.add_account(key, Account { lamports: 1_000 * LAMPORTS_PER_SOL, ..Account::default() });
+        }
+        let ctx = program_test.start_with_context().await;
+        Bench {
+            ctx,
+            program_id,
+            initializer,
+            taker,
+            arbiter,
+            treasury,
+            beneficiary: Pubkey::new_unique(),
+            next_id: 0,
+            budgets: parse_budgets(include_str!("compute_units.txt")),
+            consumed: BTreeMap::new(),
+        }
+    }
+
+    /// Runs `ix` signed by the fee payer and `signers`, recording what it
+    /// consumed.
+    async fn run(&mut self, ix: Instruction, signers: &[&Keypair]) {
+        self.run_after(Vec::new(), ix, signers).await;
+    }
+
+    /// Like `run`, after the precompile checks `ix` reads in `checks`.
+    async fn run_after(&mut self, checks: Vec<Instruction>, ix: Instruction, signers: &[&Keypair]) {
+        let name  = instruction_name(&EscrowInstruction::try_from_slice(&ix.data).unwrap());
+        let limit = self.budgets.get(&name).copied().unwrap_or(MAX_COMPUTE_UNIT_LIMIT);
+        let mut ixs = vec![ComputeBudgetInstruction::set_compute_unit_limit(limit)];
+        ixs.extend(checks);
+        ixs.push(ix);
+        let blockhash = self.ctx.get_new_latest_blockhash().await.unwrap();
+        let mut all   = vec![&self.ctx.payer];
+        all.extend_from_slice(signers);
+        let tx = Transaction::new_signed_with_payer(&ixs, Some(&self.ctx.payer.pubkey()), &all, blockhash);
+        let outcome = self.ctx.banks_client.process_transaction_with_metadata(tx).await.unwrap();
+        outcome.result.unwrap_or_else(|err| panic!("{name} failed within its {limit} CU: {err}"));
+        let consumed = outcome.metadata.unwrap().compute_units_consumed;
+        let most     = self.consumed.entry(name).or_default();
+        *most = (*most).max(consumed);
+    }
+
+    /// Sends an instruction of another program, which goes unmetered.
+    async fn send(&mut self, ixs: &[Instruction], signers: &[&Keypair]) {
+        let blockhash = self.ctx.get_new_latest_blockhash().await.unwrap();
+        let mut all   = vec![&self.ctx.payer];
+        all.extend_from_slice(signers);
+        let tx = Transaction::new_signed_with_payer(ixs, Some(&self.ctx.payer.pubkey()), &all, blockhash);
+        self.ctx.banks_client.process_transaction(tx).await.unwrap();
+    }
+
+    fn id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn escrow(&self, id: u64) -> Pubkey {
+        find_escrow_address(&self.program_id, &self.initializer.pubkey(), id).0
+    }
+
+    async fn now(&mut self) -> i64 {
+        self.ctx.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+    }
+
+    async fn warp_to(&mut self, unix_timestamp: i64) {
+        let mut clock: Clock = self.ctx.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp = unix_timestamp;
+        self.ctx.set_sysvar(&clock);
+    }
+
+    fn funded_keypair(&mut self) -> Keypair {
+        let keypair = Keypair::new();
+        let account = Account { lamports: 10 * LAMPORTS_PER_SOL, ..Account::default() };
+        self.ctx.set_account(&keypair.pubkey(), &account.into());
+        keypair
+    }
+
+    /// Initializes escrow `id` between the parties, arbitrated by the
+    /// bench's arbiter.
+    async fn initialize(&mut self, id: u64, expiry_ts: i64) {
+        let (initializer, taker) = (self.initializer.insecure_clone(), self.taker.insecure_clone());
+        let ix = instruction::initialize(
+            &self.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, expiry_ts, Some(&self.arbiter.pubkey()),
+        );
+        self.run(ix, &[&initializer, &taker]).await;
+    }
+
+    async fn deposit(&mut self, id: u64) {
+        let initializer = self.initializer.insecure_clone();
+        let ix = instruction::deposit(&self.program_id, &initializer.pubkey(), &self.taker.pubkey(), id);
+        self.run(ix, &[&initializer]).await;
+    }
+
+    /// Initializes and funds a fresh escrow, returning its id.
+    async fn funded(&mut self, expiry_ts: i64) -> u64 {
+        let id = self.id();
+        self.initialize(id, expiry_ts).await;
+        self.deposit(id).await;
+        id
+    }
+
+    async fn dispute(&mut self, id: u64) {
+        let taker = self.taker.insecure_clone();
+        let ix = instruction::dispute(&self.program_id, &taker.pubkey(), &self.initializer.pubkey(), id);
+        self.run(self.settle(ix), &[&taker]).await;
+    }
+
+    /// Appends what an instruction paying out of an escrow may need: the
+    /// feature flags to charge its fee, and the stats PDAs of both parties.
+    fn settle(&self, ix: Instruction) -> Instruction {
+        let ix = instruction::with_features(&self.program_id, ix);
+        instruction::with_stats(&self.program_id, ix, &[self.initializer.pubkey(), self.taker.pubkey()])
+    }
+
+    /// Like `settle`, for an escrow open to any taker.
+    fn settle_untaken(&self, ix: Instruction) -> Instruction {
+        let ix = instruction::with_features(&self.program_id, ix);
+        instruction::with_stats(&self.program_id, ix, &[self.initializer.pubkey()])
+    }
+
+    async fn update_config(&mut self, appeal_window_secs: i64, appeal_arbiter: &Pubkey) {
+        let admin = self.ctx.payer.insecure_clone();
+        let ix = instruction::update_config(
+            &self.program_id, &admin.pubkey(), &admin.pubkey(), 100, &self.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+            2_500, 50, false, &Pubkey::default(), &self.beneficiary, 0, 0, 0, 0, 0, 100, appeal_window_secs,
+            appeal_arbiter,
+        );
+        self.run(ix, &[]).await;
+    }
+
+    fn create_mint(&mut self, decimals: u8, supply: u64) -> Pubkey {
+        self.create_mint_with_authority(decimals, supply, Pubkey::new_unique())
+    }
+
+    fn create_mint_with_authority(&mut self, decimals: u8, supply: u64, authority: Pubkey) -> Pubkey {
+        let mint = Pubkey::new_unique();
+        let mut data = vec![0; Mint::LEN];
+        Mint { mint_authority: COption::Some(authority), supply, decimals, is_initialized: true, ..Mint::default() }
+            .pack_into_slice(&mut data);
+        let account = Account { lamports: LAMPORTS_PER_SOL, data, owner: SPL_TOKEN_PROGRAM_ID, ..Account::default() };
+        self.ctx.set_account(&mint, &account.into());
+        mint
+    }
+
+    /// Creates a token account of `owner` holding `amount` of `mint`.
+    fn create_token_account(&mut self, owner: &Pubkey, mint: &Pubkey, amount: u64) -> Pubkey {
+        let address = Pubkey::new_unique();
+        let mut data = vec![0; TokenAccount::LEN];
+        TokenAccount { mint: *mint, owner: *owner, amount, state: AccountState::Initialized, ..TokenAccount::default() }
+            .pack_into_slice(&mut data);
+        let account = Account { lamports: LAMPORTS_PER_SOL, data, owner: SPL_TOKEN_PROGRAM_ID, ..Account::default() };
+        self.ctx.set_account(&address, &account.into());
+        address
+    }
+
+    /// Creates the associated wrapped SOL account of `owner`.
+    async fn create_native_ata(&mut self, owner: &Pubkey) -> Pubkey {
+        let payer = self.ctx.payer.pubkey();
+        self.send(&[create_idempotent(&payer, owner, &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID)], &[]).await;
+        find_associated_token_address(owner, &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID)
+    }
+}
+
+/// Reads `compute_units.txt`: an instruction and its budget per line, with
+/// `#` comments.
+fn parse_budgets(text: &str) -> BTreeMap<String, u32> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (name, budget) = line.split_once(char::is_whitespace).unwrap();
+            (name.to_string(), budget.trim().parse().unwrap())
+        })
+        .collect()
+}
+
+fn instruction_name(ix: &EscrowInstruction) -> String {
+    format!("{ix:?}").split(|c: char| !c.is_alphanumeric()).next().unwrap().to_string()
+}
+
+/// The name of every EscrowInstruction variant, found by decoding each tag
+/// over zeroed fields.
+fn all_instruction_names() -> Vec<String> {
+    (0..=u8::MAX)
+        .filter_map(|tag| {
+            let mut data = vec![0; 1_024];
+            data[0] = tag;
+            EscrowInstruction::deserialize(&mut &data[..]).ok()
+        })
+        .map(|ix| instruction_name(&ix))
+        .collect()
+}
+
+async fn admin(bench: &mut Bench) {
+    let program_id = bench.program_id;
+    let admin = bench.ctx.payer.insecure_clone();
+    let ix = instruction::initialize_config(&program_id, &admin.pubkey(), 0, &bench.treasury);
+    bench.run(ix, &[]).await;
+    for feature in [Feature::Fees, Feature::StrictMode, Feature::TokenEscrows] {
+        bench.run(instruction::set_feature(&program_id, &admin.pubkey(), feature, true), &[]).await;
+    }
+    bench.update_config(0, &Pubkey::default()).await;
+    for party in [bench.initializer.pubkey(), bench.taker.pubkey()] {
+        bench.run(instruction::initialize_stats(&program_id, &admin.pubkey(), &party), &[]).await;
+    }
+
+    bench.run(instruction::initialize_authorities(&program_id, &admin.pubkey()), &[]).await;
+    let pauser = bench.funded_keypair();
+    bench.run(instruction::grant_role(&program_id, &admin.pubkey(), &pauser.pubkey(), Role::Pauser), &[]).await;
+    bench.run(instruction::set_paused(&program_id, &pauser.pubkey(), true), &[&pauser]).await;
+    bench.run(instruction::set_paused(&program_id, &pauser.pubkey(), false), &[&pauser]).await;
+    bench.run(instruction::revoke_role(&program_id, &admin.pubkey(), &pauser.pubkey(), Role::Pauser), &[]).await;
+    bench.run(instruction::set_fees(&program_id, &admin.pubkey(), 100, 2_500, 50), &[]).await;
+}
+
+/// The native escrow through each way it is opened, released or cancelled.
+async fn native(bench: &mut Bench) {
+    let program_id = bench.program_id;
+    let (initializer, taker) = (bench.initializer.insecure_clone(), bench.taker.insecure_clone());
+    let treasury = bench.treasury;
+
+    // Released, then closed
+    let id = bench.funded(0).await;
+    let ix = instruction::withdraw(&program_id, &initializer.pubkey(), &taker.pubkey(), &treasury, id);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+    bench.run(instruction::close(&program_id, &initializer.pubkey(), id), &[&initializer]).await;
+
+    // Cancelled before funding
+    let id = bench.id();
+    bench.initialize(id, 0).await;
+    let ix = instruction::cancel(&program_id, &initializer.pubkey(), id);
+    bench.run(bench.settle(ix), &[&initializer]).await;
+
+    // Opened with a nonce, a reference, a referrer, and funded at once
+    let id = bench.id();
+    let ix = instruction::initialize_with_nonce(&program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, 0, None, 42);
+    bench.run(ix, &[&initializer, &taker]).await;
+    let id = bench.id();
+    let ix = instruction::initialize_with_reference(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, 0, None, [7; 32],
+    );
+    bench.run(ix, &[&initializer, &taker]).await;
+    let id = bench.id();
+    let referrer = Pubkey::new_unique();
+    let ix = instruction::initialize_referred(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, 0, None, &referrer,
+    );
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+    let ix = instruction::withdraw_referred(&program_id, &initializer.pubkey(), &taker.pubkey(), &treasury, &referrer, id);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+    let id = bench.id();
+    let ix = instruction::initialize_and_deposit(&program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, 0, None);
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::mutual_cancel(&program_id, &initializer.pubkey(), &taker.pubkey(), id);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+
+    // Funded in two parts, and paid out in two
+    let id = bench.id();
+    bench.initialize(id, 0).await;
+    let ix = instruction::deposit_additional(&program_id, &initializer.pubkey(), &taker.pubkey(), id, AMOUNT / 2);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::deposit_additional(&program_id, &initializer.pubkey(), &taker.pubkey(), id, AMOUNT / 2);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::withdraw_partial(&program_id, &initializer.pubkey(), &taker.pubkey(), &treasury, id, AMOUNT / 2);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+    let ix = instruction::withdraw_to(&program_id, &initializer.pubkey(), &taker.pubkey(), &treasury, &Pubkey::new_unique(), id);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+
+    // Accepted by a taker who didn't sign it
+    let id = bench.id();
+    let ix = instruction::without_taker_signature(instruction::initialize(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, 0, None,
+    ));
+    bench.run(ix, &[&initializer]).await;
+    bench.run(instruction::accept_escrow(&program_id, &taker.pubkey(), &initializer.pubkey(), id), &[&taker]).await;
+
+    // Paid by a third party, released by the taker after the dispute window
+    let id = bench.id();
+    bench.initialize(id, 0).await;
+    let funder = bench.funded_keypair();
+    let ix = instruction::deposit_from(&program_id, &funder.pubkey(), &initializer.pubkey(), &taker.pubkey(), id);
+    bench.run(ix, &[&funder]).await;
+    let now = bench.now().await;
+    bench.run(instruction::request_withdraw(&program_id, &taker.pubkey(), &initializer.pubkey(), id), &[&taker]).await;
+    bench.warp_to(now + DISPUTE_WINDOW_SECS + 1).await;
+    let ix = instruction::finalize_withdraw(&program_id, &taker.pubkey(), &initializer.pubkey(), &treasury, id);
+    bench.run(bench.settle(ix), &[&taker]).await;
+
+    // Refunded after expiry, by the initializer and by a cranker
+    let now = bench.now().await;
+    let refunded = bench.funded(now + 60).await;
+    let cranked  = bench.funded(now + 60).await;
+    bench.warp_to(now + 61).await;
+    let ix = instruction::refund(&program_id, &initializer.pubkey(), refunded);
+    bench.run(bench.settle(ix), &[&initializer]).await;
+    let cranker = bench.funded_keypair();
+    let ix = instruction::crank_refund(&program_id, &cranker.pubkey(), &initializer.pubkey(), cranked);
+    bench.run(bench.settle(ix), &[&cranker]).await;
+
+    // The rent paid by someone else, and the terms taken from a template
+    let id = bench.id();
+    let payer = bench.funded_keypair();
+    let ix = instruction::initialize(&program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, 0, None);
+    bench.run(instruction::with_payer(&payer.pubkey(), ix), &[&initializer, &taker, &payer]).await;
+    let admin = bench.ctx.payer.insecure_clone();
+    let name  = *b"marketplace-standard\0\0\0\0\0\0\0\0\0\0\0\0";
+    let ix = instruction::set_template(&program_id, &admin.pubkey(), &name, 500, 600, 1_000, &bench.arbiter.pubkey());
+    bench.run(ix, &[]).await;
+    let id = bench.id();
+    let ix = instruction::initialize(&program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, 0, None);
+    bench.run(instruction::with_template(&program_id, &name, ix), &[&initializer, &taker]).await;
+
+    // Strict mode checks for the deposit in the same transaction
+    let id = bench.id();
+    let ix = instruction::initialize(&program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, 0, None);
+    let ix = instruction::with_features(&program_id, instruction::with_deposit_check(ix));
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+
+    // Opened over several escrows and released together
+    let first = bench.next_id;
+    let items = vec![(taker.pubkey(), AMOUNT, first), (taker.pubkey(), AMOUNT, first + 1)];
+    bench.next_id += 2;
+    bench.run(instruction::initialize_batch(&program_id, &initializer.pubkey(), items), &[&initializer, &taker]).await;
+    bench.deposit(first).await;
+    bench.deposit(first + 1).await;
+    let escrows = [(initializer.pubkey(), first), (initializer.pubkey(), first + 1)];
+    let ix = instruction::withdraw_batch(&program_id, &taker.pubkey(), &treasury, &escrows);
+    bench.run(bench.settle(ix), &[&taker, &initializer]).await;
+
+    // Read back, and migrated from the first layout
+    let id = bench.funded(0).await;
+    bench.run(instruction::get_state(&program_id, &bench.escrow(id)), &[]).await;
+    let escrow = bench.escrow(id);
+    let mut account = bench.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+    account.data.truncate(ESCROW_STATE_LENS[0]);
+    account.data[std::mem::offset_of!(EscrowState, version)] = 0;
+    bench.ctx.set_account(&escrow, &account.into());
+    let payer = bench.ctx.payer.pubkey();
+    bench.run(instruction::migrate_state(&program_id, &payer, &escrow), &[]).await;
+}
+
+/// The terms the parties change on an open escrow.
+async fn terms(bench: &mut Bench) {
+    let program_id = bench.program_id;
+    let (initializer, taker) = (bench.initializer.insecure_clone(), bench.taker.insecure_clone());
+    let id  = bench.id();
+    let now = bench.now().await;
+    bench.initialize(id, now + 60).await;
+
+    let ix = instruction::update_amount(&program_id, &initializer.pubkey(), &taker.pubkey(), id, AMOUNT / 2);
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::extend_deadline(&program_id, &initializer.pubkey(), &taker.pubkey(), id, now + 600);
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::set_metadata(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), id, "ipfs://terms".into(), [1; 32],
+    );
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::propose_terms(&program_id, &taker.pubkey(), &initializer.pubkey(), id, AMOUNT, now + 1_000);
+    bench.run(ix, &[&taker]).await;
+    bench.run(instruction::accept_terms(&program_id, &initializer.pubkey(), &initializer.pubkey(), id), &[&initializer]).await;
+    let splits = vec![
+        Split { recipient: Pubkey::new_unique(), bps: 5_000 },
+        Split { recipient: Pubkey::new_unique(), bps: 5_000 },
+    ];
+    let recipients: Vec<_> = splits.iter().map(|split| split.recipient).collect();
+    let ix = instruction::set_splits(&program_id, &initializer.pubkey(), &taker.pubkey(), id, splits);
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::set_taker_bond(&program_id, &initializer.pubkey(), &taker.pubkey(), id, AMOUNT / 4);
+    bench.run(ix, &[&initializer, &taker]).await;
+    let second = Keypair::new();
+    let ix = instruction::set_taker(&program_id, &initializer.pubkey(), &taker.pubkey(), &second.pubkey(), id);
+    bench.run(ix, &[&initializer, &second]).await;
+    let ix = instruction::set_taker(&program_id, &initializer.pubkey(), &second.pubkey(), &taker.pubkey(), id);
+    bench.run(ix, &[&initializer, &taker]).await;
+
+    bench.deposit(id).await;
+    bench.run(instruction::deposit_bond(&program_id, &taker.pubkey(), &initializer.pubkey(), id), &[&taker]).await;
+    let ix = instruction::withdraw_split(&program_id, &initializer.pubkey(), &taker.pubkey(), &bench.treasury, id, &recipients);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+
+    // Open to an allowlist, claimed by a member
+    let id = bench.id();
+    let ix = instruction::initialize(&program_id, &initializer.pubkey(), &Pubkey::default(), AMOUNT, id, 0, None);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::set_allowed_takers(&program_id, &initializer.pubkey(), id, vec![taker.pubkey(), Pubkey::new_unique()]);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::deposit(&program_id, &initializer.pubkey(), &Pubkey::default(), id);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::claim_allowed(&program_id, &taker.pubkey(), &initializer.pubkey(), &bench.treasury, id);
+    bench.run(bench.settle(ix), &[&taker]).await;
+}
+
+/// Disputes, and the rulings on them with and without an appeal window.
+async fn disputes(bench: &mut Bench) {
+    let program_id = bench.program_id;
+    let (initializer, taker, arbiter) =
+        (bench.initializer.insecure_clone(), bench.taker.insecure_clone(), bench.arbiter.insecure_clone());
+
+    let id = bench.funded(0).await;
+    bench.dispute(id).await;
+    let ix = instruction::resolve(&program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), id, true);
+    bench.run(bench.settle(ix), &[&arbiter]).await;
+    let id = bench.funded(0).await;
+    bench.dispute(id).await;
+    let ix = instruction::resolve_partial(&program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), id, 2_500);
+    bench.run(bench.settle(ix), &[&arbiter]).await;
+
+    // Held for the appeal window, then appealed or carried out
+    let appeal_arbiter = bench.funded_keypair();
+    bench.update_config(60, &appeal_arbiter.pubkey()).await;
+    let appealed = bench.funded(0).await;
+    let ruled    = bench.funded(0).await;
+    for id in [appealed, ruled] {
+        bench.dispute(id).await;
+        let ix = instruction::resolve(&program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), id, true);
+        bench.run(bench.settle(ix), &[&arbiter]).await;
+    }
+    let ix = instruction::appeal(&program_id, &initializer.pubkey(), &initializer.pubkey(), appealed);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::resolve(
+        &program_id, &appeal_arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), appealed, false,
+    );
+    bench.run(bench.settle(ix), &[&appeal_arbiter]).await;
+    let now = bench.now().await;
+    bench.warp_to(now + 61).await;
+    let ix = instruction::finalize_ruling(&program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), ruled);
+    bench.run(bench.settle(ix), &[]).await;
+    bench.update_config(0, &Pubkey::default()).await;
+
+    // Withdrawn to a recipient the admin names, or vetoed
+    let id = bench.funded(0).await;
+    let admin     = bench.ctx.payer.insecure_clone();
+    let recipient = Pubkey::new_unique();
+    let propose = instruction::propose_emergency_withdraw(&program_id, &admin.pubkey(), &initializer.pubkey(), id, &recipient);
+    bench.run(propose.clone(), &[]).await;
+    let ix = instruction::veto_emergency_withdraw(&program_id, &taker.pubkey(), &initializer.pubkey(), id);
+    bench.run(ix, &[&taker]).await;
+    bench.run(propose, &[]).await;
+    let now = bench.now().await;
+    bench.warp_to(now + EMERGENCY_DELAY_SECS + 1).await;
+    let ix = instruction::execute_emergency_withdraw(&program_id, &admin.pubkey(), &initializer.pubkey(), id, &recipient);
+    bench.run(bench.settle(ix), &[]).await;
+}
+
+/// Escrows released by a secret, a schedule or the initializer's approval.
+async fn conditional(bench: &mut Bench) {
+    let program_id = bench.program_id;
+    let (initializer, taker) = (bench.initializer.insecure_clone(), bench.taker.insecure_clone());
+    let treasury = bench.treasury;
+
+    let id  = bench.id();
+    let now = bench.now().await;
+    let preimage = [9; 32];
+    let ix = instruction::initialize_htlc(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, now + 1_000, hash(&preimage).to_bytes(), None,
+    );
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+    let ix = instruction::claim(&program_id, &taker.pubkey(), &initializer.pubkey(), &treasury, id, preimage);
+    bench.run(bench.settle(ix), &[&taker]).await;
+
+    let id = bench.id();
+    let ix = instruction::initialize_vesting(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, now, now + 100, now + 1_000, None,
+    );
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+    bench.warp_to(now + 500).await;
+    let ix = instruction::withdraw_vested(&program_id, &taker.pubkey(), &initializer.pubkey(), &treasury, id);
+    bench.run(bench.settle(ix), &[&taker]).await;
+
+    let id = bench.id();
+    let ix = instruction::initialize_milestones(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), id, 0, vec![AMOUNT / 2, AMOUNT / 4], None,
+    );
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::add_milestone(&program_id, &initializer.pubkey(), &taker.pubkey(), id, AMOUNT / 4);
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+    bench.run(instruction::approve_milestone(&program_id, &initializer.pubkey(), id, 0), &[&initializer]).await;
+    let ix = instruction::withdraw_milestones(&program_id, &taker.pubkey(), &initializer.pubkey(), &treasury, id);
+    bench.run(bench.settle(ix), &[&taker]).await;
+
+    let id  = bench.id();
+    let now = bench.now().await;
+    let subscription = Subscription { amount_per_interval: AMOUNT / 4, interval_secs: 100, start_ts: now };
+    let ix = instruction::initialize_subscription(&program_id, &initializer.pubkey(), &taker.pubkey(), id, 4, subscription, None);
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+    bench.warp_to(now + 250).await;
+    let ix = instruction::withdraw_subscription(&program_id, &taker.pubkey(), &taker.pubkey(), &initializer.pubkey(), &treasury, id);
+    bench.run(bench.settle(ix), &[&taker]).await;
+
+    let id  = bench.id();
+    let now = bench.now().await;
+    let ix = instruction::initialize_authorization(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, now + 100, None,
+    );
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+    let ix = instruction::capture(&program_id, &taker.pubkey(), &initializer.pubkey(), &treasury, id, AMOUNT * 3 / 5);
+    bench.run(bench.settle(ix), &[&taker]).await;
+
+    // An invoice the taker issues, paid and released, or cancelled unpaid
+    let id = bench.id();
+    let ix = instruction::initialize_invoice(&program_id, &taker.pubkey(), &initializer.pubkey(), AMOUNT, id, 0);
+    bench.run(ix, &[&taker]).await;
+    bench.deposit(id).await;
+    let ix = instruction::release_invoice(&program_id, &initializer.pubkey(), &taker.pubkey(), &treasury, id);
+    bench.run(bench.settle(ix), &[&initializer]).await;
+    let id = bench.id();
+    let ix = instruction::initialize_invoice(&program_id, &taker.pubkey(), &initializer.pubkey(), AMOUNT, id, 0);
+    bench.run(ix, &[&taker]).await;
+    let ix = instruction::cancel_invoice(&program_id, &taker.pubkey(), &initializer.pubkey(), id);
+    bench.run(bench.settle(ix), &[&taker]).await;
+}
+
+/// Escrows released by someone else's word: an oracle price, an arbiter's
+/// voucher, an Ethereum key or a Wormhole message.
+async fn attested(bench: &mut Bench) {
+    let program_id = bench.program_id;
+    let (initializer, taker, arbiter) =
+        (bench.initializer.insecure_clone(), bench.taker.insecure_clone(), bench.arbiter.insecure_clone());
+    let treasury = bench.treasury;
+
+    let id  = bench.id();
+    let now = bench.now().await;
+    bench.initialize(id, 0).await;
+    let oracle = Pubkey::new_unique();
+    set_pyth_price(&mut bench.ctx, &oracle, 151_00000000, -8, now);
+    let ix = instruction::set_price_condition(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &oracle, id, 15000, -2, true,
+    );
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+    let ix = instruction::withdraw_conditioned(&program_id, &initializer.pubkey(), &taker.pubkey(), &treasury, &oracle, id);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+
+    let id = bench.funded(0).await;
+    let message   = voucher_message(&bench.escrow(id), AMOUNT);
+    let signature = arbiter.sign_message(&message);
+    let verify    = new_ed25519_instruction_with_signature(
+        &message, signature.as_ref().try_into().unwrap(), &arbiter.pubkey().to_bytes(),
+    );
+    let ix = instruction::claim_with_voucher(&program_id, &taker.pubkey(), &initializer.pubkey(), &treasury, id);
+    bench.run_after(vec![verify], bench.settle(ix), &[&taker]).await;
+
+    let id = bench.id();
+    bench.initialize(id, 0).await;
+    let eth_key = libsecp256k1::SecretKey::parse(&[7; 32]).unwrap();
+    let ix = instruction::set_eth_authority(&program_id, &initializer.pubkey(), &taker.pubkey(), id, eth_address(&eth_key));
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+    let (signature, recovery_id) = eth_sign(&eth_key, &eth_release_message(&bench.escrow(id), 0));
+    let ix = instruction::claim_with_eth_signature(
+        &program_id, &taker.pubkey(), &initializer.pubkey(), &treasury, id, signature, recovery_id,
+    );
+    bench.run(bench.settle(ix), &[&taker]).await;
+
+    let id = bench.id();
+    bench.initialize(id, 0).await;
+    let ix = instruction::set_wormhole_emitter(&program_id, &initializer.pubkey(), &taker.pubkey(), id, 2, [9; 32]);
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.deposit(id).await;
+    let vaa = Pubkey::new_unique();
+    set_posted_vaa(&mut bench.ctx, &vaa, 2, [9; 32], &vaa_release_payload(&initializer.pubkey(), id));
+    let ix = instruction::release_with_vaa(&program_id, &taker.pubkey(), &initializer.pubkey(), &treasury, &vaa, id);
+    bench.run(bench.settle(ix), &[]).await;
+
+    // Opened by a relayer on the initializer's signed terms
+    let relayed   = Keypair::new();
+    let relayer   = bench.funded_keypair();
+    let deadline  = bench.now().await + 600;
+    let (escrow, _) = find_escrow_address(&program_id, &relayed.pubkey(), 0);
+    let message   = relayed_initialize_message(&escrow, &taker.pubkey(), AMOUNT, 0, deadline);
+    let signature = relayed.sign_message(&message);
+    let verify    = new_ed25519_instruction_with_signature(
+        &message, signature.as_ref().try_into().unwrap(), &relayed.pubkey().to_bytes(),
+    );
+    let ix = instruction::initialize_relayed(
+        &program_id, &relayed.pubkey(), &taker.pubkey(), &relayer.pubkey(), AMOUNT, 0, 0, deadline,
+    );
+    bench.run_after(vec![verify], ix, &[&relayer, &taker]).await;
+}
+
+/// Many backers funding one escrow, which pays out or refunds them.
+async fn pooled(bench: &mut Bench) {
+    let program_id = bench.program_id;
+    let (initializer, taker) = (bench.initializer.insecure_clone(), bench.taker.insecure_clone());
+    let treasury = bench.treasury;
+    let (alice, bob) = (bench.funded_keypair(), bench.funded_keypair());
+
+    let id  = bench.id();
+    let now = bench.now().await;
+    let ix = instruction::initialize_crowdfund(&program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, now + 1_000);
+    bench.run(ix, &[&initializer, &taker]).await;
+    for contributor in [&alice, &bob] {
+        let ix = instruction::contribute(&program_id, &contributor.pubkey(), &initializer.pubkey(), id, AMOUNT / 2);
+        bench.run(ix, &[contributor]).await;
+    }
+    let ix = instruction::withdraw_crowdfund(&program_id, &taker.pubkey(), &initializer.pubkey(), &treasury, id);
+    bench.run(bench.settle(ix), &[&taker]).await;
+    let id = bench.id();
+    let ix = instruction::initialize_crowdfund(&program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, now + 1_000);
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::contribute(&program_id, &alice.pubkey(), &initializer.pubkey(), id, AMOUNT / 2);
+    bench.run(ix, &[&alice]).await;
+    bench.warp_to(now + 1_001).await;
+    let ix = instruction::claim_refund(&program_id, &alice.pubkey(), &initializer.pubkey(), id);
+    bench.run(bench.settle(ix), &[&alice]).await;
+
+    // A merkle distribution of two leaves, then closed
+    let id = bench.id();
+    let claimants = [bench.taker.insecure_clone(), bob];
+    let leaves    = [0, 1].map(|i| merkle::distribution_leaf(i as u32, &claimants[i].pubkey(), AMOUNT / 2));
+    let root      = merkle::hash_nodes(&leaves[0], &leaves[1]);
+    let ix = instruction::initialize_distribution(&program_id, &initializer.pubkey(), id, AMOUNT, root, 2, 0);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::deposit(&program_id, &initializer.pubkey(), &Pubkey::default(), id);
+    bench.run(ix, &[&initializer]).await;
+    for (i, claimant) in claimants.iter().enumerate() {
+        let ix = instruction::claim_distribution(
+            &program_id, &claimant.pubkey(), &initializer.pubkey(), &treasury, id, i as u32, AMOUNT / 2, vec![leaves[1 - i]],
+        );
+        bench.run(bench.settle_untaken(ix), &[claimant]).await;
+    }
+    bench.run(instruction::close_distribution(&program_id, &initializer.pubkey(), id), &[&initializer]).await;
+}
+
+/// Escrows of SPL tokens: fungible, an NFT, a swap of two mints, wrapped
+/// SOL, and an auction.
+async fn tokens(bench: &mut Bench) {
+    let program_id = bench.program_id;
+    let (initializer, taker, arbiter) =
+        (bench.initializer.insecure_clone(), bench.taker.insecure_clone(), bench.arbiter.insecure_clone());
+    let token_program = SPL_TOKEN_PROGRAM_ID;
+    let mint = bench.create_mint(6, 10 * AMOUNT);
+    let initializer_token = bench.create_token_account(&initializer.pubkey(), &mint, 10 * AMOUNT);
+    let taker_token       = bench.create_token_account(&taker.pubkey(), &mint, 0);
+    let arbiter_token     = bench.create_token_account(&arbiter.pubkey(), &mint, 0);
+    let initialize = |bench: &mut Bench, mint: &Pubkey, expiry_ts| {
+        let id = bench.id();
+        let ix = instruction::initialize_token(
+            &program_id, &initializer.pubkey(), &taker.pubkey(), mint, &token_program, AMOUNT, id, expiry_ts,
+            Some(&arbiter.pubkey()),
+        );
+        (id, ix)
+    };
+    let deposit = |id| {
+        instruction::deposit_token(&program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_token, &mint, &token_program, id)
+    };
+
+    // Released, released in two parts, and resolved
+    for _ in 0..3 {
+        let (id, ix) = initialize(bench, &mint, 0);
+        bench.run(ix, &[&initializer, &taker]).await;
+        bench.run(deposit(id), &[&initializer]).await;
+    }
+    let first = bench.next_id - 3;
+    let ix = instruction::withdraw_token(&program_id, &initializer.pubkey(), &taker.pubkey(), &taker_token, &mint, &token_program, first);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+    let ix = instruction::withdraw_partial_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &bench.treasury, &taker_token, &mint, &token_program, first + 1, AMOUNT / 2,
+    );
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+    let ix = instruction::mutual_cancel_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_token, &mint, &token_program, first + 1,
+    );
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+    bench.dispute(first + 2).await;
+    let ix = instruction::resolve_token(
+        &program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), &taker_token, &arbiter_token, &mint,
+        &token_program, first + 2, true,
+    );
+    bench.run(bench.settle(ix), &[&arbiter]).await;
+
+    // The same ruling held for the appeal window
+    bench.update_config(60, &Pubkey::new_unique()).await;
+    let (id, ix) = initialize(bench, &mint, 0);
+    bench.run(ix, &[&initializer, &taker]).await;
+    bench.run(deposit(id), &[&initializer]).await;
+    bench.dispute(id).await;
+    let ix = instruction::resolve_token(
+        &program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), &taker_token, &arbiter_token, &mint,
+        &token_program, id, true,
+    );
+    bench.run(bench.settle(ix), &[&arbiter]).await;
+    let now = bench.now().await;
+    bench.warp_to(now + 61).await;
+    let ix = instruction::finalize_ruling_token(
+        &program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), &taker_token, &arbiter_token, &mint,
+        &token_program, id,
+    );
+    bench.run(bench.settle(ix), &[]).await;
+    bench.update_config(0, &Pubkey::default()).await;
+
+    // An NFT of a verified collection
+    let nft        = bench.create_mint(0, 1);
+    let collection = Pubkey::new_unique();
+    let initializer_nft = bench.create_token_account(&initializer.pubkey(), &nft, 1);
+    let taker_nft       = bench.create_token_account(&taker.pubkey(), &nft, 0);
+    set_nft_metadata(&mut bench.ctx, &nft, &collection);
+    let id = bench.id();
+    let ix = instruction::initialize_nft(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &nft, &token_program, &collection, id, 0, None,
+    );
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::deposit_nft(&program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_nft, &nft, &token_program, id);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::withdraw_token(&program_id, &initializer.pubkey(), &taker.pubkey(), &taker_nft, &nft, &token_program, id);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+
+    // A swap for another mint, executed, and one the taker reclaims from
+    let taker_mint = bench.create_mint(6, 10 * AMOUNT);
+    let taker_pays = bench.create_token_account(&taker.pubkey(), &taker_mint, 10 * AMOUNT);
+    let initializer_receives = bench.create_token_account(&initializer.pubkey(), &taker_mint, 0);
+    let now = bench.now().await;
+    for executed in [true, false] {
+        let id = bench.id();
+        let ix = instruction::initialize_swap(
+            &program_id, &initializer.pubkey(), &taker.pubkey(), &mint, &token_program, AMOUNT, &taker_mint, AMOUNT / 2, id,
+            now + 1_000,
+        );
+        bench.run(ix, &[&initializer, &taker]).await;
+        bench.run(deposit(id), &[&initializer]).await;
+        let ix = instruction::deposit_swap(&program_id, &taker.pubkey(), &initializer.pubkey(), &taker_pays, &taker_mint, &token_program, id);
+        bench.run(ix, &[&taker]).await;
+        let ix = if executed {
+            let ix = instruction::execute_swap(
+                &program_id, &taker.pubkey(), &initializer.pubkey(), &taker.pubkey(), &initializer_receives, &taker_token,
+                &mint, &token_program, &taker_mint, &token_program, id,
+            );
+            bench.settle(ix)
+        } else {
+            instruction::reclaim_swap(&program_id, &taker.pubkey(), &initializer.pubkey(), &taker_pays, &taker_mint, &token_program, id)
+        };
+        bench.run(ix, &[&taker]).await;
+    }
+
+    // Wrapped SOL taken and paid in lamports, or paid into a new ATA
+    let (id, ix) = initialize(bench, &SPL_TOKEN_NATIVE_MINT, 0);
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::deposit_sol(&program_id, &initializer.pubkey(), &taker.pubkey(), &token_program, id);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::withdraw_sol(&program_id, &initializer.pubkey(), &taker.pubkey(), &token_program, id);
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+    bench.run(instruction::close_token(&program_id, &initializer.pubkey(), &token_program, id), &[&initializer]).await;
+    let (id, ix) = initialize(bench, &SPL_TOKEN_NATIVE_MINT, 0);
+    bench.run(ix, &[&initializer, &taker]).await;
+    let ix = instruction::deposit_sol(&program_id, &initializer.pubkey(), &taker.pubkey(), &token_program, id);
+    bench.run(ix, &[&initializer]).await;
+    let ix = instruction::withdraw_token_to_ata(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_NATIVE_MINT, &token_program, id,
+    );
+    bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+
+    // A Dutch auction bought partway down, and one called off
+    let buyer_token       = bench.create_native_ata(&taker.pubkey()).await;
+    let initializer_token = bench.create_native_ata(&initializer.pubkey()).await;
+    for bought in [true, false] {
+        let id  = bench.id();
+        let now = bench.now().await;
+        let auction = Auction {
+            start_price: 2 * AMOUNT,
+            floor_price: AMOUNT / 2,
+            start_ts:    now,
+            end_ts:      now + 1_000,
+            step_secs:   100,
+        };
+        let ix = instruction::initialize_auction(
+            &program_id, &initializer.pubkey(), &Pubkey::default(), &SPL_TOKEN_NATIVE_MINT, &token_program, AMOUNT, id, auction,
+        );
+        bench.run(ix, &[&initializer]).await;
+        let ix = instruction::deposit_sol(&program_id, &initializer.pubkey(), &Pubkey::default(), &token_program, id);
+        bench.run(ix, &[&initializer]).await;
+        if bought {
+            bench.warp_to(now + 450).await;
+            let ix = instruction::buy_auction(
+                &program_id, &taker.pubkey(), &initializer.pubkey(), &buyer_token, &SPL_TOKEN_NATIVE_MINT, &token_program, id,
+            );
+            bench.run(bench.settle(ix), &[&taker]).await;
+        } else {
+            let ix = instruction::cancel_auction(
+                &program_id, &initializer.pubkey(), &initializer_token, &SPL_TOKEN_NATIVE_MINT, &token_program, id,
+            );
+            bench.run(bench.settle_untaken(ix), &[&initializer]).await;
+        }
+    }
+}
+
+/// Writes the Metaplex metadata of `mint`, a verified member of
+/// `collection`.
+fn set_nft_metadata(ctx: &mut ProgramTestContext, mint: &Pubkey, collection: &Pubkey) {
+    let mut data = vec![4];
+    data.extend_from_slice(Pubkey::new_unique().as_ref());
+    data.extend_from_slice(mint.as_ref());
+    for field in ["nft", "NFT", "ipfs://nft"] {
+        data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        data.extend_from_slice(field.as_bytes());
+    }
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data.extend_from_slice(&[0, 0, 1, 0, 0]); // no creators, not sold, mutable, no edition nonce or standard
+    data.extend_from_slice(&[1, 1]);
+    data.extend_from_slice(collection.as_ref());
+    let account = Account { lamports: LAMPORTS_PER_SOL, data, owner: METADATA_PROGRAM_ID, ..Account::default() };
+    ctx.set_account(&find_metadata_address(mint).0, &account.into());
+}
+
+/// Escrows of stake: a stake account's authorities, and stake pool tokens.
+async fn staked(bench: &mut Bench) {
+    let program_id = bench.program_id;
+    let (initializer, taker) = (bench.initializer.insecure_clone(), bench.taker.insecure_clone());
+    let now = bench.now().await;
+
+    for expiry_ts in [0, now + 60] {
+        let stake = Keypair::new();
+        let payer = bench.ctx.payer.pubkey();
+        let ixs = stake_instruction::create_account(
+            &payer, &stake.pubkey(), &Authorized::auto(&initializer.pubkey()), &Lockup::default(), AMOUNT,
+        );
+        bench.send(&ixs, &[&stake]).await;
+        let id = bench.id();
+        let ix = instruction::initialize_stake(&program_id, &initializer.pubkey(), &taker.pubkey(), &stake.pubkey(), id, expiry_ts);
+        bench.run(ix, &[&initializer, &taker]).await;
+        if expiry_ts == 0 {
+            let ix = instruction::withdraw_stake(&program_id, &initializer.pubkey(), &taker.pubkey(), &stake.pubkey(), id);
+            bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+        } else {
+            bench.warp_to(expiry_ts + 1).await;
+            let ix = instruction::reclaim_stake(&program_id, &initializer.pubkey(), &stake.pubkey(), id);
+            bench.run(bench.settle(ix), &[&initializer]).await;
+        }
+    }
+
+    let pool = StakePool {
+        address:             Pubkey::new_unique(),
+        reserve_stake:       Pubkey::new_unique(),
+        pool_mint:           Pubkey::default(),
+        manager_fee_account: Pubkey::new_unique(),
+        token_program:       SPL_TOKEN_PROGRAM_ID,
+    };
+    let pool = StakePool { pool_mint: bench.create_mint_with_authority(9, 0, pool.withdraw_authority()), ..pool };
+    let reserve = Account { lamports: 10 * LAMPORTS_PER_SOL, owner: STAKE_POOL_PROGRAM_ID, ..Account::default() };
+    bench.ctx.set_account(&pool.reserve_stake, &reserve.into());
+    let (beneficiary, now) = (bench.beneficiary, bench.now().await);
+    for expiry_ts in [0, now + 60] {
+        let id = bench.id();
+        let ix = instruction::initialize_token(
+            &program_id, &initializer.pubkey(), &taker.pubkey(), &pool.pool_mint, &SPL_TOKEN_PROGRAM_ID, AMOUNT, id, expiry_ts,
+            None,
+        );
+        bench.run(ix, &[&initializer, &taker]).await;
+        let ix = instruction::deposit_staked(&program_id, &initializer.pubkey(), &taker.pubkey(), &pool, id);
+        bench.run(ix, &[&initializer]).await;
+        if expiry_ts == 0 {
+            let ix = instruction::withdraw_staked(&program_id, &initializer.pubkey(), &taker.pubkey(), &beneficiary, &pool, id);
+            bench.run(bench.settle(ix), &[&initializer, &taker]).await;
+        } else {
+            bench.warp_to(expiry_ts + 1).await;
+            let ix = instruction::reclaim_staked(&program_id, &initializer.pubkey(), &beneficiary, &pool, id);
+            bench.run(bench.settle(ix), &[&initializer]).await;
+        }
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "test-sbf"), ignore = "compute units are only metered under cargo test-sbf")]
+async fn instructions_stay_within_their_compute_budget() {
+    let mut bench = Bench::start().await;
+    admin(&mut bench).await;
+    native(&mut bench).await;
+    terms(&mut bench).await;
+    disputes(&mut bench).await;
+    conditional(&mut bench).await;
+    attested(&mut bench).await;
+    pooled(&mut bench).await;
+    tokens(&mut bench).await;
+    staked(&mut bench).await;
+
+    let unmetered: Vec<_> = all_instruction_names()
+        .into_iter()
+        .filter(|name| !UNMETERED.contains(&name.as_str()) && !bench.consumed.contains_key(name))
+        .collect();
+    assert!(unmetered.is_empty(), "no run of {} here to meter", unmetered.join(", "));
+    assert!(
+        std::env::var_os("SBF_OUT_DIR").is_some() || std::env::var_os("BPF_OUT_DIR").is_some(),
+        "ran against the native processor, whose compute units say nothing about the SBF build; \
+         run under cargo test-sbf --features test-sbf",
+    );
+
+    let measured = bench
+        .consumed
+        .iter()
+        .map(|(name, consumed)| format!("{name:<28} {}\n", (consumed * 11 / 10).next_multiple_of(1_000)))
+        .collect::<String>();
+    if std::env::var_os("RECORD_COMPUTE_UNITS").is_some() {
+        let path   = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/compute_units.txt");
+        let header = include_str!("compute_units.txt").lines().take_while(|line| line.starts_with('#'));
+        let header: String = header.map(|line| format!("{line}\n")).collect();
+        std::fs::write(path, header + &measured).unwrap();
+        return;
+    }
+    let unbudgeted: Vec<_> = bench.consumed.keys().filter(|name| !bench.budgets.contains_key(*name)).collect();
+    assert!(
+        unbudgeted.is_empty(),
+        "no measured budget for {unbudgeted:?}; record them with RECORD_COMPUTE_UNITS=1, measured now:\n{measured}",
+    );
+}
@@ -0,0 +1,3389 @@
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use escrow_program::{
+    associated_token::{create_idempotent, find_associated_token_address},
+    error::EscrowError,
+    event::{EscrowInitialized, EscrowReleased, Event},
+    instruction::{self, EscrowInstruction},
+    merkle,
+    processor::process_instruction,
+    stake_pool::{StakePool, STAKE_POOL_PROGRAM_ID},
+    state::{
+        eth_release_message, find_escrow_address, find_event_authority_address, find_registry_address,
+        find_stats_address, find_taker_vault_address, find_vault_address, relayed_initialize_message, vaa_release_payload, voucher_message, Auction, EscrowState, EscrowStatus,
+        Feature, RegistryState, Role, Split, StatsState, Subscription, DEFAULT_MAX_EXPIRY_HORIZON, DISPUTE_WINDOW_SECS, EMERGENCY_DELAY_SECS, ESCROW_STATE_LEN, ESCROW_STATE_LENS, ESCROW_STATE_VERSION,
+        MAX_METADATA_URI_LEN, SPL_TOKEN_NATIVE_MINT,
+    },
+};
+use common::{eth_address, eth_sign, mock_stake_pool, set_nft_metadata, set_posted_vaa, set_pyth_price, set_switchboard_price};
+use solana_ed25519_program::new_ed25519_instruction_with_signature;
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    system_instruction, system_program,
+};
+use proptest::prelude::*;
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    clock::Clock,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    hash::hashv,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    stake::{
+        instruction as stake_instruction,
+        state::{Authorized, Lockup, StakeStateV2},
+    },
+    transaction::{Transaction, TransactionError},
+};
+use spl_token_2022::{
+    extension::{transfer_fee, BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    state::{Account as TokenAccount, Mint},
+};
+
+const AMOUNT: u64 = LAMPORTS_PER_SOL;
+const ID: u64     = 0;
+const SPL_TOKEN_PROGRAM_ID: Pubkey = solana_sdk::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+const MULTISIG_PROGRAM_ID: Pubkey  = solana_sdk::pubkey!("SQDS4ep65T869zMMBKyuUq6aD6EgTu8psMjkvj52pCf");
+
+struct Env {
+    ctx:         ProgramTestContext,
+    program_id:  Pubkey,
+    initializer: Keypair,
+    taker:       Keypair,
+    treasury:    Pubkey,
+}
+
+/// Starts a bank with the program, funded parties, and an initialized config
+/// charging `fee_bps`.
+async fn setup(fee_bps: u16) -> Env {
+    setup_with(fee_bps, &[]).await
+}
+
+/// Like `setup`, also deploying a `forward` program at each of `frontends`.
+/// Both deploy `mock_stake_pool` at the stake pool program's id, and
+/// `mock_multisig` at Squads v4's.
+async fn setup_with(fee_bps: u16, frontends: &[Pubkey]) -> Env {
+    let program_id  = Pubkey::new_unique();
+    let initializer = Keypair::new();
+    let taker       = Keypair::new();
+    let treasury    = Pubkey::new_unique();
+
+    let mut program_test = ProgramTest::new("escrow_program", program_id, processor!(process_instruction));
+    for frontend in frontends {
+        program_test.add_program("frontend", *frontend, processor!(forward));
+    }
+    program_test.add_program("stake_pool", STAKE_POOL_PROGRAM_ID, processor!(mock_stake_pool));
+    program_test.add_program("multisig", MULTISIG_PROGRAM_ID, processor!(mock_multisig));
+    for key in [initializer.pubkey(), taker.pubkey(), treasury] {
+        program_test.add_account(
+            key,
+            Account { lamports: 10 * LAMPORTS_PER_SOL, ..Account::default() },
+        );
+    }
+    let mut env = Env { ctx: program_test.start_with_context().await, program_id, initializer, taker, treasury };
+
+    let admin = env.ctx.payer.insecure_clone();
+    let ix    = instruction::initialize_config(&program_id, &admin.pubkey(), 0, &treasury);
+    send(&mut env, ix, &[]).await.unwrap();
+    for feature in [Feature::Fees, Feature::StrictMode, Feature::TokenEscrows] {
+        send(&mut env, instruction::set_feature(&program_id, &admin.pubkey(), feature, true), &[]).await.unwrap();
+    }
+    if fee_bps > 0 {
+        let ix = instruction::update_config(
+            &program_id, &admin.pubkey(), &admin.pubkey(), fee_bps, &treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+            0, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+        );
+        send(&mut env, ix, &[]).await.unwrap();
+    }
+    env
+}
+
+/// A frontend program passing its data on to the program in its first
+/// account, over the remaining accounts, and signing for its `party` PDAs.
+fn forward(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let (program, rest) = accounts.split_first().unwrap();
+    let parties = [0, 1].map(|i| party_address(program_id, i));
+    let metas = rest
+        .iter()
+        .map(|a| AccountMeta {
+            pubkey:      *a.key,
+            is_signer:   a.is_signer || parties.iter().any(|(party, _)| party == a.key),
+            is_writable: a.is_writable,
+        })
+        .collect();
+    let bumps = parties.map(|(_, bump)| [bump]);
+    let seeds: [&[&[u8]]; 2] = [&[b"party", &[0], &bumps[0]], &[b"party", &[1], &bumps[1]]];
+    invoke_signed(&Instruction { program_id: *program.key, accounts: metas, data: data.to_vec() }, accounts, &seeds)
+}
+
+/// The `index`th PDA a `forward` program at `frontend` signs for.
+fn party_address(frontend: &Pubkey, index: u8) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"party", &[index]], frontend)
+}
+
+/// What `mock_multisig` keeps in a multisig account: its members, how many
+/// of them must approve an instruction, and the approvals so far, each a
+/// member and the `proposal_hash` of the instruction.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct MockMultisig {
+    threshold: u8,
+    members:   Vec<Pubkey>,
+    approvals: Vec<(Pubkey, [u8; 32])>,
+}
+
+/// `mock_multisig`'s error for executing an instruction too few members
+/// approved.
+const NOT_ENOUGH_APPROVALS: u32 = 1;
+
+/// Stands in for a Squads v4 multisig with the same approval rule: members
+/// Approve a proposed instruction, and Execute runs it, signed by the
+/// multisig's vault PDA, only once `threshold` of them approved it. Each
+/// execution uses up its approvals.
+fn mock_multisig(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let (multisig_account, rest) = accounts.split_first().unwrap();
+    let mut multisig = MockMultisig::deserialize(&mut &multisig_account.data.borrow()[..])?;
+    match data.split_first() {
+        // [multisig, member]
+        Some((0, hash)) => {
+            let member = &rest[0];
+            if !member.is_signer || !multisig.members.contains(member.key) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            let approval = (*member.key, <[u8; 32]>::try_from(hash).unwrap());
+            if !multisig.approvals.contains(&approval) {
+                multisig.approvals.push(approval);
+            }
+        }
+        // [multisig, program, accounts of the instruction..]
+        Some((1, data)) => {
+            let (program, ix_accounts) = rest.split_first().unwrap();
+            let (vault, bump) = Pubkey::find_program_address(&[b"vault", multisig_account.key.as_ref()], program_id);
+            let metas = ix_accounts
+                .iter()
+                .map(|a| AccountMeta { pubkey: *a.key, is_signer: a.is_signer || *a.key == vault, is_writable: a.is_writable })
+                .collect();
+            let ix   = Instruction { program_id: *program.key, accounts: metas, data: data.to_vec() };
+            let hash = proposal_hash(&ix);
+            if multisig.approvals.iter().filter(|(_, approved)| *approved == hash).count() < usize::from(multisig.threshold) {
+                return Err(ProgramError::Custom(NOT_ENOUGH_APPROVALS));
+            }
+            multisig.approvals.retain(|(_, approved)| *approved != hash);
+            invoke_signed(&ix, accounts, &[&[b"vault", multisig_account.key.as_ref(), &[bump]]])?;
+        }
+        _ => return Err(ProgramError::InvalidInstructionData),
+    }
+    multisig.serialize(&mut &mut multisig_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+/// What `mock_multisig` members approve: the program, accounts and data of
+/// an instruction.
+fn proposal_hash(ix: &Instruction) -> [u8; 32] {
+    let mut parts = vec![ix.program_id.as_ref()];
+    parts.extend(ix.accounts.iter().map(|meta| meta.pubkey.as_ref()));
+    parts.push(&ix.data);
+    hashv(&parts).to_bytes()
+}
+
+/// Creates a `mock_multisig` account of `members` with `threshold`, and
+/// funds its vault PDA, returning both.
+fn create_multisig(env: &mut Env, members: &[Keypair], threshold: u8) -> (Pubkey, Pubkey) {
+    let multisig = Pubkey::new_unique();
+    let state    = MockMultisig { threshold, members: members.iter().map(Keypair::pubkey).collect(), approvals: Vec::new() };
+    let mut data = borsh::to_vec(&state).unwrap();
+    data.resize(1_024, 0);
+    let account = Account { lamports: LAMPORTS_PER_SOL, data, owner: MULTISIG_PROGRAM_ID, ..Account::default() };
+    env.ctx.set_account(&multisig, &account.into());
+    let (vault, _) = Pubkey::find_program_address(&[b"vault", multisig.as_ref()], &MULTISIG_PROGRAM_ID);
+    env.ctx.set_account(&vault, &Account { lamports: 10 * LAMPORTS_PER_SOL, ..Account::default() }.into());
+    (multisig, vault)
+}
+
+/// A `mock_multisig` member's approval of `ix`.
+fn approve_proposal(multisig: &Pubkey, member: &Pubkey, ix: &Instruction) -> Instruction {
+    let mut data = vec![0];
+    data.extend(proposal_hash(ix));
+    let accounts = vec![AccountMeta::new(*multisig, false), AccountMeta::new_readonly(*member, true)];
+    Instruction { program_id: MULTISIG_PROGRAM_ID, accounts, data }
+}
+
+/// Wraps `ix` in a `mock_multisig` Execute, leaving the vault PDA among its
+/// signers for the multisig to sign.
+fn execute_proposal(multisig: &Pubkey, ix: Instruction) -> Instruction {
+    let mut accounts = vec![AccountMeta::new(*multisig, false), AccountMeta::new_readonly(ix.program_id, false)];
+    accounts.extend(ix.accounts.into_iter().map(|meta| AccountMeta {
+        is_signer: meta.is_signer && meta.pubkey.is_on_curve(),
+        ..meta
+    }));
+    let mut data = vec![1];
+    data.extend(ix.data);
+    Instruction { program_id: MULTISIG_PROGRAM_ID, accounts, data }
+}
+
+/// Has each of `approvers` approve `ix`, then executes it through the
+/// multisig.
+async fn run_proposal(env: &mut Env, multisig: &Pubkey, approvers: &[&Keypair], ix: Instruction) -> Result<(), BanksClientError> {
+    for approver in approvers {
+        send(env, approve_proposal(multisig, &approver.pubkey(), &ix), &[approver]).await.unwrap();
+    }
+    send(env, execute_proposal(multisig, ix), &[]).await
+}
+
+/// Wraps `ix` in a call to the `forward` program at `frontend`, leaving the
+/// PDAs among its signers for the frontend to sign.
+fn via_frontend(frontend: &Pubkey, ix: Instruction) -> Instruction {
+    let mut accounts = vec![AccountMeta::new_readonly(ix.program_id, false)];
+    accounts.extend(ix.accounts.into_iter().map(|meta| AccountMeta {
+        is_signer: meta.is_signer && meta.pubkey.is_on_curve(),
+        ..meta
+    }));
+    Instruction { program_id: *frontend, accounts, data: ix.data }
+}
+
+/// Sends `ix` signed by the fee payer and `signers`, against the context's
+/// current blockhash so a repeated instruction only differs after
+/// `get_new_latest_blockhash`.
+async fn send(env: &mut Env, ix: Instruction, signers: &[&Keypair]) -> Result<(), BanksClientError> {
+    let mut all = vec![&env.ctx.payer];
+    all.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        &[ix], Some(&env.ctx.payer.pubkey()), &all, env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await
+}
+
+/// Appends the stats PDAs of the test escrow's parties, which an
+/// instruction settling or disputing it then counts in.
+fn with_party_stats(env: &Env, ix: Instruction) -> Instruction {
+    instruction::with_stats(&env.program_id, ix, &[env.initializer.pubkey(), env.taker.pubkey()])
+}
+
+fn assert_ix_err(result: Result<(), BanksClientError>, expected: InstructionError) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, err) => assert_eq!(err, expected),
+        err => panic!("unexpected transaction error {err:?}"),
+    }
+}
+
+fn escrow_err(e: EscrowError) -> InstructionError {
+    InstructionError::Custom(e as u32)
+}
+
+fn escrow_address(env: &Env) -> Pubkey {
+    find_escrow_address(&env.program_id, &env.initializer.pubkey(), ID).0
+}
+
+fn vault_address(env: &Env) -> Pubkey {
+    find_vault_address(&env.program_id, &escrow_address(env)).0
+}
+
+async fn lamports(ctx: &mut ProgramTestContext, key: Pubkey) -> u64 {
+    ctx.banks_client.get_balance(key).await.unwrap()
+}
+
+async fn registry(env: &mut Env) -> RegistryState {
+    let (address, _) = find_registry_address(&env.program_id, &env.initializer.pubkey());
+    let account = env.ctx.banks_client.get_account(address).await.unwrap().unwrap();
+    RegistryState::unpack(&account.data).unwrap()
+}
+
+async fn escrow_state(env: &mut Env) -> EscrowState {
+    let account = env.ctx.banks_client.get_account(escrow_address(env)).await.unwrap().unwrap();
+    EscrowState::unpack(&account.data).unwrap()
+}
+
+async fn initialize(env: &mut Env, amount: u64) -> Result<(), BanksClientError> {
+    initialize_id(env, amount, ID).await
+}
+
+async fn initialize_id(env: &mut Env, amount: u64, id: u64) -> Result<(), BanksClientError> {
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), amount, id, 0, None,
+    );
+    send(env, ix, &[&initializer, &taker]).await
+}
+
+async fn deposit(env: &mut Env) -> Result<(), BanksClientError> {
+    let initializer = env.initializer.insecure_clone();
+    let ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &env.taker.pubkey(), ID);
+    send(env, ix, &[&initializer]).await
+}
+
+async fn withdraw(env: &mut Env) -> Result<(), BanksClientError> {
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::withdraw(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID,
+    );
+    let ix = instruction::with_features(&env.program_id, ix);
+    send(env, with_party_stats(env, ix), &[&initializer, &taker]).await
+}
+
+#[tokio::test]
+async fn happy_path_initialize_deposit_withdraw_close() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!(state.status, EscrowStatus::Created);
+    assert_eq!(state.amount, AMOUNT);
+    assert_eq!(state.taker_pubkey, env.taker.pubkey());
+    // Without lists the account is just the header, and pays rent for no more
+    let account = env.ctx.banks_client.get_account(escrow_address(&env)).await.unwrap().unwrap();
+    let rent    = env.ctx.banks_client.get_rent().await.unwrap();
+    assert_eq!(account.data.len(), ESCROW_STATE_LEN);
+    assert_eq!(account.lamports, rent.minimum_balance(ESCROW_STATE_LEN));
+
+    deposit(&mut env).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!(state.status, EscrowStatus::Funded);
+    assert_eq!(state.remaining, AMOUNT);
+
+    let taker_before = lamports(&mut env.ctx, env.taker.pubkey()).await;
+    withdraw(&mut env).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, env.taker.pubkey()).await, taker_before + AMOUNT);
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+
+    let initializer = env.initializer.insecure_clone();
+    let ix = instruction::close(&env.program_id, &initializer.pubkey(), ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let (escrow, vault) = (escrow_address(&env), vault_address(&env));
+    assert_eq!(lamports(&mut env.ctx, escrow).await, 0);
+    assert_eq!(lamports(&mut env.ctx, vault).await, 0);
+}
+
+#[tokio::test]
+async fn withdraw_pays_protocol_fee_to_treasury() {
+    let mut env = setup(250).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let taker_before    = lamports(&mut env.ctx, env.taker.pubkey()).await;
+    let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+    withdraw(&mut env).await.unwrap();
+    let fee = AMOUNT * 250 / 10_000;
+    assert_eq!(lamports(&mut env.ctx, env.treasury).await, treasury_before + fee);
+    assert_eq!(lamports(&mut env.ctx, env.taker.pubkey()).await, taker_before + AMOUNT - fee);
+}
+
+#[tokio::test]
+async fn initialize_without_taker_signature_awaits_acceptance() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::without_taker_signature(instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None,
+    ));
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    assert!(bool::from(escrow_state(&mut env).await.taker_pending));
+
+    // Once the taker accepts, the funded escrow can no longer be cancelled
+    let ix = instruction::accept_escrow(&env.program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    assert!(!bool::from(escrow_state(&mut env).await.taker_pending));
+    let ix = instruction::cancel(&env.program_id, &initializer.pubkey(), ID);
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, escrow_err(EscrowError::AlreadyFunded));
+}
+
+#[tokio::test]
+async fn initializer_cancels_a_funded_escrow_its_taker_never_accepted() {
+    let mut env = setup(0).await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = instruction::without_taker_signature(instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &env.taker.pubkey(), AMOUNT, ID, 0, None,
+    ));
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let ix = instruction::cancel(&env.program_id, &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let escrow = escrow_address(&env);
+    assert_eq!(lamports(&mut env.ctx, escrow).await, 0);
+}
+
+#[tokio::test]
+async fn withdraw_requires_the_stored_taker() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let initializer = env.initializer.insecure_clone();
+    let impostor    = Keypair::new();
+    let ix = instruction::withdraw(
+        &env.program_id, &initializer.pubkey(), &impostor.pubkey(), &env.treasury, ID,
+    );
+    assert_ix_err(send(&mut env, ix, &[&initializer, &impostor]).await, escrow_err(EscrowError::WrongTaker));
+}
+
+#[tokio::test]
+async fn initialize_rejects_wrong_escrow_pda() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let mut ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None,
+    );
+    ix.accounts[2].pubkey = Pubkey::new_unique();
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, InstructionError::InvalidSeeds);
+}
+
+#[tokio::test]
+async fn deposit_rejects_wrong_vault() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let initializer = env.initializer.insecure_clone();
+    let mut ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &env.taker.pubkey(), ID);
+    ix.accounts[3].pubkey = Pubkey::new_unique();
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, InstructionError::InvalidSeeds);
+}
+
+#[tokio::test]
+async fn initialize_rejects_wrong_system_program() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let mut ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None,
+    );
+    let system_program = ix.accounts.iter_mut().find(|meta| meta.pubkey == system_program::id()).unwrap();
+    system_program.pubkey = Pubkey::new_unique();
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, InstructionError::IncorrectProgramId);
+}
+
+#[tokio::test]
+async fn initialize_twice_fails() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    // A different amount keeps the second transaction from being deduplicated
+    assert!(initialize(&mut env, AMOUNT + 1).await.is_err());
+    assert_eq!(escrow_state(&mut env).await.amount, AMOUNT);
+}
+
+#[tokio::test]
+async fn initialize_refuses_an_occupied_escrow_address() {
+    let mut env = setup(0).await;
+    let escrow  = escrow_address(&env);
+    let account = Account { lamports: LAMPORTS_PER_SOL, data: vec![7; 8], owner: env.program_id, ..Account::default() };
+    env.ctx.set_account(&escrow, &account.into());
+    assert_ix_err(initialize(&mut env, AMOUNT).await, InstructionError::AccountAlreadyInitialized);
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+    assert_eq!(account.data, vec![7; 8]);
+}
+
+#[tokio::test]
+async fn lamports_sent_to_the_escrow_vault_and_registry_addresses_do_not_block_them() {
+    let mut env = setup(0).await;
+    let (escrow, vault) = (escrow_address(&env), vault_address(&env));
+    let (registry, _)   = find_registry_address(&env.program_id, &env.initializer.pubkey());
+    let griefer = funded_keypair(&mut env).await;
+    let rent    = env.ctx.banks_client.get_rent().await.unwrap().minimum_balance(0);
+    for address in [escrow, vault, registry] {
+        let ix = system_instruction::transfer(&griefer.pubkey(), &address, rent);
+        send(&mut env, ix, &[&griefer]).await.unwrap();
+    }
+
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+    assert_eq!(account.owner, env.program_id);
+    let account = env.ctx.banks_client.get_account(vault).await.unwrap().unwrap();
+    assert_eq!(account.owner, env.program_id);
+    let taker_before = lamports(&mut env.ctx, env.taker.pubkey()).await;
+    withdraw(&mut env).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, env.taker.pubkey()).await, taker_before + AMOUNT);
+}
+
+#[tokio::test]
+async fn counter_assigns_consecutive_ids() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    // Reusing id 0 no longer matches the id the counter hands out
+    assert_ix_err(initialize_id(&mut env, AMOUNT + 1, ID).await, InstructionError::InvalidSeeds);
+    initialize_id(&mut env, AMOUNT, ID + 1).await.unwrap();
+
+    let (escrow, _) = find_escrow_address(&env.program_id, &env.initializer.pubkey(), ID + 1);
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+    assert_eq!(EscrowState::unpack(&account.data).unwrap().id, ID + 1);
+    assert_eq!(escrow_state(&mut env).await.id, ID);
+}
+
+#[tokio::test]
+async fn registry_tracks_active_escrows() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    initialize_id(&mut env, AMOUNT, ID + 1).await.unwrap();
+    let registry_state = registry(&mut env).await;
+    assert_eq!(registry_state.next_id, ID + 2);
+    assert_eq!(registry_state.active, vec![ID, ID + 1]);
+
+    let initializer = env.initializer.insecure_clone();
+    let ix = instruction::cancel(&env.program_id, &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let registry_state = registry(&mut env).await;
+    assert_eq!(registry_state.next_id, ID + 2);
+    assert_eq!(registry_state.active, vec![ID + 1]);
+}
+
+#[tokio::test]
+async fn withdraw_before_deposit_fails() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    assert_ix_err(withdraw(&mut env).await, escrow_err(EscrowError::NotFunded));
+}
+
+#[tokio::test]
+async fn deposit_twice_fails() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let initializer = env.initializer.insecure_clone();
+    let ix = instruction::deposit_additional(&env.program_id, &initializer.pubkey(), &env.taker.pubkey(), ID, 1);
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, escrow_err(EscrowError::AlreadyFunded));
+}
+
+#[tokio::test]
+async fn withdraw_after_release_fails() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    withdraw(&mut env).await.unwrap();
+    // Same instruction again, so move to a fresh blockhash to avoid deduplication
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(withdraw(&mut env).await, escrow_err(EscrowError::AlreadySettled));
+}
+
+#[tokio::test]
+async fn cancel_refunds_unfunded_escrow() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let initializer = env.initializer.insecure_clone();
+    let (escrow, vault) = (escrow_address(&env), vault_address(&env));
+    let registry = find_registry_address(&env.program_id, &initializer.pubkey()).0;
+    let rent     = lamports(&mut env.ctx, escrow).await + lamports(&mut env.ctx, vault).await;
+    let before   = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let registry_before = lamports(&mut env.ctx, registry).await;
+
+    // The cancelled escrow is closed for the rest of the transaction too
+    let cancel  = instruction::cancel(&env.program_id, &initializer.pubkey(), ID);
+    let deposit = instruction::deposit(&env.program_id, &initializer.pubkey(), &env.taker.pubkey(), ID);
+    let tx = Transaction::new_signed_with_payer(
+        &[cancel.clone(), deposit], Some(&env.ctx.payer.pubkey()), &[&env.ctx.payer, &initializer], env.ctx.last_blockhash,
+    );
+    let err = env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap();
+    assert_eq!(err, TransactionError::InstructionError(1, escrow_err(EscrowError::StateVersion)));
+
+    let ix = with_party_stats(&env, cancel);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let shrunk = registry_before - lamports(&mut env.ctx, registry).await;
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, before + rent + shrunk);
+    assert_eq!(lamports(&mut env.ctx, escrow).await, 0);
+    assert_eq!(lamports(&mut env.ctx, vault).await, 0);
+}
+
+#[tokio::test]
+async fn cancel_rejects_funded_escrow() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let initializer = env.initializer.insecure_clone();
+    let ix = instruction::cancel(&env.program_id, &initializer.pubkey(), ID);
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, escrow_err(EscrowError::AlreadyFunded));
+}
+
+#[tokio::test]
+async fn initialize_and_deposit_funds_at_once_and_mutual_cancel_unwinds_it() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    let before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = instruction::initialize_and_deposit(&program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.deposited, state.remaining), (EscrowStatus::Funded, AMOUNT, AMOUNT));
+    let (escrow, vault) = (escrow_address(&env), vault_address(&env));
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, vault).await, AMOUNT + rent.minimum_balance(0));
+    assert_eq!(registry(&mut env).await.active, vec![ID]);
+
+    // Either party alone can't unwind it
+    let mut ix = instruction::mutual_cancel(&program_id, &initializer.pubkey(), &taker.pubkey(), ID);
+    ix.accounts[1].is_signer = false;
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, InstructionError::MissingRequiredSignature);
+
+    // Together they get the deposit and every account's rent back to the initializer
+    let ix = instruction::mutual_cancel(&program_id, &initializer.pubkey(), &taker.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, escrow).await, 0);
+    assert_eq!(lamports(&mut env.ctx, vault).await, 0);
+    assert!(registry(&mut env).await.active.is_empty());
+    // Only the registry, which stays, has cost the initializer anything
+    let (registry_address, _) = find_registry_address(&program_id, &initializer.pubkey());
+    let registry_rent = lamports(&mut env.ctx, registry_address).await;
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, before - registry_rent);
+}
+
+#[tokio::test]
+async fn mutual_cancel_returns_the_tokens_of_a_token_escrow() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id    = env.program_id;
+    let token_program = SPL_TOKEN_PROGRAM_ID;
+    let amount        = 1_000;
+    let mint              = create_mint(&mut env, &token_program, None).await;
+    let initializer_token = create_token_account(&mut env, &token_program, &mint, &initializer.pubkey(), amount).await;
+    let ix = instruction::initialize_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &mint, &token_program, amount, ID, 0, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::deposit_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_token, &mint, &token_program, ID,
+    );
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert_eq!(token_amount(&mut env, initializer_token).await, 0);
+
+    let ix = instruction::mutual_cancel_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_token, &mint, &token_program, ID,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(token_amount(&mut env, initializer_token).await, amount);
+    let (escrow, vault) = (escrow_address(&env), vault_address(&env));
+    assert!(env.ctx.banks_client.get_account(vault).await.unwrap().is_none());
+    assert_eq!(lamports(&mut env.ctx, escrow).await, 0);
+}
+
+#[tokio::test]
+async fn paused_config_blocks_initialize() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, true, 0,
+        0, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    assert_ix_err(initialize(&mut env, AMOUNT).await, escrow_err(EscrowError::Paused));
+}
+
+#[tokio::test]
+async fn config_amount_limits_bound_new_escrows() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let update = |env: &Env, min_amount, max_amount| {
+        instruction::update_config(
+            &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+            0, 0, false, &Pubkey::default(), &Pubkey::default(), min_amount, max_amount, 0, 0, 0, 0, 0,
+            &Pubkey::default(),
+        )
+    };
+    let ix = update(&env, AMOUNT, AMOUNT / 2);
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::InvalidAmount));
+    let ix = update(&env, AMOUNT / 2, AMOUNT);
+    send(&mut env, ix, &[]).await.unwrap();
+
+    assert_ix_err(initialize(&mut env, AMOUNT / 4).await, escrow_err(EscrowError::AmountOutOfLimits));
+    assert_ix_err(initialize(&mut env, AMOUNT * 2).await, escrow_err(EscrowError::AmountOutOfLimits));
+    initialize(&mut env, AMOUNT).await.unwrap();
+}
+
+#[tokio::test]
+async fn config_rate_limits_escrows_per_epoch() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 1, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+
+    initialize(&mut env, AMOUNT).await.unwrap();
+    assert_ix_err(initialize_id(&mut env, AMOUNT, ID + 1).await, escrow_err(EscrowError::RateLimited));
+
+    // The next epoch opens a new window
+    let epoch = env.ctx.banks_client.get_sysvar::<Clock>().await.unwrap().epoch + 1;
+    env.ctx.warp_to_epoch(epoch).unwrap();
+    initialize_id(&mut env, AMOUNT, ID + 1).await.unwrap();
+    let registry_state = registry(&mut env).await;
+    assert_eq!((registry_state.epoch, registry_state.created_in_epoch), (epoch, 1));
+}
+
+#[tokio::test]
+async fn creation_fee_is_refunded_on_release_and_forfeited_on_cancel() {
+    const FEE: u64 = LAMPORTS_PER_SOL / 100;
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, FEE, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    let initializer = env.initializer.insecure_clone();
+
+    // A cancelled escrow's fee goes to the treasury, which must be the config's
+    initialize(&mut env, AMOUNT).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.creation_fee, FEE);
+    let ix = instruction::cancel(&env.program_id, &initializer.pubkey(), ID);
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, InstructionError::NotEnoughAccountKeys);
+    let ix = instruction::with_creation_fee(
+        &env.program_id, instruction::cancel(&env.program_id, &initializer.pubkey(), ID), &Pubkey::new_unique(),
+    );
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, escrow_err(EscrowError::WrongTreasury));
+    let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+    let ix = instruction::with_creation_fee(
+        &env.program_id, instruction::cancel(&env.program_id, &initializer.pubkey(), ID), &env.treasury,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, env.treasury).await - treasury_before, FEE);
+
+    // A released one gives it back on Close, with the rent
+    initialize_id(&mut env, AMOUNT, ID + 1).await.unwrap();
+    let (escrow, _) = find_escrow_address(&env.program_id, &initializer.pubkey(), ID + 1);
+    let held = lamports(&mut env.ctx, escrow).await;
+    let ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &env.taker.pubkey(), ID + 1);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let taker = env.taker.insecure_clone();
+    let ix = instruction::withdraw(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID + 1);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = instruction::close(&env.program_id, &initializer.pubkey(), ID + 1);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert!(lamports(&mut env.ctx, initializer.pubkey()).await - before >= held);
+    assert_eq!(lamports(&mut env.ctx, env.treasury).await - treasury_before, FEE);
+}
+
+#[tokio::test]
+async fn instruction_with_trailing_bytes_is_rejected() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let mut ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None,
+    );
+    ix.data.push(0);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::TrailingBytes));
+}
+
+#[tokio::test]
+async fn truncated_instruction_is_rejected() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let mut ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None,
+    );
+    ix.data.pop();
+    assert_ix_err(
+        send(&mut env, ix, &[&initializer, &taker]).await,
+        escrow_err(EscrowError::MalformedInstruction),
+    );
+}
+
+#[tokio::test]
+async fn initialize_rejects_zero_amount() {
+    let mut env = setup(0).await;
+    assert_ix_err(initialize(&mut env, 0).await, escrow_err(EscrowError::InvalidAmount));
+}
+
+#[tokio::test]
+async fn partial_withdraw_keeps_vault_rent_exempt() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    // Strip the vault's rent reserve so a partial payout would leave it below the floor
+    let vault       = vault_address(&env);
+    let mut account = env.ctx.banks_client.get_account(vault).await.unwrap().unwrap();
+    account.lamports = AMOUNT;
+    env.ctx.set_account(&vault, &account.into());
+
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::withdraw_partial(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID, AMOUNT - 1);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::BelowRentFloor));
+}
+
+#[tokio::test]
+async fn partial_withdraw_pays_protocol_fee_on_each_tranche() {
+    let mut env = setup(250).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let fee = AMOUNT / 2 * 250 / 10_000;
+    for _ in 0..2 {
+        env.ctx.get_new_latest_blockhash().await.unwrap();
+        let taker_before    = lamports(&mut env.ctx, taker.pubkey()).await;
+        let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+        let ix = instruction::withdraw_partial(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID, AMOUNT / 2);
+        let ix = with_party_stats(&env, instruction::with_features(&env.program_id, ix));
+        send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+        assert_eq!(lamports(&mut env.ctx, env.treasury).await, treasury_before + fee);
+        assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + AMOUNT / 2 - fee);
+    }
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.remaining), (EscrowStatus::Released, 0));
+
+    // The treasury must be the config's
+    initialize_id(&mut env, AMOUNT, ID + 1).await.unwrap();
+    let ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID + 1);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let ix = instruction::withdraw_partial(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &Pubkey::new_unique(), ID + 1, AMOUNT / 2);
+    let ix = instruction::with_features(&env.program_id, ix);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::WrongTreasury));
+}
+
+async fn initialize_crowdfund(env: &mut Env, goal: u64, deadline: i64) {
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize_crowdfund(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), goal, ID, deadline,
+    );
+    send(env, ix, &[&initializer, &taker]).await.unwrap();
+}
+
+async fn contribute(env: &mut Env, contributor: &Keypair, amount: u64) -> Result<(), BanksClientError> {
+    let ix = instruction::contribute(&env.program_id, &contributor.pubkey(), &env.initializer.pubkey(), ID, amount);
+    send(env, ix, &[contributor]).await
+}
+
+async fn funded_keypair(env: &mut Env) -> Keypair {
+    let keypair = Keypair::new();
+    let account = Account { lamports: 10 * LAMPORTS_PER_SOL, ..Account::default() };
+    env.ctx.set_account(&keypair.pubkey(), &account.into());
+    keypair
+}
+
+async fn warp_past(env: &mut Env, deadline: i64) {
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = deadline + 1;
+    env.ctx.set_sysvar(&clock);
+}
+
+#[tokio::test]
+async fn crowdfund_reaching_goal_pays_taker() {
+    let mut env = setup(0).await;
+    let deadline = i64::MAX / 2;
+    initialize_crowdfund(&mut env, AMOUNT, deadline).await;
+    let (alice, bob) = (funded_keypair(&mut env).await, funded_keypair(&mut env).await);
+    contribute(&mut env, &alice, AMOUNT / 8).await.unwrap();
+    contribute(&mut env, &bob, AMOUNT / 2).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Created);
+    // Overshooting the goal is rejected
+    assert_ix_err(contribute(&mut env, &alice, AMOUNT / 2).await, escrow_err(EscrowError::InvalidAmount));
+    contribute(&mut env, &alice, AMOUNT * 3 / 8).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Funded);
+
+    let taker = env.taker.insecure_clone();
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::withdraw_crowdfund(
+        &env.program_id, &taker.pubkey(), &env.initializer.pubkey(), &env.treasury, ID,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + AMOUNT);
+}
+
+#[tokio::test]
+async fn crowdfund_missing_goal_refunds_contributors() {
+    let mut env = setup(0).await;
+    let deadline = 1_000_000;
+    warp_past(&mut env, deadline - 10).await;
+    initialize_crowdfund(&mut env, AMOUNT, deadline).await;
+    let alice = funded_keypair(&mut env).await;
+    contribute(&mut env, &alice, AMOUNT / 2).await.unwrap();
+
+    let ix = instruction::claim_refund(&env.program_id, &alice.pubkey(), &env.initializer.pubkey(), ID);
+    assert_ix_err(send(&mut env, ix.clone(), &[&alice]).await, escrow_err(EscrowError::NotExpired));
+    // The initializer cannot take the contributions back
+    let initializer = env.initializer.insecure_clone();
+    let cancel = instruction::cancel(&env.program_id, &initializer.pubkey(), ID);
+    assert_ix_err(send(&mut env, cancel, &[&initializer]).await, escrow_err(EscrowError::Crowdfund));
+
+    warp_past(&mut env, deadline).await;
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let before = lamports(&mut env.ctx, alice.pubkey()).await;
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&alice]).await.unwrap();
+    assert!(lamports(&mut env.ctx, alice.pubkey()).await > before + AMOUNT / 2);
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Cancelled);
+}
+
+#[tokio::test]
+async fn third_party_can_fund_deposit() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let funder = funded_keypair(&mut env).await;
+    let initializer_before = lamports(&mut env.ctx, env.initializer.pubkey()).await;
+    let ix = instruction::deposit_from(
+        &env.program_id, &funder.pubkey(), &env.initializer.pubkey(), &env.taker.pubkey(), ID,
+    );
+    send(&mut env, ix, &[&funder]).await.unwrap();
+
+    let state = escrow_state(&mut env).await;
+    assert_eq!(state.status, EscrowStatus::Funded);
+    assert_eq!(state.initializer_pubkey, env.initializer.pubkey());
+    assert_eq!(lamports(&mut env.ctx, env.initializer.pubkey()).await, initializer_before);
+    assert!(lamports(&mut env.ctx, funder.pubkey()).await <= 10 * LAMPORTS_PER_SOL - AMOUNT);
+}
+
+#[tokio::test]
+async fn deposit_without_funder_needs_initializer_signature() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let mut ix = instruction::deposit(&env.program_id, &env.initializer.pubkey(), &env.taker.pubkey(), ID);
+    ix.accounts[0].is_signer = false;
+    assert_ix_err(send(&mut env, ix, &[]).await, InstructionError::MissingRequiredSignature);
+}
+
+#[tokio::test]
+async fn withdraw_can_pay_a_separate_recipient() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let cold_wallet  = Pubkey::new_unique();
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::withdraw_to(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, &cold_wallet, ID,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, cold_wallet).await, AMOUNT);
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before);
+}
+
+#[tokio::test]
+async fn price_condition_gates_withdraw() {
+    let mut env = setup(0).await;
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+
+    // Release once the price is above 150.00, quoted by an 8-decimal feed
+    let oracle = Pubkey::new_unique();
+    set_pyth_price(&mut env.ctx, &oracle, 140_00000000, -8, now);
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::set_price_condition(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), &oracle, ID, 15000, -2, true,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let conditioned = instruction::withdraw_conditioned(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, &oracle, ID,
+    );
+    assert_ix_err(
+        send(&mut env, conditioned.clone(), &[&initializer, &taker]).await,
+        escrow_err(EscrowError::PriceConditionNotMet),
+    );
+    assert_ix_err(withdraw(&mut env).await, InstructionError::NotEnoughAccountKeys);
+
+    set_pyth_price(&mut env.ctx, &oracle, 151_00000000, -8, now - 2 * 60);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(
+        send(&mut env, conditioned.clone(), &[&initializer, &taker]).await,
+        escrow_err(EscrowError::StalePrice),
+    );
+
+    set_pyth_price(&mut env.ctx, &oracle, 151_00000000, -8, now);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let conditioned = with_party_stats(&env, conditioned);
+    send(&mut env, conditioned, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+}
+
+#[tokio::test]
+async fn price_conditioned_withdraw_pays_the_taker_or_their_recipient() {
+    let mut env = setup(0).await;
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    let oracle     = Pubkey::new_unique();
+    set_pyth_price(&mut env.ctx, &oracle, 151_00000000, -8, now);
+    for id in [ID, ID + 1] {
+        initialize_id(&mut env, AMOUNT, id).await.unwrap();
+        let ix = instruction::set_price_condition(&program_id, &initializer.pubkey(), &taker.pubkey(), &oracle, id, 15000, -2, true);
+        send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+        let ix = instruction::deposit(&program_id, &initializer.pubkey(), &taker.pubkey(), id);
+        send(&mut env, ix, &[&initializer]).await.unwrap();
+    }
+    let oracle_lamports = lamports(&mut env.ctx, oracle).await;
+
+    // Without a recipient the oracle stays the oracle, and the taker is paid
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::withdraw_conditioned(&program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, &oracle, ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + AMOUNT);
+
+    let cold_wallet = Pubkey::new_unique();
+    let ix = instruction::withdraw_conditioned_to(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, &oracle, &cold_wallet, ID + 1,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, cold_wallet).await, AMOUNT);
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + AMOUNT);
+    assert_eq!(lamports(&mut env.ctx, oracle).await, oracle_lamports);
+}
+
+#[tokio::test]
+async fn switchboard_price_condition() {
+    let mut env = setup(0).await;
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+
+    // Release once the price falls below 0.5, quoted with 6 decimals
+    let oracle = Pubkey::new_unique();
+    set_switchboard_price(&mut env.ctx, &oracle, 612_000, 6, now);
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::set_price_condition(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), &oracle, ID, 5, -1, false,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let conditioned = instruction::withdraw_conditioned(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, &oracle, ID,
+    );
+    assert_ix_err(
+        send(&mut env, conditioned.clone(), &[&initializer, &taker]).await,
+        escrow_err(EscrowError::PriceConditionNotMet),
+    );
+
+    set_switchboard_price(&mut env.ctx, &oracle, 499_999, 6, now);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let conditioned = with_party_stats(&env, conditioned);
+    send(&mut env, conditioned, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+}
+
+/// Creates and funds an HTLC escrow locked to the hash of `preimage`,
+/// timing out at `timeout`.
+async fn initialize_htlc(env: &mut Env, preimage: &[u8; 32], timeout: i64) {
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize_htlc(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, timeout, hashv(&[preimage]).to_bytes(), None,
+    );
+    send(env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(env).await.unwrap();
+}
+
+fn claim_htlc(env: &Env, preimage: [u8; 32]) -> Instruction {
+    let ix = instruction::claim(&env.program_id, &env.taker.pubkey(), &env.initializer.pubkey(), &env.treasury, ID, preimage);
+    with_party_stats(env, instruction::with_features(&env.program_id, ix))
+}
+
+#[tokio::test]
+async fn htlc_pays_whoever_opens_the_hash_lock_before_the_timeout() {
+    let mut env = setup(0).await;
+    let timeout = 1_000_000;
+    warp_past(&mut env, timeout - 100).await;
+    initialize_htlc(&mut env, &[7; 32], timeout).await;
+
+    let taker = env.taker.insecure_clone();
+    let ix = claim_htlc(&env, [8; 32]);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::InvalidPreimage));
+    // Nor can the initializer take it back early
+    let initializer = env.initializer.insecure_clone();
+    let ix = with_party_stats(&env, instruction::refund(&env.program_id, &initializer.pubkey(), ID));
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, escrow_err(EscrowError::NotExpired));
+
+    let before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = claim_htlc(&env, [7; 32]);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, before + AMOUNT);
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+}
+
+#[tokio::test]
+async fn htlc_refunds_the_initializer_after_the_timeout() {
+    let mut env = setup(0).await;
+    let timeout = 1_000_000;
+    warp_past(&mut env, timeout - 100).await;
+    initialize_htlc(&mut env, &[7; 32], timeout).await;
+
+    // Past the timeout even the right preimage is too late
+    warp_past(&mut env, timeout).await;
+    let taker = env.taker.insecure_clone();
+    let ix = claim_htlc(&env, [7; 32]);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::Expired));
+
+    let initializer = env.initializer.insecure_clone();
+    let before      = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = with_party_stats(&env, instruction::refund(&env.program_id, &initializer.pubkey(), ID));
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, before + AMOUNT);
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Cancelled);
+}
+
+/// Sends ClaimWithVoucher for the test escrow, preceded by an ed25519 check
+/// of `signer`'s signature over `message`.
+async fn claim_with_voucher(env: &mut Env, signer: &Keypair, message: &[u8]) -> Result<(), BanksClientError> {
+    let taker     = env.taker.insecure_clone();
+    let signature = signer.sign_message(message);
+    let verify    = new_ed25519_instruction_with_signature(
+        message, signature.as_ref().try_into().unwrap(), &signer.pubkey().to_bytes(),
+    );
+    let claim = with_party_stats(env, instruction::claim_with_voucher(
+        &env.program_id, &taker.pubkey(), &env.initializer.pubkey(), &env.treasury, ID,
+    ));
+    let tx = Transaction::new_signed_with_payer(
+        &[verify, claim], Some(&env.ctx.payer.pubkey()), &[&env.ctx.payer, &taker], env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await
+}
+
+#[tokio::test]
+async fn voucher_from_arbiter_releases_escrow() {
+    let mut env = setup(0).await;
+    let arbiter = Keypair::new();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, Some(&arbiter.pubkey()),
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    // Without the ed25519 instruction in front
+    let ix = instruction::claim_with_voucher(
+        &env.program_id, &taker.pubkey(), &initializer.pubkey(), &env.treasury, ID,
+    );
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::InvalidVoucher));
+
+    let message = voucher_message(&escrow_address(&env), AMOUNT);
+    let stranger = Keypair::new();
+    assert_ix_err(
+        claim_with_voucher(&mut env, &stranger, &message).await,
+        escrow_err(EscrowError::InvalidVoucher),
+    );
+    let other_amount = voucher_message(&escrow_address(&env), AMOUNT / 2);
+    assert_ix_err(
+        claim_with_voucher(&mut env, &arbiter, &other_amount).await,
+        escrow_err(EscrowError::InvalidVoucher),
+    );
+
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    claim_with_voucher(&mut env, &arbiter, &message).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+    assert!(lamports(&mut env.ctx, taker.pubkey()).await > taker_before + AMOUNT / 2);
+}
+
+#[tokio::test]
+async fn eth_signature_releases_escrow() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let eth_key = libsecp256k1::SecretKey::parse(&[7; 32]).unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::set_eth_authority(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, eth_address(&eth_key),
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let claim = |env: &Env, (signature, recovery_id): ([u8; 64], u8)| {
+        instruction::claim_with_eth_signature(
+            &env.program_id, &env.taker.pubkey(), &env.initializer.pubkey(), &env.treasury, ID, signature, recovery_id,
+        )
+    };
+    // Signed by another key, then over a stale nonce
+    let other = libsecp256k1::SecretKey::parse(&[8; 32]).unwrap();
+    let message = eth_release_message(&escrow_address(&env), 0);
+    let ix = claim(&env, eth_sign(&other, &message));
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::InvalidEthSignature));
+    let ix = claim(&env, eth_sign(&eth_key, &eth_release_message(&escrow_address(&env), 1)));
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::InvalidEthSignature));
+
+    let ix = claim(&env, eth_sign(&eth_key, &message));
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!(state.status, EscrowStatus::Released);
+    assert_eq!(state.eth_nonce, 1);
+}
+
+#[tokio::test]
+async fn wormhole_vaa_releases_escrow() {
+    const ETHEREUM: u16 = 2;
+    let emitter = [9; 32];
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::set_wormhole_emitter(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, ETHEREUM, emitter,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let payload = vaa_release_payload(&initializer.pubkey(), ID);
+    let release = |env: &Env, vaa: &Pubkey| {
+        instruction::release_with_vaa(&env.program_id, &taker.pubkey(), &initializer.pubkey(), &env.treasury, vaa, ID)
+    };
+    let wrong_chain = Pubkey::new_unique();
+    set_posted_vaa(&mut env.ctx, &wrong_chain, ETHEREUM + 1, emitter, &payload);
+    let ix = release(&env, &wrong_chain);
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::InvalidVaa));
+    let other_escrow = Pubkey::new_unique();
+    set_posted_vaa(&mut env.ctx, &other_escrow, ETHEREUM, emitter, &vaa_release_payload(&initializer.pubkey(), ID + 1));
+    let ix = release(&env, &other_escrow);
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::InvalidVaa));
+
+    // Relayed by the fee payer alone
+    let vaa = Pubkey::new_unique();
+    set_posted_vaa(&mut env.ctx, &vaa, ETHEREUM, emitter, &payload);
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = release(&env, &vaa);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + AMOUNT);
+}
+
+/// Creates and funds the test escrow with an arbiter, then has the taker
+/// RequestWithdraw at `now`.
+async fn request_withdraw(env: &mut Env, now: i64) {
+    warp_past(env, now - 1).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, Some(&Pubkey::new_unique()),
+    );
+    send(env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(env).await.unwrap();
+    let ix = instruction::request_withdraw(&env.program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    send(env, ix, &[&taker]).await.unwrap();
+    assert_eq!(escrow_state(env).await.withdraw_requested_at, now);
+}
+
+#[tokio::test]
+async fn finalize_withdraw_after_dispute_window() {
+    let mut env = setup(0).await;
+    let now = 1_000_000;
+    request_withdraw(&mut env, now).await;
+
+    let taker = env.taker.insecure_clone();
+    let finalize = instruction::finalize_withdraw(
+        &env.program_id, &taker.pubkey(), &env.initializer.pubkey(), &env.treasury, ID,
+    );
+    assert_ix_err(
+        send(&mut env, finalize.clone(), &[&taker]).await,
+        escrow_err(EscrowError::DisputeWindowOpen),
+    );
+
+    warp_past(&mut env, now + DISPUTE_WINDOW_SECS).await;
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let finalize = with_party_stats(&env, finalize);
+    send(&mut env, finalize, &[&taker]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+}
+
+#[tokio::test]
+async fn dispute_blocks_finalize_withdraw() {
+    let mut env = setup(0).await;
+    let now = 1_000_000;
+    request_withdraw(&mut env, now).await;
+
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::dispute(&env.program_id, &initializer.pubkey(), &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+
+    warp_past(&mut env, now + DISPUTE_WINDOW_SECS).await;
+    let ix = instruction::finalize_withdraw(
+        &env.program_id, &taker.pubkey(), &initializer.pubkey(), &env.treasury, ID,
+    );
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::Disputed));
+}
+
+#[tokio::test]
+async fn resolve_partial_splits_the_escrow_between_the_parties() {
+    let mut env = setup(0).await;
+    let (initializer, taker, arbiter) = (env.initializer.insecure_clone(), env.taker.insecure_clone(), Keypair::new());
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, Some(&arbiter.pubkey()),
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let ix = instruction::dispute(&env.program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+
+    let resolve = |env: &Env, taker_bps| {
+        instruction::resolve_partial(&env.program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), ID, taker_bps)
+    };
+    let ix = resolve(&env, 10_001);
+    assert_ix_err(send(&mut env, ix, &[&arbiter]).await, escrow_err(EscrowError::InvalidSplit));
+
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let taker_before       = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = resolve(&env, 2_500);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&arbiter]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await - taker_before, AMOUNT / 4);
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await - initializer_before, AMOUNT * 3 / 4);
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.remaining), (EscrowStatus::Released, 0));
+}
+
+#[tokio::test]
+async fn resolve_pays_the_arbiter_the_fee_fixed_at_initialize() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let set_arbiter_fee = |env: &Env, arbiter_fee, arbiter_fee_bps| {
+        instruction::update_config(
+            &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+            0, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, arbiter_fee, arbiter_fee_bps, 0,
+            &Pubkey::default(),
+        )
+    };
+    let ix = set_arbiter_fee(&env, AMOUNT / 100, 100);
+    send(&mut env, ix, &[]).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let arbiter = funded_keypair(&mut env).await;
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, Some(&arbiter.pubkey()),
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.arbiter_fee, state.arbiter_fee_bps), (AMOUNT / 100, 100));
+
+    // Raising the fee later doesn't reach the escrow
+    let ix = set_arbiter_fee(&env, AMOUNT / 10, 1_000);
+    send(&mut env, ix, &[]).await.unwrap();
+    let ix = instruction::dispute(&env.program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    let arbiter_before = lamports(&mut env.ctx, arbiter.pubkey()).await;
+    let taker_before   = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::resolve(&env.program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), ID, true);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&arbiter]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, arbiter.pubkey()).await - arbiter_before, AMOUNT / 50);
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await - taker_before, AMOUNT - AMOUNT / 50);
+}
+
+#[tokio::test]
+async fn resolve_pays_the_arbiter_of_a_token_escrow_in_tokens() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, AMOUNT / 100, 100, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let arbiter    = funded_keypair(&mut env).await;
+    let program_id = env.program_id;
+    let ix = instruction::initialize_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, AMOUNT, ID, 0,
+        Some(&arbiter.pubkey()),
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::deposit_sol(&program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_PROGRAM_ID, ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    // The flat fee is in lamports, so only the share applies
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.arbiter_fee, state.arbiter_fee_bps), (0, 100));
+
+    let payer = env.ctx.payer.pubkey();
+    let [taker_token, arbiter_token] = [&taker, &arbiter].map(|owner| {
+        find_associated_token_address(&owner.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID)
+    });
+    for owner in [&taker, &arbiter] {
+        let ix = create_idempotent(&payer, &owner.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID);
+        send(&mut env, ix, &[]).await.unwrap();
+    }
+    let ix = instruction::dispute(&program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    let resolve = |arbiter_token: &Pubkey| {
+        instruction::resolve_token(
+            &program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), &taker_token, arbiter_token,
+            &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, ID, true,
+        )
+    };
+    // The fee must reach the arbiter
+    let ix = with_party_stats(&env, resolve(&taker_token));
+    assert_ix_err(send(&mut env, ix, &[&arbiter]).await, InstructionError::IllegalOwner);
+    let ix = with_party_stats(&env, resolve(&arbiter_token));
+    send(&mut env, ix, &[&arbiter]).await.unwrap();
+    assert_eq!(token_amount(&mut env, arbiter_token).await, AMOUNT / 100);
+    assert_eq!(token_amount(&mut env, taker_token).await, AMOUNT - AMOUNT / 100);
+}
+
+async fn token_amount(env: &mut Env, token_account: Pubkey) -> u64 {
+    let account = env.ctx.banks_client.get_account(token_account).await.unwrap().unwrap();
+    StateWithExtensions::<TokenAccount>::unpack(&account.data).unwrap().base.amount
+}
+
+/// Creates a mint of `token_program`, with no decimals and the fee payer as
+/// its authority. A Token-2022 mint given `transfer_fee_bps` withholds that
+/// share of every transfer.
+async fn create_mint(env: &mut Env, token_program: &Pubkey, transfer_fee_bps: Option<u16>) -> Pubkey {
+    let mint  = Keypair::new();
+    let payer = env.ctx.payer.insecure_clone();
+    let extensions: &[ExtensionType] = if transfer_fee_bps.is_some() { &[ExtensionType::TransferFeeConfig] } else { &[] };
+    let space = ExtensionType::try_calculate_account_len::<Mint>(extensions).unwrap();
+    let rent  = env.ctx.banks_client.get_rent().await.unwrap().minimum_balance(space);
+    let mut ixs = vec![system_instruction::create_account(&payer.pubkey(), &mint.pubkey(), rent, space as u64, token_program)];
+    if let Some(bps) = transfer_fee_bps {
+        ixs.push(
+            transfer_fee::instruction::initialize_transfer_fee_config(token_program, &mint.pubkey(), None, None, bps, u64::MAX)
+                .unwrap(),
+        );
+    }
+    ixs.push(spl_token_2022::instruction::initialize_mint2(token_program, &mint.pubkey(), &payer.pubkey(), None, 0).unwrap());
+    let tx = Transaction::new_signed_with_payer(&ixs, Some(&payer.pubkey()), &[&payer, &mint], env.ctx.last_blockhash);
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    mint.pubkey()
+}
+
+/// Creates a token account of `owner` for `mint`, with the extensions the
+/// mint requires, and mints `amount` to it.
+async fn create_token_account(env: &mut Env, token_program: &Pubkey, mint: &Pubkey, owner: &Pubkey, amount: u64) -> Pubkey {
+    let account = Keypair::new();
+    let payer   = env.ctx.payer.insecure_clone();
+    let data    = env.ctx.banks_client.get_account(*mint).await.unwrap().unwrap().data;
+    let mint_extensions = StateWithExtensions::<Mint>::unpack(&data).unwrap().get_extension_types().unwrap();
+    let space = ExtensionType::try_calculate_account_len::<TokenAccount>(
+        &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+    )
+    .unwrap();
+    let rent = env.ctx.banks_client.get_rent().await.unwrap().minimum_balance(space);
+    let mut ixs = vec![
+        system_instruction::create_account(&payer.pubkey(), &account.pubkey(), rent, space as u64, token_program),
+        spl_token_2022::instruction::initialize_account3(token_program, &account.pubkey(), mint, owner).unwrap(),
+    ];
+    if amount > 0 {
+        ixs.push(spl_token_2022::instruction::mint_to(token_program, mint, &account.pubkey(), &payer.pubkey(), &[], amount).unwrap());
+    }
+    let tx = Transaction::new_signed_with_payer(&ixs, Some(&payer.pubkey()), &[&payer, &account], env.ctx.last_blockhash);
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    account.pubkey()
+}
+
+/// Creates and funds escrow `id` with `arbiter`, and has the arbiter rule
+/// its dispute for the taker.
+async fn dispute_and_resolve(env: &mut Env, arbiter: &Keypair, id: u64) {
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, id, 0, Some(&arbiter.pubkey()),
+    );
+    send(env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &taker.pubkey(), id);
+    send(env, ix, &[&initializer]).await.unwrap();
+    let ix = instruction::dispute(&env.program_id, &taker.pubkey(), &initializer.pubkey(), id);
+    send(env, with_party_stats(env, ix), &[&taker]).await.unwrap();
+    let ix = instruction::resolve(&env.program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), id, true);
+    send(env, ix, &[arbiter]).await.unwrap();
+}
+
+#[tokio::test]
+async fn appeal_window_holds_the_ruling_until_it_closes() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let appeal_arbiter = funded_keypair(&mut env).await;
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, 0, 0, 60, &appeal_arbiter.pubkey(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let arbiter = funded_keypair(&mut env).await;
+    // The ruling is only held, and can't be carried out within the window
+    dispute_and_resolve(&mut env, &arbiter, ID).await;
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.ruling_taker_bps), (EscrowStatus::Ruled, 10_000));
+    let ix = instruction::finalize_ruling(
+        &env.program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), ID,
+    );
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::AppealWindowOpen));
+
+    // The initializer appeals, and the appeal arbiter's ruling is final
+    let ix = instruction::appeal(&env.program_id, &initializer.pubkey(), &initializer.pubkey(), ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.arbiter_pubkey), (EscrowStatus::Disputed, appeal_arbiter.pubkey()));
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let ix = instruction::resolve(&env.program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), ID, true);
+    assert_ix_err(send(&mut env, ix, &[&arbiter]).await, escrow_err(EscrowError::WrongArbiter));
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = instruction::resolve(
+        &env.program_id, &appeal_arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), ID, false,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&appeal_arbiter]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Cancelled);
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await - initializer_before, AMOUNT);
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before);
+
+    // An unchallenged ruling goes through once the window closes, and can't be appealed after
+    dispute_and_resolve(&mut env, &arbiter, ID + 1).await;
+    let (escrow, _) = find_escrow_address(&env.program_id, &initializer.pubkey(), ID + 1);
+    let held = |data: &[u8]| EscrowState::unpack(data).unwrap();
+    let ruled_at = held(&env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap().data).ruled_at;
+    warp_past(&mut env, ruled_at + 60).await;
+    let ix = instruction::appeal(&env.program_id, &taker.pubkey(), &initializer.pubkey(), ID + 1);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::AppealWindowClosed));
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::finalize_ruling(
+        &env.program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), ID + 1,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[]).await.unwrap();
+    let status = held(&env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap().data).status;
+    assert_eq!(status, EscrowStatus::Released);
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await - taker_before, AMOUNT);
+}
+
+#[tokio::test]
+async fn appeal_window_holds_token_rulings_too() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let appeal_arbiter = funded_keypair(&mut env).await;
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, 0, 100, 60, &appeal_arbiter.pubkey(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let arbiter    = funded_keypair(&mut env).await;
+    let program_id = env.program_id;
+    let ix = instruction::initialize_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, AMOUNT, ID, 0,
+        Some(&arbiter.pubkey()),
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::deposit_sol(&program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_PROGRAM_ID, ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let payer = env.ctx.payer.pubkey();
+    let [initializer_token, taker_token, arbiter_token] = [&initializer, &taker, &arbiter].map(|owner| {
+        find_associated_token_address(&owner.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID)
+    });
+    for owner in [&initializer, &taker, &arbiter] {
+        let ix = create_idempotent(&payer, &owner.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID);
+        send(&mut env, ix, &[]).await.unwrap();
+    }
+    let ix = instruction::dispute(&program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+
+    // The ruling for the initializer is only held
+    let ix = instruction::resolve_token(
+        &program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), &initializer_token, &arbiter_token,
+        &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, ID, false,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&arbiter]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.ruling_taker_bps, state.remaining), (EscrowStatus::Ruled, 0, AMOUNT));
+    assert_eq!(token_amount(&mut env, initializer_token).await, 0);
+    let finalize = |recipient_token: &Pubkey| {
+        instruction::finalize_ruling_token(
+            &program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), recipient_token, &arbiter_token,
+            &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, ID,
+        )
+    };
+    let ix = with_party_stats(&env, finalize(&initializer_token));
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::AppealWindowOpen));
+
+    // Once the window closes, it pays out to the party ruled for
+    warp_past(&mut env, state.ruled_at + 60).await;
+    let ix = with_party_stats(&env, finalize(&taker_token));
+    assert_ix_err(send(&mut env, ix, &[]).await, InstructionError::IllegalOwner);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let ix = with_party_stats(&env, finalize(&initializer_token));
+    send(&mut env, ix, &[]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Cancelled);
+    assert_eq!(token_amount(&mut env, initializer_token).await, AMOUNT - AMOUNT / 100);
+    assert_eq!(token_amount(&mut env, arbiter_token).await, AMOUNT / 100);
+}
+
+const BOND: u64 = AMOUNT / 4;
+
+/// Creates the test escrow expiring at `expiry_ts` and asks for a `BOND`
+/// from the taker, then funds it.
+async fn initialize_bonded(env: &mut Env, expiry_ts: i64) {
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, expiry_ts, None,
+    );
+    send(env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::set_taker_bond(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, BOND);
+    send(env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(env).await.unwrap();
+}
+
+async fn deposit_bond(env: &mut Env) -> Result<(), BanksClientError> {
+    let taker = env.taker.insecure_clone();
+    let ix = instruction::deposit_bond(&env.program_id, &taker.pubkey(), &env.initializer.pubkey(), ID);
+    send(env, ix, &[&taker]).await
+}
+
+#[tokio::test]
+async fn taker_bond_settles_with_withdraw() {
+    let mut env = setup(0).await;
+    initialize_bonded(&mut env, 0).await;
+    assert_ix_err(withdraw(&mut env).await, escrow_err(EscrowError::BondNotPosted));
+
+    let vault        = vault_address(&env);
+    let vault_before = lamports(&mut env.ctx, vault).await;
+    deposit_bond(&mut env).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, vault).await, vault_before + BOND);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(deposit_bond(&mut env).await, escrow_err(EscrowError::BondPosted));
+
+    let taker_before = lamports(&mut env.ctx, env.taker.pubkey()).await;
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    withdraw(&mut env).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, env.taker.pubkey()).await, taker_before + AMOUNT + BOND);
+}
+
+#[tokio::test]
+async fn taker_bond_forfeited_on_expiry() {
+    let mut env = setup(0).await;
+    let expiry = 1_000_000;
+    warp_past(&mut env, expiry - 10).await;
+    initialize_bonded(&mut env, expiry).await;
+    deposit_bond(&mut env).await.unwrap();
+
+    warp_past(&mut env, expiry).await;
+    let initializer = env.initializer.insecure_clone();
+    let before      = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = instruction::refund(&env.program_id, &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    // The fee payer covers the transaction, so the initializer nets both legs
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, before + AMOUNT + BOND);
+    assert!(!bool::from(escrow_state(&mut env).await.bond_held));
+}
+
+#[tokio::test]
+async fn set_taker_redirects_escrow() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+
+    // Before funding the initializer and the new taker decide alone
+    let second = Keypair::new();
+    let ix = instruction::set_taker(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &second.pubkey(), ID);
+    send(&mut env, ix, &[&initializer, &second]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.taker_pubkey, second.pubkey());
+    env.taker = second.insecure_clone();
+    deposit(&mut env).await.unwrap();
+
+    let third = Keypair::new();
+    let ix = instruction::set_taker(&env.program_id, &initializer.pubkey(), &second.pubkey(), &third.pubkey(), ID);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &third]).await, InstructionError::MissingRequiredSignature);
+    let ix = instruction::set_taker_funded(
+        &env.program_id, &initializer.pubkey(), &second.pubkey(), &third.pubkey(), ID,
+    );
+    send(&mut env, ix, &[&initializer, &second, &third]).await.unwrap();
+
+    env.taker = third;
+    withdraw(&mut env).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, env.taker.pubkey()).await, AMOUNT);
+}
+
+#[tokio::test]
+async fn update_amount_before_funding() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::deposit_additional(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, AMOUNT / 2);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+
+    let update = |env: &Env, new_amount| {
+        instruction::update_amount(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, new_amount)
+    };
+    // Below what is already deposited
+    let ix = update(&env, AMOUNT / 4);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::InvalidAmount));
+
+    let ix = update(&env, AMOUNT / 2);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.amount, state.status), (AMOUNT / 2, EscrowStatus::Funded));
+
+    let ix = update(&env, AMOUNT);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::AlreadyFunded));
+}
+
+#[tokio::test]
+async fn extend_deadline_within_horizon() {
+    let mut env = setup(0).await;
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, now + 60, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+
+    let extend = |env: &Env, new_expiry| {
+        instruction::extend_deadline(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, new_expiry)
+    };
+    for new_expiry in [now + 60, now + DEFAULT_MAX_EXPIRY_HORIZON + 1] {
+        let ix = extend(&env, new_expiry);
+        assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::InvalidExpiry));
+    }
+    let ix = extend(&env, now + DEFAULT_MAX_EXPIRY_HORIZON);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.expiry_ts, now + DEFAULT_MAX_EXPIRY_HORIZON);
+}
+
+#[tokio::test]
+async fn allowlisted_member_claims_open_escrow() {
+    let mut env = setup(0).await;
+    let initializer = env.initializer.insecure_clone();
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &Pubkey::default(), AMOUNT, ID, 0, None,
+    );
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let (vendor_a, vendor_b) = (Keypair::new(), Keypair::new());
+    let ix = instruction::set_allowed_takers(
+        &env.program_id, &initializer.pubkey(), ID, vec![vendor_a.pubkey(), vendor_b.pubkey()],
+    );
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &Pubkey::default(), ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+
+    let stranger = Keypair::new();
+    let ix = instruction::accept_escrow(&env.program_id, &stranger.pubkey(), &initializer.pubkey(), ID);
+    assert_ix_err(send(&mut env, ix, &[&stranger]).await, escrow_err(EscrowError::NotAllowed));
+    let ix = instruction::claim_allowed(&env.program_id, &stranger.pubkey(), &initializer.pubkey(), &env.treasury, ID);
+    assert_ix_err(send(&mut env, ix, &[&stranger]).await, escrow_err(EscrowError::NotAllowed));
+
+    let ix = instruction::claim_allowed(&env.program_id, &vendor_b.pubkey(), &initializer.pubkey(), &env.treasury, ID);
+    let ix = instruction::with_stats(&env.program_id, ix, &[initializer.pubkey(), vendor_b.pubkey()]);
+    send(&mut env, ix, &[&vendor_b]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.taker_pubkey), (EscrowStatus::Released, vendor_b.pubkey()));
+    assert_eq!(lamports(&mut env.ctx, vendor_b.pubkey()).await, AMOUNT);
+}
+
+#[tokio::test]
+async fn distribution_pays_each_leaf_once() {
+    let mut env = setup(0).await;
+    let initializer = env.initializer.insecure_clone();
+    let claimants   = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let amounts     = [AMOUNT / 2, AMOUNT / 4, AMOUNT / 4];
+    let leaves: Vec<_> = (0..3)
+        .map(|i| merkle::distribution_leaf(i as u32, &claimants[i].pubkey(), amounts[i]))
+        .collect();
+    let pair = merkle::hash_nodes(&leaves[0], &leaves[1]);
+    let root = merkle::hash_nodes(&pair, &leaves[2]);
+
+    let ix = instruction::initialize_distribution(&env.program_id, &initializer.pubkey(), ID, AMOUNT, root, 3, 0);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &Pubkey::default(), ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+
+    let claim = |env: &Env, i: usize, amount, proof| {
+        instruction::claim_distribution(
+            &env.program_id, &claimants[i].pubkey(), &initializer.pubkey(), &env.treasury, ID, i as u32, amount, proof,
+        )
+    };
+    let ix = claim(&env, 0, AMOUNT, vec![leaves[1], leaves[2]]);
+    assert_ix_err(send(&mut env, ix, &[&claimants[0]]).await, escrow_err(EscrowError::InvalidProof));
+    let ix = claim(&env, 0, amounts[0], vec![leaves[1], leaves[2]]);
+    send(&mut env, ix, &[&claimants[0]]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, claimants[0].pubkey()).await, amounts[0]);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let ix = claim(&env, 0, amounts[0], vec![leaves[1], leaves[2]]);
+    assert_ix_err(send(&mut env, ix, &[&claimants[0]]).await, escrow_err(EscrowError::AlreadyClaimed));
+
+    let ix = claim(&env, 2, amounts[2], vec![pair]);
+    send(&mut env, ix, &[&claimants[2]]).await.unwrap();
+    let ix = claim(&env, 1, amounts[1], vec![leaves[0], leaves[2]]);
+    let ix = instruction::with_stats(&env.program_id, ix, &[env.initializer.pubkey(), claimants[1].pubkey()]);
+    send(&mut env, ix, &[&claimants[1]]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.remaining), (EscrowStatus::Released, 0));
+
+    let ix = instruction::close_distribution(&env.program_id, &initializer.pubkey(), ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+}
+
+#[tokio::test]
+async fn withdraw_pays_each_split_its_share() {
+    let mut env = setup(100).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let recipients = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+    let splits = recipients
+        .iter()
+        .zip([5_000, 3_000, 2_000])
+        .map(|(recipient, bps)| Split { recipient: *recipient, bps })
+        .collect();
+    let ix = instruction::set_splits(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, splits);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let ix = instruction::withdraw_partial(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID, AMOUNT / 2);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::Split));
+    let withdraw = |env: &Env, recipients: &[Pubkey]| {
+        let ix = instruction::withdraw_split(
+            &env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID, recipients,
+        );
+        instruction::with_features(&env.program_id, ix)
+    };
+    let ix = withdraw(&env, &[recipients[1], recipients[0], recipients[2]]);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::InvalidSplit));
+
+    let ix = withdraw(&env, &recipients);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let net = AMOUNT - AMOUNT / 100;
+    for (recipient, share) in recipients.into_iter().zip([net / 2, net * 3 / 10, net / 5]) {
+        assert_eq!(lamports(&mut env.ctx, recipient).await, share);
+    }
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+}
+
+#[tokio::test]
+async fn withdraw_pays_referrer_its_share_of_the_fee() {
+    let mut env = setup(100).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 100, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        2_500, 0, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    let referrer = funded_keypair(&mut env).await.pubkey();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize_referred(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None, &referrer,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.referrer, referrer);
+    deposit(&mut env).await.unwrap();
+
+    let withdraw = |env: &Env, referrer: Pubkey| {
+        instruction::withdraw_referred(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, &referrer, ID)
+    };
+    let ix = withdraw(&env, Pubkey::new_unique());
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::WrongReferrer));
+    let referrer_before = lamports(&mut env.ctx, referrer).await;
+    let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+    let ix = instruction::with_features(&env.program_id, withdraw(&env, referrer));
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let fee = AMOUNT / 100;
+    assert_eq!(lamports(&mut env.ctx, referrer).await - referrer_before, fee / 4);
+    assert_eq!(lamports(&mut env.ctx, env.treasury).await - treasury_before, fee - fee / 4);
+}
+
+#[tokio::test]
+async fn migrate_state_upgrades_v1_escrow() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    // Rewrite the escrow as a v1 account, whose header has no version and
+    // stops before the fields of v2
+    let escrow = escrow_address(&env);
+    let mut account = env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+    account.data.truncate(ESCROW_STATE_LENS[0]);
+    account.data[std::mem::offset_of!(EscrowState, version)] = 0;
+    env.ctx.set_account(&escrow, &account.into());
+    assert!(deposit(&mut env).await.is_err());
+
+    let payer = env.ctx.payer.pubkey();
+    let ix = instruction::migrate_state(&env.program_id, &payer, &escrow);
+    send(&mut env, ix, &[]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.version, ESCROW_STATE_VERSION);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let ix = instruction::migrate_state(&env.program_id, &payer, &escrow);
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::StateVersion));
+}
+
+#[tokio::test]
+async fn migrate_state_upgrades_v2_escrow() {
+    let mut env = setup(0).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    // A v2 account stops before the auction
+    let escrow = escrow_address(&env);
+    let mut account = env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+    account.data.truncate(ESCROW_STATE_LENS[1]);
+    account.data[std::mem::offset_of!(EscrowState, version)] = 2;
+    env.ctx.set_account(&escrow, &account.into());
+    assert!(deposit(&mut env).await.is_err());
+
+    let payer = env.ctx.payer.pubkey();
+    let ix = instruction::migrate_state(&env.program_id, &payer, &escrow);
+    send(&mut env, ix, &[]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.version, state.auction), (ESCROW_STATE_VERSION, Auction::default()));
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    deposit(&mut env).await.unwrap();
+}
+
+#[tokio::test]
+async fn milestones_pay_only_approved_tranches_less_the_fee() {
+    let mut env = setup(250).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let tranche = AMOUNT / 64;
+    let ix = instruction::initialize_milestones(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, 0, vec![tranche; 65], None,
+    );
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::InvalidMilestone));
+    let ix = instruction::initialize_milestones(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, 0, vec![tranche; 64], None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    // Full at 64
+    let ix = instruction::add_milestone(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, tranche);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::InvalidMilestone));
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.amount, state.milestone_count), (AMOUNT, 64));
+    deposit(&mut env).await.unwrap();
+
+    let ix = instruction::withdraw_milestones(&env.program_id, &taker.pubkey(), &initializer.pubkey(), &env.treasury, ID);
+    let ix = with_party_stats(&env, instruction::with_features(&env.program_id, ix));
+    assert_ix_err(send(&mut env, ix.clone(), &[&taker]).await, escrow_err(EscrowError::NothingApproved));
+    let program_id = env.program_id;
+    let approve    = |index| instruction::approve_milestone(&program_id, &initializer.pubkey(), ID, index);
+    assert_ix_err(send(&mut env, approve(64), &[&initializer]).await, escrow_err(EscrowError::InvalidMilestone));
+    for index in [0, 63] {
+        send(&mut env, approve(index), &[&initializer]).await.unwrap();
+    }
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(send(&mut env, approve(63), &[&initializer]).await, escrow_err(EscrowError::InvalidMilestone));
+
+    let taker_before    = lamports(&mut env.ctx, taker.pubkey()).await;
+    let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+    send(&mut env, ix.clone(), &[&taker]).await.unwrap();
+    let fee = 2 * tranche * 250 / 10_000;
+    assert_eq!(lamports(&mut env.ctx, env.treasury).await, treasury_before + fee);
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + 2 * tranche - fee);
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.remaining), (EscrowStatus::Funded, AMOUNT - 2 * tranche));
+
+    // Released tranches don't pay twice
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::NothingApproved));
+}
+
+#[tokio::test]
+async fn add_milestone_grows_the_escrow() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize_milestones(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, 0, vec![AMOUNT / 2], None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::add_milestone(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, AMOUNT / 4);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+
+    let state   = escrow_state(&mut env).await;
+    let account = env.ctx.banks_client.get_account(escrow_address(&env)).await.unwrap().unwrap();
+    assert_eq!((state.amount, state.milestone_count), (AMOUNT * 3 / 4, 2));
+    assert_eq!(account.data.len(), state.space());
+    deposit(&mut env).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.deposited), (EscrowStatus::Funded, AMOUNT * 3 / 4));
+}
+
+#[tokio::test]
+async fn initialize_with_nonce_records_and_echoes_it() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize_with_nonce(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None, 42,
+    );
+    let ix = instruction::with_event_cpi(&env.program_id, ix);
+    let tx = Transaction::new_signed_with_payer(
+        &[ix], Some(&env.ctx.payer.pubkey()), &[&env.ctx.payer, &initializer, &taker], env.ctx.last_blockhash,
+    );
+    let keys    = tx.message.account_keys.clone();
+    let details = env.ctx.banks_client.simulate_transaction(tx.clone()).await.unwrap().simulation_details.unwrap();
+    let inner   = &details.inner_instructions.unwrap()[0];
+    let data    = inner
+        .iter()
+        .find(|ix| keys[ix.instruction.program_id_index as usize] == env.program_id)
+        .map(|ix| EscrowInstruction::try_from_slice(&ix.instruction.data).unwrap())
+        .unwrap();
+    let EscrowInstruction::EmitEvent { data } = data else { panic!("unexpected self-CPI {data:?}") };
+    assert_eq!(EscrowInitialized::decode(&data).map(|event| event.nonce), Some(42));
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.nonce, 42);
+
+    // Plain Initialize leaves it unset
+    initialize_id(&mut env, AMOUNT, ID + 1).await.unwrap();
+    let (escrow, _) = find_escrow_address(&env.program_id, &initializer.pubkey(), ID + 1);
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+    assert_eq!(EscrowState::unpack(&account.data).unwrap().nonce, 0);
+}
+
+#[tokio::test]
+async fn initialize_with_reference_records_and_echoes_it() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let reference = [7; 32];
+    let ix = instruction::initialize_with_reference(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None, reference,
+    );
+    let ix = instruction::with_event_cpi(&env.program_id, ix);
+    let tx = Transaction::new_signed_with_payer(
+        &[ix], Some(&env.ctx.payer.pubkey()), &[&env.ctx.payer, &initializer, &taker], env.ctx.last_blockhash,
+    );
+    let keys    = tx.message.account_keys.clone();
+    let details = env.ctx.banks_client.simulate_transaction(tx.clone()).await.unwrap().simulation_details.unwrap();
+    let data    = details.inner_instructions.unwrap()[0]
+        .iter()
+        .find(|ix| keys[ix.instruction.program_id_index as usize] == env.program_id)
+        .map(|ix| EscrowInstruction::try_from_slice(&ix.instruction.data).unwrap())
+        .unwrap();
+    let EscrowInstruction::EmitEvent { data } = data else { panic!("unexpected self-CPI {data:?}") };
+    assert_eq!(EscrowInitialized::decode(&data).map(|event| event.reference), Some(reference));
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.reference, state.nonce), (reference, 0));
+}
+
+#[tokio::test]
+async fn set_metadata_fixes_the_terms_once() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let set = |env: &Env, uri: &str, hash| {
+        instruction::set_metadata(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, uri.into(), hash)
+    };
+
+    let ix = set(&env, "", [1; 32]);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::InvalidTerms));
+    let ix = set(&env, &"a".repeat(MAX_METADATA_URI_LEN + 1), [1; 32]);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::InvalidTerms));
+
+    let ix = set(&env, "ipfs://terms", [1; 32]);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    let account = env.ctx.banks_client.get_account(escrow_address(&env)).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), state.space());
+    // The URI is the last list, so it ends the account
+    let uri = &account.data[account.data.len() - usize::from(state.metadata_uri_len)..];
+    assert_eq!((uri, state.metadata_hash), (&b"ipfs://terms"[..], [1; 32]));
+
+    // Agreed terms can't be swapped
+    let ix = set(&env, "ipfs://other", [2; 32]);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::TermsAlreadySet));
+}
+
+#[tokio::test]
+async fn withdraw_with_memo_logs_the_settlement() {
+    let mut env = setup(100).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::withdraw(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID);
+    let ix = instruction::with_features(&env.program_id, ix);
+    let ix = instruction::with_memo(with_party_stats(&env, ix));
+    let tx = Transaction::new_signed_with_payer(
+        &[ix], Some(&env.ctx.payer.pubkey()), &[&env.ctx.payer, &initializer, &taker], env.ctx.last_blockhash,
+    );
+    let details = env.ctx.banks_client.simulate_transaction(tx.clone()).await.unwrap().simulation_details.unwrap();
+    let memo    = format!("flo escrow {} #{ID} released", escrow_address(&env));
+    assert!(details.logs.iter().any(|log| log.contains(&memo)), "no memo in {:?}", details.logs);
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+}
+
+#[tokio::test]
+async fn withdraw_records_event_in_self_cpi() {
+    let mut env = setup(100).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::withdraw(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID);
+    let ix = instruction::with_features(&env.program_id, ix);
+    let ix = instruction::with_event_cpi(&env.program_id, with_party_stats(&env, ix));
+    let tx = Transaction::new_signed_with_payer(
+        &[ix], Some(&env.ctx.payer.pubkey()), &[&env.ctx.payer, &initializer, &taker], env.ctx.last_blockhash,
+    );
+    let keys    = tx.message.account_keys.clone();
+    let details = env.ctx.banks_client.simulate_transaction(tx.clone()).await.unwrap().simulation_details.unwrap();
+    let inner   = &details.inner_instructions.unwrap()[0];
+    let data    = inner
+        .iter()
+        .find(|ix| keys[ix.instruction.program_id_index as usize] == env.program_id)
+        .map(|ix| EscrowInstruction::try_from_slice(&ix.instruction.data).unwrap())
+        .unwrap();
+    let EscrowInstruction::EmitEvent { data } = data else { panic!("unexpected self-CPI {data:?}") };
+    let fee = AMOUNT / 100;
+    assert_eq!(
+        EscrowReleased::decode(&data),
+        Some(EscrowReleased { escrow: escrow_address(&env), recipient: taker.pubkey(), amount: AMOUNT - fee, fee }),
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+
+    // Nobody else can sign for the event authority
+    let (authority, _) = find_event_authority_address(&env.program_id);
+    let ix = Instruction::new_with_borsh(
+        env.program_id,
+        &EscrowInstruction::EmitEvent { data },
+        vec![AccountMeta::new_readonly(authority, false)],
+    );
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::EventAuthority));
+}
+
+#[tokio::test]
+async fn get_state_returns_escrow_state() {
+    let mut env = setup(100).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let ix = instruction::get_state(&env.program_id, &escrow_address(&env));
+    let tx = Transaction::new_signed_with_payer(
+        &[ix], Some(&env.ctx.payer.pubkey()), &[&env.ctx.payer], env.ctx.last_blockhash,
+    );
+    let details = env.ctx.banks_client.simulate_transaction(tx).await.unwrap().simulation_details.unwrap();
+    let returned = details.return_data.unwrap();
+    assert_eq!(returned.program_id, env.program_id);
+    let account = env.ctx.banks_client.get_account(escrow_address(&env)).await.unwrap().unwrap();
+    assert_eq!(returned.data, account.data);
+    let state = EscrowState::unpack(&returned.data).unwrap();
+    assert_eq!((state.status, state.deposited), (EscrowStatus::Funded, AMOUNT));
+}
+
+#[tokio::test]
+async fn allowed_caller_restricts_cpi() {
+    let (frontend, rogue) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut env = setup_with(0, &[frontend, rogue]).await;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let get_state = instruction::get_state(&env.program_id, &escrow_address(&env));
+    let checked   = instruction::with_caller_accounts(&env.program_id, get_state.clone());
+
+    // Without a restriction any program may call, but only with the accounts
+    send(&mut env, via_frontend(&rogue, checked.clone()), &[]).await.unwrap();
+    assert_ix_err(send(&mut env, via_frontend(&rogue, get_state), &[]).await, escrow_err(EscrowError::CallerNotAllowed));
+
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &frontend, &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(send(&mut env, via_frontend(&rogue, checked.clone()), &[]).await, escrow_err(EscrowError::CallerNotAllowed));
+    send(&mut env, via_frontend(&frontend, checked), &[]).await.unwrap();
+    // Leaving the accounts off doesn't get around it
+    let initializer = env.initializer.insecure_clone();
+    let ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &env.taker.pubkey(), ID);
+    assert_ix_err(send(&mut env, via_frontend(&rogue, ix), &[&initializer]).await, escrow_err(EscrowError::CallerNotAllowed));
+
+    // Direct calls are never restricted
+    deposit(&mut env).await.unwrap();
+}
+
+#[tokio::test]
+async fn allowed_caller_covers_instructions_that_take_no_config() {
+    let (frontend, rogue) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut env = setup_with(0, &[frontend, rogue]).await;
+    let (initializer, taker, arbiter) = (env.initializer.insecure_clone(), env.taker.insecure_clone(), Keypair::new());
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let ix = instruction::initialize(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID + 1, 0, Some(&arbiter.pubkey()),
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID + 1);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let ix = instruction::dispute(&env.program_id, &taker.pubkey(), &initializer.pubkey(), ID + 1);
+    let ix = instruction::with_stats(&env.program_id, ix, &[initializer.pubkey(), taker.pubkey()]);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &frontend, &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+
+    let cancel  = with_party_stats(&env, instruction::cancel(&env.program_id, &initializer.pubkey(), ID));
+    let resolve = with_party_stats(&env, instruction::resolve(
+        &env.program_id, &arbiter.pubkey(), &initializer.pubkey(), &taker.pubkey(), ID + 1, true,
+    ));
+    for (ix, signer) in [(cancel, &initializer), (resolve, &arbiter)] {
+        let checked = instruction::with_caller_accounts(&env.program_id, ix.clone());
+        assert_ix_err(send(&mut env, via_frontend(&rogue, ix), &[signer]).await, escrow_err(EscrowError::CallerNotAllowed));
+        assert_ix_err(send(&mut env, via_frontend(&rogue, checked), &[signer]).await, escrow_err(EscrowError::CallerNotAllowed));
+    }
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Created);
+}
+
+#[tokio::test]
+async fn program_derived_parties_sign_through_cpi() {
+    let (frontend, rogue) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut env = setup_with(0, &[frontend, rogue]).await;
+    let (initializer, taker) = (party_address(&frontend, 0).0, party_address(&frontend, 1).0);
+    for party in [initializer, taker] {
+        let account = Account { lamports: 10 * LAMPORTS_PER_SOL, ..Account::default() };
+        env.ctx.set_account(&party, &account.into());
+    }
+    let program_id = env.program_id;
+    let cpi = |ix| via_frontend(&frontend, instruction::with_caller_accounts(&program_id, ix));
+
+    let ix = instruction::initialize(&program_id, &initializer, &taker, AMOUNT, ID, 0, None);
+    send(&mut env, cpi(ix), &[]).await.unwrap();
+    send(&mut env, cpi(instruction::deposit(&program_id, &initializer, &taker, ID)), &[]).await.unwrap();
+    let before = lamports(&mut env.ctx, taker).await;
+    let ix     = instruction::withdraw(&program_id, &initializer, &taker, &env.treasury, ID);
+    send(&mut env, cpi(instruction::with_stats(&program_id, ix, &[initializer, taker])), &[]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker).await, before + AMOUNT);
+
+    // Another program can't sign for the frontend's PDAs
+    let ix = instruction::initialize(&program_id, &initializer, &taker, AMOUNT, ID + 1, 0, None);
+    let ix = via_frontend(&rogue, instruction::with_caller_accounts(&program_id, ix));
+    assert_ix_err(send(&mut env, ix, &[]).await, InstructionError::MissingRequiredSignature);
+}
+
+#[tokio::test]
+async fn wrapped_sol_escrow_takes_and_pays_lamports() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    let ix = instruction::initialize_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, AMOUNT, ID, 0, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::deposit_sol(&program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_PROGRAM_ID, ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.deposited, AMOUNT);
+
+    let before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::withdraw_sol(&program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_PROGRAM_ID, ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, before + AMOUNT);
+
+    let ix = instruction::close_token(&program_id, &initializer.pubkey(), &SPL_TOKEN_PROGRAM_ID, ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert!(env.ctx.banks_client.get_account(escrow_address(&env)).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn spl_token_escrow_round_trip_closes_the_vault() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id    = env.program_id;
+    let token_program = SPL_TOKEN_PROGRAM_ID;
+    let amount        = 1_000;
+    let mint              = create_mint(&mut env, &token_program, None).await;
+    let initializer_token = create_token_account(&mut env, &token_program, &mint, &initializer.pubkey(), amount).await;
+    let taker_token       = create_token_account(&mut env, &token_program, &mint, &taker.pubkey(), 0).await;
+
+    let ix = instruction::initialize_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &mint, &token_program, amount, ID, 0, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let vault = vault_address(&env);
+    assert_eq!(env.ctx.banks_client.get_account(vault).await.unwrap().unwrap().owner, token_program);
+    let ix = instruction::deposit_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_token, &mint, &token_program, ID,
+    );
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert_eq!(token_amount(&mut env, initializer_token).await, 0);
+    assert_eq!(token_amount(&mut env, vault).await, amount);
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.mint, state.deposited), (EscrowStatus::Funded, mint, amount));
+
+    let ix = instruction::withdraw_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &taker_token, &mint, &token_program, ID,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(token_amount(&mut env, taker_token).await, amount);
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+
+    // Close hands the initializer the rent of the escrow and the emptied
+    // vault, and what the registry no longer needs
+    let escrow   = escrow_address(&env);
+    let registry = find_registry_address(&program_id, &initializer.pubkey()).0;
+    let reclaims = lamports(&mut env.ctx, escrow).await + lamports(&mut env.ctx, vault).await;
+    let before   = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let registry_before = lamports(&mut env.ctx, registry).await;
+    let ix = instruction::close_token(&program_id, &initializer.pubkey(), &token_program, ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let shrunk = registry_before - lamports(&mut env.ctx, registry).await;
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, before + reclaims + shrunk);
+    assert!(env.ctx.banks_client.get_account(vault).await.unwrap().is_none());
+    assert_eq!(lamports(&mut env.ctx, escrow).await, 0);
+}
+
+#[tokio::test]
+async fn nft_escrow_takes_only_a_verified_member_of_its_collection() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id    = env.program_id;
+    let token_program = SPL_TOKEN_PROGRAM_ID;
+    let collection    = Pubkey::new_unique();
+    let mint              = create_mint(&mut env, &token_program, None).await;
+    let initializer_token = create_token_account(&mut env, &token_program, &mint, &initializer.pubkey(), 1).await;
+    let taker_token       = create_token_account(&mut env, &token_program, &mint, &taker.pubkey(), 0).await;
+
+    let ix = instruction::initialize_nft(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &mint, &token_program, &collection, ID, 0, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.collection, collection);
+    let deposit_nft = instruction::deposit_nft(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_token, &mint, &token_program, ID,
+    );
+
+    // The NFT must come with its metadata, through DepositNft
+    let ix = instruction::deposit_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_token, &mint, &token_program, ID,
+    );
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, escrow_err(EscrowError::InvalidMetadata));
+    assert_ix_err(send(&mut env, deposit_nft.clone(), &[&initializer]).await, escrow_err(EscrowError::InvalidMetadata));
+
+    // Naming the collection isn't enough until the collection verifies it
+    set_nft_metadata(&mut env.ctx, &mint, &collection, false);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(send(&mut env, deposit_nft.clone(), &[&initializer]).await, escrow_err(EscrowError::CollectionMismatch));
+    set_nft_metadata(&mut env.ctx, &mint, &Pubkey::new_unique(), true);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(send(&mut env, deposit_nft.clone(), &[&initializer]).await, escrow_err(EscrowError::CollectionMismatch));
+
+    set_nft_metadata(&mut env.ctx, &mint, &collection, true);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    send(&mut env, deposit_nft, &[&initializer]).await.unwrap();
+    let vault = vault_address(&env);
+    assert_eq!(token_amount(&mut env, vault).await, 1);
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Funded);
+
+    let ix = instruction::withdraw_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &taker_token, &mint, &token_program, ID,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(token_amount(&mut env, taker_token).await, 1);
+}
+
+#[tokio::test]
+async fn transfer_fee_mint_escrow_records_what_the_vault_received() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id    = env.program_id;
+    let token_program = spl_token_2022::id();
+    let amount        = 1_000_000;
+    // 1% of every transfer is withheld
+    let mint              = create_mint(&mut env, &token_program, Some(100)).await;
+    let initializer_token = create_token_account(&mut env, &token_program, &mint, &initializer.pubkey(), amount).await;
+    let taker_token       = create_token_account(&mut env, &token_program, &mint, &taker.pubkey(), 0).await;
+
+    let ix = instruction::initialize_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &mint, &token_program, amount, ID, 0, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::deposit_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_token, &mint, &token_program, ID,
+    );
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.amount, state.deposited, state.remaining), (amount, amount - amount / 100, amount - amount / 100));
+    let vault = vault_address(&env);
+    assert_eq!(token_amount(&mut env, vault).await, state.deposited);
+
+    // The release is charged the fee again, on what the vault holds
+    let ix = instruction::withdraw_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &taker_token, &mint, &token_program, ID,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(token_amount(&mut env, taker_token).await, state.deposited - state.deposited / 100);
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.remaining), (EscrowStatus::Released, 0));
+}
+
+#[tokio::test]
+async fn swap_crosses_both_legs_or_hands_the_taker_leg_back() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    // The legs may sit in different token programs
+    let (token_program, taker_token_program) = (SPL_TOKEN_PROGRAM_ID, spl_token_2022::id());
+    let (amount, taker_amount) = (1_000, 250);
+    let mint       = create_mint(&mut env, &token_program, None).await;
+    let taker_mint = create_mint(&mut env, &taker_token_program, None).await;
+    let initializer_token   = create_token_account(&mut env, &token_program, &mint, &initializer.pubkey(), 2 * amount).await;
+    let initializer_receive = create_token_account(&mut env, &taker_token_program, &taker_mint, &initializer.pubkey(), 0).await;
+    let taker_token   = create_token_account(&mut env, &taker_token_program, &taker_mint, &taker.pubkey(), 2 * taker_amount).await;
+    let taker_receive = create_token_account(&mut env, &token_program, &mint, &taker.pubkey(), 0).await;
+
+    for id in [ID, ID + 1] {
+        let ix = instruction::initialize_swap(
+            &program_id, &initializer.pubkey(), &taker.pubkey(), &mint, &token_program, amount, &taker_mint, taker_amount, id, 0,
+        );
+        send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+        let ix = instruction::deposit_token(
+            &program_id, &initializer.pubkey(), &taker.pubkey(), &initializer_token, &mint, &token_program, id,
+        );
+        send(&mut env, ix, &[&initializer]).await.unwrap();
+        let ix = instruction::deposit_swap(
+            &program_id, &taker.pubkey(), &initializer.pubkey(), &taker_token, &taker_mint, &taker_token_program, id,
+        );
+        send(&mut env, ix, &[&taker]).await.unwrap();
+    }
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.remaining, state.taker_deposited), (EscrowStatus::Funded, amount, taker_amount));
+
+    // Either party may execute; the taker gets its vault rent back
+    let (taker_vault, _) = find_taker_vault_address(&program_id, &escrow_address(&env));
+    let vault_rent     = lamports(&mut env.ctx, taker_vault).await;
+    let taker_lamports = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::execute_swap(
+        &program_id, &initializer.pubkey(), &initializer.pubkey(), &taker.pubkey(), &initializer_receive, &taker_receive,
+        &mint, &token_program, &taker_mint, &taker_token_program, ID,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert_eq!(token_amount(&mut env, taker_receive).await, amount);
+    assert_eq!(token_amount(&mut env, initializer_receive).await, taker_amount);
+    assert!(env.ctx.banks_client.get_account(taker_vault).await.unwrap().is_none());
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_lamports + vault_rent);
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.remaining, state.taker_deposited), (EscrowStatus::Released, 0, 0));
+
+    // Until it is executed, the taker can take their leg back, and then it can't be
+    let ix = instruction::reclaim_swap(
+        &program_id, &taker.pubkey(), &initializer.pubkey(), &taker_token, &taker_mint, &taker_token_program, ID + 1,
+    );
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    assert_eq!(token_amount(&mut env, taker_token).await, taker_amount);
+    let (escrow, _)      = find_escrow_address(&program_id, &initializer.pubkey(), ID + 1);
+    let (taker_vault, _) = find_taker_vault_address(&program_id, &escrow);
+    assert!(env.ctx.banks_client.get_account(taker_vault).await.unwrap().is_none());
+    let ix = instruction::execute_swap(
+        &program_id, &taker.pubkey(), &initializer.pubkey(), &taker.pubkey(), &initializer_receive, &taker_receive,
+        &mint, &token_program, &taker_mint, &taker_token_program, ID + 1,
+    );
+    let ix = with_party_stats(&env, ix);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::TakerDeposit));
+}
+
+#[tokio::test]
+async fn withdraw_token_creates_the_taker_ata() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    let ix = instruction::initialize_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, AMOUNT, ID, 0, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::deposit_sol(&program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_PROGRAM_ID, ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+
+    let ata = find_associated_token_address(&taker.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID);
+    assert!(env.ctx.banks_client.get_account(ata).await.unwrap().is_none());
+    let ix = instruction::withdraw_token_to_ata(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, ID,
+    );
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let account = env.ctx.banks_client.get_account(ata).await.unwrap().unwrap();
+    let token   = StateWithExtensions::<TokenAccount>::unpack(&account.data).unwrap().base;
+    assert_eq!((token.owner, token.amount), (taker.pubkey(), AMOUNT));
+}
+
+#[tokio::test]
+async fn stake_account_authorities_move_through_escrow() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    let stake      = Keypair::new();
+    let authorized = Authorized::auto(&initializer.pubkey());
+    let ixs = stake_instruction::create_account(
+        &env.ctx.payer.pubkey(), &stake.pubkey(), &authorized, &Lockup::default(), AMOUNT,
+    );
+    let tx = Transaction::new_signed_with_payer(
+        &ixs, Some(&env.ctx.payer.pubkey()), &[&env.ctx.payer, &stake], env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let ix = instruction::initialize_stake(&program_id, &initializer.pubkey(), &taker.pubkey(), &stake.pubkey(), ID, 0);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.stake_account), (EscrowStatus::Funded, stake.pubkey()));
+    let held = lamports(&mut env.ctx, stake.pubkey()).await;
+    assert_eq!(stake_authorized(&mut env, stake.pubkey()).await, Authorized::auto(&escrow_address(&env)));
+
+    // The escrow PDA must keep its authorities until WithdrawStake
+    assert_ix_err(withdraw(&mut env).await, escrow_err(EscrowError::StakeEscrow));
+
+    let ix = instruction::withdraw_stake(&program_id, &initializer.pubkey(), &taker.pubkey(), &stake.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(stake_authorized(&mut env, stake.pubkey()).await, Authorized::auto(&taker.pubkey()));
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+    assert_eq!(lamports(&mut env.ctx, stake.pubkey()).await, held);
+}
+
+async fn stake_authorized(env: &mut Env, stake: Pubkey) -> Authorized {
+    let account = env.ctx.banks_client.get_account(stake).await.unwrap().unwrap();
+    match account.deserialize_data::<StakeStateV2>().unwrap() {
+        StakeStateV2::Initialized(meta) => meta.authorized,
+        state => panic!("unexpected stake state {state:?}"),
+    }
+}
+
+#[tokio::test]
+async fn stake_pool_escrow_pays_the_yield_to_the_beneficiary() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    let pool = StakePool {
+        address:             Pubkey::new_unique(),
+        reserve_stake:       Pubkey::new_unique(),
+        pool_mint:           Pubkey::new_unique(),
+        manager_fee_account: Pubkey::new_unique(),
+        token_program:       SPL_TOKEN_PROGRAM_ID,
+    };
+    let mut mint = vec![0; Mint::LEN];
+    Mint { mint_authority: COption::Some(pool.withdraw_authority()), decimals: 9, is_initialized: true, ..Mint::default() }
+        .pack_into_slice(&mut mint);
+    let mint = Account { lamports: LAMPORTS_PER_SOL, data: mint, owner: SPL_TOKEN_PROGRAM_ID, ..Account::default() };
+    env.ctx.set_account(&pool.pool_mint, &mint.into());
+    let reserve = Account { lamports: 10 * LAMPORTS_PER_SOL, owner: STAKE_POOL_PROGRAM_ID, ..Account::default() };
+    env.ctx.set_account(&pool.reserve_stake, &reserve.into());
+    let beneficiary = Pubkey::new_unique();
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &Pubkey::default(), &beneficiary, 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+
+    let ix = instruction::initialize_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &pool.pool_mint, &SPL_TOKEN_PROGRAM_ID, AMOUNT, ID, 0, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let ix = instruction::deposit_staked(&program_id, &initializer.pubkey(), &taker.pubkey(), &pool, ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.deposited, state.stake_pool), (AMOUNT, pool.address));
+
+    // The pool tokens only leave through WithdrawStaked
+    let ix = instruction::withdraw_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &Pubkey::new_unique(), &pool.pool_mint, &SPL_TOKEN_PROGRAM_ID, ID,
+    );
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::StakeEscrow));
+
+    let before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::withdraw_staked(&program_id, &initializer.pubkey(), &taker.pubkey(), &beneficiary, &pool, ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, before + AMOUNT);
+    assert_eq!(lamports(&mut env.ctx, beneficiary).await, AMOUNT / 10);
+}
+
+#[tokio::test]
+async fn crank_refund_pays_bounty_after_expiry() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 50, false, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    let expiry = 1_000_000;
+    warp_past(&mut env, expiry - 10).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize(&env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, expiry, None);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let cranker = funded_keypair(&mut env).await;
+    let ix = instruction::crank_refund(&env.program_id, &cranker.pubkey(), &initializer.pubkey(), ID);
+    assert_ix_err(send(&mut env, ix.clone(), &[&cranker]).await, escrow_err(EscrowError::NotExpired));
+
+    warp_past(&mut env, expiry).await;
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let cranker_before     = lamports(&mut env.ctx, cranker.pubkey()).await;
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&cranker]).await.unwrap();
+    let bounty = AMOUNT / 200;
+    assert_eq!(lamports(&mut env.ctx, cranker.pubkey()).await, cranker_before + bounty);
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, initializer_before + AMOUNT - bounty);
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Cancelled);
+}
+
+#[tokio::test]
+async fn initialize_batch_creates_each_escrow() {
+    let mut env = setup(0).await;
+    let initializer = env.initializer.insecure_clone();
+    let takers = [funded_keypair(&mut env).await, funded_keypair(&mut env).await, funded_keypair(&mut env).await];
+    let items: Vec<_> = takers.iter().zip(0..).map(|(t, id)| (t.pubkey(), AMOUNT + id, id)).collect();
+
+    let mut signers = vec![&initializer];
+    signers.extend(&takers);
+    // Ids must follow the registry's counter
+    let mut skipped = items.clone();
+    skipped[2].2 = 3;
+    let ix = instruction::initialize_batch(&env.program_id, &initializer.pubkey(), skipped);
+    assert_ix_err(send(&mut env, ix, &signers).await, InstructionError::InvalidSeeds);
+
+    let ix = instruction::initialize_batch(&env.program_id, &initializer.pubkey(), items.clone());
+    send(&mut env, ix, &signers).await.unwrap();
+    for (taker, amount, id) in items {
+        let (escrow, _) = find_escrow_address(&env.program_id, &initializer.pubkey(), id);
+        let account = env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+        let state   = EscrowState::unpack(&account.data).unwrap();
+        assert_eq!((state.taker_pubkey, state.amount, state.status), (taker, amount, EscrowStatus::Created));
+    }
+    assert_eq!(registry(&mut env).await.active, vec![0, 1, 2]);
+}
+
+#[tokio::test]
+async fn withdraw_batch_settles_each_escrow() {
+    let mut env = setup(100).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    for id in [ID, ID + 1] {
+        initialize_id(&mut env, AMOUNT, id).await.unwrap();
+        let ix = instruction::deposit(&env.program_id, &initializer.pubkey(), &taker.pubkey(), id);
+        send(&mut env, ix, &[&initializer]).await.unwrap();
+    }
+
+    let escrows = [(initializer.pubkey(), ID), (initializer.pubkey(), ID + 1)];
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::withdraw_batch(&env.program_id, &taker.pubkey(), &env.treasury, &escrows);
+    let ix = with_party_stats(&env, instruction::with_features(&env.program_id, ix));
+    send(&mut env, ix, &[&taker, &initializer]).await.unwrap();
+    let fee = AMOUNT / 100;
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + 2 * (AMOUNT - fee));
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Released);
+
+    // A settled escrow fails the batch like it fails Withdraw
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let ix = instruction::withdraw_batch(&env.program_id, &taker.pubkey(), &env.treasury, &escrows[..1]);
+    assert_ix_err(send(&mut env, ix, &[&taker, &initializer]).await, escrow_err(EscrowError::AlreadySettled));
+}
+
+#[tokio::test]
+async fn strict_mode_requires_deposit_in_the_same_transaction() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &env.program_id, &admin.pubkey(), &admin.pubkey(), 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, true, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let initialize = instruction::initialize(&env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None);
+    let initialize = instruction::with_features(&env.program_id, instruction::with_deposit_check(initialize));
+    assert_ix_err(
+        send(&mut env, initialize.clone(), &[&initializer, &taker]).await,
+        escrow_err(EscrowError::DepositMissing),
+    );
+
+    let deposit = instruction::deposit(&env.program_id, &initializer.pubkey(), &taker.pubkey(), ID);
+    let tx = Transaction::new_signed_with_payer(
+        &[initialize, deposit],
+        Some(&env.ctx.payer.pubkey()),
+        &[&env.ctx.payer, &initializer, &taker],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Funded);
+}
+
+#[tokio::test]
+async fn dutch_auction_sells_at_the_stepped_price() {
+    let mut env = setup(0).await;
+    let initializer = env.initializer.insecure_clone();
+    let program_id  = env.program_id;
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    let auction = Auction {
+        start_price: 2 * LAMPORTS_PER_SOL,
+        floor_price: LAMPORTS_PER_SOL / 2,
+        start_ts:    clock.unix_timestamp,
+        end_ts:      clock.unix_timestamp + 1_000,
+        step_secs:   100,
+    };
+    let bad = Auction { floor_price: 3 * LAMPORTS_PER_SOL, ..auction };
+    let ix = instruction::initialize_auction(
+        &program_id, &initializer.pubkey(), &Pubkey::default(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, AMOUNT, ID, bad,
+    );
+    assert_ix_err(send(&mut env, ix, &[&initializer]).await, escrow_err(EscrowError::InvalidAuction));
+    let ix = instruction::initialize_auction(
+        &program_id, &initializer.pubkey(), &Pubkey::default(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, AMOUNT, ID, auction,
+    );
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let ix = instruction::deposit_sol(&program_id, &initializer.pubkey(), &Pubkey::default(), &SPL_TOKEN_PROGRAM_ID, ID);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+
+    let buyer       = funded_keypair(&mut env).await;
+    let buyer_token = find_associated_token_address(&buyer.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID);
+    let ix = create_idempotent(&buyer.pubkey(), &buyer.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID);
+    send(&mut env, ix, &[&buyer]).await.unwrap();
+
+    // 450s in, the price has taken four 100s steps down
+    clock.unix_timestamp += 450;
+    env.ctx.set_sysvar(&clock);
+    let price = 2 * LAMPORTS_PER_SOL - 4 * (3 * LAMPORTS_PER_SOL / 2) / 10;
+    assert_eq!(auction.price_at(clock.unix_timestamp), price);
+    let before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = instruction::buy_auction(
+        &program_id, &buyer.pubkey(), &initializer.pubkey(), &buyer_token, &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, ID,
+    );
+    let ix = instruction::with_stats(&program_id, ix, &[initializer.pubkey(), buyer.pubkey()]);
+    send(&mut env, ix, &[&buyer]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, before + price);
+    let account = env.ctx.banks_client.get_account(buyer_token).await.unwrap().unwrap();
+    assert_eq!(StateWithExtensions::<TokenAccount>::unpack(&account.data).unwrap().base.amount, AMOUNT);
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.taker_pubkey), (EscrowStatus::Released, buyer.pubkey()));
+
+    let initializer_token = find_associated_token_address(&initializer.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID);
+    let ix = instruction::cancel_auction(
+        &program_id, &initializer.pubkey(), &initializer_token, &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, ID,
+    );
+    assert!(send(&mut env, ix, &[&initializer]).await.is_err());
+}
+
+#[tokio::test]
+async fn counter_offers_apply_once_accepted() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    initialize(&mut env, AMOUNT).await.unwrap();
+    let clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    let expiry = clock.unix_timestamp + 1_000;
+
+    let stranger = funded_keypair(&mut env).await;
+    let ix = instruction::propose_terms(&program_id, &stranger.pubkey(), &initializer.pubkey(), ID, AMOUNT / 2, expiry);
+    assert_ix_err(send(&mut env, ix, &[&stranger]).await, escrow_err(EscrowError::NotParty));
+    let ix = instruction::propose_terms(&program_id, &taker.pubkey(), &initializer.pubkey(), ID, AMOUNT / 2, expiry);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    let ix = instruction::accept_terms(&program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::NoProposal));
+
+    // The initializer counters, which replaces the taker's proposal
+    let ix = instruction::propose_terms(&program_id, &initializer.pubkey(), &initializer.pubkey(), ID, AMOUNT * 3 / 4, expiry);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.amount, state.proposed_by), (AMOUNT, initializer.pubkey()));
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let ix = instruction::accept_terms(&program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.amount, state.expiry_ts, state.proposed_by), (AMOUNT * 3 / 4, expiry, Pubkey::default()));
+
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let ix = instruction::accept_terms(&program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::NoProposal));
+}
+
+async fn party_stats(env: &mut Env, party: Pubkey) -> StatsState {
+    let stats   = find_stats_address(&env.program_id, &party).0;
+    let account = env.ctx.banks_client.get_account(stats).await.unwrap().unwrap();
+    StatsState::try_from_slice(&account.data).unwrap()
+}
+
+#[tokio::test]
+async fn stats_count_the_escrows_each_party_settled() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let (program_id, payer)  = (env.program_id, env.ctx.payer.pubkey());
+    let parties = [initializer.pubkey(), taker.pubkey()];
+    for party in parties {
+        send(&mut env, instruction::initialize_stats(&program_id, &payer, &party), &[]).await.unwrap();
+    }
+
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let ix = instruction::withdraw(&program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID);
+    send(&mut env, instruction::with_stats(&program_id, ix, &parties), &[&initializer, &taker]).await.unwrap();
+
+    // Stats are optional: a cancel given none, or only the initializer's,
+    // goes through and counts just the parties it was given
+    initialize_id(&mut env, AMOUNT, 1).await.unwrap();
+    let ix = instruction::cancel(&program_id, &initializer.pubkey(), 1);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    initialize_id(&mut env, AMOUNT, 2).await.unwrap();
+    let ix = instruction::cancel(&program_id, &initializer.pubkey(), 2);
+    let ix = instruction::with_stats(&program_id, ix, &parties[..1]);
+    send(&mut env, instruction::with_event_cpi(&program_id, ix), &[&initializer]).await.unwrap();
+
+    // Passing the initializer's stats twice counts the cancellation once
+    initialize_id(&mut env, AMOUNT, 3).await.unwrap();
+    let ix = instruction::cancel(&program_id, &initializer.pubkey(), 3);
+    let ix = instruction::with_stats(&program_id, ix, &[initializer.pubkey(), initializer.pubkey(), taker.pubkey()]);
+    send(&mut env, instruction::with_event_cpi(&program_id, ix), &[&initializer]).await.unwrap();
+
+    let stats = party_stats(&mut env, initializer.pubkey()).await;
+    assert_eq!((stats.completed, stats.cancelled, stats.disputed, stats.volume), (1, 2, 0, AMOUNT));
+    let stats = party_stats(&mut env, taker.pubkey()).await;
+    assert_eq!((stats.completed, stats.cancelled, stats.volume), (1, 1, AMOUNT));
+}
+
+#[tokio::test]
+async fn separate_payer_covers_the_initializer_rent() {
+    let mut env = setup(0).await;
+    let taker       = env.taker.insecure_clone();
+    let relayer     = funded_keypair(&mut env).await;
+    let initializer = Keypair::new();
+    let ix = instruction::initialize(&env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None);
+    send(&mut env, instruction::with_payer(&relayer.pubkey(), ix), &[&initializer, &taker, &relayer]).await.unwrap();
+
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, 0);
+    let (escrow, _) = find_escrow_address(&env.program_id, &initializer.pubkey(), ID);
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+    let state   = EscrowState::unpack(&account.data).unwrap();
+    assert_eq!((state.initializer_pubkey, state.status), (initializer.pubkey(), EscrowStatus::Created));
+    assert!(lamports(&mut env.ctx, relayer.pubkey()).await < 10 * LAMPORTS_PER_SOL);
+}
+
+#[tokio::test]
+async fn relayer_opens_and_funds_an_escrow_the_initializer_signed_off_chain() {
+    let mut env = setup(0).await;
+    let taker       = env.taker.insecure_clone();
+    let relayer     = funded_keypair(&mut env).await;
+    let initializer = Keypair::new();
+    let program_id  = env.program_id;
+    let clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    let deadline    = clock.unix_timestamp + 600;
+    let (escrow, _) = find_escrow_address(&program_id, &initializer.pubkey(), ID);
+    let message     = relayed_initialize_message(&escrow, &taker.pubkey(), AMOUNT, 0, deadline);
+    let signature   = initializer.sign_message(&message);
+    let verify      = new_ed25519_instruction_with_signature(
+        &message, signature.as_ref().try_into().unwrap(), &initializer.pubkey().to_bytes(),
+    );
+    let relayed = |amount| {
+        instruction::initialize_relayed(
+            &program_id, &initializer.pubkey(), &taker.pubkey(), &relayer.pubkey(), amount, ID, 0, deadline,
+        )
+    };
+    let deposit = instruction::deposit_from(&program_id, &relayer.pubkey(), &initializer.pubkey(), &taker.pubkey(), ID);
+
+    // The relayer can't change the terms the initializer signed
+    let tx = Transaction::new_signed_with_payer(
+        &[verify.clone(), relayed(2 * AMOUNT)], Some(&relayer.pubkey()), &[&relayer, &taker], env.ctx.last_blockhash,
+    );
+    let err = env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap();
+    assert_eq!(err, TransactionError::InstructionError(1, escrow_err(EscrowError::InvalidVoucher)));
+
+    let tx = Transaction::new_signed_with_payer(
+        &[verify, relayed(AMOUNT), deposit], Some(&relayer.pubkey()), &[&relayer, &taker], env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    let account = env.ctx.banks_client.get_account(escrow).await.unwrap().unwrap();
+    let state   = EscrowState::unpack(&account.data).unwrap();
+    assert_eq!((state.initializer_pubkey, state.status), (initializer.pubkey(), EscrowStatus::Funded));
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, 0);
+}
+
+#[tokio::test]
+async fn vesting_releases_linearly_after_the_cliff_less_the_fee() {
+    let mut env = setup(250).await;
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize_vesting(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, now, now + 100, now + 1_000, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let ix = instruction::withdraw_vested(&env.program_id, &taker.pubkey(), &initializer.pubkey(), &env.treasury, ID);
+    let ix = with_party_stats(&env, instruction::with_features(&env.program_id, ix));
+    // Halfway to the cliff nothing has vested yet
+    warp_past(&mut env, now + 49).await;
+    assert_ix_err(send(&mut env, ix.clone(), &[&taker]).await, escrow_err(EscrowError::NothingVested));
+
+    // A quarter and a half in, then past the end; each withdrawal pays what vested since the last
+    for (at, payout) in [(now + 249, AMOUNT / 4), (now + 499, AMOUNT / 4), (now + 1_999, AMOUNT / 2)] {
+        warp_past(&mut env, at).await;
+        env.ctx.get_new_latest_blockhash().await.unwrap();
+        let taker_before    = lamports(&mut env.ctx, taker.pubkey()).await;
+        let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+        send(&mut env, ix.clone(), &[&taker]).await.unwrap();
+        let fee = payout * 250 / 10_000;
+        assert_eq!(lamports(&mut env.ctx, env.treasury).await, treasury_before + fee);
+        assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + payout - fee);
+
+        // Nothing more until the clock moves on
+        env.ctx.get_new_latest_blockhash().await.unwrap();
+        assert!(send(&mut env, ix.clone(), &[&taker]).await.is_err());
+    }
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.remaining, state.status), (0, EscrowStatus::Released));
+}
+
+#[tokio::test]
+async fn subscription_releases_one_tranche_per_elapsed_interval() {
+    let mut env = setup(250).await;
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let tranche      = AMOUNT / 4;
+    let subscription = Subscription { amount_per_interval: tranche, interval_secs: 100, start_ts: now };
+    let ix = instruction::initialize_subscription(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), ID, 4, subscription, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.amount, AMOUNT);
+
+    let cranker = funded_keypair(&mut env).await;
+    let ix = instruction::withdraw_subscription(&env.program_id, &cranker.pubkey(), &taker.pubkey(), &initializer.pubkey(), &env.treasury, ID);
+    let ix = instruction::with_features(&env.program_id, ix);
+    assert_ix_err(send(&mut env, ix.clone(), &[&cranker]).await, escrow_err(EscrowError::NothingVested));
+
+    // Two and a half intervals in, two tranches are due
+    warp_past(&mut env, now + 249).await;
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let before          = lamports(&mut env.ctx, taker.pubkey()).await;
+    let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+    send(&mut env, ix.clone(), &[&cranker]).await.unwrap();
+    let fee = 2 * tranche * 250 / 10_000;
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, before + 2 * tranche - fee);
+    assert_eq!(lamports(&mut env.ctx, env.treasury).await, treasury_before + fee);
+    assert_eq!(escrow_state(&mut env).await.remaining, 2 * tranche);
+
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(send(&mut env, ix.clone(), &[&cranker]).await, escrow_err(EscrowError::NothingVested));
+
+    warp_past(&mut env, now + 1_000).await;
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let ix = instruction::withdraw_subscription(&env.program_id, &taker.pubkey(), &taker.pubkey(), &initializer.pubkey(), &env.treasury, ID);
+    let ix = with_party_stats(&env, instruction::with_features(&env.program_id, ix));
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.remaining, state.status), (0, EscrowStatus::Released));
+}
+
+#[tokio::test]
+async fn capture_takes_part_of_an_authorization_and_refunds_the_rest() {
+    let mut env = setup(250).await;
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize_authorization(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, now + 100, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    let ix = instruction::capture(&env.program_id, &taker.pubkey(), &initializer.pubkey(), &env.treasury, ID, AMOUNT + 1);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::InvalidAmount));
+
+    let captured           = AMOUNT * 3 / 5;
+    let fee                = captured * 250 / 10_000;
+    let taker_before       = lamports(&mut env.ctx, taker.pubkey()).await;
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = instruction::capture(&env.program_id, &taker.pubkey(), &initializer.pubkey(), &env.treasury, ID, captured);
+    let ix = with_party_stats(&env, instruction::with_features(&env.program_id, ix));
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + captured - fee);
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, initializer_before + AMOUNT - captured);
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.captured, state.remaining, state.status), (captured, 0, EscrowStatus::Released));
+}
+
+#[tokio::test]
+async fn capture_after_the_window_fails() {
+    let mut env = setup(0).await;
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize_authorization(
+        &env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, now + 100, None,
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+
+    warp_past(&mut env, now + 100).await;
+    let ix = instruction::capture(&env.program_id, &taker.pubkey(), &initializer.pubkey(), &env.treasury, ID, AMOUNT);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::Expired));
+    let ix = instruction::refund(&env.program_id, &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert_eq!(escrow_state(&mut env).await.status, EscrowStatus::Cancelled);
+}
+
+#[tokio::test]
+async fn payer_funds_and_releases_an_invoice_the_taker_issued() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let initializer_before = lamports(&mut env.ctx, initializer.pubkey()).await;
+    let ix = instruction::initialize_invoice(&env.program_id, &taker.pubkey(), &initializer.pubkey(), AMOUNT, ID, 0);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert!(state.is_invoice());
+    assert_eq!((state.initializer_pubkey, state.created_by), (initializer.pubkey(), taker.pubkey()));
+    assert_eq!(lamports(&mut env.ctx, initializer.pubkey()).await, initializer_before);
+
+    deposit(&mut env).await.unwrap();
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::release_invoice(&env.program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&initializer]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + AMOUNT);
+}
+
+#[tokio::test]
+async fn taker_cancels_an_unpaid_invoice() {
+    let mut env = setup(0).await;
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let ix = instruction::initialize_invoice(&env.program_id, &taker.pubkey(), &initializer.pubkey(), AMOUNT, ID, 0);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+
+    // An ordinary escrow needs both signatures to release, and isn't the taker's to cancel
+    initialize_id(&mut env, AMOUNT, ID + 1).await.unwrap();
+    let ix = instruction::cancel_invoice(&env.program_id, &taker.pubkey(), &initializer.pubkey(), ID + 1);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::NotInvoice));
+
+    let (escrow, vault) = (escrow_address(&env), vault_address(&env));
+    let rent         = lamports(&mut env.ctx, escrow).await + lamports(&mut env.ctx, vault).await;
+    let taker_before = lamports(&mut env.ctx, taker.pubkey()).await;
+    let ix = instruction::cancel_invoice(&env.program_id, &taker.pubkey(), &initializer.pubkey(), ID);
+    let ix = with_party_stats(&env, ix);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    assert!(env.ctx.banks_client.get_account(escrow).await.unwrap().is_none());
+    assert_eq!(lamports(&mut env.ctx, taker.pubkey()).await, taker_before + rent);
+    assert_eq!(registry(&mut env).await.active, vec![ID + 1]);
+}
+
+#[tokio::test]
+async fn initialize_takes_the_parameters_of_its_template() {
+    let mut env = setup(100).await;
+    let admin   = env.ctx.payer.insecure_clone();
+    let arbiter = Pubkey::new_unique();
+    let name    = *b"marketplace-standard\0\0\0\0\0\0\0\0\0\0\0\0";
+    let ix = instruction::set_template(&env.program_id, &admin.pubkey(), &name, 500, 600, 1_000, &arbiter);
+    send(&mut env, ix, &[]).await.unwrap();
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let other = Pubkey::new_unique();
+    let ix = instruction::initialize(&env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, Some(&other));
+    let ix = instruction::with_template(&env.program_id, &name, ix);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::InvalidTemplate));
+
+    let ix = instruction::initialize(&env.program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None);
+    let ix = instruction::with_template(&env.program_id, &name, ix);
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.arbiter_pubkey, state.expiry_ts), (arbiter, now + 1_000));
+    assert_eq!((state.fee_bps, state.dispute_window_secs), (500, 600));
+
+    deposit(&mut env).await.unwrap();
+    let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+    withdraw(&mut env).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, env.treasury).await, treasury_before + AMOUNT * 500 / 10_000);
+}
+
+#[tokio::test]
+async fn granted_roles_carry_out_their_duties_only() {
+    let mut env = setup(0).await;
+    let admin  = env.ctx.payer.insecure_clone();
+    let pauser = funded_keypair(&mut env).await;
+    let ix = instruction::initialize_authorities(&env.program_id, &admin.pubkey());
+    send(&mut env, ix, &[]).await.unwrap();
+
+    let ix = instruction::set_paused(&env.program_id, &pauser.pubkey(), true);
+    assert_ix_err(send(&mut env, ix.clone(), &[&pauser]).await, escrow_err(EscrowError::MissingRole));
+    let grant = instruction::grant_role(&env.program_id, &admin.pubkey(), &pauser.pubkey(), Role::Pauser);
+    send(&mut env, grant, &[]).await.unwrap();
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    send(&mut env, ix, &[&pauser]).await.unwrap();
+    assert_ix_err(initialize(&mut env, AMOUNT).await, escrow_err(EscrowError::Paused));
+
+    // A Pauser can neither change the fees nor hand out roles
+    let ix = instruction::set_fees(&env.program_id, &pauser.pubkey(), 100, 0, 0);
+    assert_ix_err(send(&mut env, ix, &[&pauser]).await, escrow_err(EscrowError::MissingRole));
+    let ix = instruction::grant_role(&env.program_id, &pauser.pubkey(), &pauser.pubkey(), Role::FeeManager);
+    assert_ix_err(send(&mut env, ix, &[&pauser]).await, escrow_err(EscrowError::MissingRole));
+
+    let revoke = instruction::revoke_role(&env.program_id, &admin.pubkey(), &pauser.pubkey(), Role::Pauser);
+    send(&mut env, revoke, &[]).await.unwrap();
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let ix = instruction::set_paused(&env.program_id, &pauser.pubkey(), false);
+    assert_ix_err(send(&mut env, ix, &[&pauser]).await, escrow_err(EscrowError::MissingRole));
+}
+
+#[tokio::test]
+async fn multisig_pda_runs_admin_instructions_through_cpi() {
+    let frontend    = Pubkey::new_unique();
+    let mut env     = setup_with(0, &[frontend]).await;
+    let program_id  = env.program_id;
+    let members     = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let (multisig, vault) = create_multisig(&mut env, &members, 2);
+
+    // Hand the admin key to the multisig's vault PDA, and restrict CPI to
+    // another program, which admin instructions are exempt from
+    let admin = env.ctx.payer.insecure_clone();
+    let ix = instruction::update_config(
+        &program_id, &admin.pubkey(), &vault, 0, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &frontend, &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+
+    // One approval is short of the 2-of-3 threshold, and outsiders can't approve
+    let update = instruction::update_config(
+        &program_id, &vault, &vault, 100, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, false, &frontend, &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    let err = run_proposal(&mut env, &multisig, &[&members[0]], update.clone()).await;
+    assert_ix_err(err, InstructionError::Custom(NOT_ENOUGH_APPROVALS));
+    let outsider = Keypair::new();
+    let ix = approve_proposal(&multisig, &outsider.pubkey(), &update);
+    assert_ix_err(send(&mut env, ix, &[&outsider]).await, InstructionError::MissingRequiredSignature);
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    run_proposal(&mut env, &multisig, &[&members[2]], update.clone()).await.unwrap();
+    // The approvals are used up
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let err = send(&mut env, execute_proposal(&multisig, update), &[]).await;
+    assert_ix_err(err, InstructionError::Custom(NOT_ENOUGH_APPROVALS));
+
+    let approvers = [&members[0], &members[1]];
+    for ix in [
+        instruction::initialize_authorities(&program_id, &vault),
+        instruction::set_fees(&program_id, &vault, 250, 0, 0),
+        instruction::set_paused(&program_id, &vault, true),
+    ] {
+        run_proposal(&mut env, &multisig, &approvers, ix).await.unwrap();
+    }
+    assert_ix_err(initialize(&mut env, AMOUNT).await, escrow_err(EscrowError::Paused));
+
+    // The old admin key no longer counts
+    let ix = instruction::set_paused(&program_id, &admin.pubkey(), false);
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::MissingRole));
+    run_proposal(&mut env, &multisig, &approvers, instruction::set_paused(&program_id, &vault, false)).await.unwrap();
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+    withdraw(&mut env).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, env.treasury).await, treasury_before + AMOUNT * 250 / 10_000);
+}
+
+#[tokio::test]
+async fn emergency_withdraw_waits_out_the_delay_and_can_be_vetoed() {
+    let mut env = setup(0).await;
+    let admin       = env.ctx.payer.insecure_clone();
+    let initializer = env.initializer.pubkey();
+    let taker       = env.taker.insecure_clone();
+    let recipient   = Pubkey::new_unique();
+    initialize(&mut env, AMOUNT).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let now = 1_000_000;
+    warp_past(&mut env, now - 1).await;
+
+    let propose = instruction::propose_emergency_withdraw(&env.program_id, &admin.pubkey(), &initializer, ID, &recipient);
+    let execute = instruction::execute_emergency_withdraw(&env.program_id, &admin.pubkey(), &initializer, ID, &recipient);
+    send(&mut env, propose.clone(), &[]).await.unwrap();
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.emergency_recipient, state.emergency_proposed_at), (recipient, now));
+    assert_ix_err(send(&mut env, execute.clone(), &[]).await, escrow_err(EscrowError::NoEmergency));
+
+    // Either party can call it off while the delay runs
+    let ix = instruction::veto_emergency_withdraw(&env.program_id, &taker.pubkey(), &initializer, ID);
+    send(&mut env, ix, &[&taker]).await.unwrap();
+    warp_past(&mut env, now + EMERGENCY_DELAY_SECS).await;
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_ix_err(send(&mut env, execute.clone(), &[]).await, escrow_err(EscrowError::NoEmergency));
+
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    send(&mut env, propose, &[]).await.unwrap();
+    warp_past(&mut env, now + 2 * EMERGENCY_DELAY_SECS).await;
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let execute = with_party_stats(&env, execute);
+    send(&mut env, execute, &[]).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, recipient).await, AMOUNT);
+    let state = escrow_state(&mut env).await;
+    assert_eq!((state.status, state.remaining), (EscrowStatus::Cancelled, 0));
+}
+
+#[tokio::test]
+async fn feature_flags_gate_token_escrows_and_fees() {
+    let mut env = setup(0).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    send(&mut env, instruction::initialize_authorities(&program_id, &admin.pubkey()), &[]).await.unwrap();
+    let ix = instruction::set_feature(&program_id, &taker.pubkey(), Feature::TokenEscrows, false);
+    assert_ix_err(send(&mut env, ix, &[&taker]).await, escrow_err(EscrowError::WrongAdmin));
+    for feature in [Feature::TokenEscrows, Feature::Fees] {
+        let ix = instruction::set_feature(&program_id, &admin.pubkey(), feature, false);
+        send(&mut env, ix, &[]).await.unwrap();
+    }
+
+    let init = instruction::initialize_token(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), &SPL_TOKEN_NATIVE_MINT, &SPL_TOKEN_PROGRAM_ID, AMOUNT, ID, 0, None,
+    );
+    assert_ix_err(send(&mut env, init.clone(), &[&initializer, &taker]).await, escrow_err(EscrowError::FeatureDisabled));
+    // Leaving the flags out doesn't get around them
+    let mut bare = init.clone();
+    bare.accounts.pop();
+    assert_ix_err(send(&mut env, bare, &[&initializer, &taker]).await, InstructionError::NotEnoughAccountKeys);
+    let ix = instruction::set_fees(&program_id, &admin.pubkey(), 100, 0, 0);
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::FeatureDisabled));
+    let ix = instruction::set_fees(&program_id, &admin.pubkey(), 0, 0, 0);
+    send(&mut env, ix, &[]).await.unwrap();
+
+    env.ctx.get_new_latest_blockhash().await.unwrap();
+    let ix = instruction::set_feature(&program_id, &admin.pubkey(), Feature::TokenEscrows, true);
+    send(&mut env, ix, &[]).await.unwrap();
+    send(&mut env, init, &[&initializer, &taker]).await.unwrap();
+}
+
+#[tokio::test]
+async fn disabled_features_stop_charging_fees_and_enforcing_strict_mode() {
+    let mut env = setup(100).await;
+    let admin = env.ctx.payer.insecure_clone();
+    let (initializer, taker) = (env.initializer.insecure_clone(), env.taker.insecure_clone());
+    let program_id = env.program_id;
+    let ix = instruction::update_config(
+        &program_id, &admin.pubkey(), &admin.pubkey(), 100, &env.treasury, false, DEFAULT_MAX_EXPIRY_HORIZON,
+        0, 0, true, &Pubkey::default(), &Pubkey::default(), 0, 0, 0, 0, 0, 0, 0, &Pubkey::default(),
+    );
+    send(&mut env, ix, &[]).await.unwrap();
+    let init = instruction::initialize(&program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, 0, None);
+    let init = instruction::with_deposit_check(init);
+    // Leaving the flags out doesn't get around strict mode
+    let err = send(&mut env, init.clone(), &[&initializer, &taker]).await;
+    assert_ix_err(err, escrow_err(EscrowError::FeaturesAccountMissing));
+
+    for feature in [Feature::Fees, Feature::StrictMode] {
+        send(&mut env, instruction::set_feature(&program_id, &admin.pubkey(), feature, false), &[]).await.unwrap();
+    }
+    // The config keeps its fee and strict mode, but neither applies while off
+    let init = instruction::with_features(&program_id, init);
+    send(&mut env, init, &[&initializer, &taker]).await.unwrap();
+    deposit(&mut env).await.unwrap();
+    let ix = instruction::withdraw(&program_id, &initializer.pubkey(), &taker.pubkey(), &env.treasury, ID);
+    let ix = with_party_stats(&env, ix);
+    assert_ix_err(send(&mut env, ix, &[&initializer, &taker]).await, escrow_err(EscrowError::FeaturesAccountMissing));
+    let treasury_before = lamports(&mut env.ctx, env.treasury).await;
+    withdraw(&mut env).await.unwrap();
+    assert_eq!(lamports(&mut env.ctx, env.treasury).await, treasury_before);
+
+    // Nor can a config be created with a fee
+    let ix = instruction::initialize_config(&program_id, &admin.pubkey(), 100, &env.treasury);
+    assert_ix_err(send(&mut env, ix, &[]).await, escrow_err(EscrowError::FeatureDisabled));
+}
+
+/// A step of the escrow lifecycle, to run against the program and the
+/// `Lifecycle` model alike.
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    Deposit,
+    Withdraw { taker_signs: bool },
+    Cancel,
+    Refund,
+    Close,
+    Dispute,
+    Resolve { release_to_taker: bool },
+    Expire,
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        Just(Step::Deposit),
+        any::<bool>().prop_map(|taker_signs| Step::Withdraw { taker_signs }),
+        Just(Step::Cancel),
+        Just(Step::Refund),
+        Just(Step::Close),
+        Just(Step::Dispute),
+        any::<bool>().prop_map(|release_to_taker| Step::Resolve { release_to_taker }),
+        Just(Step::Expire),
+    ]
+}
+
+/// What a native escrow with an arbiter and an expiry should go through:
+/// its status, `None` once its account is gone, and whether it expired.
+struct Lifecycle {
+    status:  Option<EscrowStatus>,
+    expired: bool,
+}
+
+impl Lifecycle {
+    /// Applies `step`, returning whether the program should accept it.
+    fn apply(&mut self, step: Step) -> bool {
+        let next = match (step, self.status) {
+            (Step::Expire, _) => {
+                self.expired = true;
+                return true;
+            }
+            (Step::Deposit, Some(EscrowStatus::Created)) if !self.expired => Some(EscrowStatus::Funded),
+            (Step::Withdraw { taker_signs: true }, Some(EscrowStatus::Funded)) => Some(EscrowStatus::Released),
+            (Step::Cancel, Some(EscrowStatus::Created)) => None,
+            (Step::Refund, Some(EscrowStatus::Funded)) if self.expired => Some(EscrowStatus::Cancelled),
+            (Step::Close, Some(EscrowStatus::Released | EscrowStatus::Cancelled)) => None,
+            (Step::Dispute, Some(EscrowStatus::Funded)) => Some(EscrowStatus::Disputed),
+            (Step::Resolve { release_to_taker }, Some(EscrowStatus::Disputed)) => {
+                Some(if release_to_taker { EscrowStatus::Released } else { EscrowStatus::Cancelled })
+            }
+            _ => return false,
+        };
+        self.status = next;
+        true
+    }
+}
+
+async fn run_lifecycle(steps: &[Step]) {
+    let mut env = setup(100).await;
+    let (initializer, taker, arbiter) = (env.initializer.insecure_clone(), env.taker.insecure_clone(), Keypair::new());
+    let program_id = env.program_id;
+    let clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    let expiry = clock.unix_timestamp + 1_000;
+    let ix = instruction::initialize(
+        &program_id, &initializer.pubkey(), &taker.pubkey(), AMOUNT, ID, expiry, Some(&arbiter.pubkey()),
+    );
+    send(&mut env, ix, &[&initializer, &taker]).await.unwrap();
+
+    let mut model = Lifecycle { status: Some(EscrowStatus::Created), expired: false };
+    for &step in steps {
+        let (from, to) = (initializer.pubkey(), taker.pubkey());
+        let (ix, signers) = match step {
+            Step::Deposit => (instruction::deposit(&program_id, &from, &to, ID), vec![&initializer]),
+            Step::Withdraw { taker_signs } => {
+                let mut ix = instruction::withdraw(&program_id, &from, &to, &env.treasury, ID);
+                ix.accounts[1].is_signer = taker_signs;
+                (ix, if taker_signs { vec![&initializer, &taker] } else { vec![&initializer] })
+            }
+            Step::Cancel => (instruction::cancel(&program_id, &from, ID), vec![&initializer]),
+            Step::Refund => (instruction::refund(&program_id, &from, ID), vec![&initializer]),
+            Step::Close => (instruction::close(&program_id, &from, ID), vec![&initializer]),
+            Step::Dispute => (instruction::dispute(&program_id, &to, &from, ID), vec![&taker]),
+            Step::Resolve { release_to_taker } => {
+                (instruction::resolve(&program_id, &arbiter.pubkey(), &from, &to, ID, release_to_taker), vec![&arbiter])
+            }
+            Step::Expire => {
+                warp_past(&mut env, expiry).await;
+                model.apply(step);
+                continue;
+            }
+        };
+        env.ctx.get_new_latest_blockhash().await.unwrap();
+        let ix     = instruction::with_stats(&program_id, instruction::with_features(&program_id, ix), &[from, to]);
+        let result = send(&mut env, ix, &signers).await;
+        assert_eq!(result.is_ok(), model.apply(step), "{step:?} in {steps:?}: {result:?}");
+
+        let account = env.ctx.banks_client.get_account(escrow_address(&env)).await.unwrap();
+        let status  = account.map(|account| EscrowState::unpack(&account.data).unwrap().status);
+        assert_eq!(status, model.status, "status after {step:?} in {steps:?}");
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn lifecycle_matches_the_model(steps in prop::collection::vec(step(), 1..12)) {
+        tokio::runtime::Runtime::new().unwrap().block_on(run_lifecycle(&steps));
+    }
+}